@@ -0,0 +1,162 @@
+//! The address book of on chain addresses, stored in the user config, which on chain importers use
+//! to tell transfers between the user's own wallets from payments to or gifts from others.
+
+use crate::transactions::Movement;
+use chrono::Duration;
+use serde::{Deserialize, Serialize};
+use std::{fs::File, path::PathBuf};
+
+/// An on chain address with a label
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Address {
+    pub address: String,
+    /// e.g. a wallet name, the ENS name or who the address belongs to
+    pub label: String,
+    /// Whether the address is one of the user's own, so transfers to and from it don't change
+    /// the beneficial ownership of the assets
+    pub owned: bool,
+}
+
+lazy_static::lazy_static! {
+    static ref ADDRESS_BOOK: Vec<Address> = read_address_book().unwrap_or_else(|err| {
+        tracing::warn!("Unable to read the address book: {}", err);
+        Vec::new()
+    });
+}
+
+/// The file containing the address book, in the config directory
+pub fn address_book_path() -> Option<PathBuf> {
+    Some(crate::utils::config_dir()?.join("addresses.csv"))
+}
+
+pub fn read_address_book() -> color_eyre::Result<Vec<Address>> {
+    match address_book_path() {
+        Some(path) if path.exists() => {
+            let mut rdr = csv::Reader::from_reader(File::open(path)?);
+            Ok(rdr.deserialize().collect::<Result<Vec<_>, _>>()?)
+        }
+        _ => Ok(Vec::new()),
+    }
+}
+
+pub fn write_address_book(addresses: &[Address]) -> color_eyre::Result<()> {
+    let path = address_book_path().ok_or(color_eyre::eyre::eyre!(
+        "No config directory, set TAXC_CONFIG_DIR or HOME"
+    ))?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    crate::utils::write_csv(addresses.to_vec(), File::create(path)?)
+}
+
+/// Find an address in the address book. Hex and bech32 addresses are case insensitive, and Bitcoin
+/// Cash addresses may be with or without their prefix.
+pub fn find(address: &str) -> Option<&'static Address> {
+    find_in(&ADDRESS_BOOK, address)
+}
+
+fn find_in<'a>(address_book: &'a [Address], address: &str) -> Option<&'a Address> {
+    let without_prefix = |a: &str| a.rsplit(':').next().unwrap_or_default().to_lowercase();
+    let address = without_prefix(address);
+    address_book
+        .iter()
+        .find(|entry| without_prefix(&entry.address) == address)
+}
+
+/// Whether a transfer to or from the counterparty changes beneficial ownership, `None` if the
+/// address is not in the address book
+pub fn ownership_changed(counterparty: &str) -> Option<bool> {
+    find(counterparty).map(|entry| !entry.owned)
+}
+
+/// Adds addresses learned from matching transfers to the address book, unless they are already
+/// in it, returning how many were added
+pub fn learn(learned: Vec<Address>) -> color_eyre::Result<usize> {
+    let mut address_book = read_address_book()?;
+    let mut added = 0;
+    for address in learned {
+        if find_in(&address_book, &address.address).is_none() {
+            tracing::info!("Learned {} is {}", address.address, address.label);
+            address_book.push(address);
+            added += 1;
+        }
+    }
+    if added > 0 {
+        write_address_book(&address_book)?;
+    }
+    Ok(added)
+}
+
+/// Whether the incoming transfer is the outgoing one arriving, e.g. a withdrawal from an exchange
+/// received by a wallet. It is of the same asset and the amount sent, or that less the fee,
+/// received within a day. Exchanges may record the time of the withdrawal after it was sent, so
+/// it may be received up to an hour before.
+pub fn is_same_transfer(sent: &Movement, received: &Movement) -> bool {
+    let amount = *sent.amount.amount();
+    let less_fee = if sent.fee.currency() == sent.amount.currency() {
+        amount - *sent.fee.amount()
+    } else {
+        amount
+    };
+    let received_amount = *received.amount.amount();
+    sent.amount.currency() == received.amount.currency()
+        && (received_amount == amount || received_amount == less_fee)
+        && received.date_time >= sent.date_time - Duration::hours(1)
+        && received.date_time <= sent.date_time + Duration::days(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn addresses_are_found_ignoring_case_and_prefix() {
+        let csv = "\
+address,label,owned
+0xd8dA6BF26964aF9D7eEd9e03E53415D37aA96045,vitalik.eth,false
+bitcoincash:qpm2qsznhks23z7629mms6s4cwef74vcwvy22gdx6a,Electron Cash,true
+";
+        let address_book = csv::Reader::from_reader(csv.as_bytes())
+            .deserialize()
+            .collect::<Result<Vec<Address>, _>>()
+            .unwrap();
+        let find = |address| find_in(&address_book, address).map(|a| (a.label.as_str(), a.owned));
+
+        assert_eq!(
+            find("0xd8da6bf26964af9d7eed9e03e53415d37aa96045"),
+            Some(("vitalik.eth", false))
+        );
+        assert_eq!(
+            find("qpm2qsznhks23z7629mms6s4cwef74vcwvy22gdx6a"),
+            Some(("Electron Cash", true))
+        );
+        assert_eq!(find("0x0000000000000000000000000000000000000000"), None);
+    }
+
+    #[test]
+    fn transfers_match_by_amount_and_time() {
+        use crate::money::amount;
+        use chrono::NaiveDate;
+        use rust_decimal_macros::dec;
+
+        let movement = |hour, value, fee| Movement {
+            date_time: NaiveDate::from_ymd(2021, 3, 1).and_hms(hour, 0, 0),
+            amount: amount("BTC", value).unwrap(),
+            fee: amount("BTC", fee).unwrap(),
+            exchange: None,
+            account: None,
+            ownership_changed: false,
+            notes: None,
+        };
+        let sent = movement(10, dec!(0.5), dec!(0.0005));
+
+        assert!(is_same_transfer(&sent, &movement(11, dec!(0.5), dec!(0))));
+        assert!(is_same_transfer(
+            &sent,
+            &movement(11, dec!(0.4995), dec!(0))
+        ));
+        assert!(is_same_transfer(&sent, &movement(9, dec!(0.5), dec!(0))));
+        assert!(!is_same_transfer(&sent, &movement(8, dec!(0.5), dec!(0))));
+        assert!(!is_same_transfer(&sent, &movement(11, dec!(0.49), dec!(0))));
+    }
+}
@@ -0,0 +1,69 @@
+use crate::{
+    cmd::{import::exchanges::otc, imports},
+    trades::TradeRecord,
+    transactions::Transaction,
+};
+use argh::FromArgs;
+use rust_decimal::Decimal;
+use std::{convert::TryFrom, path::PathBuf};
+
+/// Add a single OTC or peer to peer trade to a transactions file, which can be undone with
+/// `imports undo`
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "add-trade")]
+pub struct AddTradeCommand {
+    /// the csv file containing the transactions to add the trade to
+    #[argh(option)]
+    txs: PathBuf,
+    /// the date and time of the trade e.g. "2021-01-31 14:00:00"
+    #[argh(option)]
+    date_time: String,
+    /// buy or sell of the asset
+    #[argh(option)]
+    side: String,
+    /// the asset bought or sold e.g. BTC
+    #[argh(option)]
+    asset: String,
+    /// the amount of the asset bought or sold
+    #[argh(option)]
+    amount: Decimal,
+    /// the currency paid or received for the asset e.g. GBP
+    #[argh(option)]
+    quote_asset: String,
+    /// the total amount paid or received for the asset
+    #[argh(option)]
+    quote_amount: Decimal,
+    /// the currency any fee was paid in
+    #[argh(option, default = "String::new()")]
+    fee_asset: String,
+    /// the amount of any fee
+    #[argh(option)]
+    fee_amount: Option<Decimal>,
+    /// who the trade was made with
+    #[argh(option, default = "String::new()")]
+    counterparty: String,
+    /// reference of the settlement e.g. a bank transfer or transaction hash
+    #[argh(option, default = "String::new()")]
+    settlement_reference: String,
+}
+
+impl AddTradeCommand {
+    pub fn exec(&self) -> color_eyre::Result<()> {
+        let record = otc::Record {
+            date_time: self.date_time.clone(),
+            side: self.side.clone(),
+            asset: self.asset.clone(),
+            amount: self.amount,
+            quote_asset: self.quote_asset.clone(),
+            quote_amount: self.quote_amount,
+            fee_asset: self.fee_asset.clone(),
+            fee_amount: self.fee_amount,
+            counterparty: self.counterparty.clone(),
+            settlement_reference: self.settlement_reference.clone(),
+        };
+        let trade = Transaction::try_from(record)?;
+        let source = format!("add-trade {}", self.counterparty);
+        imports::append_import(&self.txs, source, vec![TradeRecord::from(&trade)])?;
+        Ok(())
+    }
+}
@@ -0,0 +1,110 @@
+use crate::addresses::{self, Address};
+use argh::FromArgs;
+use color_eyre::eyre;
+use serde::Deserialize;
+use std::io;
+
+/// List or add addresses in the address book, used by on chain importers to tell transfers
+/// between your own wallets from payments and gifts
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "addresses")]
+pub struct AddressesCommand {
+    #[argh(subcommand)]
+    sub: AddressesSubCommand,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+enum AddressesSubCommand {
+    List(ListCommand),
+    Add(AddCommand),
+}
+
+impl AddressesCommand {
+    pub fn exec(&self) -> color_eyre::Result<()> {
+        match self.sub {
+            AddressesSubCommand::List(ref list) => list.exec(),
+            AddressesSubCommand::Add(ref add) => add.exec(),
+        }
+    }
+
+    /// Whether the command writes the address book
+    pub fn modifies_data(&self) -> bool {
+        matches!(self.sub, AddressesSubCommand::Add(_))
+    }
+}
+
+/// List the addresses in the address book
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "list")]
+struct ListCommand {}
+
+impl ListCommand {
+    fn exec(&self) -> color_eyre::Result<()> {
+        crate::utils::write_csv(addresses::read_address_book()?, io::stdout())
+    }
+}
+
+/// Add an address to the address book, or update its label and ownership
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "add")]
+struct AddCommand {
+    /// the address e.g. 0xd8da6bf26964af9d7eed9e03e53415d37aa96045
+    #[argh(positional)]
+    address: String,
+    /// a label for the address, defaults to the ENS name of Ethereum addresses
+    #[argh(option)]
+    label: Option<String>,
+    /// whether the address is one of your own wallets, otherwise transfers to and from it are
+    /// treated as changing ownership e.g. payments and gifts
+    #[argh(switch)]
+    owned: bool,
+}
+
+/// Resolves the primary ENS name of an address
+const ENS_API_ENDPOINT: &str = "https://api.ensideas.com/ens/resolve";
+
+#[derive(Debug, Deserialize)]
+struct EnsResponse {
+    name: Option<String>,
+}
+
+impl AddCommand {
+    fn exec(&self) -> color_eyre::Result<()> {
+        let label = match self.label {
+            Some(ref label) => label.clone(),
+            None if self.address.starts_with("0x") => {
+                let name = reverse_lookup(&self.address).unwrap_or_else(|err| {
+                    tracing::warn!(
+                        "Unable to look up the ENS name of {}: {}",
+                        self.address,
+                        err
+                    );
+                    None
+                });
+                name.ok_or(eyre::eyre!(
+                    "No ENS name for {}, give a --label",
+                    self.address
+                ))?
+            }
+            None => return Err(eyre::eyre!("A --label is required")),
+        };
+        let mut address_book = addresses::read_address_book()?;
+        address_book.retain(|entry| !entry.address.eq_ignore_ascii_case(&self.address));
+        address_book.push(Address {
+            address: self.address.clone(),
+            label: label.clone(),
+            owned: self.owned,
+        });
+        addresses::write_address_book(&address_book)?;
+        tracing::info!("Added {} as {}", self.address, label);
+        Ok(())
+    }
+}
+
+/// GET /ens/resolve/:address
+fn reverse_lookup(address: &str) -> color_eyre::Result<Option<String>> {
+    let url = format!("{}/{}", ENS_API_ENDPOINT, address);
+    let response: EnsResponse = ureq::get(&url).call()?.into_json()?;
+    Ok(response.name)
+}
@@ -0,0 +1,556 @@
+//! Read only audit bundles for sharing with an accountant. A bundle contains the transactions and
+//! prices along with their hashes and the options of the calculation, so the accountant can run
+//! the reports and add review comments, and a change to the data without updating the manifest
+//! e.g. a file edited by mistake is detected.
+//!
+//! The manifest isn't signed, so a bundle doesn't prove who created it: the data could be
+//! replaced along with its hashes. Share it over a channel which does, or with its own hash.
+
+use crate::{
+    cmd::{
+        prices::Prices,
+        report::cgt::{self, TaxEvent},
+    },
+    transactions,
+};
+use argh::FromArgs;
+use chrono::Utc;
+use color_eyre::eyre;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{fs::File, io::Read, path::PathBuf};
+
+const MANIFEST: &str = "manifest.json";
+const TRANSACTIONS: &str = "transactions.csv";
+const PRICES: &str = "prices.csv";
+const COMMENTS: &str = "comments.csv";
+const OPENING_POOLS: &str = "opening_pools.csv";
+
+/// Create and review read only audit bundles
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "audit")]
+pub struct AuditCommand {
+    #[argh(subcommand)]
+    sub: AuditSubCommand,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+enum AuditSubCommand {
+    Bundle(BundleCommand),
+    Report(AuditReportCommand),
+    Comment(CommentCommand),
+    Comments(CommentsCommand),
+}
+
+impl AuditCommand {
+    pub fn exec(&self) -> color_eyre::Result<()> {
+        match self.sub {
+            AuditSubCommand::Bundle(ref bundle) => bundle.exec(),
+            AuditSubCommand::Report(ref report) => report.exec(),
+            AuditSubCommand::Comment(ref comment) => comment.exec(),
+            AuditSubCommand::Comments(ref comments) => comments.exec(),
+        }
+    }
+}
+
+/// Create an audit bundle from the transactions and prices
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "bundle")]
+struct BundleCommand {
+    /// the csv file containing the transactions
+    #[argh(option)]
+    txs: PathBuf,
+    /// the csv file with prices in GBP, required so the bundle gives the same results offline
+    #[argh(option)]
+    prices: PathBuf,
+    /// the bundle to write e.g. 2021.audit.tar.zst
+    #[argh(option, short = 'o')]
+    output: PathBuf,
+    /// when each tax year starts, as for `report`: uk (6 April, the default), calendar, july or a
+    /// custom start date MM-DD
+    #[argh(option, default = "String::from(\"uk\")")]
+    year_start: String,
+    /// how to treat transfers flagged as changing beneficial ownership, as for `report`:
+    /// disposal (the default) or ignore
+    #[argh(option, default = "String::from(\"disposal\")")]
+    ownership_transfers: String,
+    /// how to value trades with a GBP, EUR or USD leg, as for `report`: consideration (the
+    /// default) or market
+    #[argh(option, default = "String::from(\"consideration\")")]
+    valuation: String,
+    /// the number of days before or after a trade without a price on its day to use the nearest
+    /// price from, as for `report`, default 0
+    #[argh(option, default = "0")]
+    price_tolerance: u32,
+    /// a token which is worthless from a date, as for `report` e.g. LUNA:2022-05-13 or
+    /// LUNA:2022-05-13:claim. Can be repeated.
+    #[argh(option)]
+    worthless: Vec<String>,
+    /// optional csv file of existing Section 104 holdings to start the pools with, as for `report`
+    #[argh(option)]
+    opening_pools: Option<PathBuf>,
+    /// how to treat disposals of more than is held, as for `report`: zero (the default) or
+    /// exclude
+    #[argh(option, default = "String::from(\"zero\")")]
+    unknown_basis: String,
+    /// add the gas paid for swaps on EVM chains to the cost of the tokens acquired, as for
+    /// `report`
+    #[argh(switch)]
+    capitalise_swap_gas: bool,
+    /// how to present the fees paid on disposals, as for `report`: costs (the default) or
+    /// proceeds
+    #[argh(option, default = "String::from(\"costs\")")]
+    disposal_fees: String,
+}
+
+impl BundleCommand {
+    fn exec(&self) -> color_eyre::Result<()> {
+        let mut transactions = Vec::new();
+        crate::encryption::open(&self.txs)?.read_to_end(&mut transactions)?;
+        let mut prices = Vec::new();
+        File::open(&self.prices)?.read_to_end(&mut prices)?;
+        let opening_pools = match self.opening_pools {
+            Some(ref path) => {
+                let mut opening_pools = Vec::new();
+                crate::encryption::open(path)?.read_to_end(&mut opening_pools)?;
+                Some(opening_pools)
+            }
+            None => None,
+        };
+        let options = CalculationOptions {
+            year_start: self.year_start.clone(),
+            ownership_transfers: self.ownership_transfers.clone(),
+            valuation: self.valuation.clone(),
+            price_tolerance: self.price_tolerance,
+            worthless: self.worthless.clone(),
+            unknown_basis: self.unknown_basis.clone(),
+            capitalise_swap_gas: self.capitalise_swap_gas,
+            disposal_fees: self.disposal_fees.clone(),
+        };
+        let bundle = Bundle::new(transactions, prices, opening_pools, options);
+        // check the bundle can be reported on before sharing it
+        bundle.report()?;
+        bundle.write(&self.output)?;
+        tracing::info!("Created audit bundle {}", self.output.display());
+        Ok(())
+    }
+}
+
+/// Run the report over the data in an audit bundle
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "report")]
+struct AuditReportCommand {
+    /// the audit bundle
+    #[argh(positional)]
+    bundle: PathBuf,
+    /// the tax year for which to produce the report e.g. 2020-21 or 2021
+    #[argh(option)]
+    year: Option<cgt::ReportPeriod>,
+    /// show the details of a single disposal, including its matched acquisitions and comments
+    #[argh(option)]
+    disposal: Option<usize>,
+}
+
+impl AuditReportCommand {
+    fn exec(&self) -> color_eyre::Result<()> {
+        let bundle = Bundle::read(&self.bundle)?;
+        let report = bundle.report()?;
+        let year_start = bundle.manifest.options.options(None)?.year_start;
+        let gains = match self.year {
+            Some(cgt::ReportPeriod::TaxYear(year)) => {
+                report.gains(Some(year.with_start(year_start)))
+            }
+            Some(cgt::ReportPeriod::DateRange(from, to)) => report.gains_between(from, to),
+            Some(cgt::ReportPeriod::Current) => {
+                let today = Utc::now().naive_utc();
+                report.gains(Some(year_start.tax_year(today)))
+            }
+            None => report.gains(None),
+        };
+        match self.disposal {
+            Some(id) => {
+                let event = gains
+                    .disposals()
+                    .find(|event| event.id() == id)
+                    .cloned()
+                    .ok_or(eyre::eyre!("No disposal with id {}", id))?;
+                for comment in bundle.comments.iter().filter(|c| c.disposal_id == id) {
+                    tracing::info!(
+                        "Comment by {} at {}: {}",
+                        comment.author,
+                        comment.date_time,
+                        comment.text
+                    );
+                }
+                TaxEvent::write_json(vec![event], cgt::Rounding::Exact, std::io::stdout())
+            }
+            None => TaxEvent::write_csv(gains, cgt::Rounding::Exact, std::io::stdout()),
+        }
+    }
+}
+
+/// Add a review comment on a disposal to an audit bundle
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "comment")]
+struct CommentCommand {
+    /// the audit bundle
+    #[argh(positional)]
+    bundle: PathBuf,
+    /// the id of the disposal, as shown in the report output
+    #[argh(option)]
+    disposal: usize,
+    /// the name of the reviewer
+    #[argh(option)]
+    author: String,
+    /// the comment
+    #[argh(option)]
+    text: String,
+}
+
+impl CommentCommand {
+    fn exec(&self) -> color_eyre::Result<()> {
+        let mut bundle = Bundle::read(&self.bundle)?;
+        bundle.comment(self.disposal, self.author.clone(), self.text.clone())?;
+        bundle.write(&self.bundle)
+    }
+}
+
+/// List the review comments in an audit bundle
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "comments")]
+struct CommentsCommand {
+    /// the audit bundle
+    #[argh(positional)]
+    bundle: PathBuf,
+}
+
+impl CommentsCommand {
+    fn exec(&self) -> color_eyre::Result<()> {
+        let bundle = Bundle::read(&self.bundle)?;
+        crate::utils::write_csv(bundle.comments, std::io::stdout())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    created_at: String,
+    transactions_sha256: String,
+    prices_sha256: String,
+    /// Bundles created before the opening pools were bundled have none
+    #[serde(default)]
+    opening_pools_sha256: Option<String>,
+    /// Bundles created before the options were recorded were calculated with the defaults
+    #[serde(default)]
+    options: CalculationOptions,
+}
+
+/// The options of the calculation, in the form given to `report`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct CalculationOptions {
+    year_start: String,
+    ownership_transfers: String,
+    valuation: String,
+    price_tolerance: u32,
+    worthless: Vec<String>,
+    unknown_basis: String,
+    capitalise_swap_gas: bool,
+    disposal_fees: String,
+}
+
+impl Default for CalculationOptions {
+    fn default() -> Self {
+        CalculationOptions {
+            year_start: "uk".into(),
+            ownership_transfers: "disposal".into(),
+            valuation: "consideration".into(),
+            price_tolerance: 0,
+            worthless: Vec::new(),
+            unknown_basis: "zero".into(),
+            capitalise_swap_gas: false,
+            disposal_fees: "costs".into(),
+        }
+    }
+}
+
+impl CalculationOptions {
+    /// The options as for `report`, with the opening pools csv if any
+    fn options(&self, opening_pools: Option<&[u8]>) -> color_eyre::Result<cgt::Options> {
+        Ok(cgt::Options {
+            year_start: parse_option("year start", &self.year_start)?,
+            ownership_transfers: parse_option("ownership transfers", &self.ownership_transfers)?,
+            valuation: parse_option("valuation", &self.valuation)?,
+            worthless: self
+                .worthless
+                .iter()
+                .map(|worthless| parse_option("worthless token", worthless))
+                .collect::<color_eyre::Result<_>>()?,
+            opening_pools: match opening_pools {
+                Some(csv) => cgt::OpeningPool::read_csv(csv)?,
+                None => Vec::new(),
+            },
+            unknown_basis: parse_option("unknown basis", &self.unknown_basis)?,
+            capitalise_swap_gas: self.capitalise_swap_gas,
+            disposal_fees: parse_option("disposal fees", &self.disposal_fees)?,
+        })
+    }
+}
+
+fn parse_option<T>(name: &str, value: &str) -> color_eyre::Result<T>
+where
+    T: std::str::FromStr<Err = String>,
+{
+    value
+        .parse()
+        .map_err(|err| eyre::eyre!("Invalid {} {} in the bundle: {}", name, value, err))
+}
+
+#[derive(Serialize, Deserialize)]
+struct Comment {
+    disposal_id: usize,
+    date_time: String,
+    author: String,
+    text: String,
+}
+
+struct Bundle {
+    manifest: Manifest,
+    transactions: Vec<u8>,
+    prices: Vec<u8>,
+    opening_pools: Option<Vec<u8>>,
+    comments: Vec<Comment>,
+}
+
+impl Bundle {
+    fn new(
+        transactions: Vec<u8>,
+        prices: Vec<u8>,
+        opening_pools: Option<Vec<u8>>,
+        options: CalculationOptions,
+    ) -> Self {
+        Bundle {
+            manifest: Manifest {
+                created_at: Utc::now().to_rfc3339(),
+                transactions_sha256: sha256(&transactions),
+                prices_sha256: sha256(&prices),
+                opening_pools_sha256: opening_pools.as_deref().map(sha256),
+                options,
+            },
+            transactions,
+            prices,
+            opening_pools,
+            comments: Vec::new(),
+        }
+    }
+
+    /// The report over the data of the bundle, with the options it was created with
+    fn report(&self) -> color_eyre::Result<cgt::TaxReport<'static>> {
+        let transactions = transactions::read_csv(&self.transactions[..])?;
+        let mut prices = Prices::read_csv(&self.prices[..])?;
+        let options = self
+            .manifest
+            .options
+            .options(self.opening_pools.as_deref())?;
+        for worthless in options.worthless.iter() {
+            prices.mark_worthless(worthless.currency, worthless.from);
+        }
+        prices.set_nearest_within(self.manifest.options.price_tolerance);
+        cgt::calculate(transactions, &prices, &options)
+    }
+
+    /// Add a review comment on a disposal of the report
+    fn comment(
+        &mut self,
+        disposal_id: usize,
+        author: String,
+        text: String,
+    ) -> color_eyre::Result<()> {
+        let report = self.report()?;
+        if !report
+            .gains(None)
+            .disposals()
+            .any(|d| d.id() == disposal_id)
+        {
+            return Err(eyre::eyre!("No disposal with id {}", disposal_id));
+        }
+        self.comments.push(Comment {
+            disposal_id,
+            date_time: Utc::now().to_rfc3339(),
+            author,
+            text,
+        });
+        Ok(())
+    }
+
+    /// Read the bundle, checking the data matches the hashes in the manifest
+    fn read(path: &PathBuf) -> color_eyre::Result<Self> {
+        let decoder = zstd::Decoder::new(File::open(path)?)?;
+        let mut archive = tar::Archive::new(decoder);
+        let (mut manifest, mut transactions, mut prices, mut comments) = (None, None, None, None);
+        let mut opening_pools = None;
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let name = entry.path()?.to_string_lossy().to_string();
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data)?;
+            match name.as_str() {
+                MANIFEST => manifest = Some(serde_json::from_slice::<Manifest>(&data)?),
+                TRANSACTIONS => transactions = Some(data),
+                PRICES => prices = Some(data),
+                OPENING_POOLS => opening_pools = Some(data),
+                COMMENTS => {
+                    let mut rdr = csv::Reader::from_reader(&data[..]);
+                    comments = Some(rdr.deserialize().collect::<Result<Vec<Comment>, _>>()?)
+                }
+                name => tracing::warn!("Ignoring unexpected file {} in audit bundle", name),
+            }
+        }
+        let missing = |file| eyre::eyre!("Audit bundle is missing {}", file);
+        let bundle = Bundle {
+            manifest: manifest.ok_or_else(|| missing(MANIFEST))?,
+            transactions: transactions.ok_or_else(|| missing(TRANSACTIONS))?,
+            prices: prices.ok_or_else(|| missing(PRICES))?,
+            opening_pools,
+            comments: comments.unwrap_or_default(),
+        };
+        if sha256(&bundle.transactions) != bundle.manifest.transactions_sha256
+            || sha256(&bundle.prices) != bundle.manifest.prices_sha256
+            || bundle.opening_pools.as_deref().map(sha256) != bundle.manifest.opening_pools_sha256
+        {
+            return Err(eyre::eyre!(
+                "The data in the audit bundle has been modified since it was created at {}",
+                bundle.manifest.created_at
+            ));
+        }
+        Ok(bundle)
+    }
+
+    fn write(&self, path: &PathBuf) -> color_eyre::Result<()> {
+        let mut comments = Vec::new();
+        crate::utils::write_csv(self.comments.iter().collect(), &mut comments)?;
+        let mut files = vec![
+            (MANIFEST, serde_json::to_vec_pretty(&self.manifest)?),
+            (TRANSACTIONS, self.transactions.clone()),
+            (PRICES, self.prices.clone()),
+            (COMMENTS, comments),
+        ];
+        if let Some(ref opening_pools) = self.opening_pools {
+            files.push((OPENING_POOLS, opening_pools.clone()));
+        }
+        let encoder = zstd::Encoder::new(File::create(path)?, 0)?;
+        let mut archive = tar::Builder::new(encoder);
+        for (name, data) in files.iter() {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            // the data files are not meant to be edited once bundled
+            header.set_mode(0o444);
+            header.set_cksum();
+            archive.append_data(&mut header, name, &data[..])?;
+        }
+        archive.into_inner()?.finish()?;
+        Ok(())
+    }
+}
+
+fn sha256(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trades::SCHEMA_VERSION;
+
+    fn transactions() -> Vec<u8> {
+        format!(
+            "version,date_time,kind,buy_asset,buy_amount,sell_asset,sell_amount,fee_asset,\
+             fee_amount,rate,exchange,exchange_id,notes,ownership_changed,received_date_time\n\
+             {v},2020-01-01T12:00:00+00:00,Buy,BTC,10,GBP,1000,GBP,0,100,,,,,\n\
+             {v},2020-06-01T12:00:00+00:00,Sell,GBP,2000,BTC,5,GBP,0,400,,,,,\n",
+            v = SCHEMA_VERSION
+        )
+        .into_bytes()
+    }
+
+    fn prices() -> Vec<u8> {
+        b"base_currency,quote_currency,date_time,rate\n".to_vec()
+    }
+
+    #[test]
+    fn bundles_are_read_with_their_options_and_comments() {
+        let path = std::env::temp_dir().join(format!("taxc-audit-{}.tar.zst", std::process::id()));
+        let options = CalculationOptions {
+            year_start: "calendar".into(),
+            price_tolerance: 3,
+            ..CalculationOptions::default()
+        };
+        let mut bundle = Bundle::new(transactions(), prices(), None, options.clone());
+        let disposal_id = bundle
+            .report()
+            .unwrap()
+            .gains(None)
+            .disposals()
+            .map(|d| d.id())
+            .next()
+            .expect("a disposal");
+
+        assert_eq!(
+            bundle
+                .comment(disposal_id + 100, "Accountant".into(), "Checked".into())
+                .err()
+                .expect("an unknown disposal")
+                .to_string(),
+            format!("No disposal with id {}", disposal_id + 100)
+        );
+        bundle
+            .comment(disposal_id, "Accountant".into(), "Checked".into())
+            .unwrap();
+        bundle.write(&path).unwrap();
+
+        let read = Bundle::read(&path).unwrap();
+        assert_eq!(read.manifest.options, options);
+        assert_eq!(
+            read.manifest.options.options(None).unwrap().year_start,
+            cgt::YearStart::CALENDAR
+        );
+        assert_eq!(read.comments.len(), 1);
+        assert_eq!(read.comments[0].disposal_id, disposal_id);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn data_changed_without_the_manifest_is_detected() {
+        let path = std::env::temp_dir().join(format!(
+            "taxc-audit-modified-{}.tar.zst",
+            std::process::id()
+        ));
+        let mut bundle = Bundle::new(
+            transactions(),
+            prices(),
+            None,
+            CalculationOptions::default(),
+        );
+        bundle.transactions.truncate(bundle.transactions.len() / 2);
+        bundle.write(&path).unwrap();
+
+        let err = Bundle::read(&path).err().expect("modified data");
+        assert!(err
+            .to_string()
+            .starts_with("The data in the audit bundle has been modified"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn invalid_options_are_errors() {
+        let options = CalculationOptions {
+            valuation: "cheapest".into(),
+            ..CalculationOptions::default()
+        };
+        assert!(options
+            .options(None)
+            .err()
+            .expect("an invalid valuation")
+            .to_string()
+            .starts_with("Invalid valuation cheapest in the bundle"));
+    }
+}
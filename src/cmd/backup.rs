@@ -0,0 +1,252 @@
+use crate::cmd::{imports, journal};
+use argh::FromArgs;
+use color_eyre::eyre;
+use std::{
+    collections::HashSet,
+    fs::File,
+    path::{Component, Path, PathBuf},
+};
+
+/// The directory of the archive holding the files of the config directory e.g. currencies,
+/// aliases and tax rate overrides
+const CONFIG_DIR: &str = "config";
+/// The directory of the archive holding the files of the cache directory e.g. prices
+const CACHE_DIR: &str = "cache";
+
+/// Bundle transaction, price and other data files into a single compressed archive, with the
+/// files of the config and cache directories
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "backup")]
+pub struct BackupCommand {
+    /// the archive to write e.g. backup.tar.zst
+    #[argh(option, short = 'o')]
+    output: PathBuf,
+    /// the files to back up e.g. transactions csv files and stores, with the import sessions and
+    /// journal of each transactions file
+    #[argh(positional)]
+    files: Vec<PathBuf>,
+}
+
+impl BackupCommand {
+    pub fn exec(&self) -> color_eyre::Result<()> {
+        let config_dir = crate::utils::config_dir();
+        let cache_dir = crate::utils::cache_dir();
+        let paths = backup_paths(&self.files, config_dir.as_deref(), cache_dir.as_deref())?;
+        write_archive(&self.output, &paths)?;
+        tracing::info!(
+            "Backed up {} files to {}",
+            paths.len(),
+            self.output.display()
+        );
+        Ok(())
+    }
+}
+
+/// The files to back up with their names in the archive: the given files with the import
+/// sessions and journal alongside them, then the files of the config and cache directories
+fn backup_paths(
+    files: &[PathBuf],
+    config_dir: Option<&Path>,
+    cache_dir: Option<&Path>,
+) -> color_eyre::Result<Vec<(PathBuf, PathBuf)>> {
+    let mut paths = Vec::new();
+    for file in files.iter() {
+        let sidecars = vec![imports::sessions_path(file), journal::journal_path(file)];
+        for path in Some(file.clone())
+            .into_iter()
+            .chain(sidecars.into_iter().filter(|path| path.exists()))
+        {
+            let name = path
+                .file_name()
+                .ok_or(eyre::eyre!("Not a file: {}", path.display()))?;
+            paths.push((path.clone(), PathBuf::from(name)));
+        }
+    }
+    for (dir, archive_dir) in vec![(config_dir, CONFIG_DIR), (cache_dir, CACHE_DIR)] {
+        let dir = match dir {
+            Some(dir) if dir.is_dir() => dir,
+            _ => continue,
+        };
+        let mut dir_paths = std::fs::read_dir(dir)?
+            .map(|entry| Ok(entry?.path()))
+            .collect::<color_eyre::Result<Vec<_>>>()?;
+        dir_paths.retain(|path| path.is_file());
+        dir_paths.sort();
+        for path in dir_paths {
+            let name = Path::new(archive_dir).join(path.file_name().expect("Files have a name"));
+            paths.push((path, name));
+        }
+    }
+
+    // the given files are restored into a single directory, so names must be unique
+    let mut names = HashSet::new();
+    for (_, name) in paths.iter() {
+        if !names.insert(name.clone()) {
+            return Err(eyre::eyre!("Duplicate file name {}", name.display()));
+        }
+    }
+    Ok(paths)
+}
+
+/// Write the files to a compressed archive with the given names
+fn write_archive(output: &Path, paths: &[(PathBuf, PathBuf)]) -> color_eyre::Result<()> {
+    let encoder = zstd::Encoder::new(File::create(output)?, 0)?;
+    let mut archive = tar::Builder::new(encoder);
+    for (path, name) in paths.iter() {
+        archive.append_path_with_name(path, name)?;
+    }
+    archive.into_inner()?.finish()?;
+    Ok(())
+}
+
+/// Restore files from an archive created with the backup command
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "restore")]
+pub struct RestoreCommand {
+    /// the archive to restore from e.g. backup.tar.zst
+    #[argh(positional)]
+    archive: PathBuf,
+    /// the directory to restore the files into, defaults to the current directory. The files of
+    /// the config and cache directories are restored into those directories.
+    #[argh(option, short = 'd', default = "PathBuf::from(\".\")")]
+    dir: PathBuf,
+    /// overwrite existing files
+    #[argh(switch)]
+    force: bool,
+}
+
+impl RestoreCommand {
+    pub fn exec(&self) -> color_eyre::Result<()> {
+        let config_dir = crate::utils::config_dir();
+        let cache_dir = crate::utils::cache_dir();
+        let count = restore(
+            &self.archive,
+            &self.dir,
+            config_dir.as_deref(),
+            cache_dir.as_deref(),
+            self.force,
+        )?;
+        tracing::info!("Restored {} files to {}", count, self.dir.display());
+        Ok(())
+    }
+}
+
+/// Restore the files of the archive into the directory, and the config and cache directories.
+/// Returns the number of files restored.
+fn restore(
+    archive: &Path,
+    dir: &Path,
+    config_dir: Option<&Path>,
+    cache_dir: Option<&Path>,
+    force: bool,
+) -> color_eyre::Result<usize> {
+    let decoder = zstd::Decoder::new(File::open(archive)?)?;
+    let mut archive = tar::Archive::new(decoder);
+    let mut count = 0;
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.into_owned();
+        let (target_dir, file_name) = if let Ok(file_name) = name.strip_prefix(CONFIG_DIR) {
+            let config_dir = config_dir.ok_or(eyre::eyre!(
+                "No config directory, set TAXC_CONFIG_DIR or HOME"
+            ))?;
+            (config_dir, file_name)
+        } else if let Ok(file_name) = name.strip_prefix(CACHE_DIR) {
+            let cache_dir = cache_dir.ok_or(eyre::eyre!(
+                "No cache directory, set TAXC_CACHE_DIR or HOME"
+            ))?;
+            (cache_dir, file_name)
+        } else {
+            (dir, name.as_path())
+        };
+        let mut components = file_name.components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(_)), None) => (),
+            _ => return Err(eyre::eyre!("Unexpected file {} in archive", name.display())),
+        }
+
+        let path = target_dir.join(file_name);
+        if path.exists() && !force {
+            return Err(eyre::eyre!(
+                "{} already exists, use --force to overwrite",
+                path.display()
+            ));
+        }
+        std::fs::create_dir_all(target_dir)?;
+        entry.unpack(&path)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(path: &Path, contents: &str) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn backups_are_restored_with_the_config_and_cache() {
+        let root = std::env::temp_dir().join(format!("taxc-backup-{}", std::process::id()));
+        let (data, config, cache) = (root.join("data"), root.join("config"), root.join("cache"));
+        let txs = data.join("txs.csv");
+        write(&txs, "version,date_time\n");
+        write(&imports::sessions_path(&txs), "[]");
+        write(&config.join("tax_rates.toml"), "[years]\n");
+        write(&cache.join("coingecko-GBP.csv"), "date,rate\n");
+
+        let paths = backup_paths(
+            &[txs.clone()],
+            Some(config.as_path()),
+            Some(cache.as_path()),
+        )
+        .unwrap();
+        assert_eq!(
+            paths
+                .iter()
+                .map(|(_, name)| name.clone())
+                .collect::<Vec<_>>(),
+            vec![
+                PathBuf::from("txs.csv"),
+                PathBuf::from("txs.csv.imports.json"),
+                PathBuf::from("config/tax_rates.toml"),
+                PathBuf::from("cache/coingecko-GBP.csv"),
+            ]
+        );
+        let archive = root.join("backup.tar.zst");
+        write_archive(&archive, &paths).unwrap();
+
+        let restored = root.join("restored");
+        let restore_into = |force| {
+            restore(
+                &archive,
+                &restored.join("data"),
+                Some(restored.join("config").as_path()),
+                Some(restored.join("cache").as_path()),
+                force,
+            )
+        };
+        assert_eq!(restore_into(false).unwrap(), 4);
+        for (path, name) in paths.iter() {
+            let restored_path = match name.parent() {
+                Some(dir) if dir != Path::new("") => restored.join(name),
+                _ => restored.join("data").join(name),
+            };
+            assert_eq!(
+                std::fs::read_to_string(restored_path).unwrap(),
+                std::fs::read_to_string(path).unwrap()
+            );
+        }
+        assert!(restore_into(false).is_err());
+        assert_eq!(restore_into(true).unwrap(), 4);
+
+        let other = root.join("other").join("txs.csv");
+        write(&other, "version,date_time\n");
+        assert!(backup_paths(&[txs, other], None, None).is_err());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
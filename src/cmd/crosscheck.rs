@@ -0,0 +1,246 @@
+use crate::{
+    cmd::{prices::Prices, report::cgt},
+    currencies::GBP,
+    money::display_amount,
+    transactions, Money,
+};
+use argh::FromArgs;
+use chrono::{DateTime, NaiveDate};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, Read},
+    path::PathBuf,
+};
+
+/// Compare the disposals on an exchange against the exchange's own gain/loss report
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "crosscheck")]
+pub struct CrosscheckCommand {
+    /// the csv file containing the transactions
+    #[argh(option)]
+    txs: PathBuf,
+    /// optional csv file with prices in GBP for ETH and BTC, instead of fetching from Coingecko.
+    #[argh(option)]
+    prices: Option<PathBuf>,
+    /// the exchange or account the report is from e.g. coinbase or coinbase:personal
+    #[argh(option)]
+    exchange: String,
+    /// the exchange's gain/loss report csv, in the format of the Coinbase gain/loss report
+    #[argh(option)]
+    report: PathBuf,
+    /// the difference in GBP above which proceeds or costs are reported as mismatched, default 1
+    #[argh(option, default = "Decimal::new(1, 0)")]
+    tolerance: Decimal,
+}
+
+/// A disposal in the exchange's report e.g. the Coinbase gain/loss report
+#[derive(Debug, Deserialize)]
+struct ExchangeDisposal {
+    #[serde(rename = "Asset name")]
+    asset: String,
+    #[serde(rename = "Amount")]
+    amount: Decimal,
+    /// e.g. 2021-01-31T14:00:00Z or 2021-01-31
+    #[serde(rename = "Date of Disposition")]
+    date: String,
+    #[serde(rename = "Proceeds (GBP)")]
+    proceeds: Decimal,
+    #[serde(rename = "Cost basis (GBP)")]
+    cost_basis: Decimal,
+}
+
+/// Totals of the disposals of an asset on a day
+#[derive(Debug, Default, Clone)]
+struct Totals {
+    amount: Decimal,
+    proceeds: Decimal,
+    costs: Decimal,
+}
+
+#[derive(Serialize)]
+struct CrosscheckRecord {
+    date: NaiveDate,
+    asset: String,
+    amount: Decimal,
+    exchange_amount: Decimal,
+    proceeds: String,
+    exchange_proceeds: String,
+    proceeds_difference: String,
+    allowable_costs: String,
+    exchange_cost_basis: String,
+    costs_difference: String,
+    status: &'static str,
+}
+
+impl CrosscheckCommand {
+    /// Returns the number of days which differ from the exchange report
+    pub fn exec(&self) -> color_eyre::Result<usize> {
+        let transactions = transactions::read_csv(crate::encryption::open(&self.txs)?)?;
+        let prices = match self.prices {
+            None => Prices::from_coingecko_api(GBP, false)?,
+            Some(ref path) => Prices::read_csv(File::open(path)?)?,
+        };
+        let report = cgt::calculate(transactions, &prices, &cgt::Options::default())?;
+        let gains = report.gains(None).for_account(&self.exchange);
+
+        let mut ours: BTreeMap<(NaiveDate, String), Totals> = BTreeMap::new();
+        for event in gains.disposals() {
+            let trade = event.trade();
+            let totals = ours
+                .entry((
+                    trade.date_time.date(),
+                    trade.sell.currency().code.to_string(),
+                ))
+                .or_default();
+            totals.amount += *trade.sell.amount();
+            totals.proceeds += *event.proceeds().amount();
+            totals.costs += *event.allowable_costs().amount();
+        }
+
+        let theirs = read_report(File::open(&self.report)?)?;
+        let records = compare(&ours, &theirs, self.tolerance);
+
+        let mismatches = records.iter().filter(|r| r.status != "ok").count();
+        tracing::info!(
+            "{} of {} days of disposals differ from the exchange report",
+            mismatches,
+            records.len()
+        );
+        crate::utils::write_csv(records, io::stdout())?;
+        Ok(mismatches)
+    }
+}
+
+fn parse_date(s: &str) -> color_eyre::Result<NaiveDate> {
+    Ok(match DateTime::parse_from_rfc3339(s) {
+        Ok(date_time) => date_time.naive_utc().date(),
+        Err(_) => NaiveDate::parse_from_str(s, "%Y-%m-%d")?,
+    })
+}
+
+/// Read the exchange's report, totalled by the day and asset of the disposals
+fn read_report<R: Read>(reader: R) -> color_eyre::Result<BTreeMap<(NaiveDate, String), Totals>> {
+    let mut theirs: BTreeMap<(NaiveDate, String), Totals> = BTreeMap::new();
+    let mut rdr = csv::Reader::from_reader(reader);
+    for disposal in rdr.deserialize::<ExchangeDisposal>() {
+        let disposal = disposal?;
+        let totals = theirs
+            .entry((parse_date(&disposal.date)?, disposal.asset.to_uppercase()))
+            .or_default();
+        totals.amount += disposal.amount;
+        totals.proceeds += disposal.proceeds;
+        totals.costs += disposal.cost_basis;
+    }
+    Ok(theirs)
+}
+
+/// Compare the totals of each day and asset, in date order, with those of the exchange's report
+fn compare(
+    ours: &BTreeMap<(NaiveDate, String), Totals>,
+    theirs: &BTreeMap<(NaiveDate, String), Totals>,
+    tolerance: Decimal,
+) -> Vec<CrosscheckRecord> {
+    let mut keys = ours
+        .keys()
+        .chain(theirs.keys())
+        .cloned()
+        .collect::<Vec<_>>();
+    keys.sort();
+    keys.dedup();
+    let gbp = |amount: Decimal| display_amount(&Money::from_decimal(amount, GBP));
+    keys.into_iter()
+        .map(|key| {
+            let status = match (ours.get(&key), theirs.get(&key)) {
+                (Some(_), None) => "missing from exchange report",
+                (None, Some(_)) => "missing from transactions",
+                (Some(o), Some(t)) if o.amount != t.amount => "amount mismatch",
+                (Some(o), Some(t)) if (o.proceeds - t.proceeds).abs() > tolerance => {
+                    "proceeds mismatch"
+                }
+                (Some(o), Some(t)) if (o.costs - t.costs).abs() > tolerance => {
+                    "cost basis mismatch"
+                }
+                _ => "ok",
+            };
+            let o = ours.get(&key).cloned().unwrap_or_default();
+            let t = theirs.get(&key).cloned().unwrap_or_default();
+            let (date, asset) = key;
+            CrosscheckRecord {
+                date,
+                asset,
+                amount: o.amount,
+                exchange_amount: t.amount,
+                proceeds: gbp(o.proceeds),
+                exchange_proceeds: gbp(t.proceeds),
+                proceeds_difference: gbp(o.proceeds - t.proceeds),
+                allowable_costs: gbp(o.costs),
+                exchange_cost_basis: gbp(t.costs),
+                costs_difference: gbp(o.costs - t.costs),
+                status,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn days_of_disposals_are_compared_with_the_exchange_report() {
+        let report = "\
+Asset name,Amount,Date of Disposition,Proceeds (GBP),Cost basis (GBP)
+btc,0.1,2021-01-31T14:00:00Z,300,200
+BTC,0.1,2021-01-31,300,200
+ETH,1,2021-02-01,900,500
+ETH,2,2021-02-02,100,50
+";
+        let theirs = read_report(report.as_bytes()).unwrap();
+        let day = |d| NaiveDate::from_ymd(2021, 2, d);
+        let btc = theirs
+            .get(&(NaiveDate::from_ymd(2021, 1, 31), "BTC".to_string()))
+            .unwrap();
+        assert_eq!(
+            (btc.amount, btc.proceeds, btc.costs),
+            (dec!(0.2), dec!(600), dec!(400))
+        );
+
+        let totals = |amount, proceeds, costs| Totals {
+            amount,
+            proceeds,
+            costs,
+        };
+        let mut ours = BTreeMap::new();
+        ours.insert(
+            (NaiveDate::from_ymd(2021, 1, 31), "BTC".to_string()),
+            totals(dec!(0.2), dec!(600.5), dec!(399.5)),
+        );
+        ours.insert(
+            (day(1), "ETH".to_string()),
+            totals(dec!(1), dec!(902), dec!(500)),
+        );
+        ours.insert(
+            (day(3), "ETH".to_string()),
+            totals(dec!(1), dec!(100), dec!(50)),
+        );
+
+        let records = compare(&ours, &theirs, dec!(1));
+        assert_eq!(
+            records
+                .iter()
+                .map(|r| (r.date, r.asset.as_str(), r.status))
+                .collect::<Vec<_>>(),
+            vec![
+                (NaiveDate::from_ymd(2021, 1, 31), "BTC", "ok"),
+                (day(1), "ETH", "proceeds mismatch"),
+                (day(2), "ETH", "missing from transactions"),
+                (day(3), "ETH", "missing from exchange report"),
+            ]
+        );
+        assert_eq!(records[1].proceeds_difference, "2.00");
+    }
+}
@@ -0,0 +1,246 @@
+use crate::money::{self, Instrument, UserCurrency, CODES};
+use argh::FromArgs;
+use color_eyre::eyre;
+use serde::Serialize;
+use std::io::{self, BufRead, Write};
+
+/// List known currencies, add new tokens, or map the codes exchanges use to known currencies
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "currencies")]
+pub struct CurrenciesCommand {
+    #[argh(subcommand)]
+    sub: CurrenciesSubCommand,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+enum CurrenciesSubCommand {
+    List(ListCommand),
+    Add(AddCommand),
+    Alias(AliasCommand),
+}
+
+impl CurrenciesCommand {
+    pub fn exec(&self) -> color_eyre::Result<()> {
+        match self.sub {
+            CurrenciesSubCommand::List(ref list) => list.exec(),
+            CurrenciesSubCommand::Add(ref add) => add.exec(),
+            CurrenciesSubCommand::Alias(ref alias) => alias.exec(),
+        }
+    }
+
+    /// Whether the command writes the user currencies config
+    pub fn modifies_data(&self) -> bool {
+        !matches!(self.sub, CurrenciesSubCommand::List(_))
+    }
+}
+
+/// List the built in and user currencies, with their decimal places, the decimal places shown
+/// in reports, CoinGecko ids and whether they are securities
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "list")]
+struct ListCommand {}
+
+#[derive(Serialize)]
+struct CurrencyRecord {
+    code: String,
+    name: String,
+    decimals: u32,
+    /// The decimal places amounts are shown with in reports, see `display_precision.toml`
+    display_decimals: u32,
+    coingecko_id: String,
+    instrument: Instrument,
+    isin: String,
+    source: &'static str,
+}
+
+impl ListCommand {
+    fn exec(&self) -> color_eyre::Result<()> {
+        let built_in = CODES.iter().map(|code| {
+            let currency = money::find(code).expect("Built in currencies should be known");
+            CurrencyRecord {
+                code: currency.code.to_string(),
+                name: currency.name.to_string(),
+                decimals: currency.exponent,
+                display_decimals: money::display_precision(currency),
+                coingecko_id: crate::cmd::prices::coingecko_id(currency)
+                    .unwrap_or_default()
+                    .to_string(),
+                instrument: Instrument::Crypto,
+                isin: String::new(),
+                source: "built in",
+            }
+        });
+        let user = money::read_user_currencies()?
+            .into_iter()
+            .map(|currency| CurrencyRecord {
+                display_decimals: money::find(&currency.code)
+                    .map_or(currency.exponent, money::display_precision),
+                code: currency.code,
+                name: currency.name,
+                decimals: currency.exponent,
+                coingecko_id: currency.coingecko_id,
+                instrument: currency.instrument,
+                isin: currency.isin.unwrap_or_default(),
+                source: "user",
+            });
+        crate::utils::write_csv(built_in.chain(user).collect(), io::stdout())
+    }
+}
+
+/// Add a token to the user config, so it can be imported and priced, or a tokenised stock with
+/// --isin, which is pooled and reported separately from crypto
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "add")]
+struct AddCommand {
+    /// the code of the token e.g. LINK
+    #[argh(positional)]
+    code: String,
+    /// the name of the token, defaults to the code
+    #[argh(option)]
+    name: Option<String>,
+    /// the number of decimal places, default 18
+    #[argh(option, default = "18")]
+    decimals: u32,
+    /// the id of the token on CoinGecko e.g. chainlink, prompted for if not given
+    #[argh(option)]
+    coingecko_id: Option<String>,
+    /// the ISIN of the stock a tokenised stock tracks e.g. US0378331005 for AAPL, adding it as a
+    /// security instead of crypto. Securities aren't on CoinGecko, so are priced with --prices.
+    #[argh(option)]
+    isin: Option<String>,
+}
+
+impl AddCommand {
+    fn exec(&self) -> color_eyre::Result<()> {
+        let currency = self.user_currency()?;
+        let code = currency.code.clone();
+        let mut user_currencies = money::read_user_currencies()?;
+        user_currencies.push(currency);
+        money::write_user_currencies(&user_currencies)?;
+        tracing::info!("Added {}", code);
+        Ok(())
+    }
+
+    /// The currency to add, prompting for its CoinGecko id if not given
+    fn user_currency(&self) -> color_eyre::Result<UserCurrency> {
+        let code = self.code.to_uppercase();
+        if money::find(&code).is_some() {
+            return Err(eyre::eyre!("Currency {} is already known", code));
+        }
+        let suggestions = money::suggestions(&code);
+        if !suggestions.is_empty() {
+            tracing::warn!(
+                "{} is close to {}, if it is the same currency map it with `currencies alias {} \
+                 {}` instead",
+                code,
+                suggestions.join(", "),
+                code,
+                suggestions[0]
+            );
+        }
+        // the minor units of the currency must fit in a u64
+        if self.decimals > 18 {
+            return Err(eyre::eyre!("At most 18 decimal places are supported"));
+        }
+        let isin = self.isin.as_ref().map(|isin| isin.to_uppercase());
+        if let Some(ref isin) = isin {
+            if !money::is_valid_isin(isin) {
+                return Err(eyre::eyre!("{} is not a valid ISIN", isin));
+            }
+        }
+        let coingecko_id = match self.coingecko_id {
+            Some(ref id) => id.clone(),
+            None if isin.is_some() => String::new(),
+            None => {
+                eprint!("CoinGecko id for {} (e.g. chainlink for LINK): ", code);
+                io::stderr().flush()?;
+                let mut line = String::new();
+                io::stdin().lock().read_line(&mut line)?;
+                line.trim().to_string()
+            }
+        };
+        Ok(UserCurrency {
+            code: code.clone(),
+            name: self.name.clone().unwrap_or(code),
+            exponent: self.decimals,
+            coingecko_id,
+            instrument: if isin.is_some() {
+                Instrument::Security
+            } else {
+                Instrument::Crypto
+            },
+            isin,
+        })
+    }
+}
+
+/// Map a code an exchange uses to a known currency e.g. IOTA to MIOTA, so it is imported as that
+/// currency
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "alias")]
+struct AliasCommand {
+    /// the code used by the exchange e.g. IOTA
+    #[argh(positional)]
+    alias: String,
+    /// the code of the known currency e.g. MIOTA
+    #[argh(positional)]
+    code: String,
+}
+
+impl AliasCommand {
+    fn exec(&self) -> color_eyre::Result<()> {
+        let alias = self.alias.to_uppercase();
+        let code = self.code.to_uppercase();
+        let currency = money::lookup(&code)?;
+        if money::find(&alias).is_some() {
+            return Err(eyre::eyre!("Currency {} is already known", alias));
+        }
+        let mut aliases = money::read_aliases()?;
+        aliases.insert(alias.clone(), currency.code.to_string());
+        money::write_aliases(&aliases)?;
+        tracing::info!("Added {} as an alias of {}", alias, currency.code);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add(args: &[&str]) -> color_eyre::Result<UserCurrency> {
+        AddCommand::from_args(&["add"], args)
+            .expect("Valid arguments")
+            .user_currency()
+    }
+
+    #[test]
+    fn added_currencies_are_validated() {
+        let link = add(&["link", "--coingecko-id", "chainlink"]).unwrap();
+        assert_eq!(
+            (link.code.as_str(), link.name.as_str(), link.exponent),
+            ("LINK", "LINK", 18)
+        );
+        assert_eq!(link.coingecko_id, "chainlink");
+        assert_eq!(link.instrument, Instrument::Crypto);
+
+        let apple = add(&["aapl", "--decimals", "8", "--isin", "us0378331005"]).unwrap();
+        assert_eq!(apple.instrument, Instrument::Security);
+        assert_eq!(apple.isin, Some("US0378331005".to_string()));
+        assert_eq!(apple.coingecko_id, "");
+
+        let error = |args: &[&str]| add(args).err().expect("Invalid currency").to_string();
+        assert_eq!(
+            error(&["btc", "--coingecko-id", "bitcoin"]),
+            "Currency BTC is already known"
+        );
+        assert_eq!(
+            error(&["link", "--coingecko-id", "chainlink", "--decimals", "19"]),
+            "At most 18 decimal places are supported"
+        );
+        assert_eq!(
+            error(&["aapl", "--isin", "US0378331006"]),
+            "US0378331006 is not a valid ISIN"
+        );
+    }
+}
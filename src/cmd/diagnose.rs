@@ -0,0 +1,317 @@
+//! Anonymised bundles for reporting calculation bugs without revealing the user's finances.
+//!
+//! The scrambling is deterministic for a given seed and preserves the matching behaviour:
+//! - dates move by a multiple of four years, so leap days stay in leap years and the days
+//!   between dates, tax years, same day and 30 day windows are all kept between 1901 and 2099
+//! - the amounts of each currency are scaled by the same factor, so pools match in the same
+//!   proportions, with rates and prices scaled to match
+//! - crypto currencies are swapped for other known currencies with the same decimal places
+//! - exchanges and accounts are renamed, and exchange ids and notes removed
+
+use crate::{
+    cmd::{
+        prices::{self, Prices},
+        report::cgt,
+    },
+    money::{amount, currencies, exact_amount, parse_money_parts, CODES},
+    trades::TradeRecord,
+    transactions,
+};
+use argh::FromArgs;
+use chrono::{DateTime, Datelike, Utc};
+use color_eyre::eyre;
+use rust_decimal::Decimal;
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    fs::File,
+    hash::{Hash, Hasher},
+    path::PathBuf,
+};
+
+/// Fiat currencies, which are left as they are
+const FIAT: &[&str] = &["EUR", "GBP", "USD"];
+
+/// Create an anonymised bundle of the transactions and prices, for attaching to a bug report
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "diagnose")]
+pub struct DiagnoseCommand {
+    /// the csv file containing the transactions
+    #[argh(option)]
+    txs: PathBuf,
+    /// the csv file with prices in GBP
+    #[argh(option)]
+    prices: PathBuf,
+    /// the bundle to write e.g. diagnose.tar.zst
+    #[argh(option, short = 'o')]
+    output: PathBuf,
+    /// the seed for scrambling, a different seed gives different values
+    #[argh(option, default = "0")]
+    seed: u64,
+}
+
+impl DiagnoseCommand {
+    pub fn exec(&self) -> color_eyre::Result<()> {
+        let transactions = transactions::read_csv(crate::encryption::open(&self.txs)?)?;
+        let records = transactions
+            .iter()
+            .map(TradeRecord::from)
+            .collect::<Vec<_>>();
+        let price_records = prices::read_records(&self.prices)?;
+
+        let mut scrambler = Scrambler::new(self.seed);
+        let scrambled = records
+            .into_iter()
+            .map(|record| scrambler.trade(record))
+            .collect::<color_eyre::Result<Vec<_>>>()?;
+        let scrambled_prices = price_records
+            .into_iter()
+            .map(|record| scrambler.price(record))
+            .collect::<color_eyre::Result<Vec<_>>>()?;
+
+        let mut txs_csv = Vec::new();
+        crate::utils::write_csv(scrambled, &mut txs_csv)?;
+        let mut prices_csv = Vec::new();
+        crate::utils::write_csv(scrambled_prices, &mut prices_csv)?;
+
+        // check the scrambled data still produces the same disposals
+        let original = {
+            let prices = Prices::read_csv(File::open(&self.prices)?)?;
+            let report = cgt::calculate(transactions, &prices, &cgt::Options::default())?;
+            report.gains(None).disposal_count()
+        };
+        let anonymised = {
+            let transactions = transactions::read_csv(&txs_csv[..])?;
+            let prices = Prices::read_csv(&prices_csv[..])?;
+            let report = cgt::calculate(transactions, &prices, &cgt::Options::default())?;
+            report.gains(None).disposal_count()
+        };
+        if original != anonymised {
+            tracing::warn!(
+                "The anonymised data has {} disposals, the original {}",
+                anonymised,
+                original
+            );
+        }
+
+        let encoder = zstd::Encoder::new(File::create(&self.output)?, 0)?;
+        let mut archive = tar::Builder::new(encoder);
+        for (name, data) in [("transactions.csv", txs_csv), ("prices.csv", prices_csv)].iter() {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            archive.append_data(&mut header, name, &data[..])?;
+        }
+        archive.into_inner()?.finish()?;
+        tracing::info!("Created anonymised bundle {}", self.output.display());
+        Ok(())
+    }
+}
+
+struct Scrambler {
+    seed: u64,
+    years: i32,
+    currencies: HashMap<String, String>,
+    exchanges: HashMap<String, String>,
+    accounts: HashMap<String, String>,
+}
+
+impl Scrambler {
+    fn new(seed: u64) -> Self {
+        let mut scrambler = Scrambler {
+            seed,
+            years: 0,
+            currencies: HashMap::new(),
+            exchanges: HashMap::new(),
+            accounts: HashMap::new(),
+        };
+        // every fourth year is a leap year between 1901 and 2099
+        scrambler.years = 4 * (1 + (scrambler.hash("years") % 5) as i32);
+
+        // swap currencies within groups with the same decimal places, ordered by their hashes
+        let mut groups: HashMap<u32, Vec<&str>> = HashMap::new();
+        for code in CODES.iter().filter(|code| !FIAT.contains(code)) {
+            let currency = currencies::find(code).expect("Built in currencies should be known");
+            groups.entry(currency.exponent).or_default().push(*code);
+        }
+        for codes in groups.values() {
+            let mut shuffled = codes.clone();
+            shuffled.sort_by_key(|code| scrambler.hash(code));
+            for (from, to) in codes.iter().zip(shuffled) {
+                scrambler
+                    .currencies
+                    .insert(from.to_string(), to.to_string());
+            }
+        }
+        scrambler
+    }
+
+    fn hash(&self, value: &str) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        (self.seed, value).hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn currency(&self, code: &str) -> String {
+        self.currencies
+            .get(code)
+            .cloned()
+            .unwrap_or_else(|| code.to_string())
+    }
+
+    /// The factor by which amounts of the currency are scaled, between 1 and 10
+    fn factor(&self, code: &str) -> Decimal {
+        Decimal::new(100 + (self.hash(code) % 900) as i64, 2)
+    }
+
+    fn amount(&self, code: &str, value: &str) -> color_eyre::Result<String> {
+        if code.is_empty() || value.is_empty() {
+            return Ok(value.to_string());
+        }
+        let money = parse_money_parts(code, value)
+            .map_err(|e| eyre::eyre!("Invalid {} amount {}: {:?}", code, value, e))?;
+        let scaled = *money.amount() * self.factor(code);
+        Ok(exact_amount(&amount(&self.currency(code), scaled)?))
+    }
+
+    fn date_time(&self, date_time: &str) -> color_eyre::Result<String> {
+        let date_time = DateTime::parse_from_rfc3339(date_time)?.naive_utc();
+        let year = date_time.year() + self.years;
+        let shifted = date_time
+            .with_year(year)
+            .filter(|_| date_time.year() > 1900 && year < 2100)
+            .ok_or(eyre::eyre!("Unable to shift date {}", date_time))?;
+        Ok(DateTime::<Utc>::from_utc(shifted, Utc).to_rfc3339())
+    }
+
+    fn trade(&mut self, record: TradeRecord) -> color_eyre::Result<TradeRecord> {
+        // the rate is the amount of the quote currency per unit of the base currency
+        let (base, quote) = if record.kind == "Buy" || record.kind == "Contribution" {
+            (&record.buy_asset, &record.sell_asset)
+        } else {
+            (&record.sell_asset, &record.buy_asset)
+        };
+        let rate = record.rate * self.factor(quote) / self.factor(base);
+        let next = self.exchanges.len() + 1;
+        let exchange = if record.exchange.is_empty() {
+            String::new()
+        } else {
+            self.exchanges
+                .entry(record.exchange.clone())
+                .or_insert_with(|| format!("exchange-{}", next))
+                .clone()
+        };
+        Ok(TradeRecord {
+            date_time: self.date_time(&record.date_time)?,
+            buy_asset: self.currency(&record.buy_asset),
+            buy_amount: self.amount(&record.buy_asset, &record.buy_amount)?,
+            sell_asset: self.currency(&record.sell_asset),
+            sell_amount: self.amount(&record.sell_asset, &record.sell_amount)?,
+            fee_asset: self.currency(&record.fee_asset),
+            fee_amount: self.amount(&record.fee_asset, &record.fee_amount)?,
+            rate: rate.round_dp(12),
+            exchange,
+            exchange_id: String::new(),
+            notes: String::new(),
+            account: record.account.as_ref().map(|account| {
+                let next = self.accounts.len() + 1;
+                self.accounts
+                    .entry(account.clone())
+                    .or_insert_with(|| format!("account-{}", next))
+                    .clone()
+            }),
+            received_date_time: record
+                .received_date_time
+                .as_ref()
+                .map(|dt| self.date_time(dt))
+                .transpose()?,
+            ..record
+        })
+    }
+
+    fn price(&self, record: prices::Record) -> color_eyre::Result<prices::Record> {
+        let rate =
+            record.rate * self.factor(&record.quote_currency) / self.factor(&record.base_currency);
+        Ok(prices::Record {
+            base_currency: self.currency(&record.base_currency),
+            quote_currency: self.currency(&record.quote_currency),
+            date_time: self.date_time(&record.date_time)?,
+            rate: rate.round_dp(12),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scrambling_is_deterministic_and_keeps_the_day_of_year() {
+        let scrambler = Scrambler::new(42);
+        let again = Scrambler::new(42);
+        assert_eq!(scrambler.currency("BTC"), again.currency("BTC"));
+        assert_eq!(scrambler.factor("GBP"), again.factor("GBP"));
+        assert_eq!(scrambler.currency("GBP"), "GBP");
+
+        let btc = currencies::find(&scrambler.currency("BTC")).unwrap();
+        assert_eq!(btc.exponent, 8);
+
+        let shifted = scrambler.date_time("2020-04-06T12:00:00+00:00").unwrap();
+        assert!(shifted.ends_with("-04-06T12:00:00+00:00"));
+        assert_ne!(shifted, "2020-04-06T12:00:00+00:00");
+        let leap_day = scrambler.date_time("2020-02-29T12:00:00+00:00").unwrap();
+        assert!(leap_day.ends_with("-02-29T12:00:00+00:00"));
+    }
+
+    #[test]
+    fn scrambling_keeps_the_matching_rules() {
+        // the buy back is 31 days after the sale in a leap year, so it isn't matched with it,
+        // but would be if moved to a year without 29 February
+        let txs = "\
+version,date_time,kind,buy_asset,buy_amount,sell_asset,sell_amount,fee_asset,fee_amount,rate,exchange,exchange_id,notes,ownership_changed,received_date_time
+5,2020-01-10T12:00:00+00:00,Buy,BTC,2,GBP,10000,GBP,0,5000,,,,,
+5,2020-02-01T12:00:00+00:00,Sell,GBP,6000,BTC,1,GBP,0,6000,,,,,
+5,2020-03-03T12:00:00+00:00,Buy,BTC,1,GBP,7000,GBP,0,7000,,,,,
+5,2020-03-03T12:00:00+00:00,Sell,GBP,3500,BTC,0.5,GBP,0,7000,,,,,
+5,2020-03-04T12:00:00+00:00,Sell,GBP,2000,BTC,0.25,GBP,0,8000,,,,,
+5,2020-04-02T12:00:00+00:00,Buy,BTC,0.25,GBP,2250,GBP,0,9000,,,,,
+";
+        let rules = |txs: &[u8]| {
+            let transactions = transactions::read_csv(txs).unwrap();
+            let report =
+                cgt::calculate(transactions, &Prices::default(), &cgt::Options::default()).unwrap();
+            report
+                .gains(None)
+                .disposals()
+                .map(|disposal| {
+                    disposal
+                        .matches()
+                        .iter()
+                        .map(|matched| matched.rule())
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>()
+        };
+        let original = rules(txs.as_bytes());
+        assert_eq!(
+            original,
+            vec![
+                vec![cgt::MatchingRule::Pool],
+                vec![cgt::MatchingRule::SameDay],
+                vec![cgt::MatchingRule::ThirtyDay],
+            ]
+        );
+
+        for seed in 0..10 {
+            let mut scrambler = Scrambler::new(seed);
+            assert_eq!(scrambler.years % 4, 0);
+            let records = csv::Reader::from_reader(txs.as_bytes())
+                .deserialize::<TradeRecord>()
+                .map(|record| scrambler.trade(record.unwrap()).unwrap())
+                .collect::<Vec<_>>();
+            let mut scrambled = Vec::new();
+            crate::utils::write_csv(records, &mut scrambled).unwrap();
+            assert_eq!(rules(&scrambled), original, "Seed {}", seed);
+        }
+    }
+}
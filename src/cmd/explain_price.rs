@@ -0,0 +1,192 @@
+use crate::{
+    cmd::prices::{self, CurrencyPair, Prices},
+    currencies::GBP,
+};
+use argh::FromArgs;
+use chrono::NaiveDate;
+use color_eyre::eyre;
+use serde::Serialize;
+use std::{fs::File, io, path::PathBuf};
+
+/// Show how the price of a pair on a date is looked up, exactly as the report would
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "explain-price")]
+pub struct ExplainPriceCommand {
+    /// the pair to price e.g. ETH/GBP or ETH-GBP
+    #[argh(positional)]
+    pair: String,
+    /// the date of the price e.g. 2021-01-31
+    #[argh(positional)]
+    date: NaiveDate,
+    /// optional csv file with prices in GBP for ETH and BTC, instead of fetching from Coingecko.
+    #[argh(option)]
+    prices: Option<PathBuf>,
+    /// the number of days before or after the date without a price to use the nearest price
+    /// from, as given to `report`, default 0
+    #[argh(option, default = "0")]
+    price_tolerance: u32,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct ExplainRecord {
+    step: &'static str,
+    source: String,
+    result: String,
+}
+
+impl ExplainPriceCommand {
+    pub fn exec(&self) -> color_eyre::Result<()> {
+        let pair = prices::parse_pair(&self.pair)?;
+        if pair.quote != GBP {
+            return Err(eyre::eyre!(
+                "Only GBP prices are used by the report, the quote currency should be GBP"
+            ));
+        }
+
+        if pair.base == GBP {
+            let records = vec![ExplainRecord {
+                step: "final",
+                source: "GBP".into(),
+                result: "1".into(),
+            }];
+            return crate::utils::write_csv(records, io::stdout());
+        }
+
+        let (source, mut prices) = match self.prices {
+            None => (
+                "coingecko".to_string(),
+                Prices::from_coingecko_api(GBP, false)?,
+            ),
+            Some(ref path) => (
+                path.display().to_string(),
+                Prices::read_csv(File::open(path)?)?,
+            ),
+        };
+        prices.set_nearest_within(self.price_tolerance);
+        let records = explain(&pair, self.date, self.price_tolerance, source, &prices);
+        crate::utils::write_csv(records, io::stdout())
+    }
+}
+
+/// The steps of looking up the price of a pair on a date, with the prices set to the tolerance
+/// of the report
+fn explain<'a>(
+    pair: &CurrencyPair<'a>,
+    date: NaiveDate,
+    price_tolerance: u32,
+    source: String,
+    prices: &Prices<'a>,
+) -> Vec<ExplainRecord> {
+    let mut records = vec![ExplainRecord {
+        step: "trade rate",
+        source: "transactions".into(),
+        result: format!(
+            "trades of {} with {} are valued at their own rate, not a looked up price",
+            pair.base.code, pair.quote.code
+        ),
+    }];
+    records.push(ExplainRecord {
+        step: "coverage",
+        source: source.clone(),
+        result: match prices.coverage(pair) {
+            Some((count, first, last)) => {
+                format!("{} {} prices from {} to {}", count, pair, first, last)
+            }
+            None => format!("no {} prices", pair),
+        },
+    });
+    let price = prices.get(pair.clone(), date);
+    let exact = price.as_ref().filter(|p| p.date_time.date() == date);
+    records.push(ExplainRecord {
+        step: "exact date",
+        source: source.clone(),
+        result: exact.map_or("no price".into(), |p| {
+            format!("{} at {}", p.rate, p.date_time)
+        }),
+    });
+    if exact.is_none() && price_tolerance > 0 {
+        records.push(ExplainRecord {
+            step: "nearest date",
+            source: source.clone(),
+            result: price
+                .as_ref()
+                .map_or(format!("no price within {} days", price_tolerance), |p| {
+                    format!("{} at {}", p.rate, p.date_time)
+                }),
+        });
+    }
+    records.push(ExplainRecord {
+        step: "final",
+        source,
+        result: price.map_or(
+            format!(
+                "no price, the report fails for {} transactions on {}",
+                pair.base.code, date
+            ),
+            |p| format!("{} on {}", p.rate, p.date_time.date()),
+        ),
+    });
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prices(tolerance: u32) -> Prices<'static> {
+        let csv = "base_currency,quote_currency,date_time,rate
+BTC,GBP,2021-01-01T00:00:00+00:00,20000
+BTC,GBP,2021-01-05T00:00:00+00:00,25000
+";
+        let mut prices = Prices::read_csv(csv.as_bytes()).unwrap();
+        prices.set_nearest_within(tolerance);
+        prices
+    }
+
+    fn results(date: &str, tolerance: u32) -> Vec<(&'static str, String)> {
+        let pair = prices::parse_pair("BTC/GBP").unwrap();
+        let date = date.parse().unwrap();
+        explain(
+            &pair,
+            date,
+            tolerance,
+            "prices.csv".into(),
+            &prices(tolerance),
+        )
+        .into_iter()
+        .skip(2)
+        .map(|record| (record.step, record.result))
+        .collect()
+    }
+
+    #[test]
+    fn the_nearest_price_is_used_within_the_tolerance() {
+        assert_eq!(
+            results("2021-01-05", 2),
+            vec![
+                ("exact date", "25000 at 2021-01-05 00:00:00".to_string()),
+                ("final", "25000 on 2021-01-05".to_string()),
+            ]
+        );
+        assert_eq!(
+            results("2021-01-03", 2),
+            vec![
+                ("exact date", "no price".to_string()),
+                ("nearest date", "20000 at 2021-01-01 00:00:00".to_string()),
+                ("final", "20000 on 2021-01-01".to_string()),
+            ]
+        );
+        assert_eq!(
+            results("2021-01-03", 1),
+            vec![
+                ("exact date", "no price".to_string()),
+                ("nearest date", "no price within 1 days".to_string()),
+                (
+                    "final",
+                    "no price, the report fails for BTC transactions on 2021-01-03".to_string()
+                ),
+            ]
+        );
+        assert_eq!(results("2021-01-03", 0).len(), 2);
+    }
+}
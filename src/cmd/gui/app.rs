@@ -0,0 +1,231 @@
+use crate::{
+    cmd::{prices::Prices, report::cgt},
+    currencies::GBP,
+    money::display_amount,
+    transactions::{self, Transaction},
+};
+use eframe::{egui, epi};
+use std::{collections::BTreeMap, fs::File, path::PathBuf};
+
+pub fn run(prices: Option<&PathBuf>, txs: &[PathBuf]) -> color_eyre::Result<()> {
+    let prices = match prices {
+        None => Prices::from_coingecko_api(GBP, false)?,
+        Some(path) => Prices::read_csv(File::open(path)?)?,
+    };
+    let mut app = TaxcApp {
+        prices,
+        files: Vec::new(),
+        transactions: Vec::new(),
+        holdings: Vec::new(),
+        years: BTreeMap::new(),
+        view: View::Holdings,
+        error: None,
+    };
+    for path in txs {
+        app.import(path.clone());
+    }
+    eframe::run_native(Box::new(app), epi::NativeOptions::default())
+}
+
+#[derive(PartialEq)]
+enum View {
+    Holdings,
+    Year(cgt::TaxYear),
+}
+
+/// The report is recalculated on each import, and kept as display rows since it borrows the
+/// prices.
+struct TaxcApp {
+    prices: Prices<'static>,
+    files: Vec<PathBuf>,
+    transactions: Vec<Transaction<'static>>,
+    /// currency, amount held and pooled costs
+    holdings: Vec<[String; 3]>,
+    /// date, sold, proceeds, allowable costs and gain of each disposal, by tax year
+    years: BTreeMap<cgt::TaxYear, Vec<[String; 5]>>,
+    view: View,
+    error: Option<String>,
+}
+
+impl TaxcApp {
+    fn import(&mut self, path: PathBuf) {
+        let result = crate::encryption::open(&path).and_then(transactions::read_csv);
+        match result {
+            Ok(transactions) => {
+                tracing::info!(
+                    "Imported {} transactions from {}",
+                    transactions.len(),
+                    path.display()
+                );
+                self.transactions.extend(transactions);
+                self.files.push(path);
+                self.recalculate();
+            }
+            Err(err) => self.error = Some(format!("{}: {}", path.display(), err)),
+        }
+    }
+
+    fn recalculate(&mut self) {
+        let options = cgt::Options::default();
+        let report = match cgt::calculate(self.transactions.clone(), &self.prices, &options) {
+            Ok(report) => report,
+            Err(err) => {
+                self.error = Some(err.to_string());
+                return;
+            }
+        };
+        self.holdings = report
+            .pools
+            .iter()
+            .map(|(currency, pool)| {
+                [
+                    currency.clone(),
+                    display_amount(pool.total()),
+                    display_amount(pool.costs()),
+                ]
+            })
+            .collect();
+        self.years = report
+            .years
+            .keys()
+            .map(|year| {
+                let gains = report.gains(Some(*year));
+                let disposals = gains
+                    .disposals()
+                    .map(|d| {
+                        [
+                            d.trade().date_time.date().to_string(),
+                            format!(
+                                "{} {}",
+                                display_amount(&d.trade().sell),
+                                d.trade().sell.currency().code
+                            ),
+                            display_amount(d.proceeds()),
+                            display_amount(d.allowable_costs()),
+                            display_amount(&d.gain()),
+                        ]
+                    })
+                    .collect();
+                (*year, disposals)
+            })
+            .collect();
+        self.error = None;
+    }
+}
+
+impl epi::App for TaxcApp {
+    fn name(&self) -> &str {
+        "taxc"
+    }
+
+    fn update(&mut self, ctx: &egui::CtxRef, _frame: &mut epi::Frame<'_>) {
+        let dropped = ctx
+            .input()
+            .raw
+            .dropped_files
+            .iter()
+            .filter_map(|file| file.path.clone())
+            .collect::<Vec<_>>();
+        for path in dropped {
+            self.import(path);
+        }
+
+        egui::SidePanel::left("views").show(ctx, |ui| {
+            ui.heading("taxc");
+            ui.selectable_value(&mut self.view, View::Holdings, "Holdings");
+            for year in self.years.keys() {
+                ui.selectable_value(&mut self.view, View::Year(*year), year.to_string());
+            }
+            ui.separator();
+            ui.label("Drop transaction csv files here to import them");
+            for file in self.files.iter() {
+                ui.small(file.display().to_string());
+            }
+        });
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            if let Some(ref error) = self.error {
+                ui.colored_label(egui::Color32::RED, error);
+            }
+            let (titles, rows): (&[&str], Vec<&[String]>) = match self.view {
+                View::Holdings => (
+                    &["Currency", "Amount", "Costs (GBP)"],
+                    self.holdings.iter().map(|r| &r[..]).collect(),
+                ),
+                View::Year(year) => (
+                    &["Date", "Sold", "Proceeds", "Allowable Costs", "Gain"],
+                    self.years
+                        .get(&year)
+                        .map(|rows| rows.iter().map(|r| &r[..]).collect())
+                        .unwrap_or_default(),
+                ),
+            };
+            egui::ScrollArea::auto_sized().show(ui, |ui| {
+                egui::Grid::new("table").striped(true).show(ui, |ui| {
+                    for title in titles {
+                        ui.strong(*title);
+                    }
+                    ui.end_row();
+                    for row in rows {
+                        for cell in row {
+                            ui.label(cell);
+                        }
+                        ui.end_row();
+                    }
+                });
+            });
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn imported_files_are_shown_as_holdings_and_disposals_by_year() {
+        let path = std::env::temp_dir().join(format!("taxc-gui-{}.csv", std::process::id()));
+        std::fs::write(
+            &path,
+            "\
+version,date_time,kind,buy_asset,buy_amount,sell_asset,sell_amount,fee_asset,fee_amount,rate,exchange,exchange_id,notes,ownership_changed,received_date_time
+5,2018-05-01T12:00:00+00:00,Buy,BTC,2,GBP,100,GBP,0,50,,,,,
+5,2018-06-01T12:00:00+00:00,Sell,GBP,80,BTC,1,GBP,0,80,,,,,
+",
+        )
+        .unwrap();
+        let mut app = TaxcApp {
+            prices: Prices::default(),
+            files: Vec::new(),
+            transactions: Vec::new(),
+            holdings: Vec::new(),
+            years: BTreeMap::new(),
+            view: View::Holdings,
+            error: None,
+        };
+
+        app.import(path.clone());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(app.error, None);
+        assert_eq!(app.files, vec![path.clone()]);
+        assert!(app.holdings.contains(&[
+            "BTC".to_string(),
+            "1.00000000".to_string(),
+            "50.00".to_string()
+        ]));
+        assert_eq!(
+            app.years.get(&cgt::TaxYear::uk(2019)),
+            Some(&vec![[
+                "2018-06-01".to_string(),
+                "1.00000000 BTC".to_string(),
+                "80.00".to_string(),
+                "50.00".to_string(),
+                "30.00".to_string(),
+            ]])
+        );
+
+        app.import(path);
+        assert!(app.error.is_some());
+        assert_eq!(app.files.len(), 1);
+    }
+}
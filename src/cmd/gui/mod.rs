@@ -0,0 +1,47 @@
+//! Desktop app for users who'd rather not use the command line, built with the `gui` feature.
+
+#[cfg(feature = "gui")]
+mod app;
+
+use argh::FromArgs;
+use std::path::PathBuf;
+
+/// Open the desktop app, for importing transactions and viewing holdings and yearly reports
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "gui")]
+pub struct GuiCommand {
+    /// optional csv file with prices in GBP, instead of fetching from Coingecko.
+    #[argh(option)]
+    prices: Option<PathBuf>,
+    /// csv files containing transactions to open, more can be dropped onto the window
+    #[argh(positional)]
+    txs: Vec<PathBuf>,
+}
+
+impl GuiCommand {
+    #[cfg(feature = "gui")]
+    pub fn exec(&self) -> color_eyre::Result<()> {
+        app::run(self.prices.as_ref(), &self.txs)
+    }
+
+    #[cfg(not(feature = "gui"))]
+    pub fn exec(&self) -> color_eyre::Result<()> {
+        Err(color_eyre::eyre::eyre!(
+            "taxc was built without the desktop app, rebuild with `--features gui`"
+        ))
+    }
+}
+
+#[cfg(all(test, not(feature = "gui")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_app_needs_the_gui_feature() {
+        let gui = GuiCommand::from_args(&["gui"], &["txs.csv"]).unwrap();
+        assert_eq!(
+            gui.exec().unwrap_err().to_string(),
+            "taxc was built without the desktop app, rebuild with `--features gui`"
+        );
+    }
+}
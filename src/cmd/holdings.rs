@@ -0,0 +1,166 @@
+use crate::{
+    cmd::{
+        prices::Prices,
+        report::{
+            cgt::{self, Options, TaxReport},
+            closing_pools::closing_pools,
+        },
+    },
+    currencies::GBP,
+    encryption, transactions,
+};
+use argh::FromArgs;
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::{collections::BTreeSet, fs::File, io, path::PathBuf};
+
+/// Show the Section 104 holding of each asset at the end of a day, or how it changed between two
+/// days with the trades which changed it
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "holdings")]
+pub struct HoldingsCommand {
+    /// the csv file containing the transactions
+    #[argh(option)]
+    txs: PathBuf,
+    /// optional csv file with prices in GBP for ETH and BTC, instead of fetching from Coingecko.
+    #[argh(option)]
+    prices: Option<PathBuf>,
+    /// fetch the prices from Coingecko even if they were already cached today
+    #[argh(switch)]
+    refresh: bool,
+    /// the date of the holdings e.g. 2021-04-05, after the transactions on that day
+    #[argh(option)]
+    at: NaiveDate,
+    /// optional date to compare the holdings at --at with, showing the change in the quantity
+    /// and cost of each asset from the earlier to the later date
+    #[argh(option)]
+    compare: Option<NaiveDate>,
+    /// optional csv file of existing Section 104 holdings to start the pools with, as for
+    /// `report --opening-pools`
+    #[argh(option)]
+    opening_pools: Option<PathBuf>,
+}
+
+#[derive(Debug, Serialize)]
+struct HoldingRecord {
+    asset: String,
+    from: NaiveDate,
+    from_quantity: Decimal,
+    from_cost: Decimal,
+    to: NaiveDate,
+    to_quantity: Decimal,
+    to_cost: Decimal,
+    quantity_change: Decimal,
+    cost_change: Decimal,
+    /// The ids of the events which changed the pool, as shown in the report output
+    events: String,
+}
+
+impl HoldingsCommand {
+    pub fn exec(&self) -> color_eyre::Result<()> {
+        let transactions = transactions::read_csv(encryption::open(&self.txs)?)?;
+        let prices = match self.prices {
+            None => Prices::from_coingecko_api(GBP, self.refresh)?,
+            Some(ref path) => Prices::read_csv(File::open(path)?)?,
+        };
+        let options = Options {
+            opening_pools: match self.opening_pools {
+                None => Vec::new(),
+                Some(ref path) => cgt::OpeningPool::read_csv(encryption::open(path)?)?,
+            },
+            ..Default::default()
+        };
+        let report = cgt::calculate(transactions, &prices, &options)?;
+        let (from, to) = match self.compare {
+            Some(compare) if compare < self.at => (compare, self.at),
+            Some(compare) => (self.at, compare),
+            None => (self.at, self.at),
+        };
+        let records = compare_holdings(&report, &options.opening_pools, from, to);
+        crate::utils::write_csv(records, io::stdout())
+    }
+}
+
+/// The holdings of each asset held on either date, with the events between them which changed
+/// its pool
+fn compare_holdings(
+    report: &TaxReport,
+    opening_pools: &[cgt::OpeningPool],
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Vec<HoldingRecord> {
+    let before = closing_pools(report, opening_pools, from);
+    let after = closing_pools(report, opening_pools, to);
+    let zero = (Decimal::new(0, 0), Decimal::new(0, 0));
+    let gains = report.gains(None);
+    before
+        .keys()
+        .chain(after.keys())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .map(|asset| {
+            let (from_quantity, from_cost) = before.get(asset).cloned().unwrap_or(zero);
+            let (to_quantity, to_cost) = after.get(asset).cloned().unwrap_or(zero);
+            let events = gains
+                .gains
+                .iter()
+                .filter(|event| {
+                    let date = event.trade().date_time.date();
+                    date > from
+                        && date <= to
+                        && event
+                            .pools()
+                            .any(|pool| pool.currency().code == asset.as_str())
+                })
+                .map(|event| format!("#{}", event.id()))
+                .collect::<Vec<_>>();
+            HoldingRecord {
+                asset: asset.clone(),
+                from,
+                from_quantity,
+                from_cost,
+                to,
+                to_quantity,
+                to_cost,
+                quantity_change: to_quantity - from_quantity,
+                cost_change: to_cost - from_cost,
+                events: events.join(" "),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn holdings_changes_link_the_events_between_the_dates() {
+        let txs = "\
+version,date_time,kind,buy_asset,buy_amount,sell_asset,sell_amount,fee_asset,fee_amount,rate,exchange,exchange_id,notes,ownership_changed,received_date_time
+5,2018-01-01T12:00:00+00:00,Buy,BTC,2,GBP,200,GBP,0,100,,,,,
+5,2018-02-01T12:00:00+00:00,Buy,ETH,10,GBP,500,GBP,0,50,,,,,
+5,2018-03-01T12:00:00+00:00,Sell,GBP,300,BTC,1,GBP,0,300,,,,,
+5,2018-04-01T12:00:00+00:00,Buy,BTC,1,GBP,400,GBP,0,400,,,,,
+";
+        let transactions = transactions::read_csv(txs.as_bytes()).unwrap();
+        let prices = Prices::default();
+        let report = cgt::calculate(transactions, &prices, &Options::default()).unwrap();
+        let date = |d: &str| NaiveDate::parse_from_str(d, "%Y-%m-%d").unwrap();
+
+        let records = compare_holdings(&report, &[], date("2018-01-15"), date("2018-04-05"));
+
+        assert_eq!(records.len(), 2);
+        let btc = &records[0];
+        assert_eq!(btc.asset, "BTC");
+        assert_eq!((btc.from_quantity, btc.from_cost), (dec!(2), dec!(200)));
+        assert_eq!((btc.to_quantity, btc.to_cost), (dec!(2), dec!(500)));
+        assert_eq!(btc.cost_change, dec!(300));
+        assert_eq!(btc.events, "#3 #4");
+        let eth = &records[1];
+        assert_eq!((eth.from_quantity, eth.to_quantity), (dec!(0), dec!(10)));
+        assert_eq!(eth.events, "#2");
+    }
+}
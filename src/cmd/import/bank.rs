@@ -0,0 +1,376 @@
+use crate::{
+    money::{amount, currencies::GBP, zero},
+    trades::TradeRecord,
+    transactions::{self, Movement, Transaction},
+};
+use argh::FromArgs;
+use chrono::{Duration, NaiveDate};
+use color_eyre::eyre;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::{collections::BTreeMap, fs::File, io, path::PathBuf};
+
+/// Import the payments between a bank account and exchanges from a bank statement, as GBP
+/// withdrawals from and deposits to the bank, to reconcile the money sent to exchanges with the
+/// transactions imported from them
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "bank")]
+pub struct ImportBankCommand {
+    /// the csv bank statement, with columns Date, Description and Amount, negative for payments
+    /// out. Other rows than payments to and from exchanges are skipped.
+    #[argh(positional)]
+    file: PathBuf,
+    /// the format of the dates in the statement, default %d/%m/%Y
+    #[argh(option, default = "String::from(\"%d/%m/%Y\")")]
+    date_format: String,
+    /// text in the description of payments to or from an exchange, and the exchange e.g.
+    /// "CB PAYMENTS:Coinbase", in addition to the names of well known exchanges. Can be repeated.
+    #[argh(option)]
+    payee: Vec<Payee>,
+    /// label the transactions with the bank account, default bank
+    #[argh(option, default = "String::from(\"bank\")")]
+    account: String,
+    /// optional csv file of the transactions imported from the exchanges, to log the money sent
+    /// to each exchange against its transactions, warning of exchanges with none
+    #[argh(option)]
+    txs: Option<PathBuf>,
+    /// with --txs, the days after a payment to an exchange, or before a payment from it, in which
+    /// its transactions are expected. Payments without any suggest a missing export, default 30
+    #[argh(option, default = "30")]
+    gap_days: i64,
+}
+
+/// Text in the description of a bank payment identifying an exchange, matched case insensitively
+#[derive(Debug, Clone, PartialEq)]
+pub struct Payee {
+    pattern: String,
+    exchange: String,
+}
+
+impl std::str::FromStr for Payee {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.rsplitn(2, ':').collect::<Vec<_>>()[..] {
+            [exchange, pattern] if !exchange.is_empty() && !pattern.is_empty() => Ok(Payee {
+                pattern: pattern.to_lowercase(),
+                exchange: exchange.into(),
+            }),
+            _ => Err(format!(
+                "Invalid payee {}, expected e.g. CB PAYMENTS:Coinbase",
+                s
+            )),
+        }
+    }
+}
+
+/// Names in the descriptions of payments to or from exchanges, including the companies behind
+/// them which appear on statements
+const KNOWN_PAYEES: &[(&str, &str)] = &[
+    ("coinbase", "Coinbase"),
+    ("binance", "Binance"),
+    ("kraken", "Kraken"),
+    ("payward", "Kraken"),
+    ("bitstamp", "Bitstamp"),
+    ("gemini", "Gemini"),
+    ("uphold", "Uphold"),
+    ("crypto.com", "Crypto.com"),
+    ("foris dax", "Crypto.com"),
+    ("bittrex", "Bittrex"),
+    ("luno", "Luno"),
+    ("bitpanda", "Bitpanda"),
+];
+
+#[derive(Debug, Clone, Deserialize)]
+struct Record {
+    #[serde(rename = "Date", alias = "date", alias = "Transaction Date")]
+    date: String,
+    #[serde(
+        rename = "Description",
+        alias = "description",
+        alias = "Memo",
+        alias = "Payee"
+    )]
+    description: String,
+    #[serde(rename = "Amount", alias = "amount", alias = "Value")]
+    amount: Decimal,
+}
+
+impl ImportBankCommand {
+    /// Returns the number of payments without imported transactions, when checked with --txs
+    pub fn exec(&self) -> color_eyre::Result<usize> {
+        let mut rdr = csv::Reader::from_reader(File::open(&self.file)?);
+        let records: Vec<Record> = rdr.deserialize().collect::<Result<_, _>>()?;
+        let mut ramps = Vec::new();
+        for record in records.iter() {
+            if let Some(exchange) = self.exchange_of(&record.description) {
+                ramps.push((exchange, self.to_transaction(record, &exchange)?));
+            }
+        }
+        ramps.sort_by_key(|(_, tx)| tx.date_time());
+        tracing::info!(
+            "Imported {} payments to and from exchanges of {} bank statement rows",
+            ramps.len(),
+            records.len()
+        );
+        let mut problems = 0;
+        if let Some(ref path) = self.txs {
+            let imported = transactions::read_csv(crate::encryption::open(path)?)?;
+            let unimported = reconcile(&ramps, &imported);
+            let gaps = gaps(&ramps, &imported, self.gap_days);
+            for (exchange, tx) in gaps.iter() {
+                tracing::warn!(
+                    "{} {} on {} without any of its transactions within {} days, check for a \
+                     missing export",
+                    match tx {
+                        Transaction::Withdrawal(_) => "Sent to",
+                        _ => "Received from",
+                    },
+                    exchange,
+                    tx.date_time().date(),
+                    self.gap_days
+                );
+            }
+            problems = unimported + gaps.len();
+        }
+        let trade_records = ramps.iter().map(|(_, tx)| TradeRecord::from(tx)).collect();
+        crate::utils::write_csv(trade_records, io::stdout())?;
+        Ok(problems)
+    }
+
+    /// The exchange paid or paying, from the user's payees then the known exchanges
+    fn exchange_of(&self, description: &str) -> Option<String> {
+        let description = description.to_lowercase();
+        self.payee
+            .iter()
+            .map(|payee| (payee.pattern.as_str(), payee.exchange.as_str()))
+            .chain(KNOWN_PAYEES.iter().cloned())
+            .find(|(pattern, _)| description.contains(pattern))
+            .map(|(_, exchange)| exchange.to_string())
+    }
+
+    /// A payment out of the bank is a withdrawal from it, a payment in a deposit to it
+    fn to_transaction<'a>(
+        &self,
+        record: &Record,
+        exchange: &str,
+    ) -> color_eyre::Result<Transaction<'a>> {
+        let date = NaiveDate::parse_from_str(record.date.trim(), &self.date_format)
+            .map_err(|err| eyre::eyre!("Invalid date {}: {}", record.date, err))?;
+        let direction = if record.amount.is_sign_negative() {
+            "To"
+        } else {
+            "From"
+        };
+        let movement = Movement {
+            date_time: date.and_hms(0, 0, 0),
+            amount: amount(GBP.code, record.amount.abs())?,
+            fee: zero(GBP),
+            exchange: Some(self.account.clone()),
+            account: None,
+            ownership_changed: false,
+            notes: Some(format!(
+                "{} {}: {}",
+                direction,
+                exchange,
+                record.description.trim()
+            )),
+        };
+        if record.amount.is_sign_negative() {
+            Ok(Transaction::Withdrawal(movement))
+        } else {
+            Ok(Transaction::Deposit(movement))
+        }
+    }
+}
+
+/// Logs the money sent to and received from each exchange with the number of transactions
+/// imported from it, warning of exchanges which were paid but have no transactions. Returns the
+/// number of those exchanges.
+fn reconcile(ramps: &[(String, Transaction)], imported: &[Transaction]) -> usize {
+    let mut totals: BTreeMap<&str, (Decimal, Decimal)> = BTreeMap::new();
+    for (exchange, tx) in ramps {
+        let (sent, received) = totals.entry(exchange).or_default();
+        match tx {
+            Transaction::Withdrawal(movement) => *sent += *movement.amount.amount(),
+            Transaction::Deposit(movement) => *received += *movement.amount.amount(),
+            _ => (),
+        }
+    }
+    let mut unimported = 0;
+    for (exchange, (sent, received)) in totals {
+        let count = imported.iter().filter(|tx| is_on(tx, exchange)).count();
+        if count == 0 {
+            unimported += 1;
+            tracing::warn!(
+                "Sent £{} to and received £{} from {}, but none of its transactions are imported",
+                sent,
+                received,
+                exchange
+            );
+        } else {
+            tracing::info!(
+                "Sent £{} to and received £{} from {}, with {} transactions imported",
+                sent,
+                received,
+                exchange,
+                count
+            );
+        }
+    }
+    unimported
+}
+
+/// The payments to exchanges with no transactions imported in the days after, and from exchanges
+/// with none in the days before, when the money would have been traded. Exchanges without any
+/// transactions imported are left to [`reconcile`].
+fn gaps<'r, 'a>(
+    ramps: &'r [(String, Transaction<'a>)],
+    imported: &[Transaction],
+    days: i64,
+) -> Vec<&'r (String, Transaction<'a>)> {
+    ramps
+        .iter()
+        .filter(|(exchange, tx)| {
+            let on_exchange = imported
+                .iter()
+                .filter(|t| is_on(t, exchange))
+                .map(|t| t.date_time().date())
+                .collect::<Vec<_>>();
+            let date = tx.date_time().date();
+            let (from, to) = match tx {
+                Transaction::Withdrawal(_) => (date, date + Duration::days(days)),
+                _ => (date - Duration::days(days), date),
+            };
+            !on_exchange.is_empty() && !on_exchange.iter().any(|date| *date >= from && *date <= to)
+        })
+        .collect()
+}
+
+/// Whether the transaction is on the exchange, including its accounts e.g. Coinbase Pro for
+/// Coinbase, since statements name the company rather than the account
+fn is_on(tx: &Transaction, exchange: &str) -> bool {
+    tx.exchange().map_or(false, |e| {
+        e.to_lowercase().starts_with(&exchange.to_lowercase())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn command(payee: Vec<Payee>) -> ImportBankCommand {
+        ImportBankCommand {
+            file: PathBuf::new(),
+            date_format: "%d/%m/%Y".into(),
+            payee,
+            account: "bank".into(),
+            txs: None,
+            gap_days: 30,
+        }
+    }
+
+    #[test]
+    fn payments_to_and_from_exchanges_are_transfers_of_the_bank() {
+        let statement = "\
+Date,Description,Amount
+01/03/2021,TESCO STORES,-25.40
+02/03/2021,COINBASE UK LTD,-500.00
+03/03/2021,CB PAYMENTS REF 123,-250.00
+15/03/2021,PAYWARD LTD,1200.50
+";
+        let mut rdr = csv::Reader::from_reader(statement.as_bytes());
+        let records: Vec<Record> = rdr.deserialize().collect::<Result<_, _>>().unwrap();
+        let command = command(vec!["cb payments:Coinbase".parse().unwrap()]);
+
+        let ramps = records
+            .iter()
+            .filter_map(|record| {
+                let exchange = command.exchange_of(&record.description)?;
+                Some(command.to_transaction(record, &exchange).unwrap())
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(ramps.len(), 3, "The shopping is skipped");
+        match &ramps[1] {
+            Transaction::Withdrawal(movement) => {
+                assert_eq!(*movement.amount.amount(), dec!(250));
+                assert_eq!(movement.exchange.as_deref(), Some("bank"));
+                assert_eq!(
+                    movement.notes.as_deref(),
+                    Some("To Coinbase: CB PAYMENTS REF 123")
+                );
+            }
+            _ => panic!("A payment out should be a withdrawal"),
+        }
+        match &ramps[2] {
+            Transaction::Deposit(movement) => {
+                assert_eq!(*movement.amount.amount(), dec!(1200.50));
+                assert_eq!(movement.date_time.date(), NaiveDate::from_ymd(2021, 3, 15));
+            }
+            _ => panic!("A payment in should be a deposit"),
+        }
+    }
+
+    #[test]
+    fn exchanges_paid_without_transactions_are_unimported() {
+        let payment = |exchange: &str| {
+            let record = Record {
+                date: "02/03/2021".into(),
+                description: exchange.into(),
+                amount: dec!(-100),
+            };
+            let tx = command(Vec::new())
+                .to_transaction(&record, exchange)
+                .unwrap();
+            (exchange.to_string(), tx)
+        };
+        let ramps = vec![payment("Coinbase"), payment("Kraken")];
+        let imported = transactions::read_csv(
+            "\
+version,date_time,kind,buy_asset,buy_amount,sell_asset,sell_amount,fee_asset,fee_amount,rate,exchange,exchange_id,notes,ownership_changed,received_date_time
+5,2021-03-02T12:00:00+00:00,Buy,BTC,0.01,GBP,100,GBP,0,10000,Coinbase Pro,,,,
+"
+            .as_bytes(),
+        )
+        .unwrap();
+
+        assert_eq!(reconcile(&ramps, &imported), 1, "Kraken is not imported");
+    }
+
+    #[test]
+    fn payments_without_transactions_nearby_are_gaps() {
+        let payment = |date: &str, amount| {
+            let record = Record {
+                date: date.into(),
+                description: "COINBASE UK LTD".into(),
+                amount,
+            };
+            let tx = command(Vec::new())
+                .to_transaction(&record, "Coinbase")
+                .unwrap();
+            ("Coinbase".to_string(), tx)
+        };
+        let ramps = vec![
+            payment("02/03/2021", dec!(-100)),
+            payment("01/06/2021", dec!(-100)),
+            payment("20/03/2021", dec!(150)),
+            payment("01/12/2021", dec!(150)),
+        ];
+        let imported = transactions::read_csv(
+            "\
+version,date_time,kind,buy_asset,buy_amount,sell_asset,sell_amount,fee_asset,fee_amount,rate,exchange,exchange_id,notes,ownership_changed,received_date_time
+5,2021-03-10T12:00:00+00:00,Buy,BTC,0.01,GBP,100,GBP,0,10000,Coinbase Pro,,,,
+"
+            .as_bytes(),
+        )
+        .unwrap();
+
+        let dates = gaps(&ramps, &imported, 30)
+            .iter()
+            .map(|(_, tx)| tx.date_time().date().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(dates, ["2021-06-01", "2021-12-01"]);
+    }
+}
@@ -0,0 +1,516 @@
+//! The transactions of an Ethereum address, fetched from the
+//! [Etherscan API](https://docs.etherscan.io/api-endpoints/accounts). The normal transactions,
+//! the internal transactions of contracts sending ETH and the ERC-20 token transfers are combined
+//! by their transaction hash into the changes to the balances of the address:
+//!
+//! - one asset sent and another received is a trade e.g. a swap on a DEX
+//! - otherwise each asset sent or received is a withdrawal or a deposit, labelled with the
+//!   address book as for the etherscan csv
+//! - the gas of transactions sent by the address is the fee of the trade or of the first
+//!   withdrawal, or a standalone fee e.g. for approvals and failed transactions
+//!
+//! Transfers of tokens which aren't a known currency are skipped, as they are mostly spam, so
+//! add the currencies of the tokens held with `currencies add`.
+
+use crate::{
+    addresses,
+    cmd::import::exchanges::subgraph::scale,
+    money::{
+        currencies::{Currency, ETH},
+        zero,
+    },
+    trades::{Trade, TradeKind, TradeRecord},
+    transactions::{Movement, Transaction},
+    Money,
+};
+use argh::FromArgs;
+use chrono::NaiveDateTime;
+use color_eyre::eyre;
+use rust_decimal::Decimal;
+use serde::{de::DeserializeOwned, Deserialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// Import the trades, transfers and gas of an Ethereum address from Etherscan
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "ethereum")]
+pub struct EthereumApiCommand {
+    /// the address to import the transactions of
+    #[argh(option)]
+    address: String,
+    /// the Etherscan api key
+    #[argh(option)]
+    api_key: String,
+    /// the url of an Etherscan compatible API to use instead, e.g. for a testnet
+    #[argh(option)]
+    endpoint: Option<String>,
+}
+
+const API_ENDPOINT: &str = "https://api.etherscan.io/api";
+/// The most transactions Etherscan returns for a query
+const PAGE_SIZE: usize = 10_000;
+const WEI_DECIMALS: u32 = 18;
+
+impl EthereumApiCommand {
+    pub fn exec(&self) -> color_eyre::Result<()> {
+        crate::utils::write_csv(self.fetch()?, std::io::stdout())
+    }
+
+    /// Download the transactions of the address
+    pub fn fetch(&self) -> color_eyre::Result<Vec<TradeRecord>> {
+        // addresses are returned lowercase by Etherscan
+        let address = self.address.to_lowercase();
+        let normal = self.fetch_all::<NormalTx>("txlist", &address)?;
+        let internal = self.fetch_all::<InternalTx>("txlistinternal", &address)?;
+        let tokens = self.fetch_all::<TokenTx>("tokentx", &address)?;
+        let transactions = to_transactions(&address, &normal, &internal, &tokens)?;
+        tracing::info!("Imported {} Ethereum transactions", transactions.len());
+        Ok(transactions.iter().map(TradeRecord::from).collect())
+    }
+
+    /// GET ?module=account&action=<action>, paging through the results by block, as only the
+    /// first 10,000 results of a query can be paged through
+    fn fetch_all<T: EtherscanTx>(&self, action: &str, address: &str) -> color_eyre::Result<Vec<T>> {
+        let endpoint = self.endpoint.as_deref().unwrap_or(API_ENDPOINT);
+        let mut all = Vec::new();
+        let mut start_block = 0;
+        loop {
+            tracing::debug!(
+                "Fetching {} of {} from block {}",
+                action,
+                address,
+                start_block
+            );
+            let response: Response<T> = ureq::get(endpoint)
+                .query("module", "account")
+                .query("action", action)
+                .query("address", address)
+                .query("startblock", &start_block.to_string())
+                .query("sort", "asc")
+                .query("page", "1")
+                .query("offset", &PAGE_SIZE.to_string())
+                .query("apikey", &self.api_key)
+                .call()?
+                .into_json()?;
+            let mut page = match response.result {
+                ResponseResult::Transactions(page) => page,
+                ResponseResult::Error(err) => {
+                    return Err(eyre::eyre!(
+                        "Etherscan API error: {}, {}",
+                        response.message,
+                        err
+                    ))
+                }
+            };
+            if page.len() < PAGE_SIZE {
+                all.extend(page);
+                break;
+            }
+            // the last block may continue on the next page, so it is fetched again from the start
+            let last_block = page.last().map_or(Ok(0), |tx| tx.block_number())?;
+            page.retain(|tx| tx.block_number().map_or(false, |block| block != last_block));
+            if page.is_empty() {
+                return Err(eyre::eyre!(
+                    "More than {} {} results in block {}",
+                    PAGE_SIZE,
+                    action,
+                    last_block
+                ));
+            }
+            all.extend(page);
+            start_block = last_block;
+        }
+        tracing::info!("Fetched {} {} results", all.len(), action);
+        Ok(all)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Response<T> {
+    message: String,
+    result: ResponseResult<T>,
+}
+
+/// The transactions, or the error message e.g. for an invalid api key. No transactions is an
+/// empty list with a status of 0.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ResponseResult<T> {
+    Transactions(Vec<T>),
+    Error(String),
+}
+
+/// A result of the Etherscan account API, paged through by its block
+trait EtherscanTx: DeserializeOwned {
+    fn block_number(&self) -> color_eyre::Result<u64>;
+}
+
+impl EtherscanTx for NormalTx {
+    fn block_number(&self) -> color_eyre::Result<u64> {
+        Ok(self.block_number.parse()?)
+    }
+}
+
+impl EtherscanTx for InternalTx {
+    fn block_number(&self) -> color_eyre::Result<u64> {
+        Ok(self.block_number.parse()?)
+    }
+}
+
+impl EtherscanTx for TokenTx {
+    fn block_number(&self) -> color_eyre::Result<u64> {
+        Ok(self.block_number.parse()?)
+    }
+}
+
+/// A transaction sent from or to the address, with any ETH it transferred
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NormalTx {
+    block_number: String,
+    time_stamp: String,
+    hash: String,
+    from: String,
+    to: String,
+    /// In wei
+    value: String,
+    /// In wei
+    gas_price: String,
+    gas_used: String,
+    /// `1` if the transaction failed, so only the gas was spent
+    is_error: String,
+    /// The contract method called e.g. `approve(address _spender, uint256 _value)`
+    #[serde(default)]
+    function_name: String,
+}
+
+/// ETH sent by a contract within a transaction e.g. the proceeds of a swap for ETH
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct InternalTx {
+    block_number: String,
+    time_stamp: String,
+    hash: String,
+    from: String,
+    to: String,
+    /// In wei
+    value: String,
+    is_error: String,
+}
+
+/// A transfer of an ERC-20 token
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TokenTx {
+    block_number: String,
+    time_stamp: String,
+    hash: String,
+    from: String,
+    to: String,
+    /// In the smallest unit of the token
+    value: String,
+    token_symbol: String,
+    token_decimal: String,
+}
+
+/// The changes to the balances of the address made by a transaction
+#[derive(Default)]
+struct Changes {
+    timestamp: i64,
+    /// The gas paid by the address, none for transactions sent by others
+    gas: Option<Decimal>,
+    failed: bool,
+    method: String,
+    /// The assets sent, negative, and received with the counterparty of each
+    transfers: Vec<(&'static Currency, Decimal, String)>,
+}
+
+impl Changes {
+    /// Adds a transfer of the value from or to the address, ignoring transfers to itself
+    fn transfer(
+        &mut self,
+        address: &str,
+        currency: &'static Currency,
+        value: Decimal,
+        from: &str,
+        to: &str,
+    ) {
+        if value.is_zero() || from == to {
+            return;
+        }
+        if from == address {
+            self.transfers.push((currency, -value, to.to_string()));
+        } else if to == address {
+            self.transfers.push((currency, value, from.to_string()));
+        }
+    }
+
+    /// The net amount of each asset received, and sent
+    fn net(&self) -> (Vec<Money<'static>>, Vec<Money<'static>>) {
+        let mut net: BTreeMap<&str, (&'static Currency, Decimal)> = BTreeMap::new();
+        for (currency, value, _) in self.transfers.iter() {
+            net.entry(currency.code)
+                .or_insert((*currency, Decimal::new(0, 0)))
+                .1 += *value;
+        }
+        // amounts too small for the currency are left out, as they are when rounded
+        let money = |currency: &'static Currency, value: Decimal| {
+            Money::from_decimal(value.abs().round_dp(currency.exponent), currency)
+        };
+        let received = net
+            .values()
+            .filter(|(_, value)| *value > Decimal::new(0, 0))
+            .map(|(currency, value)| money(*currency, *value))
+            .filter(|amount| !amount.is_zero())
+            .collect::<Vec<_>>();
+        let sent = net
+            .values()
+            .filter(|(_, value)| *value < Decimal::new(0, 0))
+            .map(|(currency, value)| money(*currency, *value))
+            .filter(|amount| !amount.is_zero())
+            .collect::<Vec<_>>();
+        (received, sent)
+    }
+
+    fn into_transactions<'a>(self, hash: &str) -> Vec<Transaction<'a>> {
+        let date_time = NaiveDateTime::from_timestamp(self.timestamp, 0);
+        let method = if self.method == "" {
+            "Transfer"
+        } else {
+            &self.method
+        };
+        let gas = self.gas.map(|gas| Money::from_decimal(gas, ETH));
+        let movement = |amount, fee, notes, ownership_changed| Movement {
+            date_time,
+            amount,
+            fee,
+            exchange: Some("Ethereum".into()),
+            account: None,
+            ownership_changed,
+            notes: Some(notes),
+        };
+
+        if self.failed {
+            return gas
+                .map(|gas| {
+                    let notes = format!("Failed: {}; Tx {}", method, hash);
+                    Transaction::Fee(movement(gas, zero(ETH), notes, false))
+                })
+                .into_iter()
+                .collect();
+        }
+        let (mut received, mut sent) = self.net();
+        if received.len() == 1 && sent.len() == 1 {
+            let (buy, sell) = (received.remove(0), sent.remove(0));
+            return vec![Transaction::Trade(Trade {
+                date_time,
+                kind: TradeKind::Buy,
+                rate: *sell.amount() / *buy.amount(),
+                buy,
+                sell,
+                fee: gas.unwrap_or_else(|| zero(ETH)),
+                exchange: Some("Ethereum".into()),
+                account: None,
+                notes: Some(format!("{}; Tx {}", method, hash)),
+            })];
+        }
+
+        let mut gas = gas;
+        let mut transactions = Vec::new();
+        for (currency, value, counterparty) in self.transfers.iter() {
+            let direction = if value.is_sign_positive() {
+                "from"
+            } else {
+                "to"
+            };
+            let known = addresses::find(counterparty);
+            let notes = match known {
+                Some(entry) => format!("{} {} {}; Tx {}", method, direction, entry.label, hash),
+                None => format!("{}; Tx {}", method, hash),
+            };
+            let changes_ownership = known.map_or(false, |entry| !entry.owned);
+            let amount = Money::from_decimal(value.abs().round_dp(currency.exponent), *currency);
+            if value.is_sign_positive() {
+                transactions.push(Transaction::Deposit(movement(
+                    amount,
+                    zero(currency),
+                    notes,
+                    changes_ownership,
+                )));
+            } else {
+                let fee = gas.take().unwrap_or_else(|| zero(ETH));
+                transactions.push(Transaction::Withdrawal(movement(
+                    amount,
+                    fee,
+                    notes,
+                    changes_ownership,
+                )));
+            }
+        }
+        // e.g. approvals, or contract interactions which only received assets
+        if let Some(gas) = gas {
+            let notes = format!("{}; Tx {}", method, hash);
+            transactions.push(Transaction::Fee(movement(gas, zero(ETH), notes, false)));
+        }
+        transactions
+    }
+}
+
+fn wei(value: &str) -> color_eyre::Result<Decimal> {
+    scale(value, WEI_DECIMALS)
+}
+
+/// Combine the normal and internal transactions and token transfers of the address by their
+/// transaction hash, sorted by date
+fn to_transactions<'a>(
+    address: &str,
+    normal: &[NormalTx],
+    internal: &[InternalTx],
+    tokens: &[TokenTx],
+) -> color_eyre::Result<Vec<Transaction<'a>>> {
+    let mut changes: HashMap<&str, Changes> = HashMap::new();
+    for tx in normal {
+        let entry = changes.entry(&tx.hash).or_default();
+        entry.timestamp = tx.time_stamp.parse()?;
+        entry.failed = tx.is_error == "1";
+        entry.method = tx
+            .function_name
+            .split('(')
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        if tx.from == address {
+            let gas = tx.gas_used.parse::<u128>()? * tx.gas_price.parse::<u128>()?;
+            entry.gas = Some(wei(&gas.to_string())?);
+        }
+        if !entry.failed {
+            entry.transfer(address, ETH, wei(&tx.value)?, &tx.from, &tx.to);
+        }
+    }
+    for tx in internal.iter().filter(|tx| tx.is_error != "1") {
+        let entry = changes.entry(&tx.hash).or_default();
+        entry.timestamp = tx.time_stamp.parse()?;
+        entry.transfer(address, ETH, wei(&tx.value)?, &tx.from, &tx.to);
+    }
+    let mut unknown_tokens = BTreeMap::new();
+    for tx in tokens {
+        let currency = match crate::money::find(&tx.token_symbol) {
+            Some(currency) => currency,
+            None => {
+                *unknown_tokens.entry(tx.token_symbol.as_str()).or_insert(0) += 1;
+                continue;
+            }
+        };
+        let entry = changes.entry(&tx.hash).or_default();
+        entry.timestamp = tx.time_stamp.parse()?;
+        let value = scale(&tx.value, tx.token_decimal.parse()?)?;
+        entry.transfer(address, currency, value, &tx.from, &tx.to);
+    }
+    for (symbol, count) in unknown_tokens {
+        tracing::warn!(
+            "Skipped {} transfers of {}, add it with `currencies add {}` to import them",
+            count,
+            symbol,
+            symbol
+        );
+    }
+
+    let mut changes = changes.into_iter().collect::<Vec<_>>();
+    changes
+        .sort_by(|(a_hash, a), (b_hash, b)| a.timestamp.cmp(&b.timestamp).then(a_hash.cmp(b_hash)));
+    Ok(changes
+        .into_iter()
+        .flat_map(|(hash, changes)| changes.into_transactions(hash))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{money::currencies::USDC, transactions::TransactionKind};
+    use rust_decimal_macros::dec;
+
+    const ADDRESS: &str = "0x1111111111111111111111111111111111111111";
+    const OTHER: &str = "0x2222222222222222222222222222222222222222";
+    const ROUTER: &str = "0x7a250d5630b4cf539739df2c5dacb4c659f2488d";
+
+    fn normal(hash: &str, from: &str, to: &str, value: &str, method: &str) -> NormalTx {
+        let is_error = if method == "failed" { "1" } else { "0" };
+        serde_json::from_value(serde_json::json!({
+            "blockNumber": "12000000",
+            "timeStamp": "1620000000",
+            "hash": hash,
+            "from": from,
+            "to": to,
+            "value": value,
+            "gasPrice": "50000000000",
+            "gasUsed": "100000",
+            "isError": is_error,
+            "functionName": method,
+        }))
+        .unwrap()
+    }
+
+    fn token(hash: &str, from: &str, to: &str, value: &str, symbol: &str) -> TokenTx {
+        serde_json::from_value(serde_json::json!({
+            "blockNumber": "12000000",
+            "timeStamp": "1620000000",
+            "hash": hash,
+            "from": from,
+            "to": to,
+            "value": value,
+            "tokenSymbol": symbol,
+            "tokenDecimal": "6",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn swaps_are_trades_and_gas_is_a_fee() {
+        let normal = vec![
+            normal("0xa", OTHER, ADDRESS, "2000000000000000000", ""),
+            normal(
+                "0xb",
+                ADDRESS,
+                ROUTER,
+                "1000000000000000000",
+                "swapExactETHForTokens(uint256 amountOutMin, address[] path)",
+            ),
+            normal("0xc", ADDRESS, ROUTER, "0", "approve(address _spender)"),
+            normal("0xd", ADDRESS, ROUTER, "0", "failed"),
+        ];
+        let tokens = vec![
+            token("0xb", ROUTER, ADDRESS, "3000000000", "USDC"),
+            token("0xe", OTHER, ADDRESS, "1000000", "SPAMCOIN"),
+        ];
+
+        let transactions = to_transactions(ADDRESS, &normal, &[], &tokens).unwrap();
+        let kinds = transactions.iter().map(|tx| tx.kind()).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                TransactionKind::Deposit,
+                TransactionKind::Buy,
+                TransactionKind::Fee,
+                TransactionKind::Fee,
+            ],
+            "The unknown token is skipped"
+        );
+
+        assert_eq!(transactions[0].fee(), &zero(ETH), "Gas paid by the sender");
+        let swap = match &transactions[1] {
+            Transaction::Trade(trade) => trade,
+            _ => panic!("Expected the swap to be a trade"),
+        };
+        assert_eq!(swap.buy, Money::from_decimal(dec!(3000), USDC));
+        assert_eq!(swap.sell, Money::from_decimal(dec!(1), ETH));
+        assert_eq!(swap.fee, Money::from_decimal(dec!(0.005), ETH));
+        assert_eq!(swap.rate, dec!(1) / dec!(3000));
+        assert_eq!(swap.notes.as_deref(), Some("swapExactETHForTokens; Tx 0xb"));
+        match &transactions[3] {
+            Transaction::Fee(gas) => {
+                assert_eq!(gas.notes.as_deref(), Some("Failed: failed; Tx 0xd"))
+            }
+            _ => panic!("Expected the failed transaction to be a fee"),
+        }
+    }
+}
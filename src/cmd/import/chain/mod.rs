@@ -0,0 +1,3 @@
+//! Transactions fetched from the chains themselves, by the address of the user's wallet.
+
+pub mod ethereum;
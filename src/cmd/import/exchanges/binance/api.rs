@@ -1,13 +1,16 @@
 use crate::{
-    money::{amount, currencies::Currency, Money},
+    cmd::report::derivatives::{self, FundingPayment},
+    money::{amount, currencies::Currency, zero, Money},
     trades::{Trade, TradeKind, TradeRecord},
+    transactions::{Movement, Transaction},
 };
 use argh::FromArgs;
 use chrono::prelude::*;
-use chrono::NaiveDateTime;
+use chrono::{NaiveDate, NaiveDateTime};
 use color_eyre::eyre;
 use hmac::{Hmac, Mac, NewMac};
 use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
 use serde::{Deserialize, Serialize};
 use std::{convert::TryFrom, str::FromStr};
 
@@ -23,45 +26,202 @@ pub struct BinanceApiCommand {
     /// IP address. todo: make this more secure, encrypt with password? !!!
     #[argh(option)]
     secret: String,
-    /// the symbol of the market for trades to download, must be in the format BASE-QUOTE e.g
-    /// BTC-GBP
-    /// todo: could make this an option and if None fetch all from binance::api::General::exchange_info()
+    /// the symbol of the market for trades to download e.g. BTCGBP, or BTC-GBP. The base and
+    /// quote assets are resolved via the exchange info endpoint.
+    /// todo: if None fetch all from binance::api::General::exchange_info()
     #[argh(option)]
-    symbol: String,
+    symbol: Option<String>,
+    /// also download staking rewards as income, requires --since
+    #[argh(switch)]
+    staking: bool,
+    /// also download Simple Earn flexible subscriptions and redemptions as transfers, and rewards
+    /// as income, requires --since
+    #[argh(switch)]
+    earn: bool,
+    /// also download the funding payments of USDⓈ-M perpetual futures, netted per day into
+    /// income or an expense, requires --since
+    #[argh(switch)]
+    funding: bool,
+    /// only fetch trades on or after this date e.g. 2020-04-06
+    #[argh(option)]
+    since: Option<NaiveDate>,
+    /// only fetch trades on or before this date e.g. 2021-04-05
+    #[argh(option)]
+    until: Option<NaiveDate>,
+    /// label the imported trades with an account e.g. binance:bot, for users with multiple
+    /// Binance accounts
+    #[argh(option)]
+    account: Option<String>,
+    /// encrypt the output with the passphrase in the TAXC_PASSPHRASE environment variable
+    #[argh(switch)]
+    encrypt: bool,
 }
 
 const API_ENDPOINT: &'static str = "https://api.binance.com";
 const LIMIT: u64 = 200;
+/// Maximum time between the start and end time of a trades request, 24 hours
+const TRADES_WINDOW_MILLIS: u64 = 24 * 60 * 60 * 1000;
+/// Maximum page size of the staking and earn history endpoints
+const HISTORY_PAGE_SIZE: u64 = 100;
+/// Maximum time between the start and end time of a staking or earn history request, 90 days
+const HISTORY_WINDOW_MILLIS: u64 = 90 * 24 * 60 * 60 * 1000;
+const FUTURES_API_ENDPOINT: &'static str = "https://fapi.binance.com";
+/// Maximum page size of the futures income history
+const INCOME_PAGE_SIZE: usize = 1000;
 
 impl BinanceApiCommand {
     pub fn exec(&self) -> color_eyre::Result<()> {
-        let trades = self.get_trade_history()?;
-        let trade_records = self.convert_trades(trades)?;
-        crate::utils::write_csv(trade_records, std::io::stdout())
+        let trade_records = self.fetch()?;
+        crate::encryption::write_stdout(self.encrypt, |out| {
+            crate::utils::write_csv(trade_records, out)
+        })
+    }
+
+    /// Download the trades and any other history requested, sorted by date
+    pub fn fetch(&self) -> color_eyre::Result<Vec<TradeRecord>> {
+        let mut trade_records = Vec::new();
+        if let Some(ref symbol) = self.symbol {
+            let binance_symbol = symbol.replace("-", "");
+            let symbol_info = self.fetch_symbol_info(&binance_symbol)?;
+            let trades = self.get_trade_history(&symbol_info.symbol)?;
+            trade_records.extend(self.convert_trades(&symbol_info, trades)?);
+        }
+        if self.staking {
+            let rewards = self.get_history("staking rewards", |start, end, page| {
+                self.fetch_staking_rewards(start, end, page)
+            })?;
+            trade_records.extend(self.convert_history(
+                rewards,
+                Transaction::Income,
+                "Staking reward",
+            )?);
+        }
+        if self.earn {
+            let earn_history: Vec<(&str, Option<&str>, MovementKind, &str)> = vec![
+                (
+                    "subscriptionRecord",
+                    None,
+                    Transaction::Withdrawal,
+                    "Simple Earn subscription",
+                ),
+                (
+                    "redemptionRecord",
+                    None,
+                    Transaction::Deposit,
+                    "Simple Earn redemption",
+                ),
+                (
+                    "rewardsRecord",
+                    Some("REALTIME"),
+                    Transaction::Income,
+                    "Simple Earn reward",
+                ),
+                (
+                    "rewardsRecord",
+                    Some("BONUS"),
+                    Transaction::Income,
+                    "Simple Earn bonus reward",
+                ),
+            ];
+            for (record, reward_type, transaction, description) in earn_history {
+                let history = self.get_history(description, |start, end, page| {
+                    self.fetch_earn_history(record, reward_type, start, end, page)
+                })?;
+                trade_records.extend(self.convert_history(history, transaction, description)?);
+            }
+        }
+        if self.funding {
+            let payments = self.get_funding_payments()?;
+            trade_records.extend(
+                derivatives::funding_by_day(payments, "Binance")
+                    .iter()
+                    .map(TradeRecord::from),
+            );
+        }
+        for record in trade_records.iter_mut() {
+            record.account = self.account.clone();
+        }
+        trade_records.sort_by(|r1, r2| r1.date_time.cmp(&r2.date_time));
+        Ok(trade_records)
     }
 
-    /// Download the entire trade history for the current symbol from the Binance API.
-    fn get_trade_history(&self) -> color_eyre::Result<Vec<TradeHistory>> {
-        let binance_symbol = self.symbol.replace("-", "");
+    /// GET /api/v3/exchangeInfo
+    ///
+    /// [API Docs](https://github.com/binance/binance-spot-api-docs/blob/master/rest-api.md#exchange-information)
+    ///
+    /// Get the base and quote assets for the given symbol.
+    fn fetch_symbol_info(&self, symbol: &str) -> color_eyre::Result<SymbolInfo> {
+        tracing::info!("Fetching exchange info for {}", symbol);
+        let response = ureq::get(&format!("{}/api/v3/exchangeInfo", API_ENDPOINT))
+            .query("symbol", symbol)
+            .call()?;
+        let exchange_info: ExchangeInfo = response.into_json()?;
+        exchange_info
+            .symbols
+            .into_iter()
+            .find(|s| s.symbol == symbol)
+            .ok_or(eyre::eyre!("Symbol {} not found in exchange info", symbol))
+    }
+
+    /// Download the trade history for the current symbol from the Binance API, bounded by the
+    /// `since` and `until` dates if supplied.
+    ///
+    /// The first trade after `since` is found a day at a time, since a request by time only
+    /// allows querying 24 hours at once. The trades after it are then fetched by id, which can't
+    /// be combined with an end time, so those after `until` are dropped here.
+    fn get_trade_history(&self, binance_symbol: &str) -> color_eyre::Result<Vec<TradeHistory>> {
         let mut trades = Vec::new();
-        let mut next_from_id = 0;
+        let mut next_from = match self.start_time() {
+            Some(start_time) => TradesFrom::Time(start_time),
+            None => TradesFrom::Id(0),
+        };
+        let end_time = self.end_time();
+        let last_time = end_time.unwrap_or(Utc::now().timestamp_millis() as u64);
         loop {
-            let mut trades_batch = self.fetch_trade_history(&binance_symbol, next_from_id)?;
+            let query = trade_history_query(binance_symbol, &next_from, last_time);
+            let trades_batch = self.fetch_trade_history(&next_from, &query)?;
             let trade_ids = trades_batch.iter().map(|t| t.id).collect::<Vec<_>>();
             let max_id = trade_ids.iter().max();
             if let Some(max_id) = max_id {
-                log::info!("trades batch: max_id {:?}", max_id);
-                trades.append(&mut trades_batch);
-                next_from_id = max_id + 1;
+                tracing::info!("trades batch: max_id {:?}", max_id);
+                let (mut in_range, after_end): (Vec<_>, Vec<_>) = trades_batch
+                    .into_iter()
+                    .partition(|t| end_time.map_or(true, |end| t.time <= end));
+                trades.append(&mut in_range);
+                if !after_end.is_empty() {
+                    // reached the end of the requested period, so we are done
+                    break;
+                }
+                next_from = TradesFrom::Id(max_id + 1);
             } else {
-                // no more trades returned, so we are done
-                break;
+                let window_end = match next_from {
+                    TradesFrom::Time(start_time) => trades_window_end(start_time, last_time),
+                    TradesFrom::Id(_) => last_time,
+                };
+                if window_end >= last_time {
+                    // no more trades returned, so we are done
+                    break;
+                }
+                // no trades that day, so try the next one
+                next_from = TradesFrom::Time(window_end + 1);
             }
         }
-        log::info!("Fetched a total of {:?} trades", trades.len());
+        tracing::info!("Fetched a total of {:?} trades", trades.len());
         Ok(trades)
     }
 
+    /// Start of the `since` date in milliseconds
+    fn start_time(&self) -> Option<u64> {
+        self.since
+            .map(|date| date.and_hms(0, 0, 0).timestamp_millis() as u64)
+    }
+
+    /// End of the `until` date in milliseconds
+    fn end_time(&self) -> Option<u64> {
+        self.until
+            .map(|date| date.succ().and_hms(0, 0, 0).timestamp_millis() as u64 - 1)
+    }
+
     /// GET /api/v3/myTrades  (HMAC SHA256)
     ///
     /// [API Docs](https://github.com/binance/binance-spot-api-docs/blob/master/rest-api.md#account-trade-list-user_data)
@@ -69,18 +229,155 @@ impl BinanceApiCommand {
     /// Get trades for a specific account and symbol.
     fn fetch_trade_history(
         &self,
-        symbol: &str,
-        from_id: u64,
+        from: &TradesFrom,
+        query: &[(&str, String)],
     ) -> color_eyre::Result<Vec<TradeHistory>> {
-        log::info!("Fetching trades from_id {:?}", from_id);
+        tracing::info!("Fetching trades from {:?}", from);
         let mut url = url::Url::from_str(&format!("{}/api/v3/myTrades", API_ENDPOINT))?;
+        url.query_pairs_mut().extend_pairs(query);
 
+        let trades: Vec<TradeHistory> = self.signed_get(url)?.into_json()?;
+        tracing::info!("Fetched {} trades", trades.len());
+
+        Ok(trades)
+    }
+
+    /// Download all the history between the `since` and `until` dates, one window at a time
+    /// since the staking and earn endpoints only allow querying 90 days at once.
+    fn get_history<F>(&self, description: &str, fetch: F) -> color_eyre::Result<Vec<AssetRecord>>
+    where
+        F: Fn(u64, u64, u64) -> color_eyre::Result<Vec<AssetRecord>>,
+    {
+        let start_time = self.start_time().ok_or(eyre::eyre!(
+            "--since is required to download {}",
+            description
+        ))?;
+        let end_time = self
+            .end_time()
+            .unwrap_or(Utc::now().timestamp_millis() as u64);
+        let mut records = Vec::new();
+        let mut window_start = start_time;
+        while window_start <= end_time {
+            let window_end = std::cmp::min(window_start + HISTORY_WINDOW_MILLIS - 1, end_time);
+            let mut page = 1;
+            loop {
+                let batch = fetch(window_start, window_end, page)?;
+                let last_page = (batch.len() as u64) < HISTORY_PAGE_SIZE;
+                records.extend(batch);
+                if last_page {
+                    break;
+                }
+                page += 1;
+            }
+            window_start = window_end + 1;
+        }
+        tracing::info!("Fetched a total of {} {}", records.len(), description);
+        Ok(records)
+    }
+
+    /// GET /sapi/v1/staking/stakingRecord  (HMAC SHA256)
+    ///
+    /// [API Docs](https://binance-docs.github.io/apidocs/spot/en/#get-staking-history-user_data)
+    ///
+    /// Get a page of staking interest payments between the start and end times.
+    fn fetch_staking_rewards(
+        &self,
+        start_time: u64,
+        end_time: u64,
+        page: u64,
+    ) -> color_eyre::Result<Vec<AssetRecord>> {
+        tracing::info!("Fetching staking rewards from {} page {}", start_time, page);
+        let mut url =
+            url::Url::from_str(&format!("{}/sapi/v1/staking/stakingRecord", API_ENDPOINT))?;
         url.query_pairs_mut()
-            .append_pair("symbol", &format!("{}", &symbol));
-        url.query_pairs_mut()
-            .append_pair("fromId", &format!("{}", from_id));
+            .append_pair("product", "STAKING")
+            .append_pair("txnType", "INTEREST")
+            .append_pair("startTime", &format!("{}", start_time))
+            .append_pair("endTime", &format!("{}", end_time))
+            .append_pair("current", &format!("{}", page))
+            .append_pair("size", &format!("{}", HISTORY_PAGE_SIZE));
+        Ok(self.signed_get(url)?.into_json()?)
+    }
+
+    /// GET /sapi/v1/simple-earn/flexible/history/{record}  (HMAC SHA256)
+    ///
+    /// [API Docs](https://binance-docs.github.io/apidocs/spot/en/#get-flexible-subscription-record-user_data)
+    ///
+    /// Get a page of Simple Earn flexible subscriptions, redemptions or rewards between the start
+    /// and end times. Rewards require the type of reward.
+    fn fetch_earn_history(
+        &self,
+        record: &str,
+        reward_type: Option<&str>,
+        start_time: u64,
+        end_time: u64,
+        page: u64,
+    ) -> color_eyre::Result<Vec<AssetRecord>> {
+        tracing::info!("Fetching earn {} from {} page {}", record, start_time, page);
+        let mut url = url::Url::from_str(&format!(
+            "{}/sapi/v1/simple-earn/flexible/history/{}",
+            API_ENDPOINT, record
+        ))?;
+        if let Some(reward_type) = reward_type {
+            url.query_pairs_mut().append_pair("type", reward_type);
+        }
         url.query_pairs_mut()
-            .append_pair("limit", &format!("{}", LIMIT));
+            .append_pair("startTime", &format!("{}", start_time))
+            .append_pair("endTime", &format!("{}", end_time))
+            .append_pair("current", &format!("{}", page))
+            .append_pair("size", &format!("{}", HISTORY_PAGE_SIZE));
+        let earn_rows: EarnRows = self.signed_get(url)?.into_json()?;
+        Ok(earn_rows.rows)
+    }
+
+    /// GET /fapi/v1/income  (HMAC SHA256)
+    ///
+    /// [API Docs](https://binance-docs.github.io/apidocs/futures/en/#get-income-history-user_data)
+    ///
+    /// Get the funding payments between the `since` and `until` dates, a page at a time starting
+    /// after the last payment of the previous page.
+    fn get_funding_payments(&self) -> color_eyre::Result<Vec<FundingPayment>> {
+        let mut start_time = self.start_time().ok_or(eyre::eyre!(
+            "--since is required to download funding payments"
+        ))?;
+        let end_time = self
+            .end_time()
+            .unwrap_or(Utc::now().timestamp_millis() as u64);
+        let mut payments = Vec::new();
+        while start_time <= end_time {
+            tracing::info!("Fetching funding payments from {}", start_time);
+            let mut url = url::Url::from_str(&format!("{}/fapi/v1/income", FUTURES_API_ENDPOINT))?;
+            url.query_pairs_mut()
+                .append_pair("incomeType", "FUNDING_FEE")
+                .append_pair("startTime", &format!("{}", start_time))
+                .append_pair("endTime", &format!("{}", end_time))
+                .append_pair("limit", &format!("{}", INCOME_PAGE_SIZE));
+            let batch: Vec<IncomeRecord> = self.signed_get(url)?.into_json()?;
+            let last_page = batch.len() < INCOME_PAGE_SIZE;
+            match batch.last() {
+                Some(last) => start_time = last.time + 1,
+                None => break,
+            }
+            for record in batch {
+                let (currency, multiplier) = parse_asset(&record.asset)?;
+                payments.push(FundingPayment {
+                    time: from_millis(record.time),
+                    symbol: record.symbol,
+                    asset: currency,
+                    amount: record.income * multiplier,
+                });
+            }
+            if last_page {
+                break;
+            }
+        }
+        tracing::info!("Fetched a total of {} funding payments", payments.len());
+        Ok(payments)
+    }
+
+    /// Add the timestamp and HMAC SHA256 signature of the query to the request, required by all
+    /// endpoints for account data.
+    fn signed_get(&self, mut url: url::Url) -> color_eyre::Result<ureq::Response> {
         url.query_pairs_mut()
             .append_pair("timestamp", &format!("{}", Utc::now().timestamp_millis()));
 
@@ -90,36 +387,60 @@ impl BinanceApiCommand {
         signed_key.update(query_str.as_bytes());
         let signature = hex::encode(signed_key.finalize().into_bytes());
 
-        let response = ureq::get(&url.to_string())
+        Ok(ureq::get(&url.to_string())
             .set("Content-Type", "application/x-www-form-urlencoded")
             .set("x-mbx-apikey", self.api_key.as_str())
             .query("signature", signature.as_str())
-            .call()?;
-
-        let trades: Vec<TradeHistory> = response.into_json()?;
-        log::info!("Fetched {} trades", trades.len());
+            .call()?)
+    }
 
-        Ok(trades)
+    /// Convert staking or earn history into movements of the given kind
+    fn convert_history(
+        &self,
+        records: Vec<AssetRecord>,
+        transaction: MovementKind,
+        notes: &str,
+    ) -> color_eyre::Result<Vec<TradeRecord>> {
+        records
+            .into_iter()
+            .map(|record| {
+                let (currency, multiplier) = parse_asset(&record.asset)?;
+                let amount = Money::from_decimal(record.amount * multiplier, currency);
+                let tx = transaction(Movement {
+                    date_time: from_millis(record.time),
+                    fee: zero(currency),
+                    amount,
+                    exchange: Some("Binance".into()),
+                    account: None,
+                    ownership_changed: false,
+                    notes: Some(notes.into()),
+                });
+                Ok(TradeRecord::from(&tx))
+            })
+            .collect()
     }
 
-    fn convert_trades(&self, trades: Vec<TradeHistory>) -> color_eyre::Result<Vec<TradeRecord>> {
-        let mut parts = self.symbol.split('-');
-        let base_code = parts
-            .next()
-            .ok_or(eyre::eyre!("Invalid symbol {}", self.symbol))?;
-        let quote_code = parts
-            .next()
-            .ok_or(eyre::eyre!("Invalid symbol {}", self.symbol))?;
-        let base = crate::currencies::find(base_code)
-            .ok_or(eyre::eyre!("failed to find base currency {}", base_code))?;
-        let quote = crate::currencies::find(quote_code)
-            .ok_or(eyre::eyre!("failed to find quote currency {}", quote_code))?;
+    fn convert_trades(
+        &self,
+        symbol_info: &SymbolInfo,
+        trades: Vec<TradeHistory>,
+    ) -> color_eyre::Result<Vec<TradeRecord>> {
+        let (base, base_multiplier) = parse_asset(&symbol_info.base_asset)?;
+        let (quote, quote_multiplier) = parse_asset(&symbol_info.quote_asset)?;
+        if quote_multiplier != dec!(1) {
+            return Err(eyre::eyre!(
+                "Unsupported quote asset {} for {}",
+                symbol_info.quote_asset,
+                symbol_info.symbol
+            ));
+        }
 
         let trades = trades
             .into_iter()
             .map(|trade| {
                 let trade = BinanceTrade {
                     base: *base,
+                    base_multiplier,
                     quote: *quote,
                     trade: trade.clone(),
                 };
@@ -130,6 +451,75 @@ impl BinanceApiCommand {
     }
 }
 
+/// Where to start fetching a batch of trades from
+#[derive(Debug)]
+enum TradesFrom {
+    /// Trades with an id greater than or equal to this id
+    Id(u64),
+    /// Trades at or after this time in milliseconds
+    Time(u64),
+}
+
+/// The end of the window of a trades request by time, no later than the last time requested
+fn trades_window_end(start_time: u64, last_time: u64) -> u64 {
+    std::cmp::min(start_time + TRADES_WINDOW_MILLIS - 1, last_time)
+}
+
+/// The query of a trades request, before it is signed. A request by time is bounded by an end
+/// time, a request by id can't be.
+fn trade_history_query(
+    symbol: &str,
+    from: &TradesFrom,
+    last_time: u64,
+) -> Vec<(&'static str, String)> {
+    let mut query = vec![("symbol", symbol.to_string())];
+    match from {
+        TradesFrom::Id(from_id) => query.push(("fromId", from_id.to_string())),
+        TradesFrom::Time(start_time) => {
+            query.push(("startTime", start_time.to_string()));
+            query.push((
+                "endTime",
+                trades_window_end(*start_time, last_time).to_string(),
+            ));
+        }
+    }
+    query.push(("limit", LIMIT.to_string()));
+    query
+}
+
+/// Splits a Binance asset code into its currency and the number of units represented by one unit
+/// of the asset, e.g. `1000SHIB` is `(SHIB, 1000)`, `BTC` is `(BTC, 1)`
+pub(super) fn parse_asset(asset: &str) -> color_eyre::Result<(&'static Currency, Decimal)> {
+    let code = asset.trim_start_matches(|c: char| c.is_ascii_digit());
+    let multiplier = match &asset[..asset.len() - code.len()] {
+        "" => dec!(1),
+        digits => Decimal::from_str(digits)?,
+    };
+    let currency =
+        crate::money::lookup(code).map_err(|err| eyre::eyre!("Asset {}: {}", asset, err))?;
+    Ok((currency, multiplier))
+}
+
+/// Convert a Binance timestamp in milliseconds
+fn from_millis(millis: u64) -> NaiveDateTime {
+    let seconds = millis as i64 / 1000;
+    let nanos = (millis % 1000 * 1_000_000) as u32;
+    NaiveDateTime::from_timestamp(seconds, nanos)
+}
+
+#[derive(Debug, Deserialize)]
+struct ExchangeInfo {
+    symbols: Vec<SymbolInfo>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct SymbolInfo {
+    symbol: String,
+    base_asset: String,
+    quote_asset: String,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct TradeHistory {
@@ -144,10 +534,38 @@ pub struct TradeHistory {
     pub is_best_match: bool,
 }
 
-struct BinanceTrade {
-    base: Currency,
-    quote: Currency,
-    trade: TradeHistory,
+/// Constructor of the transaction for a movement e.g. `Transaction::Income`
+type MovementKind = fn(Movement<'static>) -> Transaction<'static>;
+
+/// An amount of an asset moved at a point in time, returned by the staking and earn history
+#[derive(Debug, Deserialize)]
+struct AssetRecord {
+    time: u64,
+    asset: String,
+    #[serde(alias = "rewards")]
+    amount: Decimal,
+}
+
+/// A row of the futures income history, the income is negative when paid
+#[derive(Debug, Deserialize)]
+struct IncomeRecord {
+    symbol: String,
+    income: Decimal,
+    asset: String,
+    time: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct EarnRows {
+    rows: Vec<AssetRecord>,
+}
+
+pub(super) struct BinanceTrade {
+    pub(super) base: Currency,
+    /// units of the base currency per unit of the traded asset e.g. 1000 for 1000SHIB
+    pub(super) base_multiplier: Decimal,
+    pub(super) quote: Currency,
+    pub(super) trade: TradeHistory,
 }
 
 impl<'a> TryFrom<&'a BinanceTrade> for Trade<'a> {
@@ -155,12 +573,10 @@ impl<'a> TryFrom<&'a BinanceTrade> for Trade<'a> {
 
     fn try_from(value: &'a BinanceTrade) -> Result<Trade<'a>, Self::Error> {
         let trade = &value.trade;
-        let seconds = trade.time as i64 / 1000;
-        let nanos = (trade.time % 1000 * 1_000_000) as u32;
-        let date_time = NaiveDateTime::from_timestamp(seconds, nanos);
+        let date_time = from_millis(trade.time);
 
         // base e.g. in ETH/BTC this is the ETH
-        let base_amount = Money::from_decimal(trade.qty, &value.base);
+        let base_amount = Money::from_decimal(trade.qty * value.base_multiplier, &value.base);
         // quote e.g. in ETH/BTC this is the BTC
         let quote_amount = Money::from_decimal(trade.qty * trade.price, &value.quote);
 
@@ -170,7 +586,7 @@ impl<'a> TryFrom<&'a BinanceTrade> for Trade<'a> {
             (TradeKind::Sell, quote_amount, base_amount)
         };
 
-        let fee = amount(&trade.commission_asset, trade.commission);
+        let fee = amount(&trade.commission_asset, trade.commission)?;
 
         Ok(Trade {
             date_time,
@@ -178,8 +594,149 @@ impl<'a> TryFrom<&'a BinanceTrade> for Trade<'a> {
             buy,
             sell,
             fee,
-            rate: trade.price,
+            rate: trade.price / value.base_multiplier,
             exchange: Some("Binance".into()),
+            account: None,
+            notes: None,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: u64 = TRADES_WINDOW_MILLIS;
+
+    #[test]
+    fn trades_by_time_are_requested_a_day_at_a_time_until_the_end() {
+        let query = |from, last_time| {
+            url::form_urlencoded::Serializer::new(String::new())
+                .extend_pairs(trade_history_query("BTCGBP", &from, last_time))
+                .finish()
+        };
+        assert_eq!(
+            query(TradesFrom::Time(1000), 1000 + 10 * DAY),
+            format!(
+                "symbol=BTCGBP&startTime=1000&endTime={}&limit=200",
+                1000 + DAY - 1
+            )
+        );
+        assert_eq!(
+            query(TradesFrom::Time(1000), 5000),
+            "symbol=BTCGBP&startTime=1000&endTime=5000&limit=200"
+        );
+        assert_eq!(
+            query(TradesFrom::Id(42), 5000),
+            "symbol=BTCGBP&fromId=42&limit=200"
+        );
+    }
+
+    #[test]
+    fn assets_of_multiple_units_are_traded_in_units_of_the_currency() {
+        let (btc, multiplier) = parse_asset("BTC").unwrap();
+        assert_eq!((btc.code, multiplier), ("BTC", dec!(1)));
+        let (shib, multiplier) = parse_asset("1000SHIB").unwrap();
+        assert_eq!((shib.code, multiplier), ("SHIB", dec!(1000)));
+        assert!(parse_asset("1000XYZ").is_err());
+
+        let binance_trade = BinanceTrade {
+            base: *shib,
+            base_multiplier: multiplier,
+            quote: *parse_asset("USDT").unwrap().0,
+            trade: TradeHistory {
+                id: 1,
+                price: dec!(0.02),
+                qty: dec!(5),
+                commission: dec!(0.0001),
+                commission_asset: "USDT".into(),
+                time: 1_609_459_200_000,
+                is_buyer: true,
+                is_maker: false,
+                is_best_match: true,
+            },
+        };
+        let trade = Trade::try_from(&binance_trade).unwrap();
+        assert_eq!(trade.kind, TradeKind::Buy);
+        assert_eq!(*trade.buy.amount(), dec!(5000));
+        assert_eq!(trade.buy.currency().code, "SHIB");
+        assert_eq!(*trade.sell.amount(), dec!(0.1));
+        assert_eq!(trade.rate, dec!(0.00002));
+    }
+
+    #[test]
+    fn staking_rewards_are_fetched_in_windows_and_pages_as_income() {
+        let command = BinanceApiCommand::from_args(
+            &["binance"],
+            &[
+                "--api-key",
+                "key",
+                "--secret",
+                "secret",
+                "--staking",
+                "--since",
+                "2021-01-01",
+                "--until",
+                "2021-06-30",
+            ],
+        )
+        .unwrap();
+        let reward: serde_json::Value = serde_json::json!({
+            "positionId": "1",
+            "time": 1_609_502_400_000u64,
+            "asset": "DOT",
+            "amount": "0.5",
+            "status": "SUCCESS"
+        });
+        let requests = std::cell::RefCell::new(Vec::new());
+        let rewards = command
+            .get_history("staking rewards", |start, end, page| {
+                requests.borrow_mut().push((start, end, page));
+                let count = match requests.borrow().len() {
+                    1 => HISTORY_PAGE_SIZE,
+                    2 => 1,
+                    _ => 0,
+                };
+                let batch = vec![reward.clone(); count as usize];
+                Ok(serde_json::from_value(serde_json::Value::Array(batch))?)
+            })
+            .unwrap();
+
+        let start = command.start_time().unwrap();
+        let end = command.end_time().unwrap();
+        let window_end = start + HISTORY_WINDOW_MILLIS - 1;
+        assert_eq!(
+            requests.into_inner(),
+            vec![
+                (start, window_end, 1),
+                (start, window_end, 2),
+                (window_end + 1, window_end + HISTORY_WINDOW_MILLIS, 1),
+                (window_end + HISTORY_WINDOW_MILLIS + 1, end, 1),
+            ]
+        );
+        assert_eq!(rewards.len(), HISTORY_PAGE_SIZE as usize + 1);
+
+        let records = command
+            .convert_history(rewards, Transaction::Income, "Staking")
+            .unwrap();
+        assert_eq!(records[0].kind, "Income");
+        assert_eq!(records[0].buy_asset, "DOT");
+        assert_eq!(records[0].date_time, "2021-01-01T12:00:00+00:00");
+        assert_eq!(records[0].buy_amount.parse::<Decimal>().unwrap(), dec!(0.5));
+        assert_eq!(records[0].exchange, "Binance");
+        assert_eq!(records[0].notes, "Staking");
+
+        let without_since = BinanceApiCommand::from_args(
+            &["binance"],
+            &["--api-key", "key", "--secret", "secret", "--staking"],
+        )
+        .unwrap();
+        let err = without_since
+            .get_history("staking rewards", |_, _, _| Ok(Vec::new()))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "--since is required to download staking rewards"
+        );
+    }
+}
@@ -1,10 +1,13 @@
 use chrono::NaiveDateTime;
 use serde::Deserialize;
-use std::convert::TryFrom;
+use std::{convert::TryFrom, str::FromStr};
 
+use super::api::{parse_asset, BinanceTrade, TradeHistory};
 use crate::{
+    cmd::import::exchanges::ExchangeError,
     money::amount,
-    trades::{Trade, TradeKind},
+    trades::{Trade, TradeKind, TradeRecord},
+    transactions::Transaction,
 };
 use rust_decimal::Decimal;
 
@@ -30,25 +33,25 @@ pub struct CsvRecord {
     fee_coin: String,
 }
 
-impl<'a> TryFrom<CsvRecord> for Trade<'a> {
-    type Error = crate::cmd::import::exchanges::ExchangeError;
+impl<'a> TryFrom<CsvRecord> for Transaction<'a> {
+    type Error = ExchangeError;
 
-    fn try_from(value: CsvRecord) -> Result<Trade<'a>, Self::Error> {
+    fn try_from(value: CsvRecord) -> Result<Transaction<'a>, Self::Error> {
         let date_time = NaiveDateTime::parse_from_str(value.date.as_ref(), "%Y-%m-%d %H:%M:%S")?;
 
         let (base_currency, quote_currency) = value.market.split_at(3);
 
-        let base_amount = amount(base_currency, value.amount);
-        let quote_amount = amount(quote_currency, value.total);
+        let base_amount = amount(base_currency, value.amount)?;
+        let quote_amount = amount(quote_currency, value.total)?;
 
         let (kind, sell, buy) = match value.order_type.as_ref() {
             "BUY" => (TradeKind::Buy, quote_amount, base_amount),
             "SELL" => (TradeKind::Sell, base_amount, quote_amount),
-            _ => panic!("Invalid order_type {}", value.order_type),
+            _ => return Err("Type should be BUY or SELL".into()),
         };
-        let fee = amount(value.fee_coin.as_ref(), value.fee);
+        let fee = amount(value.fee_coin.as_ref(), value.fee)?;
 
-        Ok(Trade {
+        Ok(Transaction::Trade(Trade {
             date_time,
             kind,
             buy,
@@ -56,6 +59,83 @@ impl<'a> TryFrom<CsvRecord> for Trade<'a> {
             fee,
             rate: value.price,
             exchange: Some("Binance".into()),
-        })
+            account: None,
+            notes: None,
+        }))
     }
 }
+
+// Date(UTC),Pair,Side,Price,Executed,Amount,Fee
+// 2021-03-01 10:00:00,BTCGBP,BUY,35000,0.10000000BTC,3500.00000000GBP,0.00010000BNB
+
+/// A row of the later Binance Trade History export, in which the amounts are suffixed with their
+/// asset, converted as for the trades downloaded from the API.
+#[derive(Debug, Deserialize, Clone)]
+pub struct TradeHistoryRecord {
+    #[serde(rename = "Date(UTC)")]
+    date: String,
+    #[serde(rename = "Side")]
+    side: String,
+    #[serde(rename = "Price")]
+    price: Decimal,
+    /// The amount of the base asset e.g. `0.10000000BTC`
+    #[serde(rename = "Executed")]
+    executed: String,
+    /// The amount of the quote asset e.g. `3500.00000000GBP`
+    #[serde(rename = "Amount")]
+    amount: String,
+    #[serde(rename = "Fee")]
+    fee: String,
+}
+
+impl<'a> TryFrom<TradeHistoryRecord> for Transaction<'a> {
+    type Error = ExchangeError;
+
+    fn try_from(value: TradeHistoryRecord) -> Result<Transaction<'a>, Self::Error> {
+        let date_time = NaiveDateTime::parse_from_str(value.date.as_ref(), "%Y-%m-%d %H:%M:%S")?;
+        let (qty, base) = split_amount(&value.executed)?;
+        let (_, quote) = split_amount(&value.amount)?;
+        let (commission, commission_asset) = split_amount(&value.fee)?;
+        let (base, base_multiplier) =
+            parse_asset(base).map_err(|_| ExchangeError::InvalidRecord("Unknown base asset"))?;
+        let (quote, _) =
+            parse_asset(quote).map_err(|_| ExchangeError::InvalidRecord("Unknown quote asset"))?;
+        let is_buyer = match value.side.as_ref() {
+            "BUY" => true,
+            "SELL" => false,
+            _ => return Err(ExchangeError::InvalidRecord("Side should be BUY or SELL")),
+        };
+        let trade = BinanceTrade {
+            base: *base,
+            base_multiplier,
+            quote: *quote,
+            trade: TradeHistory {
+                id: 0,
+                price: value.price,
+                qty,
+                commission,
+                commission_asset: commission_asset.to_string(),
+                time: date_time.timestamp_millis() as u64,
+                is_buyer,
+                is_maker: false,
+                is_best_match: false,
+            },
+        };
+        // the trade borrows the currencies of the api conversion, so is converted to the
+        // currencies of the transaction via its record
+        let trade = Trade::try_from(&trade)?;
+        Transaction::try_from(TradeRecord::from(&trade))
+            .map_err(|_| ExchangeError::InvalidRecord("Trade should be a valid transaction"))
+    }
+}
+
+/// Splits an amount suffixed with its asset e.g. `1,234.5USDT` into the amount and the asset.
+/// Assets starting with digits e.g. `1000SHIB` can't be told apart from the amount.
+fn split_amount(value: &str) -> Result<(Decimal, &str), ExchangeError> {
+    let value = value.trim();
+    let asset_start = value
+        .find(|c: char| c.is_ascii_alphabetic())
+        .ok_or(ExchangeError::InvalidRecord("Amount without an asset"))?;
+    let (amount, asset) = value.split_at(asset_start);
+    Ok((Decimal::from_str(&amount.replace(',', ""))?, asset))
+}
@@ -0,0 +1,65 @@
+use chrono::NaiveDateTime;
+use serde::Deserialize;
+use std::convert::TryFrom;
+
+use crate::{
+    cmd::import::exchanges::ExchangeError,
+    money::{amount, zero},
+    transactions::{Movement, Transaction},
+};
+use rust_decimal::Decimal;
+
+/// A row of the Binance transaction history statement, only Savings, Simple Earn and Launchpool
+/// operations are supported.
+#[derive(Debug, Deserialize, Clone)]
+pub struct EarnCsvRecord {
+    // User_ID,UTC_Time,Account,Operation,Coin,Change,Remark
+    #[serde(rename = "UTC_Time")]
+    date: String,
+    #[serde(rename = "Operation")]
+    operation: String,
+    #[serde(rename = "Coin")]
+    coin: String,
+    #[serde(rename = "Change")]
+    change: Decimal,
+}
+
+impl<'a> TryFrom<EarnCsvRecord> for Transaction<'a> {
+    type Error = ExchangeError;
+
+    fn try_from(value: EarnCsvRecord) -> Result<Transaction<'a>, Self::Error> {
+        let date_time = NaiveDateTime::parse_from_str(value.date.as_ref(), "%Y-%m-%d %H:%M:%S")?;
+        let amount = amount(&value.coin, value.change.abs())?;
+        let movement = Movement {
+            date_time,
+            fee: zero(amount.currency()),
+            amount,
+            exchange: Some("Binance".into()),
+            account: None,
+            ownership_changed: false,
+            notes: Some(value.operation.clone()),
+        };
+        earn_transaction(&value.operation, movement).ok_or(ExchangeError::InvalidRecord(
+            "Unsupported operation, only Savings, Simple Earn and Launchpool are supported",
+        ))
+    }
+}
+
+/// The transaction of a Savings, Simple Earn or Launchpool operation, none for other operations
+pub(super) fn earn_transaction<'a>(
+    operation: &str,
+    movement: Movement<'a>,
+) -> Option<Transaction<'a>> {
+    // moving funds in and out of earn products doesn't change their ownership, so they are
+    // transfers rather than trades
+    let operation = operation.to_lowercase();
+    if operation.contains("subscription") || operation.contains("purchase") {
+        Some(Transaction::Withdrawal(movement))
+    } else if operation.contains("redemption") {
+        Some(Transaction::Deposit(movement))
+    } else if operation.contains("interest") || operation.contains("rewards") {
+        Some(Transaction::Income(movement))
+    } else {
+        None
+    }
+}
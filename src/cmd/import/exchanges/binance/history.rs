@@ -0,0 +1,181 @@
+use super::earn::earn_transaction;
+use crate::{
+    cmd::import::exchanges::ExchangeError,
+    money::{amount, zero},
+    trades::{Trade, TradeKind},
+    transactions::{Movement, Transaction},
+};
+use chrono::NaiveDateTime;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::{collections::BTreeMap, io::Read};
+
+/// The operations of the legs of a trade, a trade being all of these rows at the same time
+const TRADE_OPERATIONS: &[&str] = &[
+    "Buy",
+    "Sell",
+    "Fee",
+    "Transaction Buy",
+    "Transaction Spend",
+    "Transaction Sold",
+    "Transaction Revenue",
+    "Transaction Fee",
+    "Transaction Related",
+    "Binance Convert",
+    "Small Assets Exchange BNB",
+    "Large OTC Trading",
+];
+
+/// A row of the full Binance Transaction History statement, each a change of the balance of one
+/// coin in one account
+#[derive(Debug, Deserialize, Clone)]
+pub struct TransactionHistoryRecord {
+    // User_ID,UTC_Time,Account,Operation,Coin,Change,Remark
+    #[serde(rename = "UTC_Time")]
+    date: String,
+    #[serde(rename = "Operation")]
+    operation: String,
+    #[serde(rename = "Coin")]
+    coin: String,
+    #[serde(rename = "Change")]
+    change: Decimal,
+}
+
+impl TransactionHistoryRecord {
+    fn date_time(&self) -> Result<NaiveDateTime, ExchangeError> {
+        Ok(NaiveDateTime::parse_from_str(
+            self.date.as_ref(),
+            "%Y-%m-%d %H:%M:%S",
+        )?)
+    }
+
+    fn is_trade(&self) -> bool {
+        TRADE_OPERATIONS.contains(&self.operation.as_str())
+    }
+
+    /// The transaction of a row which isn't part of a trade, none for transfers between the
+    /// accounts of the same user
+    fn transaction<'a>(&self) -> Result<Option<Transaction<'a>>, ExchangeError> {
+        let amount = amount(&self.coin, self.change.abs())?;
+        let movement = Movement {
+            date_time: self.date_time()?,
+            fee: zero(amount.currency()),
+            amount,
+            exchange: Some("Binance".into()),
+            account: None,
+            ownership_changed: false,
+            notes: Some(self.operation.clone()),
+        };
+        let operation = self.operation.to_lowercase();
+        if operation.contains("transfer") {
+            Ok(None)
+        } else if operation == "deposit" {
+            Ok(Some(Transaction::Deposit(movement)))
+        } else if operation == "withdraw" {
+            Ok(Some(Transaction::Withdrawal(movement)))
+        } else if operation.contains("distribution") || operation.contains("airdrop") {
+            Ok(Some(Transaction::Airdrop(movement)))
+        } else {
+            earn_transaction(&self.operation, movement).map(Some).ok_or(
+                ExchangeError::InvalidRecord(
+                    "Unsupported operation in the Binance transaction history",
+                ),
+            )
+        }
+    }
+}
+
+/// Combine the legs of a trade, with the fee if any, into a single trade. The base is what was
+/// bought for a buy, and what was sold for a sell.
+fn trade<'a>(
+    date_time: NaiveDateTime,
+    rows: &[&TransactionHistoryRecord],
+) -> Result<Transaction<'a>, ExchangeError> {
+    let mut changes: BTreeMap<&str, Decimal> = BTreeMap::new();
+    let mut fee: Option<(&str, Decimal)> = None;
+    let mut kind = TradeKind::Buy;
+    for row in rows {
+        let operation = row.operation.to_lowercase();
+        if operation.contains("fee") {
+            fee = match fee {
+                None => Some((row.coin.as_str(), row.change.abs())),
+                Some((coin, total)) if coin == row.coin => Some((coin, total + row.change.abs())),
+                Some(_) => {
+                    return Err(ExchangeError::InvalidRecord(
+                        "Trade fees in more than one coin at the same time",
+                    ))
+                }
+            };
+            continue;
+        }
+        *changes
+            .entry(&row.coin)
+            .or_insert_with(|| Decimal::new(0, 0)) += row.change;
+        if ["sell", "sold", "revenue"]
+            .iter()
+            .any(|word| operation.contains(word))
+        {
+            kind = TradeKind::Sell;
+        }
+    }
+    let single = |received: bool| -> Result<_, ExchangeError> {
+        let mut coins = changes
+            .iter()
+            .filter(|(_, change)| change.is_sign_positive() == received && !change.is_zero());
+        match (coins.next(), coins.next()) {
+            (Some((coin, change)), None) => Ok(amount(coin, change.abs())?),
+            _ => Err(ExchangeError::InvalidRecord(
+                "Trades should buy one coin with one other coin at the same time",
+            )),
+        }
+    };
+    let buy = single(true)?;
+    let sell = single(false)?;
+    let (base, quote) = match kind {
+        TradeKind::Buy => (&buy, &sell),
+        TradeKind::Sell => (&sell, &buy),
+    };
+    let rate = *quote.amount() / *base.amount();
+    let fee = match fee {
+        Some((coin, total)) => amount(coin, total)?,
+        None => zero(buy.currency()),
+    };
+    Ok(Transaction::Trade(Trade {
+        date_time,
+        kind,
+        buy,
+        sell,
+        fee,
+        rate,
+        exchange: Some("Binance".into()),
+        account: None,
+        notes: Some(rows[0].operation.clone()),
+    }))
+}
+
+/// Read the transactions of the Binance Transaction History statement, sorted by date. The rows
+/// of each trade are combined by their time.
+pub fn read_transaction_history<'a, R: Read>(
+    reader: R,
+) -> color_eyre::Result<Vec<Transaction<'a>>> {
+    let mut rdr = csv::Reader::from_reader(reader);
+    let records = rdr
+        .deserialize()
+        .collect::<Result<Vec<TransactionHistoryRecord>, _>>()?;
+    tracing::info!("Read {} csv records", records.len());
+
+    let mut transactions = Vec::new();
+    let mut trades: BTreeMap<NaiveDateTime, Vec<&TransactionHistoryRecord>> = BTreeMap::new();
+    for record in records.iter() {
+        if record.is_trade() {
+            trades.entry(record.date_time()?).or_default().push(record);
+        } else if let Some(tx) = record.transaction()? {
+            transactions.push(tx);
+        }
+    }
+    for (date_time, rows) in trades {
+        transactions.push(trade(date_time, &rows)?);
+    }
+    transactions.sort_by_key(|tx| tx.date_time());
+    Ok(transactions)
+}
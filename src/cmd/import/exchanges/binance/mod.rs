@@ -1,4 +1,11 @@
 mod api;
 mod csv;
+mod earn;
+mod history;
 
-pub use self::{api::BinanceApiCommand, csv::CsvRecord};
+pub use self::{
+    api::BinanceApiCommand,
+    csv::{CsvRecord, TradeHistoryRecord},
+    earn::EarnCsvRecord,
+    history::read_transaction_history,
+};
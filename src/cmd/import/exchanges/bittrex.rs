@@ -1,10 +1,11 @@
-use chrono::NaiveDateTime;
+use chrono::{DateTime, NaiveDateTime};
 use serde::Deserialize;
 use std::convert::TryFrom;
 
 use crate::{
     money::amount,
     trades::{Trade, TradeKind},
+    transactions::Transaction,
 };
 use rust_decimal::Decimal;
 
@@ -29,37 +30,181 @@ pub struct Record {
     opened: String,
     #[serde(rename = "Closed")]
     closed: String,
+    /// Only in later exports, older ones charged the commission in the quote currency
+    #[serde(rename = "CommissionCurrency", default)]
+    commission_currency: Option<String>,
 }
 
-impl<'a> TryFrom<Record> for Trade<'a> {
+/// Bittrex commission is at most 0.25% of the order, a higher proportion suggests the commission
+/// was charged in another currency
+const MAX_COMMISSION: (i64, u32) = (5, 3);
+
+/// An order history record, using the time the order was opened rather than closed
+#[derive(Debug, Deserialize, Clone)]
+#[serde(transparent)]
+pub struct OpenedRecord(Record);
+
+impl<'a> TryFrom<OpenedRecord> for Transaction<'a> {
+    type Error = super::ExchangeError;
+
+    fn try_from(value: OpenedRecord) -> Result<Transaction<'a>, Self::Error> {
+        let opened = value.0.opened.clone();
+        order_transaction(value.0, &opened)
+    }
+}
+
+impl<'a> TryFrom<Record> for Transaction<'a> {
+    type Error = super::ExchangeError;
+
+    fn try_from(value: Record) -> Result<Transaction<'a>, Self::Error> {
+        let closed = value.closed.clone();
+        order_transaction(value, &closed)
+    }
+}
+
+/// Whether the commission is too high a proportion of the order total to be in the quote currency
+fn is_high_commission(commission: Decimal, total: Decimal) -> bool {
+    commission.checked_div(total).map_or(true, |p| {
+        p > Decimal::new(MAX_COMMISSION.0, MAX_COMMISSION.1)
+    })
+}
+
+/// A whole order as a single trade at the given time. Orders filled over several days should be
+/// imported from the fills export instead, so each fill is matched on its own day.
+fn order_transaction<'a>(
+    value: Record,
+    timestamp: &str,
+) -> Result<Transaction<'a>, super::ExchangeError> {
+    let date_time = NaiveDateTime::parse_from_str(timestamp, "%m/%d/%Y %-I:%M:%S %p")?;
+
+    let mut market_parts = value.exchange.split('-');
+    let quote_currency = market_parts.next().ok_or("Exchange quote currency")?;
+    let base_currency = market_parts.next().ok_or("Exchange base currency")?;
+
+    let base_amount = amount(base_currency, value.quantity)?;
+    let quote_amount = amount(quote_currency, value.price)?;
+
+    let (kind, sell, buy) = match value.order_type.as_ref() {
+        "LIMIT_BUY" => (TradeKind::Buy, quote_amount, base_amount),
+        "LIMIT_SELL" => (TradeKind::Sell, base_amount, quote_amount),
+        _ => return Err("Type should be LIMIT_BUY or LIMIT_SELL".into()),
+    };
+    let fee_currency = match value.commission_currency.as_deref() {
+        Some(currency) if !currency.is_empty() => {
+            if currency != quote_currency && currency != base_currency {
+                return Err(
+                    "CommissionCurrency should be either the base or quote currency".into(),
+                );
+            }
+            currency
+        }
+        _ => {
+            if is_high_commission(value.commission_paid, value.price) {
+                tracing::warn!(
+                    "Bittrex order {} commission {} is assumed to be in {}, but is high for \
+                         its {} {} total, check the currency on Bittrex",
+                    value.order_id,
+                    value.commission_paid,
+                    quote_currency,
+                    value.price,
+                    quote_currency
+                );
+            }
+            quote_currency
+        }
+    };
+    let fee = amount(fee_currency, value.commission_paid)?;
+
+    Ok(Transaction::Trade(Trade {
+        date_time,
+        buy,
+        sell,
+        fee,
+        rate: value.limit,
+        exchange: Some("Bittrex".into()),
+        account: None,
+        notes: None,
+        kind,
+    }))
+}
+
+/// A fill of an order from the executions export. Fills of the same order are separate trades,
+/// so partial fills on different days are matched on the day they happened.
+#[derive(Debug, Deserialize, Clone)]
+pub struct FillRecord {
+    #[serde(rename = "Id")]
+    id: String,
+    #[serde(rename = "OrderId")]
+    order_id: String,
+    /// e.g. ETH-BTC, the base currency first
+    #[serde(rename = "MarketSymbol")]
+    market: String,
+    /// BUY or SELL
+    #[serde(rename = "Direction")]
+    direction: String,
+    /// e.g. 2017-12-01T09:01:00Z
+    #[serde(rename = "ExecutedAt")]
+    executed_at: String,
+    #[serde(rename = "Quantity")]
+    quantity: Decimal,
+    #[serde(rename = "Rate")]
+    rate: Decimal,
+    #[serde(rename = "Commission")]
+    commission: Decimal,
+    #[serde(rename = "CommissionCurrency", default)]
+    commission_currency: Option<String>,
+}
+
+impl<'a> TryFrom<FillRecord> for Transaction<'a> {
     type Error = super::ExchangeError;
 
-    fn try_from(value: Record) -> Result<Trade<'a>, Self::Error> {
-        let date_time =
-            NaiveDateTime::parse_from_str(value.closed.as_ref(), "%m/%d/%Y %-I:%M:%S %p").unwrap();
+    fn try_from(value: FillRecord) -> Result<Transaction<'a>, Self::Error> {
+        let date_time = DateTime::parse_from_rfc3339(&value.executed_at)?.naive_utc();
 
-        let mut market_parts = value.exchange.split('-');
-        let quote_currency = market_parts.next().expect("quote currency");
-        let base_currency = market_parts.next().expect("base currency");
+        let mut market_parts = value.market.split('-');
+        let base_currency = market_parts.next().ok_or("MarketSymbol base currency")?;
+        let quote_currency = market_parts.next().ok_or("MarketSymbol quote currency")?;
 
-        let base_amount = amount(base_currency, value.quantity);
-        let quote_amount = amount(quote_currency, value.price);
+        let base_amount = amount(base_currency, value.quantity)?;
+        let quote_amount = amount(quote_currency, value.quantity * value.rate)?;
 
-        let (kind, sell, buy) = match value.order_type.as_ref() {
-            "LIMIT_BUY" => (TradeKind::Buy, quote_amount, base_amount),
-            "LIMIT_SELL" => (TradeKind::Sell, base_amount, quote_amount),
-            _ => panic!("Invalid order_type {}", value.order_type),
+        let (kind, sell, buy) = match value.direction.as_ref() {
+            "BUY" => (TradeKind::Buy, quote_amount, base_amount),
+            "SELL" => (TradeKind::Sell, base_amount, quote_amount),
+            _ => return Err("Direction should be BUY or SELL".into()),
+        };
+        let fee_currency = match value.commission_currency.as_deref() {
+            Some(currency) if !currency.is_empty() => currency,
+            _ => quote_currency,
         };
-        let fee = amount(quote_currency, value.commission_paid);
+        let fee = amount(fee_currency, value.commission)?;
 
-        Ok(Trade {
+        Ok(Transaction::Trade(Trade {
             date_time,
             buy,
             sell,
             fee,
-            rate: value.limit,
+            rate: value.rate,
             exchange: Some("Bittrex".into()),
+            account: None,
+            notes: None,
             kind,
-        })
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commissions_above_the_maximum_are_high() {
+        assert!(!is_high_commission(
+            Decimal::new(1875, 7),
+            Decimal::new(75, 3)
+        ));
+        assert!(!is_high_commission(Decimal::new(5, 3), Decimal::new(1, 0)));
+        assert!(is_high_commission(Decimal::new(6, 3), Decimal::new(1, 0)));
+        assert!(is_high_commission(Decimal::new(1, 3), Decimal::new(0, 0)));
     }
 }
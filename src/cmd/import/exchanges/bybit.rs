@@ -0,0 +1,227 @@
+use crate::{
+    cmd::report::derivatives::{self, FundingPayment},
+    trades::TradeRecord,
+};
+use argh::FromArgs;
+use chrono::{prelude::*, NaiveDate};
+use color_eyre::eyre;
+use hmac::{Hmac, Mac, NewMac};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::str::FromStr;
+
+/// Import the funding payments of perpetual futures from the Bybit API, netted per day into
+/// income or an expense
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "bybit")]
+pub struct BybitApiCommand {
+    /// the api key, which only needs read access
+    #[argh(option)]
+    api_key: String,
+    /// the secret key
+    /// !!! This will appear in your shell history so make sure this API key is restricted to your
+    /// IP address !!!
+    #[argh(option)]
+    secret: String,
+    /// only fetch funding payments on or after this date e.g. 2020-04-06
+    #[argh(option)]
+    since: NaiveDate,
+    /// only fetch funding payments on or before this date e.g. 2021-04-05
+    #[argh(option)]
+    until: Option<NaiveDate>,
+    /// label the imported transactions with an account e.g. bybit:bot, for users with multiple
+    /// Bybit accounts
+    #[argh(option)]
+    account: Option<String>,
+    /// encrypt the output with the passphrase in the TAXC_PASSPHRASE environment variable
+    #[argh(switch)]
+    encrypt: bool,
+}
+
+const API_ENDPOINT: &str = "https://api.bybit.com";
+/// Maximum page size of the transaction log
+const PAGE_SIZE: u64 = 50;
+/// Maximum time between the start and end time of a transaction log request, 7 days
+const WINDOW_MILLIS: i64 = 7 * 24 * 60 * 60 * 1000;
+/// How long a signed request is valid for
+const RECV_WINDOW: &str = "5000";
+
+impl BybitApiCommand {
+    pub fn exec(&self) -> color_eyre::Result<()> {
+        let trade_records = self.fetch()?;
+        crate::encryption::write_stdout(self.encrypt, |out| {
+            crate::utils::write_csv(trade_records, out)
+        })
+    }
+
+    /// Download the funding payments, netted per day
+    pub fn fetch(&self) -> color_eyre::Result<Vec<TradeRecord>> {
+        let payments = self.get_funding_payments()?;
+        Ok(derivatives::funding_by_day(payments, "Bybit")
+            .iter()
+            .map(|tx| TradeRecord {
+                account: self.account.clone(),
+                ..tx.into()
+            })
+            .collect())
+    }
+
+    /// Download the funding payments between the `since` and `until` dates, one window at a time
+    /// since the transaction log only allows querying 7 days at once.
+    fn get_funding_payments(&self) -> color_eyre::Result<Vec<FundingPayment>> {
+        let start_time = self.since.and_hms(0, 0, 0).timestamp_millis();
+        let end_time = self.until.map_or(Utc::now().timestamp_millis(), |date| {
+            date.succ().and_hms(0, 0, 0).timestamp_millis() - 1
+        });
+        let mut payments = Vec::new();
+        let mut window_start = start_time;
+        while window_start <= end_time {
+            let window_end = std::cmp::min(window_start + WINDOW_MILLIS - 1, end_time);
+            let mut cursor = String::new();
+            loop {
+                let result = self.fetch_transaction_log(window_start, window_end, &cursor)?;
+                for entry in result.list {
+                    payments.push(entry.to_funding_payment()?);
+                }
+                if result.next_page_cursor.is_empty() {
+                    break;
+                }
+                cursor = result.next_page_cursor;
+            }
+            window_start = window_end + 1;
+        }
+        tracing::info!("Fetched a total of {} funding payments", payments.len());
+        Ok(payments)
+    }
+
+    /// GET /v5/account/transaction-log
+    ///
+    /// [API Docs](https://bybit-exchange.github.io/docs/v5/account/transaction-log)
+    ///
+    /// Get a page of the settlements of USDT perpetuals in the unified account, which are the
+    /// funding payments.
+    fn fetch_transaction_log(
+        &self,
+        start_time: i64,
+        end_time: i64,
+        cursor: &str,
+    ) -> color_eyre::Result<TransactionLog> {
+        tracing::info!("Fetching funding payments from {}", start_time);
+        let mut url = url::Url::from_str(&format!("{}/v5/account/transaction-log", API_ENDPOINT))?;
+        url.query_pairs_mut()
+            .append_pair("accountType", "UNIFIED")
+            .append_pair("category", "linear")
+            .append_pair("type", "SETTLEMENT")
+            .append_pair("startTime", &format!("{}", start_time))
+            .append_pair("endTime", &format!("{}", end_time))
+            .append_pair("limit", &format!("{}", PAGE_SIZE));
+        if !cursor.is_empty() {
+            url.query_pairs_mut().append_pair("cursor", cursor);
+        }
+        let response: Response = self.signed_get(url)?.into_json()?;
+        if response.ret_code != 0 {
+            return Err(eyre::eyre!("Bybit API error: {}", response.ret_msg));
+        }
+        response
+            .result
+            .ok_or(eyre::eyre!("No result in the Bybit response"))
+    }
+
+    /// Add the HMAC SHA256 signature of the timestamp, api key, receive window and query to the
+    /// request headers, required by all endpoints for account data.
+    fn signed_get(&self, url: url::Url) -> color_eyre::Result<ureq::Response> {
+        let timestamp = format!("{}", Utc::now().timestamp_millis());
+        let query_str = url.query().unwrap_or_default();
+
+        let mut signed_key = Hmac::<sha2::Sha256>::new_varkey(self.secret.as_bytes()).unwrap();
+        signed_key.update(timestamp.as_bytes());
+        signed_key.update(self.api_key.as_bytes());
+        signed_key.update(RECV_WINDOW.as_bytes());
+        signed_key.update(query_str.as_bytes());
+        let signature = hex::encode(signed_key.finalize().into_bytes());
+
+        Ok(ureq::get(&url.to_string())
+            .set("X-BAPI-API-KEY", self.api_key.as_str())
+            .set("X-BAPI-TIMESTAMP", timestamp.as_str())
+            .set("X-BAPI-RECV-WINDOW", RECV_WINDOW)
+            .set("X-BAPI-SIGN", signature.as_str())
+            .call()?)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Response {
+    ret_code: i64,
+    ret_msg: String,
+    result: Option<TransactionLog>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TransactionLog {
+    list: Vec<LogEntry>,
+    #[serde(default)]
+    next_page_cursor: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LogEntry {
+    symbol: String,
+    currency: String,
+    /// The change in the wallet balance, negative when the funding was paid
+    change: Decimal,
+    /// Timestamp in milliseconds
+    transaction_time: String,
+}
+
+impl LogEntry {
+    fn to_funding_payment(&self) -> color_eyre::Result<FundingPayment> {
+        let millis = self.transaction_time.parse::<i64>()?;
+        let asset = crate::money::lookup(&self.currency)
+            .map_err(|err| eyre::eyre!("{}: {}", self.symbol, err))?;
+        Ok(FundingPayment {
+            time: NaiveDateTime::from_timestamp(millis / 1000, (millis % 1000 * 1_000_000) as u32),
+            symbol: self.symbol.clone(),
+            asset,
+            amount: self.change,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn settlements_are_funding_payments() {
+        let json = r#"{
+            "retCode": 0,
+            "retMsg": "OK",
+            "result": {
+                "list": [
+                    { "symbol": "BTCUSDT", "currency": "USDT", "type": "SETTLEMENT", "change": "-0.31255", "funding": "0.31255", "transactionTime": "1614585600000" },
+                    { "symbol": "ETHUSDT", "currency": "USDT", "type": "SETTLEMENT", "change": "0.1", "funding": "-0.1", "transactionTime": "1614614400123" }
+                ],
+                "nextPageCursor": ""
+            }
+        }"#;
+        let response: Response = serde_json::from_str(json).unwrap();
+        let payments = response
+            .result
+            .unwrap()
+            .list
+            .iter()
+            .map(|entry| entry.to_funding_payment().unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(payments[0].amount, dec!(-0.31255), "Funding paid");
+        assert_eq!(payments[1].amount, dec!(0.1), "Funding received");
+        assert_eq!(
+            payments[1].time,
+            NaiveDate::from_ymd(2021, 3, 1).and_hms_milli(16, 0, 0, 123)
+        );
+    }
+}
@@ -0,0 +1,230 @@
+//! Imports trades from any of the exchanges supported by [CCXT](https://github.com/ccxt/ccxt),
+//! without a module per exchange here. CCXT is a JavaScript, Python and PHP library, so rather
+//! than linking against it a bridge program is run which calls `fetchMyTrades` and prints the
+//! unified trades as json, e.g. in Python:
+//!
+//! ```python
+//! exchange = getattr(ccxt, sys.argv[1])({"apiKey": ..., "secret": ...})
+//! print(json.dumps(exchange.fetch_my_trades(symbol, since)))
+//! ```
+
+use crate::{
+    trades::{TradeBuilder, TradeKind, TradeRecord},
+    Money,
+};
+use argh::FromArgs;
+use chrono::{NaiveDate, NaiveDateTime};
+use color_eyre::eyre;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::{fs::File, path::PathBuf, process::Command};
+
+/// Import trades from an exchange supported by CCXT, via a bridge program
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "ccxt")]
+pub struct CcxtApiCommand {
+    /// the CCXT id of the exchange e.g. kraken
+    #[argh(option)]
+    exchange: String,
+    /// the market of the trades to fetch in CCXT format e.g. BTC/GBP, can be repeated. If not
+    /// given the bridge fetches all markets, which not all exchanges support.
+    #[argh(option)]
+    symbol: Vec<String>,
+    /// only fetch trades on or after this date e.g. 2020-04-06
+    #[argh(option)]
+    since: Option<NaiveDate>,
+    /// the bridge program to run, default ccxt-bridge. It's run with the exchange id, followed
+    /// by --symbol for each market and --since in milliseconds if given, and must print a json
+    /// array of CCXT unified trades. API keys are left to the bridge e.g. in its environment.
+    #[argh(option, default = "String::from(\"ccxt-bridge\")")]
+    bridge: String,
+    /// optional json file of CCXT unified trades to import instead of running the bridge, e.g.
+    /// saved from an earlier run
+    #[argh(option)]
+    file: Option<PathBuf>,
+    /// label the imported trades with an account e.g. kraken:personal, for users with multiple
+    /// accounts on the exchange
+    #[argh(option)]
+    account: Option<String>,
+    /// encrypt the output with the passphrase in the TAXC_PASSPHRASE environment variable
+    #[argh(switch)]
+    encrypt: bool,
+}
+
+impl CcxtApiCommand {
+    pub fn exec(&self) -> color_eyre::Result<()> {
+        let trade_records = self.fetch()?;
+        crate::encryption::write_stdout(self.encrypt, |out| {
+            crate::utils::write_csv(trade_records, out)
+        })
+    }
+
+    /// Fetch the trades through the bridge, or read them from the file, sorted by date
+    pub fn fetch(&self) -> color_eyre::Result<Vec<TradeRecord>> {
+        let trades: Vec<UnifiedTrade> = match self.file {
+            Some(ref path) => serde_json::from_reader(File::open(path)?)?,
+            None => self.run_bridge()?,
+        };
+        tracing::info!("Fetched {} {} trades", trades.len(), self.exchange);
+        let mut trade_records = trades
+            .iter()
+            .filter_map(|trade| trade.to_trade_record(&self.exchange).transpose())
+            .collect::<color_eyre::Result<Vec<_>>>()?;
+        for record in trade_records.iter_mut() {
+            record.account = self.account.clone();
+        }
+        trade_records.sort_by(|r1, r2| r1.date_time.cmp(&r2.date_time));
+        Ok(trade_records)
+    }
+
+    fn run_bridge(&self) -> color_eyre::Result<Vec<UnifiedTrade>> {
+        let mut command = Command::new(&self.bridge);
+        command.arg(&self.exchange);
+        for symbol in self.symbol.iter() {
+            command.arg("--symbol").arg(symbol);
+        }
+        if let Some(since) = self.since {
+            let millis = since.and_hms(0, 0, 0).timestamp_millis();
+            command.arg("--since").arg(millis.to_string());
+        }
+        tracing::info!("Running {:?}", command);
+        let output = command
+            .output()
+            .map_err(|err| eyre::eyre!("Failed to run the CCXT bridge {}: {}", self.bridge, err))?;
+        if !output.status.success() {
+            return Err(eyre::eyre!(
+                "The CCXT bridge failed with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(serde_json::from_slice(&output.stdout)?)
+    }
+}
+
+/// The trade structure common to all exchanges in CCXT, with the fields needed here
+///
+/// [Docs](https://docs.ccxt.com/#/README?id=trade-structure)
+#[derive(Debug, Deserialize)]
+struct UnifiedTrade {
+    id: Option<String>,
+    /// In milliseconds
+    timestamp: i64,
+    /// The market e.g. `BTC/GBP`, or `BTC/USDT:USDT` for a derivative
+    symbol: String,
+    side: String,
+    /// Of the base currency in the quote currency
+    price: Decimal,
+    /// Of the base currency
+    amount: Decimal,
+    /// In the quote currency, `price * amount` if not given
+    cost: Option<Decimal>,
+    fee: Option<UnifiedFee>,
+}
+
+#[derive(Debug, Deserialize)]
+struct UnifiedFee {
+    cost: Decimal,
+    currency: String,
+}
+
+impl UnifiedTrade {
+    /// Spot trades only, derivative trades are skipped since they don't exchange the assets
+    fn to_trade_record(&self, exchange: &str) -> color_eyre::Result<Option<TradeRecord>> {
+        if self.symbol.contains(':') {
+            tracing::warn!(
+                "Skipping trade {} of derivative {}",
+                self.id.as_deref().unwrap_or(""),
+                self.symbol
+            );
+            return Ok(None);
+        }
+        let mut parts = self.symbol.splitn(2, '/');
+        let (base, quote) = match (parts.next(), parts.next()) {
+            (Some(base), Some(quote)) => (base, quote),
+            _ => return Err(eyre::eyre!("Invalid CCXT symbol {}", self.symbol)),
+        };
+        let money = |code: &str, amount: Decimal| -> color_eyre::Result<Money<'static>> {
+            let currency = crate::money::lookup(code)?;
+            Ok(Money::from_decimal(
+                amount.round_dp(currency.exponent),
+                currency,
+            ))
+        };
+        let base_amount = money(base, self.amount)?;
+        let quote_amount = money(quote, self.cost.unwrap_or(self.price * self.amount))?;
+        let (kind, buy, sell) = match self.side.as_str() {
+            "buy" => (TradeKind::Buy, base_amount, quote_amount),
+            "sell" => (TradeKind::Sell, quote_amount, base_amount),
+            side => return Err(eyre::eyre!("Invalid CCXT trade side {}", side)),
+        };
+        let date_time = NaiveDateTime::from_timestamp(
+            self.timestamp / 1000,
+            (self.timestamp % 1000 * 1_000_000) as u32,
+        );
+        let mut builder = TradeBuilder::new(date_time, kind, buy, sell)
+            .rate(self.price)
+            .exchange(exchange);
+        if let Some(ref fee) = self.fee {
+            builder = builder.fee(money(&fee.currency, fee.cost)?);
+        }
+        let trade = builder.build()?;
+        let mut record = TradeRecord::from(&trade);
+        record.exchange_id = self.id.clone().unwrap_or_default();
+        Ok(Some(record))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn unified_trades_are_spot_trades() {
+        let json = r#"[
+            { "id": "T1", "timestamp": 1614585600123, "datetime": "2021-03-01T08:00:00.123Z",
+              "symbol": "LTC/GBP", "order": "O1", "type": "limit", "side": "buy",
+              "takerOrMaker": "taker", "price": 150.5, "amount": 2, "cost": 301.0,
+              "fee": { "cost": 0.75, "currency": "GBP" }, "info": {} },
+            { "id": "T2", "timestamp": 1614672000000, "symbol": "ETH/BTC", "side": "sell",
+              "price": "0.03", "amount": "2", "cost": null, "fee": null },
+            { "id": "T3", "timestamp": 1614672000000, "symbol": "BTC/USDT:USDT", "side": "buy",
+              "price": 50000, "amount": 1, "cost": 50000, "fee": null }
+        ]"#;
+        let trades: Vec<UnifiedTrade> = serde_json::from_str(json).unwrap();
+        let records = trades
+            .iter()
+            .filter_map(|trade| trade.to_trade_record("kraken").unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(records.len(), 2, "The derivative trade is skipped");
+        let buy = &records[0];
+        assert_eq!(
+            (
+                buy.kind.as_str(),
+                buy.buy_asset.as_str(),
+                buy.sell_asset.as_str()
+            ),
+            ("Buy", "LTC", "GBP")
+        );
+        assert_eq!(buy.sell_amount, "301.00");
+        assert_eq!(buy.fee_amount, "0.75");
+        assert_eq!(buy.rate, dec!(150.5));
+        assert_eq!(buy.exchange, "kraken");
+        assert_eq!(buy.exchange_id, "T1");
+        assert!(buy.date_time.starts_with("2021-03-01T08:00:00.123"));
+
+        let sell = &records[1];
+        assert_eq!(
+            (
+                sell.kind.as_str(),
+                sell.buy_asset.as_str(),
+                sell.sell_asset.as_str()
+            ),
+            ("Sell", "BTC", "ETH")
+        );
+        assert_eq!(sell.buy_amount, "0.06000000");
+        assert_eq!(sell.fee_asset, "ETH");
+    }
+}
@@ -0,0 +1,215 @@
+use crate::{
+    money::{amount, zero},
+    trades::TradeRecord,
+    transactions::{Movement, Transaction},
+};
+use argh::FromArgs;
+use chrono::{prelude::*, NaiveDate};
+use color_eyre::eyre;
+use hmac::{Hmac, Mac, NewMac};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+/// Import staking rewards and interest as income from the Coinbase API
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "coinbase")]
+pub struct CoinbaseApiCommand {
+    /// the api key, which only needs the wallet:accounts:read and wallet:transactions:read
+    /// permissions
+    #[argh(option)]
+    api_key: String,
+    /// the secret key
+    /// !!! This will appear in your shell history so make sure this API key is restricted to your
+    /// IP address !!!
+    #[argh(option)]
+    secret: String,
+    /// only import rewards on or after this date e.g. 2020-04-06
+    #[argh(option)]
+    since: Option<NaiveDate>,
+    /// only import rewards on or before this date e.g. 2021-04-05
+    #[argh(option)]
+    until: Option<NaiveDate>,
+    /// label the imported transactions with an account e.g. coinbase:personal, for users with
+    /// multiple Coinbase accounts
+    #[argh(option)]
+    account: Option<String>,
+    /// encrypt the output with the passphrase in the TAXC_PASSPHRASE environment variable
+    #[argh(switch)]
+    encrypt: bool,
+}
+
+const API_ENDPOINT: &str = "https://api.coinbase.com";
+/// The date of the version of the API the responses are parsed as
+const API_VERSION: &str = "2021-03-01";
+/// Maximum page size of the accounts and transactions
+const LIMIT: u64 = 100;
+/// The transaction types of rewards: staking, the inflation rewards of Cosmos and Tezos, and the
+/// interest on USDC and DAI
+const REWARD_TYPES: &[&str] = &["staking_reward", "inflation_reward", "interest"];
+
+impl CoinbaseApiCommand {
+    pub fn exec(&self) -> color_eyre::Result<()> {
+        let trade_records = self.fetch()?;
+        crate::encryption::write_stdout(self.encrypt, |out| {
+            crate::utils::write_csv(trade_records, out)
+        })
+    }
+
+    /// Download the rewards of all the accounts, each of which holds one currency
+    pub fn fetch(&self) -> color_eyre::Result<Vec<TradeRecord>> {
+        let accounts: Vec<Account> = self.get_all(&format!("/v2/accounts?limit={}", LIMIT))?;
+        let mut rewards = Vec::new();
+        for account in accounts {
+            let transactions: Vec<CoinbaseTransaction> = self.get_all(&format!(
+                "/v2/accounts/{}/transactions?limit={}",
+                account.id, LIMIT
+            ))?;
+            for tx in transactions {
+                if let Some(reward) = tx.to_reward()? {
+                    rewards.push(reward);
+                }
+            }
+        }
+        rewards.retain(|tx| {
+            let date = tx.date_time().date();
+            self.since.map_or(true, |since| date >= since)
+                && self.until.map_or(true, |until| date <= until)
+        });
+        rewards.sort_by_key(|tx| tx.date_time());
+        tracing::info!("Fetched a total of {} rewards", rewards.len());
+        Ok(rewards
+            .iter()
+            .map(|tx| TradeRecord {
+                account: self.account.clone(),
+                ..tx.into()
+            })
+            .collect())
+    }
+
+    /// GET all the pages of a list e.g. /v2/accounts/:account_id/transactions
+    ///
+    /// [API Docs](https://docs.cloud.coinbase.com/sign-in-with-coinbase/docs/api-transactions)
+    fn get_all<T>(&self, path: &str) -> color_eyre::Result<Vec<T>>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        let mut items = Vec::new();
+        let mut next = Some(path.to_string());
+        while let Some(path) = next {
+            tracing::info!("Fetching {}", path);
+            let page: Page<T> = self.signed_get(&path)?.into_json()?;
+            items.extend(page.data);
+            next = page.pagination.next_uri;
+        }
+        Ok(items)
+    }
+
+    /// Add the HMAC SHA256 signature of the timestamp, method and path to the request headers,
+    /// required by all endpoints for account data.
+    fn signed_get(&self, path: &str) -> color_eyre::Result<ureq::Response> {
+        let timestamp = format!("{}", Utc::now().timestamp());
+
+        let mut signed_key = Hmac::<sha2::Sha256>::new_varkey(self.secret.as_bytes()).unwrap();
+        signed_key.update(timestamp.as_bytes());
+        signed_key.update(b"GET");
+        signed_key.update(path.as_bytes());
+        let signature = hex::encode(signed_key.finalize().into_bytes());
+
+        Ok(ureq::get(&format!("{}{}", API_ENDPOINT, path))
+            .set("CB-ACCESS-KEY", self.api_key.as_str())
+            .set("CB-ACCESS-SIGN", signature.as_str())
+            .set("CB-ACCESS-TIMESTAMP", timestamp.as_str())
+            .set("CB-VERSION", API_VERSION)
+            .call()?)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Page<T> {
+    pagination: Pagination,
+    data: Vec<T>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Pagination {
+    /// The path of the next page, none for the last
+    next_uri: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Account {
+    id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct CoinbaseTransaction {
+    id: String,
+    #[serde(rename = "type")]
+    kind: String,
+    status: String,
+    amount: CoinbaseAmount,
+    /// e.g. 2021-03-01T12:00:00Z
+    created_at: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct CoinbaseAmount {
+    amount: Decimal,
+    currency: String,
+}
+
+impl CoinbaseTransaction {
+    /// The transaction as income if it's a completed reward
+    fn to_reward(&self) -> color_eyre::Result<Option<Transaction<'static>>> {
+        if !REWARD_TYPES.contains(&self.kind.as_str()) || self.status != "completed" {
+            return Ok(None);
+        }
+        let amount = amount(&self.amount.currency, self.amount.amount.abs())?;
+        Ok(Some(Transaction::Income(Movement {
+            date_time: DateTime::parse_from_rfc3339(&self.created_at)?.naive_utc(),
+            fee: zero(amount.currency()),
+            amount,
+            exchange: Some("Coinbase".into()),
+            account: None,
+            ownership_changed: false,
+            notes: Some(format!("{} {}", self.kind, self.id)),
+        })))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transactions::TransactionKind;
+
+    #[test]
+    fn completed_rewards_are_income() {
+        let json = r#"{
+            "pagination": { "ending_before": null, "starting_after": null, "limit": 100, "order": "desc", "previous_uri": null, "next_uri": null },
+            "data": [
+                { "id": "t1", "type": "staking_reward", "status": "completed", "amount": { "amount": "0.00123", "currency": "ETH" }, "native_amount": { "amount": "2.10", "currency": "GBP" }, "created_at": "2021-03-01T12:00:00Z" },
+                { "id": "t2", "type": "buy", "status": "completed", "amount": { "amount": "1.0", "currency": "ETH" }, "native_amount": { "amount": "1200.00", "currency": "GBP" }, "created_at": "2021-03-02T12:00:00Z" },
+                { "id": "t3", "type": "interest", "status": "pending", "amount": { "amount": "0.01", "currency": "USDC" }, "native_amount": { "amount": "0.01", "currency": "GBP" }, "created_at": "2021-03-03T12:00:00Z" }
+            ]
+        }"#;
+        let page: Page<CoinbaseTransaction> = serde_json::from_str(json).unwrap();
+        assert_eq!(page.pagination.next_uri, None);
+        let rewards = page
+            .data
+            .iter()
+            .filter_map(|tx| tx.to_reward().unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(rewards.len(), 1, "Only the completed rewards");
+        assert_eq!(rewards[0].kind(), TransactionKind::Income);
+        let record = TradeRecord::from(&rewards[0]);
+        assert_eq!(record.buy_asset, "ETH");
+        assert_eq!(
+            record.buy_amount.parse::<Decimal>().unwrap(),
+            Decimal::new(123, 5)
+        );
+        assert_eq!(record.date_time, "2021-03-01T12:00:00+00:00");
+        assert_eq!(record.exchange, "Coinbase");
+        assert_eq!(record.notes, "staking_reward t1");
+    }
+}
@@ -0,0 +1,213 @@
+mod api;
+
+pub use self::api::CoinbaseApiCommand;
+
+use chrono::NaiveDateTime;
+use serde::Deserialize;
+use std::{convert::TryFrom, str::FromStr};
+
+use super::ExchangeError;
+use crate::{
+    money::{amount, zero},
+    trades::{Trade, TradeKind},
+    transactions::{Movement, Transaction},
+};
+use rust_decimal::Decimal;
+
+// trade id,product,side,created at,size,size unit,price,fee,total,price/fee/total unit
+// 155157,ETH-GBP,SELL,2018-11-20T21:39:45.667Z,5.41307455,ETH,101.86,1.654127320989,549.721646342011,GBP
+//
+// Later exports have a negative total for BUY rows, the amount paid, while earlier exports have
+// positive totals for both sides. The direction is always taken from the side.
+
+#[derive(Debug, Deserialize, Clone)]
+#[allow(non_snake_case)]
+pub struct Record {
+    #[serde(rename = "trade id")]
+    trade_id: String,
+    product: String,
+    side: String,
+    #[serde(rename = "created at")]
+    created_at: String,
+    size: Decimal,
+    #[serde(rename = "size unit")]
+    size_unit: String,
+    price: Decimal,
+    fee: Decimal,
+    total: Decimal,
+    #[serde(rename = "price/fee/total unit")]
+    unit: String,
+}
+
+impl<'a> TryFrom<Record> for Transaction<'a> {
+    type Error = ExchangeError;
+
+    fn try_from(value: Record) -> Result<Transaction<'a>, Self::Error> {
+        // 2018-11-20T21:39:45.667Z
+        let date_time =
+            NaiveDateTime::parse_from_str(value.created_at.as_ref(), "%Y-%m-%dT%H:%M:%S%.fZ")?;
+
+        let mut market_parts = value.product.split('-');
+        let base_currency = market_parts.next().expect("base currency");
+        let quote_currency = market_parts.next().expect("quote currency");
+
+        let negative = Decimal::new(0, 0);
+        if value.size < negative || value.price < negative || value.fee < negative {
+            return Err("size, price and fee should not be negative".into());
+        }
+        let kind = match value.side.as_ref() {
+            "BUY" => TradeKind::Buy,
+            "SELL" if value.total < negative => {
+                return Err("SELL total should not be negative".into())
+            }
+            "SELL" => TradeKind::Sell,
+            _ => return Err("side should be BUY or SELL".into()),
+        };
+
+        let base_amount = amount(base_currency, value.size)?;
+        let quote_amount = amount(quote_currency, value.total.abs())?;
+        let (sell, buy) = match kind {
+            TradeKind::Buy => (quote_amount, base_amount),
+            TradeKind::Sell => (base_amount, quote_amount),
+        };
+        let fee = amount(value.unit.as_ref(), value.fee)?;
+
+        Ok(Transaction::Trade(Trade {
+            date_time,
+            kind,
+            buy,
+            sell,
+            fee,
+            rate: value.price,
+            exchange: Some("Coinbase Pro".into()),
+            account: None,
+            notes: None,
+        }))
+    }
+}
+
+// Timestamp,Transaction Type,Asset,Quantity Transacted,Spot Price Currency,Spot Price at Transaction,Subtotal,Total (inclusive of fees and/or spread),Fees and/or Spread,Notes
+// 2021-04-01T10:00:00Z,Buy,BTC,0.01,GBP,40000.00,400.00,405.99,5.99,Bought 0.01 BTC for £405.99 GBP
+
+/// A row of the retail Coinbase transaction history report, as opposed to the Coinbase Pro fills
+/// of `Record`. The lines of the report before the header row should be removed first.
+///
+/// Buys, sells and conversions are trades with the fees in the spot price currency, rewards and
+/// staking are income, and sends and receives are transfers. Conversions only have the amount
+/// received in the notes e.g. `Converted 0.01 BTC to 0.15 ETH`. Later reports have an ID column,
+/// negative quantities for outflows and the currency symbol in the amounts, which are all
+/// accepted.
+#[derive(Debug, Deserialize, Clone)]
+pub struct HistoryRecord {
+    #[serde(rename = "Timestamp")]
+    timestamp: String,
+    #[serde(rename = "Transaction Type")]
+    kind: String,
+    #[serde(rename = "Asset")]
+    asset: String,
+    #[serde(rename = "Quantity Transacted")]
+    quantity: String,
+    #[serde(rename = "Spot Price Currency")]
+    spot_currency: String,
+    #[serde(rename = "Spot Price at Transaction")]
+    spot_price: String,
+    #[serde(
+        rename = "Total (inclusive of fees and/or spread)",
+        alias = "Total (inclusive of fees)"
+    )]
+    total: String,
+    #[serde(rename = "Fees and/or Spread", alias = "Fees")]
+    fees: String,
+    #[serde(rename = "Notes", default)]
+    notes: String,
+}
+
+impl<'a> TryFrom<HistoryRecord> for Transaction<'a> {
+    type Error = ExchangeError;
+
+    fn try_from(value: HistoryRecord) -> Result<Transaction<'a>, Self::Error> {
+        // 2021-04-01T10:00:00Z or 2023-04-01 10:00:00 UTC
+        let date_time = NaiveDateTime::parse_from_str(&value.timestamp, "%Y-%m-%dT%H:%M:%SZ")
+            .or_else(|_| {
+                NaiveDateTime::parse_from_str(&value.timestamp, "%Y-%m-%d %H:%M:%S UTC")
+            })?;
+        let quantity = amount(&value.asset, parse_decimal(&value.quantity)?.abs())?;
+        let total = amount(&value.spot_currency, parse_decimal(&value.total)?.abs())?;
+        let fee = amount(&value.spot_currency, parse_decimal(&value.fees)?.abs())?;
+        let rate = parse_decimal(&value.spot_price)?;
+        let trade = |kind, buy, sell, rate| -> Result<Transaction<'a>, ExchangeError> {
+            Ok(Transaction::Trade(Trade {
+                date_time,
+                kind,
+                buy,
+                sell,
+                fee: fee.clone(),
+                rate,
+                exchange: Some("Coinbase".into()),
+                account: None,
+                notes: None,
+            }))
+        };
+        let movement = Movement {
+            date_time,
+            fee: zero(quantity.currency()),
+            amount: quantity.clone(),
+            exchange: Some("Coinbase".into()),
+            account: None,
+            ownership_changed: false,
+            notes: Some(if value.notes.is_empty() {
+                value.kind.clone()
+            } else {
+                value.notes.clone()
+            }),
+        };
+
+        match value.kind.as_ref() {
+            "Buy" | "Advanced Trade Buy" => trade(TradeKind::Buy, quantity, total, rate),
+            "Sell" | "Advanced Trade Sell" => trade(TradeKind::Sell, total, quantity, rate),
+            "Convert" => {
+                let (code, received) = converted_to(&value.notes)?;
+                let received = amount(&code, received)?;
+                let rate = received
+                    .amount()
+                    .checked_div(*quantity.amount())
+                    .unwrap_or_default();
+                trade(TradeKind::Sell, received, quantity, rate)
+            }
+            "Rewards Income" | "Coinbase Earn" | "Learning Reward" | "Staking Income"
+            | "Inflation Reward" => Ok(Transaction::Income(movement)),
+            "Receive" | "Deposit" => Ok(Transaction::Deposit(movement)),
+            "Send" | "Withdrawal" => Ok(Transaction::Withdrawal(movement)),
+            _ => Err(ExchangeError::InvalidRecord(
+                "Unsupported transaction type, expected a buy, sell, convert, reward, send or \
+                 receive",
+            )),
+        }
+    }
+}
+
+/// A decimal without the currency symbol or thousands separators, and zero if empty
+fn parse_decimal(value: &str) -> Result<Decimal, ExchangeError> {
+    let digits = value
+        .chars()
+        .filter(|c| c.is_ascii_digit() || *c == '.' || *c == '-')
+        .collect::<String>();
+    if digits.is_empty() {
+        return Ok(Decimal::new(0, 0));
+    }
+    Ok(Decimal::from_str(&digits)?)
+}
+
+/// The currency and amount received by a conversion, from notes such as
+/// `Converted 0.01 BTC to 0.15 ETH`
+fn converted_to(notes: &str) -> Result<(String, Decimal), ExchangeError> {
+    let words = notes.split_whitespace().collect::<Vec<_>>();
+    match words.as_slice() {
+        ["Converted", _, _, "to", received, code, ..] => {
+            Ok((code.to_string(), parse_decimal(received)?))
+        }
+        _ => Err(ExchangeError::InvalidRecord(
+            "Convert notes should be e.g. Converted 0.01 BTC to 0.15 ETH",
+        )),
+    }
+}
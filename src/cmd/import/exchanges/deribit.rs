@@ -0,0 +1,132 @@
+use chrono::NaiveDateTime;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::convert::TryFrom;
+
+use super::ExchangeError;
+use crate::{
+    money::amount,
+    transactions::{Movement, Transaction},
+};
+
+/// A row of the Deribit transaction log of a BTC or ETH account.
+///
+/// Options and futures are cash settled in the currency of the account, so the cash flow of a
+/// row is the realised profit or loss: the premium received or paid for an option trade, and the
+/// gain or loss when a position is settled or delivered. Cash received is income and cash paid
+/// is an expense, with notes starting with `Option premium`, `Option settlement` or
+/// `Futures settlement` which the derivatives report categorises by. Futures trades don't move
+/// any cash until settled, so only their fee is imported, and options expiring worthless are a
+/// fee of nothing.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Record {
+    // "ID","UserSeq","Date","Instrument","Type","Side","Size","Position","Price","Mark Price",
+    // "Index Price","Cash Flow","Funding","Fee Rate","Fee Charged","Fee Balance","Change",
+    // "Balance","Equity","Trade ID","Order ID","Currency","Note","Info"
+    #[serde(rename = "ID")]
+    id: String,
+    #[serde(rename = "Date")]
+    date: String,
+    /// e.g. `BTC-25JUN21-40000-C`, `BTC-PERPETUAL` or empty for transfers
+    #[serde(rename = "Instrument")]
+    instrument: String,
+    /// e.g. `trade`, `settlement`, `delivery`, `deposit` or `withdrawal`
+    #[serde(rename = "Type")]
+    kind: String,
+    /// e.g. `open buy` or `close sell`
+    #[serde(rename = "Side", default)]
+    side: String,
+    #[serde(rename = "Size", default)]
+    size: Option<Decimal>,
+    #[serde(rename = "Cash Flow")]
+    cash_flow: Decimal,
+    /// Negative for maker rebates
+    #[serde(rename = "Fee Charged", default)]
+    fee: Option<Decimal>,
+    #[serde(rename = "Change")]
+    change: Decimal,
+    /// Older exports don't have the currency, so it's taken from the instrument
+    #[serde(rename = "Currency", default)]
+    currency: String,
+}
+
+impl Record {
+    fn currency(&self) -> Result<String, ExchangeError> {
+        if !self.currency.is_empty() {
+            return Ok(self.currency.to_uppercase());
+        }
+        match self.instrument.split('-').next() {
+            Some(code) if !code.is_empty() => Ok(code.to_uppercase()),
+            _ => Err(ExchangeError::InvalidRecord(
+                "Deribit row without a currency or instrument",
+            )),
+        }
+    }
+
+    /// Options are named with their expiry, strike and C or P for a call or put
+    fn is_option(&self) -> bool {
+        self.instrument.ends_with("-C") || self.instrument.ends_with("-P")
+    }
+}
+
+impl<'a> TryFrom<Record> for Transaction<'a> {
+    type Error = ExchangeError;
+
+    fn try_from(value: Record) -> Result<Transaction<'a>, Self::Error> {
+        let date_time = NaiveDateTime::parse_from_str(&value.date, "%Y-%m-%d %H:%M:%S")?;
+        let currency = value.currency()?;
+        // maker rebates are cash received, with no fee paid
+        let fee = value.fee.unwrap_or_default();
+        let (fee, rebate) = if fee < Decimal::new(0, 0) {
+            (Decimal::new(0, 0), fee.abs())
+        } else {
+            (fee, Decimal::new(0, 0))
+        };
+        let movement = |amount_value: Decimal, fee_value: Decimal, notes: String| Movement {
+            date_time,
+            amount: amount(&currency, amount_value)?,
+            fee: amount(&currency, fee_value)?,
+            exchange: Some("Deribit".into()),
+            account: None,
+            ownership_changed: false,
+            notes: Some(format!("{}; Id {}", notes, value.id)),
+        };
+
+        match value.kind.as_ref() {
+            "deposit" => Ok(Transaction::Deposit(movement(
+                value.change,
+                Decimal::new(0, 0),
+                "Deposit".into(),
+            ))),
+            "withdrawal" => Ok(Transaction::Withdrawal(movement(
+                value.change.abs() - fee,
+                fee,
+                "Withdrawal".into(),
+            ))),
+            "trade" | "settlement" | "delivery" => {
+                let description = match (value.kind.as_ref(), value.is_option()) {
+                    ("trade", true) => format!(
+                        "Option premium {} {} {}",
+                        value.side,
+                        value.size.unwrap_or_default(),
+                        value.instrument
+                    ),
+                    (_, true) => format!("Option settlement {}", value.instrument),
+                    ("trade", false) => format!("Futures trade {}", value.instrument),
+                    (_, false) => format!("Futures settlement {}", value.instrument),
+                };
+                let cash = value.cash_flow + rebate;
+                if cash > Decimal::new(0, 0) {
+                    Ok(Transaction::Income(movement(cash, fee, description)))
+                } else if cash < Decimal::new(0, 0) {
+                    Ok(Transaction::Expense(movement(cash.abs(), fee, description)))
+                } else {
+                    Ok(Transaction::Fee(movement(fee, Decimal::new(0, 0), description)))
+                }
+            }
+            _ => Err(ExchangeError::InvalidRecord(
+                "Unsupported type, only trades, settlements, deliveries and transfers are supported",
+            )),
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use crate::{
+    money::{currencies::ETH, zero},
+    trades::TradeRecord,
+    transactions::{Movement, Transaction},
+    Money,
+};
+use argh::FromArgs;
+use chrono::NaiveDateTime;
+use color_eyre::eyre;
+use rust_decimal::Decimal;
+use serde::{de, Deserialize};
+use std::{fmt, io::Read, str::FromStr};
+
+/// Import mining payouts from the Ethermine API as income
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "ethermine")]
+pub struct EthermineApiCommand {
+    /// the address of the miner which receives the payouts
+    #[argh(option)]
+    address: String,
+    /// label the imported transactions with an account e.g. ethermine:rig2, for users with
+    /// multiple miners
+    #[argh(option)]
+    account: Option<String>,
+    /// encrypt the output with the passphrase in the TAXC_PASSPHRASE environment variable
+    #[argh(switch)]
+    encrypt: bool,
+}
+
+const API_ENDPOINT: &str = "https://api.ethermine.org";
+/// The decimal places of an amount of ETH in wei
+const WEI_DECIMALS: u32 = 18;
+
+impl EthermineApiCommand {
+    pub fn exec(&self) -> color_eyre::Result<()> {
+        let trade_records = self.fetch()?;
+        crate::encryption::write_stdout(self.encrypt, |out| {
+            crate::utils::write_csv(trade_records, out)
+        })
+    }
+
+    /// Download the payouts as income
+    ///
+    /// GET /miner/:miner/payouts
+    ///
+    /// [API Docs](https://api.ethermine.org/docs/#/Miner/miner_payouts)
+    pub fn fetch(&self) -> color_eyre::Result<Vec<TradeRecord>> {
+        tracing::info!("Fetching payouts for {}", self.address);
+        let url = format!("{}/miner/{}/payouts", API_ENDPOINT, self.address);
+        let response = ureq::get(&url).call()?.into_reader();
+        Ok(read_payouts(response, self.account.as_deref())?
+            .iter()
+            .map(TradeRecord::from)
+            .collect())
+    }
+}
+
+/// Read a response of the payouts endpoint as income, labelled with the account if given
+pub fn read_payouts<R: Read>(
+    reader: R,
+    account: Option<&str>,
+) -> color_eyre::Result<Vec<Transaction<'static>>> {
+    let response: PayoutsResponse = serde_json::from_reader(reader)?;
+    if response.status != "OK" {
+        return Err(eyre::eyre!("Ethermine API error: {}", response.status));
+    }
+    tracing::info!("Fetched {} payouts", response.data.len());
+    Ok(response
+        .data
+        .iter()
+        .map(|payout| payout.to_income(account))
+        .collect())
+}
+
+#[derive(Debug, Deserialize)]
+struct PayoutsResponse {
+    status: String,
+    data: Vec<Payout>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Payout {
+    /// Unix timestamp in seconds
+    paid_on: i64,
+    /// Amount in ETH, paid in wei
+    #[serde(deserialize_with = "eth_from_wei")]
+    amount: Decimal,
+    tx_hash: String,
+}
+
+impl Payout {
+    fn to_income(&self, account: Option<&str>) -> Transaction<'static> {
+        Transaction::Income(Movement {
+            date_time: NaiveDateTime::from_timestamp(self.paid_on, 0),
+            amount: Money::from_decimal(self.amount, ETH),
+            fee: zero(ETH),
+            exchange: Some("Ethermine".into()),
+            account: account.map(String::from),
+            ownership_changed: false,
+            notes: Some(format!("Mining payout {}", self.tx_hash)),
+        })
+    }
+}
+
+/// Deserialize an amount of wei as ETH, from either a JSON integer or a string of one. A float
+/// can't hold every amount of wei exactly, so JSON numbers too large for a `u64`, which are read
+/// as floats, are errors rather than rounded.
+fn eth_from_wei<'de, D>(deserializer: D) -> Result<Decimal, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    struct WeiVisitor;
+
+    impl<'de> de::Visitor<'de> for WeiVisitor {
+        type Value = Decimal;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "an integer amount of wei, as a number or a string")
+        }
+
+        fn visit_u64<E: de::Error>(self, wei: u64) -> Result<Decimal, E> {
+            self.visit_str(&wei.to_string())
+        }
+
+        fn visit_u128<E: de::Error>(self, wei: u128) -> Result<Decimal, E> {
+            self.visit_str(&wei.to_string())
+        }
+
+        fn visit_str<E: de::Error>(self, wei: &str) -> Result<Decimal, E> {
+            if wei.is_empty() || !wei.chars().all(|c| c.is_ascii_digit()) {
+                return Err(E::invalid_value(de::Unexpected::Str(wei), &self));
+            }
+            let mut eth = Decimal::from_str(wei).map_err(E::custom)?;
+            eth.set_scale(WEI_DECIMALS).map_err(E::custom)?;
+            Ok(eth)
+        }
+    }
+
+    deserializer.deserialize_any(WeiVisitor)
+}
@@ -0,0 +1,119 @@
+use chrono::NaiveDateTime;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::convert::TryFrom;
+
+use super::ExchangeError;
+use crate::{
+    addresses,
+    money::{amount, currencies::ETH, zero},
+    transactions::{Movement, Transaction},
+};
+
+/// A row of the Etherscan csv export of the transactions of an address.
+///
+/// Failed transactions don't move any value but still spend the gas, so they are imported as a
+/// fee (a disposal of ETH with nothing acquired) flagged by notes starting with `Failed`, which the
+/// gas report categorises by.
+///
+/// Transfers to and from addresses in the address book are labelled, and those with addresses
+/// which aren't the user's own are flagged as changing ownership e.g. payments and gifts.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Record {
+    // "Transaction Hash","Blockno","UnixTimestamp","DateTime (UTC)","From","To","ContractAddress",
+    // "Value_IN(ETH)","Value_OUT(ETH)","CurrentValue","TxnFee(ETH)","TxnFee(USD)",
+    // "Historical $Price/Eth","Status","ErrCode","Method"
+    #[serde(rename = "Transaction Hash", alias = "Txhash")]
+    hash: String,
+    #[serde(rename = "UnixTimestamp")]
+    timestamp: i64,
+    #[serde(rename = "From")]
+    from: String,
+    #[serde(rename = "To")]
+    to: String,
+    #[serde(rename = "Value_IN(ETH)")]
+    value_in: Decimal,
+    #[serde(rename = "Value_OUT(ETH)")]
+    value_out: Decimal,
+    #[serde(rename = "TxnFee(ETH)")]
+    fee: Decimal,
+    /// Empty for successful transactions, otherwise e.g. `Error(0)`
+    #[serde(rename = "Status", default)]
+    status: String,
+    /// Why the transaction failed e.g. `Out of gas` or `Reverted`
+    #[serde(rename = "ErrCode", default)]
+    err_code: String,
+    /// The contract method called e.g. `Transfer` or `Approve`
+    #[serde(rename = "Method", default)]
+    method: String,
+}
+
+impl Record {
+    fn is_failed(&self) -> bool {
+        self.status.starts_with("Error") || self.err_code != ""
+    }
+}
+
+impl<'a> TryFrom<Record> for Transaction<'a> {
+    type Error = ExchangeError;
+
+    fn try_from(value: Record) -> Result<Transaction<'a>, Self::Error> {
+        // the counterparty is the sender of incoming transfers, otherwise the recipient or contract
+        let (direction, counterparty) = if value.value_in > Decimal::new(0, 0) {
+            ("from", &value.from)
+        } else {
+            ("to", &value.to)
+        };
+        let known = addresses::find(counterparty);
+        let method = if value.method == "" {
+            "Transfer".to_string()
+        } else {
+            value.method.clone()
+        };
+        let notes = match known {
+            Some(entry) => format!(
+                "{} {} {}; Tx {}",
+                method, direction, entry.label, value.hash
+            ),
+            None => format!("{}; Tx {}", method, value.hash),
+        };
+        let changes_ownership = known.map_or(false, |entry| !entry.owned);
+        let movement = |amount, fee, notes, ownership_changed| Movement {
+            date_time: NaiveDateTime::from_timestamp(value.timestamp, 0),
+            amount,
+            fee,
+            exchange: Some("Ethereum".into()),
+            account: None,
+            ownership_changed,
+            notes: Some(notes),
+        };
+        let gas = amount("ETH", value.fee)?;
+
+        if value.is_failed() {
+            tracing::warn!(
+                "Transaction {} failed with {}, only the gas is imported",
+                value.hash,
+                value.err_code
+            );
+            let notes = format!("Failed: {}", notes);
+            Ok(Transaction::Fee(movement(gas, zero(ETH), notes, false)))
+        } else if value.value_in > Decimal::new(0, 0) {
+            // the gas of incoming transfers is paid by the sender
+            Ok(Transaction::Deposit(movement(
+                amount("ETH", value.value_in)?,
+                zero(ETH),
+                notes,
+                changes_ownership,
+            )))
+        } else if value.value_out > Decimal::new(0, 0) {
+            Ok(Transaction::Withdrawal(movement(
+                amount("ETH", value.value_out)?,
+                gas,
+                notes,
+                changes_ownership,
+            )))
+        } else {
+            Ok(Transaction::Fee(movement(gas, zero(ETH), notes, false)))
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use crate::{
+    money::{amount, zero},
+    trades::TradeRecord,
+    transactions::{Movement, Transaction},
+};
+use argh::FromArgs;
+use chrono::{prelude::*, NaiveDate};
+use color_eyre::eyre;
+use hmac::{Hmac, Mac, NewMac};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+/// Import staking and earn rewards as income from the Gemini API
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "gemini")]
+pub struct GeminiApiCommand {
+    /// the api key, which only needs the auditor role
+    #[argh(option)]
+    api_key: String,
+    /// the secret key
+    /// !!! This will appear in your shell history so make sure this API key is restricted to your
+    /// IP address !!!
+    #[argh(option)]
+    secret: String,
+    /// only fetch rewards on or after this date e.g. 2020-04-06
+    #[argh(option)]
+    since: NaiveDate,
+    /// only fetch rewards on or before this date e.g. 2021-04-05
+    #[argh(option)]
+    until: Option<NaiveDate>,
+    /// label the imported transactions with an account e.g. gemini:personal, for users with
+    /// multiple Gemini accounts
+    #[argh(option)]
+    account: Option<String>,
+    /// encrypt the output with the passphrase in the TAXC_PASSPHRASE environment variable
+    #[argh(switch)]
+    encrypt: bool,
+}
+
+const API_ENDPOINT: &str = "https://api.gemini.com";
+/// The histories of the staking and the Earn programs, which have the same format
+const HISTORY_PATHS: &[&str] = &["/v1/staking/history", "/v1/earn/history"];
+/// Maximum page size of the histories
+const LIMIT: usize = 500;
+
+impl GeminiApiCommand {
+    pub fn exec(&self) -> color_eyre::Result<()> {
+        let trade_records = self.fetch()?;
+        crate::encryption::write_stdout(self.encrypt, |out| {
+            crate::utils::write_csv(trade_records, out)
+        })
+    }
+
+    /// Download the staking and Earn rewards
+    pub fn fetch(&self) -> color_eyre::Result<Vec<TradeRecord>> {
+        let mut rewards = Vec::new();
+        for path in HISTORY_PATHS {
+            for tx in self.get_history(path)? {
+                if let Some(reward) = tx.to_reward()? {
+                    rewards.push(reward);
+                }
+            }
+        }
+        rewards.sort_by_key(|tx| tx.date_time());
+        tracing::info!("Fetched a total of {} rewards", rewards.len());
+        Ok(rewards
+            .iter()
+            .map(|tx| TradeRecord {
+                account: self.account.clone(),
+                ..tx.into()
+            })
+            .collect())
+    }
+
+    /// Download the transactions of all the providers between the `since` and `until` dates, a
+    /// page at a time from the oldest
+    fn get_history(&self, path: &str) -> color_eyre::Result<Vec<GeminiTransaction>> {
+        let mut since = self.since.and_hms(0, 0, 0).timestamp_millis();
+        let until = self.until.map_or(Utc::now().timestamp_millis(), |date| {
+            date.succ().and_hms(0, 0, 0).timestamp_millis() - 1
+        });
+        let mut transactions = Vec::new();
+        loop {
+            let page = self
+                .fetch_history(path, since, until)?
+                .into_iter()
+                .flat_map(|provider| provider.transactions)
+                .collect::<Vec<_>>();
+            let last_page = page.len() < LIMIT;
+            if let Some(latest) = page.iter().map(|tx| tx.date_time).max() {
+                since = latest + 1;
+            }
+            transactions.extend(page);
+            if last_page {
+                break;
+            }
+        }
+        Ok(transactions)
+    }
+
+    /// POST /v1/staking/history or /v1/earn/history
+    ///
+    /// [API Docs](https://docs.gemini.com/rest-api/#staking-history)
+    ///
+    /// Get a page of the transactions of each provider, the oldest first.
+    fn fetch_history(
+        &self,
+        path: &str,
+        since: i64,
+        until: i64,
+    ) -> color_eyre::Result<Vec<ProviderHistory>> {
+        tracing::info!("Fetching {} from {}", path, since);
+        let payload = serde_json::json!({
+            "request": path,
+            "nonce": Utc::now().timestamp_millis(),
+            "since": since,
+            "until": until,
+            "limit": LIMIT,
+            "sortAsc": true,
+        });
+        Ok(self.signed_post(path, &payload)?.into_json()?)
+    }
+
+    /// Add the base64 encoded payload and its HMAC SHA384 signature to the request headers,
+    /// required by all private endpoints. The request has no body.
+    fn signed_post(
+        &self,
+        path: &str,
+        payload: &serde_json::Value,
+    ) -> color_eyre::Result<ureq::Response> {
+        let payload = base64::encode(payload.to_string());
+
+        let mut signed_key = Hmac::<sha2::Sha384>::new_varkey(self.secret.as_bytes()).unwrap();
+        signed_key.update(payload.as_bytes());
+        let signature = hex::encode(signed_key.finalize().into_bytes());
+
+        Ok(ureq::post(&format!("{}{}", API_ENDPOINT, path))
+            .set("Content-Type", "text/plain")
+            .set("Cache-Control", "no-cache")
+            .set("X-GEMINI-APIKEY", self.api_key.as_str())
+            .set("X-GEMINI-PAYLOAD", payload.as_str())
+            .set("X-GEMINI-SIGNATURE", signature.as_str())
+            .call()?)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ProviderHistory {
+    transactions: Vec<GeminiTransaction>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiTransaction {
+    transaction_id: String,
+    /// e.g. Deposit, Redeem or Interest
+    transaction_type: String,
+    amount_currency: String,
+    amount: Decimal,
+    /// Timestamp in milliseconds
+    date_time: i64,
+}
+
+impl GeminiTransaction {
+    /// The transaction as income if it's a reward, the deposits to and redemptions from the
+    /// programs being transfers
+    fn to_reward(&self) -> color_eyre::Result<Option<Transaction<'static>>> {
+        if self.transaction_type != "Interest" {
+            return Ok(None);
+        }
+        let amount = amount(&self.amount_currency, self.amount.abs())?;
+        let millis = self.date_time;
+        Ok(Some(Transaction::Income(Movement {
+            date_time: NaiveDateTime::from_timestamp(
+                millis / 1000,
+                (millis % 1000 * 1_000_000) as u32,
+            ),
+            fee: zero(amount.currency()),
+            amount,
+            exchange: Some("Gemini".into()),
+            account: None,
+            ownership_changed: false,
+            notes: Some(self.transaction_id.clone()),
+        })))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transactions::TransactionKind;
+
+    #[test]
+    fn interest_is_income() {
+        let json = r#"[
+            {
+                "providerId": "62b21e17-2534-4b9f-afcf-b7edb609dd8d",
+                "transactions": [
+                    { "transactionId": "65QN4XM5", "transactionType": "Deposit", "amountCurrency": "ETH", "amount": 1, "priceCurrency": "GBP", "priceAmount": 1200, "dateTime": 1614589200000 },
+                    { "transactionId": "YTZ3UPV5", "transactionType": "Interest", "amountCurrency": "ETH", "amount": 0.0001, "priceCurrency": "GBP", "priceAmount": 0.12, "dateTime": 1614675600123 },
+                    { "transactionId": "4U2Z9VQH", "transactionType": "Redeem", "amountCurrency": "ETH", "amount": 1, "priceCurrency": "GBP", "priceAmount": 1300, "dateTime": 1614762000000 }
+                ]
+            }
+        ]"#;
+        let history: Vec<ProviderHistory> = serde_json::from_str(json).unwrap();
+        let rewards = history
+            .iter()
+            .flat_map(|provider| provider.transactions.iter())
+            .filter_map(|tx| tx.to_reward().unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(rewards.len(), 1, "Deposits and redemptions are transfers");
+        assert_eq!(rewards[0].kind(), TransactionKind::Income);
+        let record = TradeRecord::from(&rewards[0]);
+        assert_eq!(record.buy_asset, "ETH");
+        assert_eq!(
+            record.buy_amount.parse::<Decimal>().unwrap(),
+            Decimal::new(1, 4)
+        );
+        assert_eq!(record.date_time, "2021-03-02T09:00:00.123+00:00");
+        assert_eq!(record.exchange, "Gemini");
+        assert_eq!(record.notes, "YTZ3UPV5");
+    }
+}
@@ -0,0 +1,244 @@
+use super::LedgerRecord;
+use crate::{trades::TradeRecord, transactions::Transaction};
+use argh::FromArgs;
+use chrono::{prelude::*, NaiveDate};
+use color_eyre::eyre;
+use hmac::{Hmac, Mac, NewMac};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use sha2::{Digest, Sha256, Sha512};
+use std::{collections::BTreeMap, convert::TryFrom};
+
+/// Import staking and earn rewards as income from the Kraken API
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "kraken")]
+pub struct KrakenApiCommand {
+    /// the api key, which only needs the permission to query the ledger
+    #[argh(option)]
+    api_key: String,
+    /// the private key, base64 encoded as Kraken shows it
+    /// !!! This will appear in your shell history so make sure this API key is restricted to your
+    /// IP address !!!
+    #[argh(option)]
+    secret: String,
+    /// only fetch rewards on or after this date e.g. 2020-04-06
+    #[argh(option)]
+    since: NaiveDate,
+    /// only fetch rewards on or before this date e.g. 2021-04-05
+    #[argh(option)]
+    until: Option<NaiveDate>,
+    /// label the imported transactions with an account e.g. kraken:personal, for users with
+    /// multiple Kraken accounts
+    #[argh(option)]
+    account: Option<String>,
+    /// encrypt the output with the passphrase in the TAXC_PASSPHRASE environment variable
+    #[argh(switch)]
+    encrypt: bool,
+}
+
+const API_ENDPOINT: &str = "https://api.kraken.com";
+const LEDGERS_PATH: &str = "/0/private/Ledgers";
+/// The ledger types with rewards, the older staking rewards, and the earn rewards which are
+/// alongside the allocations to and from the earn wallet
+const REWARD_TYPES: &[&str] = &["staking", "earn"];
+
+impl KrakenApiCommand {
+    pub fn exec(&self) -> color_eyre::Result<()> {
+        let trade_records = self.fetch()?;
+        crate::encryption::write_stdout(self.encrypt, |out| {
+            crate::utils::write_csv(trade_records, out)
+        })
+    }
+
+    /// Download the staking and earn rewards
+    pub fn fetch(&self) -> color_eyre::Result<Vec<TradeRecord>> {
+        let mut rewards = Vec::new();
+        for kind in REWARD_TYPES {
+            rewards.extend(self.get_ledger(kind)?);
+        }
+        rewards.sort_by_key(|tx| tx.date_time());
+        tracing::info!("Fetched a total of {} rewards", rewards.len());
+        Ok(rewards
+            .iter()
+            .map(|tx| TradeRecord {
+                account: self.account.clone(),
+                ..tx.into()
+            })
+            .collect())
+    }
+
+    /// Download the rewards in the ledger entries of the type, a page at a time until all of
+    /// those between the `since` and `until` dates have been fetched
+    fn get_ledger(&self, kind: &str) -> color_eyre::Result<Vec<Transaction<'static>>> {
+        let start = self.since.and_hms(0, 0, 0).timestamp();
+        let end = self.until.map_or(Utc::now().timestamp(), |date| {
+            date.succ().and_hms(0, 0, 0).timestamp() - 1
+        });
+        let mut rewards = Vec::new();
+        let mut offset = 0;
+        loop {
+            let ledger = self.fetch_ledgers(kind, start, end, offset)?;
+            let count = ledger.count;
+            offset += ledger.ledger.len();
+            let last_page = ledger.ledger.is_empty() || offset >= count;
+            rewards.extend(ledger.rewards()?);
+            if last_page {
+                break;
+            }
+        }
+        Ok(rewards)
+    }
+
+    /// POST /0/private/Ledgers
+    ///
+    /// [API Docs](https://docs.kraken.com/rest/#operation/getLedgers)
+    ///
+    /// Get a page of the ledger entries of the type, the most recent first.
+    fn fetch_ledgers(
+        &self,
+        kind: &str,
+        start: i64,
+        end: i64,
+        offset: usize,
+    ) -> color_eyre::Result<Ledger> {
+        tracing::info!("Fetching {} ledger entries from {}", kind, offset);
+        let nonce = format!("{}", Utc::now().timestamp_millis());
+        let body = url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("nonce", &nonce)
+            .append_pair("type", kind)
+            .append_pair("start", &format!("{}", start))
+            .append_pair("end", &format!("{}", end))
+            .append_pair("ofs", &format!("{}", offset))
+            .finish();
+        let response: Response = self.signed_post(LEDGERS_PATH, &nonce, &body)?.into_json()?;
+        if !response.error.is_empty() {
+            return Err(eyre::eyre!(
+                "Kraken API error: {}",
+                response.error.join(", ")
+            ));
+        }
+        response
+            .result
+            .ok_or(eyre::eyre!("No result in the Kraken response"))
+    }
+
+    /// Sign the request with the HMAC SHA512 of the path and the SHA256 of the nonce and body,
+    /// keyed by the decoded secret, required by all private endpoints.
+    fn signed_post(
+        &self,
+        path: &str,
+        nonce: &str,
+        body: &str,
+    ) -> color_eyre::Result<ureq::Response> {
+        let mut hashed_body = Sha256::new();
+        hashed_body.update(nonce.as_bytes());
+        hashed_body.update(body.as_bytes());
+
+        let secret = base64::decode(&self.secret)?;
+        let mut signed_key = Hmac::<Sha512>::new_varkey(&secret).unwrap();
+        signed_key.update(path.as_bytes());
+        signed_key.update(&hashed_body.finalize());
+        let signature = base64::encode(signed_key.finalize().into_bytes());
+
+        Ok(ureq::post(&format!("{}{}", API_ENDPOINT, path))
+            .set("API-Key", self.api_key.as_str())
+            .set("API-Sign", signature.as_str())
+            .set("Content-Type", "application/x-www-form-urlencoded")
+            .send_string(body)?)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Response {
+    error: Vec<String>,
+    result: Option<Ledger>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Ledger {
+    /// The entries by their ledger id
+    ledger: BTreeMap<String, LedgerEntry>,
+    /// The number of entries of the query, across all the pages
+    count: usize,
+}
+
+#[derive(Debug, Deserialize)]
+struct LedgerEntry {
+    refid: String,
+    /// Unix time in seconds, with a fraction
+    time: f64,
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    subtype: String,
+    asset: String,
+    amount: Decimal,
+    fee: Decimal,
+}
+
+impl Ledger {
+    /// The rewards in the entries as income, the same as the rows of the ledgers.csv export. The
+    /// allocations to and from the earn wallet are transfers, so they're left out.
+    fn rewards(self) -> color_eyre::Result<Vec<Transaction<'static>>> {
+        let mut rewards = Vec::new();
+        for (_, entry) in self.ledger {
+            let millis = (entry.time * 1000.0) as i64;
+            let date_time =
+                NaiveDateTime::from_timestamp(millis / 1000, (millis % 1000 * 1_000_000) as u32);
+            let record = LedgerRecord {
+                refid: entry.refid,
+                time: date_time.format("%Y-%m-%d %H:%M:%S%.f").to_string(),
+                kind: entry.kind,
+                subtype: entry.subtype,
+                asset: entry.asset,
+                amount: entry.amount,
+                fee: entry.fee,
+            };
+            if let tx @ Transaction::Income(_) = Transaction::try_from(record)? {
+                rewards.push(tx);
+            }
+        }
+        Ok(rewards)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transactions::TransactionKind;
+
+    #[test]
+    fn staking_and_earn_rewards_are_income() {
+        let json = r#"{
+            "error": [],
+            "result": {
+                "ledger": {
+                    "L1-AAAAA-AAAAAA": { "refid": "R1-AAAAA-AAAAAA", "time": 1614589200.1234, "type": "staking", "subtype": "", "aclass": "currency", "asset": "DOT.S", "amount": "0.0512", "fee": "0.0000", "balance": "10.0512" },
+                    "L2-BBBBB-BBBBBB": { "refid": "R2-BBBBB-BBBBBB", "time": 1614675600.0, "type": "earn", "subtype": "allocation", "aclass": "currency", "asset": "XXBT", "amount": "-0.1", "fee": "0.0000", "balance": "0.0" },
+                    "L3-CCCCC-CCCCCC": { "refid": "R3-CCCCC-CCCCCC", "time": 1614762000.0, "type": "earn", "subtype": "reward", "aclass": "currency", "asset": "XBT.M", "amount": "0.00001", "fee": "0.0000", "balance": "0.10001" }
+                },
+                "count": 3
+            }
+        }"#;
+        let response: Response = serde_json::from_str(json).unwrap();
+        let mut rewards = response.result.unwrap().rewards().unwrap();
+        rewards.sort_by_key(|tx| tx.date_time());
+
+        let records = rewards.iter().map(TradeRecord::from).collect::<Vec<_>>();
+        assert_eq!(
+            rewards.iter().map(|tx| tx.kind()).collect::<Vec<_>>(),
+            vec![TransactionKind::Income, TransactionKind::Income]
+        );
+        assert_eq!(records[0].buy_asset, "DOT");
+        assert_eq!(
+            records[0].buy_amount.parse::<Decimal>().unwrap(),
+            Decimal::new(512, 4)
+        );
+        assert_eq!(records[0].date_time, "2021-03-01T09:00:00.123+00:00");
+        assert_eq!(records[0].exchange, "Kraken");
+        assert_eq!(
+            (records[1].buy_asset.as_str(), records[1].notes.as_str()),
+            ("BTC", "R3-CCCCC-CCCCCC")
+        );
+    }
+}
@@ -0,0 +1,201 @@
+mod api;
+
+pub use self::api::KrakenApiCommand;
+
+use chrono::NaiveDateTime;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::convert::TryFrom;
+
+use super::ExchangeError;
+use crate::{
+    money::amount,
+    trades::{Trade, TradeKind},
+    transactions::{Movement, Transaction},
+};
+
+// txid,ordertxid,pair,time,type,ordertype,price,cost,fee,vol,margin,misc,ledgers
+// TXID1-AAAAA-BBBBBB,OXID1-CCCCC-DDDDDD,XXBTZGBP,2021-03-01 12:00:00.1234,buy,limit,35000.0,3500.0,5.6,0.1,0.0,,"L1,L2"
+
+/// A row of the Kraken trades.csv export.
+///
+/// The cost is in the quote currency of the pair before the fee, which is also charged in the
+/// quote currency, so a buy sells the cost plus the fee and a sell buys the cost less the fee.
+#[derive(Debug, Deserialize, Clone)]
+pub struct Record {
+    txid: String,
+    /// e.g. `XXBTZGBP`, `DOTGBP` or `XBT/GBP` in later exports
+    pair: String,
+    time: String,
+    #[serde(rename = "type")]
+    side: String,
+    price: Decimal,
+    cost: Decimal,
+    fee: Decimal,
+    vol: Decimal,
+}
+
+impl<'a> TryFrom<Record> for Transaction<'a> {
+    type Error = ExchangeError;
+
+    fn try_from(value: Record) -> Result<Transaction<'a>, Self::Error> {
+        let date_time = parse_time(&value.time)?;
+        let (base, quote) = split_pair(&value.pair).ok_or(ExchangeError::InvalidRecord(
+            "Kraken pair with an unknown quote",
+        ))?;
+
+        let (kind, sell, buy) = match value.side.as_ref() {
+            "buy" => (
+                TradeKind::Buy,
+                amount(&quote, value.cost + value.fee)?,
+                amount(&base, value.vol)?,
+            ),
+            "sell" => (
+                TradeKind::Sell,
+                amount(&base, value.vol)?,
+                amount(&quote, value.cost - value.fee)?,
+            ),
+            _ => return Err(ExchangeError::InvalidRecord("type should be buy or sell")),
+        };
+
+        Ok(Transaction::Trade(Trade {
+            date_time,
+            kind,
+            buy,
+            sell,
+            fee: amount(&quote, value.fee)?,
+            rate: value.price,
+            exchange: Some("Kraken".into()),
+            account: None,
+            notes: Some(value.txid),
+        }))
+    }
+}
+
+// "txid","refid","time","type","subtype","aclass","asset","amount","fee","balance"
+// "L1-AAAAA-BBBBBB","R1-CCCCC-DDDDDD","2021-03-01 09:00:00","deposit","","currency","ZGBP",5000.0000,0.0000,5000.0000
+
+/// A row of the Kraken ledgers.csv export, for the deposits, withdrawals and staking rewards
+/// which aren't in trades.csv.
+///
+/// Moving funds between the spot and staking or earn wallets is a withdrawal from one and a
+/// deposit to the other, since the ownership doesn't change. The trade, spend and receive rows
+/// are the legs of trades which are imported from trades.csv, so they should be filtered out of
+/// the export first.
+#[derive(Debug, Deserialize, Clone)]
+pub struct LedgerRecord {
+    refid: String,
+    time: String,
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    subtype: String,
+    /// e.g. `XXBT`, `ZGBP`, `DOT` or `DOT.S` for the staked balance
+    asset: String,
+    /// Negative for withdrawals, before the fee
+    amount: Decimal,
+    fee: Decimal,
+}
+
+impl<'a> TryFrom<LedgerRecord> for Transaction<'a> {
+    type Error = ExchangeError;
+
+    fn try_from(value: LedgerRecord) -> Result<Transaction<'a>, Self::Error> {
+        let date_time = parse_time(&value.time)?;
+        let currency = asset_code(&value.asset);
+        let movement = Movement {
+            date_time,
+            amount: amount(&currency, value.amount.abs())?,
+            fee: amount(&currency, value.fee)?,
+            exchange: Some("Kraken".into()),
+            account: None,
+            ownership_changed: false,
+            notes: Some(value.refid.clone()),
+        };
+        let inflow = value.amount.is_sign_positive();
+        match (value.kind.as_ref(), value.subtype.as_ref()) {
+            ("staking", _) | ("earn", "reward") | ("dividend", _) => {
+                Ok(Transaction::Income(movement))
+            }
+            ("deposit", _) => Ok(Transaction::Deposit(movement)),
+            ("withdrawal", _) => Ok(Transaction::Withdrawal(movement)),
+            ("transfer", _) | ("earn", _) if inflow => Ok(Transaction::Deposit(movement)),
+            ("transfer", _) | ("earn", _) => Ok(Transaction::Withdrawal(movement)),
+            _ => Err(ExchangeError::InvalidRecord(
+                "Unsupported ledger type, trades should be imported from trades.csv",
+            )),
+        }
+    }
+}
+
+fn parse_time(time: &str) -> Result<NaiveDateTime, ExchangeError> {
+    Ok(NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M:%S%.f")?)
+}
+
+/// Assets listed before Kraken dropped the prefixes, X for crypto and Z for fiat
+const PREFIXED: &[&str] = &[
+    "XXBT", "XETH", "XETC", "XLTC", "XXRP", "XXLM", "XXMR", "XZEC", "XREP", "XMLN", "XXDG", "ZGBP",
+    "ZEUR", "ZUSD", "ZCAD", "ZJPY", "ZAUD", "ZCHF",
+];
+
+/// Quote currencies of the pairs without a separator, longest first so that `USDT` isn't split
+/// as `USD`
+const QUOTES: &[&str] = &[
+    "USDT", "USDC", "ZGBP", "ZEUR", "ZUSD", "ZCAD", "ZJPY", "XXBT", "XETH", "GBP", "EUR", "USD",
+    "CAD", "JPY", "AUD", "CHF", "DAI", "XBT", "ETH", "DOT",
+];
+
+/// The currency code of a Kraken asset, without the legacy prefix or the suffix of the staked
+/// or opt in rewards balances, and with Kraken's own codes for Bitcoin and Dogecoin
+fn asset_code(asset: &str) -> String {
+    let asset = asset.split('.').next().unwrap_or(asset);
+    let asset = if PREFIXED.contains(&asset) {
+        &asset[1..]
+    } else {
+        asset
+    };
+    match asset {
+        "XBT" => "BTC",
+        "XDG" => "DOGE",
+        "ETH2" => "ETH",
+        code => code,
+    }
+    .to_string()
+}
+
+/// Split a pair into the base and quote currency codes
+fn split_pair(pair: &str) -> Option<(String, String)> {
+    let mut parts = pair.split('/');
+    if let (Some(base), Some(quote)) = (parts.next(), parts.next()) {
+        return Some((asset_code(base), asset_code(quote)));
+    }
+    if pair.len() == 8 && PREFIXED.contains(&&pair[..4]) && PREFIXED.contains(&&pair[4..]) {
+        return Some((asset_code(&pair[..4]), asset_code(&pair[4..])));
+    }
+    QUOTES.iter().find_map(|quote| {
+        let base = pair.strip_suffix(quote)?;
+        if base.is_empty() {
+            None
+        } else {
+            Some((asset_code(base), asset_code(quote)))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pairs_are_split_with_and_without_legacy_prefixes() {
+        let split = |pair| split_pair(pair).unwrap();
+        assert_eq!(split("XXBTZGBP"), ("BTC".into(), "GBP".into()));
+        assert_eq!(split("XETHXXBT"), ("ETH".into(), "BTC".into()));
+        assert_eq!(split("DOTGBP"), ("DOT".into(), "GBP".into()));
+        assert_eq!(split("XBTUSDT"), ("BTC".into(), "USDT".into()));
+        assert_eq!(split("USDTZUSD"), ("USDT".into(), "USD".into()));
+        assert_eq!(split("XBT/GBP"), ("BTC".into(), "GBP".into()));
+        assert_eq!(asset_code("DOT.S"), "DOT");
+        assert_eq!(asset_code("XXDG"), "DOGE");
+    }
+}
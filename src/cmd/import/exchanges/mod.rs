@@ -1,8 +1,24 @@
 pub mod binance;
 pub mod bittrex;
+pub mod bybit;
+pub mod ccxt;
 pub mod coinbase;
+pub mod deribit;
+pub mod ethermine;
+pub mod etherscan;
+pub mod gemini;
+pub mod kraken;
+pub mod monero;
+pub mod nicehash;
+pub mod otc;
 pub mod poloniex;
+pub mod subgraph;
 pub mod uphold;
+pub mod utxo;
+pub mod zcash;
+
+#[cfg(test)]
+mod tests;
 
 #[derive(Debug, derive_more::From, derive_more::Display)]
 pub enum ExchangeError {
@@ -10,6 +26,8 @@ pub enum ExchangeError {
     DateParse(chrono::format::ParseError),
     InvalidRecord(&'static str),
     DecimalError(rust_decimal::Error),
+    InvalidTrade(crate::trades::TradeError),
+    UnknownCurrency(crate::money::UnknownCurrency),
 }
 
 impl std::error::Error for ExchangeError {}
@@ -0,0 +1,57 @@
+use chrono::NaiveDateTime;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::convert::TryFrom;
+
+use super::ExchangeError;
+use crate::{
+    money::amount,
+    transactions::{Movement, Transaction},
+};
+
+/// A row of the Monero GUI wallet transaction export. Amounts are only visible to the wallet, or
+/// a view key scan in the same format, so incoming transfers are deposits until recategorised
+/// e.g. as mining income.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Record {
+    // blockHeight,epoch,date,direction,amount,atomicAmount,fee,txid,label,subaddrAccount,paymentId,description
+    /// Seconds since the unix epoch
+    epoch: i64,
+    /// in or out
+    direction: String,
+    amount: Decimal,
+    #[serde(default)]
+    fee: Option<Decimal>,
+    txid: String,
+    #[serde(default)]
+    description: String,
+}
+
+impl<'a> TryFrom<Record> for Transaction<'a> {
+    type Error = ExchangeError;
+
+    fn try_from(value: Record) -> Result<Transaction<'a>, Self::Error> {
+        let date_time = NaiveDateTime::from_timestamp(value.epoch, 0);
+        let movement = Movement {
+            date_time,
+            amount: amount("XMR", value.amount.abs())?,
+            fee: amount("XMR", value.fee.unwrap_or_default().abs())?,
+            exchange: Some("Monero".into()),
+            account: None,
+            ownership_changed: false,
+            notes: if value.description == "" {
+                Some(format!("Tx {}", value.txid))
+            } else {
+                Some(format!("{}; Tx {}", value.description, value.txid))
+            },
+        };
+        match value.direction.to_lowercase().as_ref() {
+            "in" => Ok(Transaction::Deposit(movement)),
+            "out" => Ok(Transaction::Withdrawal(movement)),
+            _ => Err(ExchangeError::InvalidRecord(
+                "Direction should be either in or out",
+            )),
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use chrono::NaiveDateTime;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::convert::TryFrom;
+
+use super::ExchangeError;
+use crate::{
+    money::{amount, zero},
+    transactions::{Movement, Transaction},
+};
+
+/// A row of a NiceHash wallet report for BTC
+#[derive(Clone, Debug, Deserialize)]
+pub struct Record {
+    // Date time,Purpose,Amount (BTC)
+    #[serde(rename = "Date time")]
+    date_time: String,
+    #[serde(rename = "Purpose")]
+    purpose: String,
+    #[serde(rename = "Amount (BTC)")]
+    amount: Decimal,
+}
+
+impl<'a> TryFrom<Record> for Transaction<'a> {
+    type Error = ExchangeError;
+
+    fn try_from(value: Record) -> Result<Transaction<'a>, Self::Error> {
+        let date_time = NaiveDateTime::parse_from_str(
+            value.date_time.trim_end_matches(" GMT"),
+            "%Y-%m-%d %H:%M:%S",
+        )?;
+        let amount = amount("BTC", value.amount.abs())?;
+        let movement = Movement {
+            date_time,
+            fee: zero(amount.currency()),
+            amount,
+            exchange: Some("NiceHash".into()),
+            account: None,
+            ownership_changed: false,
+            notes: Some(value.purpose.clone()),
+        };
+        let purpose = value.purpose.to_lowercase();
+        if purpose.contains("fee") {
+            Ok(Transaction::Fee(movement))
+        } else if purpose.contains("mining") {
+            Ok(Transaction::Income(movement))
+        } else if purpose.contains("withdrawal") {
+            Ok(Transaction::Withdrawal(movement))
+        } else if purpose.contains("deposit") {
+            Ok(Transaction::Deposit(movement))
+        } else {
+            Err(ExchangeError::InvalidRecord(
+                "Unsupported purpose, only mining payouts, fees and transfers are supported",
+            ))
+        }
+    }
+}
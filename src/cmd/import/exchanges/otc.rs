@@ -0,0 +1,85 @@
+use chrono::{DateTime, NaiveDateTime};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::convert::TryFrom;
+
+use super::ExchangeError;
+use crate::{
+    money::{amount, find},
+    trades::{TradeBuilder, TradeKind},
+    transactions::Transaction,
+};
+
+/// A trade made directly with a counterparty, over the counter or peer to peer, which won't
+/// appear in any exchange export.
+///
+/// date_time,side,asset,amount,quote_asset,quote_amount,fee_asset,fee_amount,counterparty,settlement_reference
+#[derive(Clone, Debug, Deserialize)]
+pub struct Record {
+    /// e.g. 2021-01-31 14:00:00 or 2021-01-31T14:00:00+00:00
+    pub date_time: String,
+    /// buy or sell of the asset
+    pub side: String,
+    pub asset: String,
+    pub amount: Decimal,
+    /// the currency paid or received for the asset
+    pub quote_asset: String,
+    pub quote_amount: Decimal,
+    #[serde(default)]
+    pub fee_asset: String,
+    #[serde(default)]
+    pub fee_amount: Option<Decimal>,
+    #[serde(default)]
+    pub counterparty: String,
+    /// e.g. a bank transfer or transaction hash
+    #[serde(default)]
+    pub settlement_reference: String,
+}
+
+impl<'a> TryFrom<Record> for Transaction<'a> {
+    type Error = ExchangeError;
+
+    fn try_from(value: Record) -> Result<Transaction<'a>, Self::Error> {
+        if find(&value.asset).is_none() || find(&value.quote_asset).is_none() {
+            return Err("Both asset and quote asset should be known currencies".into());
+        }
+        if value.amount.is_zero() {
+            return Err("Amount should not be zero".into());
+        }
+
+        let date_time = DateTime::parse_from_rfc3339(&value.date_time)
+            .map(|dt| dt.naive_utc())
+            .or_else(|_| NaiveDateTime::parse_from_str(&value.date_time, "%Y-%m-%d %H:%M:%S"))?;
+
+        let base = amount(&value.asset, value.amount)?;
+        let quote = amount(&value.quote_asset, value.quote_amount)?;
+        let (kind, buy, sell) = match value.side.to_lowercase().as_ref() {
+            "buy" => (TradeKind::Buy, base, quote),
+            "sell" => (TradeKind::Sell, quote, base),
+            _ => return Err("Side should be either buy or sell".into()),
+        };
+        let fee = match value.fee_amount {
+            Some(fee_amount) if value.fee_asset != "" => amount(&value.fee_asset, fee_amount)?,
+            _ => amount(&value.quote_asset, Decimal::new(0, 0))?,
+        };
+
+        let notes = [
+            ("Counterparty", &value.counterparty),
+            ("Settlement", &value.settlement_reference),
+        ]
+        .iter()
+        .filter(|(_, v)| *v != "")
+        .map(|(k, v)| format!("{}: {}", k, v))
+        .collect::<Vec<_>>()
+        .join("; ");
+
+        let mut trade = TradeBuilder::new(date_time, kind, buy, sell)
+            .fee(fee)
+            .rate(value.quote_amount / value.amount)
+            .exchange("OTC");
+        if notes != "" {
+            trade = trade.notes(notes);
+        }
+        Ok(Transaction::Trade(trade.build()?))
+    }
+}
@@ -5,6 +5,7 @@ use std::convert::TryFrom;
 use crate::{
     money::amount,
     trades::{Trade, TradeKind},
+    transactions::Transaction,
 };
 use rust_decimal::Decimal;
 
@@ -31,10 +32,10 @@ pub struct Record {
     quote_total_less_fee: Decimal,
 }
 
-impl<'a> TryFrom<Record> for Trade<'a> {
+impl<'a> TryFrom<Record> for Transaction<'a> {
     type Error = super::ExchangeError;
 
-    fn try_from(value: Record) -> Result<Trade<'a>, Self::Error> {
+    fn try_from(value: Record) -> Result<Transaction<'a>, Self::Error> {
         let date_time =
             NaiveDateTime::parse_from_str(value.date.as_ref(), "%Y-%m-%d %H:%M:%S").unwrap();
 
@@ -46,22 +47,22 @@ impl<'a> TryFrom<Record> for Trade<'a> {
 
         let (kind, sell, buy, fee) = match value.order_type.as_ref() {
             "Buy" => {
-                let buy = amount(base_currency, value.amount);
-                let sell = amount(quote_currency, value.total);
+                let buy = amount(base_currency, value.amount)?;
+                let sell = amount(quote_currency, value.total)?;
                 let fee_base = value.amount - value.quote_total_less_fee;
-                let fee = amount(quote_currency, fee_base * value.price);
+                let fee = amount(quote_currency, fee_base * value.price)?;
                 (TradeKind::Buy, sell, buy, fee)
             }
             "Sell" => {
-                let buy = amount(quote_currency, value.base_total_less_fee);
-                let sell = amount(base_currency, value.amount);
-                let fee = amount(quote_currency, value.total - value.base_total_less_fee);
+                let buy = amount(quote_currency, value.base_total_less_fee)?;
+                let sell = amount(base_currency, value.amount)?;
+                let fee = amount(quote_currency, value.total - value.base_total_less_fee)?;
                 (TradeKind::Sell, sell, buy, fee)
             }
             _ => panic!("Invalid order_type {}", value.order_type),
         };
 
-        Ok(Trade {
+        Ok(Transaction::Trade(Trade {
             date_time,
             kind,
             buy,
@@ -69,6 +70,8 @@ impl<'a> TryFrom<Record> for Trade<'a> {
             fee,
             rate: value.price,
             exchange: Some("Poloniex".into()),
-        })
+            account: None,
+            notes: None,
+        }))
     }
 }
@@ -0,0 +1,201 @@
+//! Aave v2 lending and borrowing. Deposits accrue interest, so the amount redeemed over that
+//! deposited is interest earned, and likewise the amount repaid over that borrowed is interest paid.
+
+use super::{currency, fetch_all, scale, Entity};
+use crate::{
+    money::zero,
+    transactions::{Movement, Transaction},
+    Money,
+};
+use chrono::NaiveDateTime;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+pub const ENDPOINT: &str = "https://api.thegraph.com/subgraphs/name/aave/protocol-v2";
+
+const FIELDS: &str = "id amount timestamp reserve { symbol decimals }";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Action {
+    Deposit,
+    Redeem,
+    Borrow,
+    Repay,
+}
+
+impl Action {
+    /// The entities of the action in the subgraph
+    fn entities(&self) -> &'static str {
+        match self {
+            Action::Deposit => "deposits",
+            Action::Redeem => "redeemUnderlyings",
+            Action::Borrow => "borrows",
+            Action::Repay => "repays",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Event {
+    /// The transaction hash and log index
+    id: String,
+    /// The amount of the token, unscaled
+    amount: String,
+    /// Unix timestamp in seconds
+    timestamp: i64,
+    reserve: Reserve,
+}
+
+impl Entity for Event {
+    fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Reserve {
+    symbol: String,
+    decimals: u32,
+}
+
+pub fn fetch_transactions<'a>(
+    endpoint: &str,
+    owner: &str,
+) -> color_eyre::Result<Vec<Transaction<'a>>> {
+    let mut events = Vec::new();
+    for action in &[
+        Action::Deposit,
+        Action::Redeem,
+        Action::Borrow,
+        Action::Repay,
+    ] {
+        let query = format!(
+            "query($owner: String!, $first: Int!, $lastId: ID!) {{ \
+             {}(first: $first, orderBy: id, where: {{ user: $owner, id_gt: $lastId }}) {{ {} }} }}",
+            action.entities(),
+            FIELDS
+        );
+        let fetched: Vec<Event> = fetch_all(endpoint, &query, action.entities(), owner)?;
+        events.extend(fetched.into_iter().map(|event| (*action, event)));
+    }
+    to_transactions(events)
+}
+
+/// Deposits are collateral withdrawn from the wallet, returned as a deposit when redeemed, and
+/// borrows are loans deposited in the wallet, withdrawn when repaid. Interest is the amount
+/// redeemed or repaid over the principal outstanding in the reserve.
+fn to_transactions<'a>(
+    mut events: Vec<(Action, Event)>,
+) -> color_eyre::Result<Vec<Transaction<'a>>> {
+    events.sort_by_key(|(_, event)| event.timestamp);
+
+    let mut supplied: HashMap<String, Decimal> = HashMap::new();
+    let mut borrowed: HashMap<String, Decimal> = HashMap::new();
+    let mut transactions = Vec::new();
+    for (action, event) in events {
+        let currency = currency(&event.reserve.symbol)?;
+        let value = scale(&event.amount, event.reserve.decimals)?.round_dp(currency.exponent);
+        let tx_hash = event.id.split(':').next().unwrap_or_default();
+        let movement = |value, description: &str| Movement {
+            date_time: NaiveDateTime::from_timestamp(event.timestamp, 0),
+            amount: Money::from_decimal(value, currency),
+            fee: zero(currency),
+            exchange: Some("Ethereum".into()),
+            account: None,
+            ownership_changed: false,
+            notes: Some(format!("Aave v2 {}; Tx {}", description, tx_hash)),
+        };
+        let principal = match action {
+            Action::Deposit | Action::Redeem => &mut supplied,
+            Action::Borrow | Action::Repay => &mut borrowed,
+        };
+        let outstanding = principal
+            .entry(event.reserve.symbol.clone())
+            .or_insert(Decimal::new(0, 0));
+        match action {
+            Action::Deposit => {
+                *outstanding += value;
+                transactions.push(Transaction::Withdrawal(movement(value, "deposit")));
+            }
+            Action::Borrow => {
+                *outstanding += value;
+                transactions.push(Transaction::Deposit(movement(value, "borrow")));
+            }
+            Action::Redeem | Action::Repay => {
+                let returned = value.min(*outstanding);
+                let interest = value - returned;
+                *outstanding -= returned;
+                if action == Action::Redeem {
+                    if returned > Decimal::new(0, 0) {
+                        transactions.push(Transaction::Deposit(movement(returned, "redeem")));
+                    }
+                    if interest > Decimal::new(0, 0) {
+                        transactions.push(Transaction::Income(movement(interest, "interest")));
+                    }
+                } else {
+                    if returned > Decimal::new(0, 0) {
+                        transactions.push(Transaction::Withdrawal(movement(returned, "repay")));
+                    }
+                    if interest > Decimal::new(0, 0) {
+                        transactions
+                            .push(Transaction::Expense(movement(interest, "loan interest")));
+                    }
+                }
+            }
+        }
+    }
+    Ok(transactions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::money::currencies::USDC;
+    use rust_decimal_macros::dec;
+
+    fn event(action: Action, amount: &str, timestamp: i64) -> (Action, Event) {
+        let json = serde_json::json!({
+            "id": format!("0x{}:1", timestamp),
+            "amount": amount,
+            "timestamp": timestamp,
+            "reserve": { "symbol": "USDC", "decimals": 6 },
+        });
+        (action, serde_json::from_value(json).unwrap())
+    }
+
+    #[test]
+    fn interest_is_the_amount_over_the_principal() {
+        let events = vec![
+            event(Action::Redeem, "1020000000", 1625097600),
+            event(Action::Deposit, "1000000000", 1609459200),
+            event(Action::Borrow, "500000000", 1612137600),
+            event(Action::Repay, "505000000", 1622505600),
+        ];
+
+        let transactions = to_transactions(events).unwrap();
+        let amounts = transactions
+            .iter()
+            .map(|tx| match tx {
+                Transaction::Withdrawal(m) => ("withdrawal", m.amount.clone()),
+                Transaction::Deposit(m) => ("deposit", m.amount.clone()),
+                Transaction::Income(m) => ("income", m.amount.clone()),
+                Transaction::Expense(m) => ("expense", m.amount.clone()),
+                _ => panic!("Expected a movement of the position"),
+            })
+            .collect::<Vec<_>>();
+        let usdc = |value| Money::from_decimal(value, USDC);
+
+        assert_eq!(
+            amounts,
+            vec![
+                ("withdrawal", usdc(dec!(1000))),
+                ("deposit", usdc(dec!(500))),
+                ("withdrawal", usdc(dec!(500))),
+                ("expense", usdc(dec!(5))),
+                ("deposit", usdc(dec!(1000))),
+                ("income", usdc(dec!(20))),
+            ]
+        );
+    }
+}
@@ -0,0 +1,176 @@
+//! Positions in DeFi protocols, fetched from their subgraphs on [The Graph](https://thegraph.com).
+//! The lifecycle events of a position are imported as the movements in and out of it, with the
+//! fees and interest it earned as income, rather than guessing from the token transfers.
+
+mod aave;
+mod uniswap;
+
+use crate::trades::TradeRecord;
+use argh::FromArgs;
+use color_eyre::eyre;
+use rust_decimal::Decimal;
+use serde::{de::DeserializeOwned, Deserialize};
+use std::{collections::HashMap, str::FromStr};
+
+/// Import liquidity and lending positions from the subgraph of a DeFi protocol
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "subgraph")]
+pub struct SubgraphApiCommand {
+    /// the protocol of the positions: uniswap-v3 or aave-v2
+    #[argh(option)]
+    protocol: Protocol,
+    /// the address owning the positions
+    #[argh(option)]
+    address: String,
+    /// the url of the subgraph to query instead of the default for the protocol e.g. a
+    /// deployment on another chain
+    #[argh(option)]
+    endpoint: Option<String>,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, derive_more::Display)]
+pub enum Protocol {
+    #[display(fmt = "Uniswap v3")]
+    UniswapV3,
+    #[display(fmt = "Aave v2")]
+    AaveV2,
+}
+
+impl std::str::FromStr for Protocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_ref() {
+            "uniswap-v3" => Ok(Self::UniswapV3),
+            "aave-v2" => Ok(Self::AaveV2),
+            _ => Err(format!("Unsupported protocol {}", s)),
+        }
+    }
+}
+
+impl SubgraphApiCommand {
+    pub fn exec(&self) -> color_eyre::Result<()> {
+        crate::utils::write_csv(self.fetch()?, std::io::stdout())
+    }
+
+    /// Download the transactions of the positions of the address
+    pub fn fetch(&self) -> color_eyre::Result<Vec<TradeRecord>> {
+        // addresses are stored lowercase by the subgraphs
+        let address = self.address.to_lowercase();
+        let transactions = match self.protocol {
+            Protocol::UniswapV3 => {
+                let endpoint = self.endpoint.as_deref().unwrap_or(uniswap::ENDPOINT);
+                uniswap::fetch_transactions(endpoint, &address)?
+            }
+            Protocol::AaveV2 => {
+                let endpoint = self.endpoint.as_deref().unwrap_or(aave::ENDPOINT);
+                aave::fetch_transactions(endpoint, &address)?
+            }
+        };
+        tracing::info!(
+            "Imported {} {} transactions",
+            transactions.len(),
+            self.protocol
+        );
+        Ok(transactions.iter().map(TradeRecord::from).collect())
+    }
+}
+
+/// The most entities a subgraph returns for a query
+const PAGE_SIZE: usize = 1000;
+
+#[derive(Debug, Deserialize)]
+struct GraphQlResponse<T> {
+    data: Option<T>,
+    errors: Option<Vec<GraphQlError>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphQlError {
+    message: String,
+}
+
+/// An entity of a subgraph, paged through by its id
+trait Entity: DeserializeOwned {
+    fn id(&self) -> &str;
+}
+
+/// POST a query for the entities of the owner, paging through them by id. The query takes the
+/// `$owner`, `$first` and `$lastId` variables.
+fn fetch_all<T: Entity>(
+    endpoint: &str,
+    query: &str,
+    entities: &str,
+    owner: &str,
+) -> color_eyre::Result<Vec<T>> {
+    let mut all = Vec::new();
+    let mut last_id = String::new();
+    loop {
+        tracing::debug!("Fetching {} of {} after '{}'", entities, owner, last_id);
+        let body = serde_json::json!({
+            "query": query,
+            "variables": { "owner": owner, "first": PAGE_SIZE, "lastId": last_id },
+        });
+        let response: GraphQlResponse<HashMap<String, Vec<T>>> =
+            ureq::post(endpoint).send_json(body)?.into_json()?;
+        if let Some(errors) = response.errors {
+            let messages = errors
+                .into_iter()
+                .map(|error| error.message)
+                .collect::<Vec<_>>();
+            return Err(eyre::eyre!("Subgraph error: {}", messages.join(", ")));
+        }
+        let page = response
+            .data
+            .and_then(|mut data| data.remove(entities))
+            .ok_or(eyre::eyre!("No {} in the subgraph response", entities))?;
+        let len = page.len();
+        if let Some(last) = page.last() {
+            last_id = last.id().to_string();
+        }
+        all.extend(page);
+        if len < PAGE_SIZE {
+            break;
+        }
+    }
+    tracing::info!("Fetched {} {}", all.len(), entities);
+    Ok(all)
+}
+
+/// Parses a subgraph BigDecimal, which may have more decimal places than a Decimal can hold
+fn parse_decimal(value: &str) -> color_eyre::Result<Decimal> {
+    let value = match value.find('.') {
+        Some(point) => &value[..value.len().min(point + 19)],
+        None => value,
+    };
+    Ok(Decimal::from_str(value)?)
+}
+
+/// Scales an integer token amount e.g. a subgraph BigInt by the decimals of the token
+pub(crate) fn scale(raw: &str, decimals: u32) -> color_eyre::Result<Decimal> {
+    let decimals = decimals as usize;
+    let padded = format!("{:0>width$}", raw, width = decimals + 1);
+    let (units, fraction) = padded.split_at(padded.len() - decimals);
+    parse_decimal(&format!("{}.{}", units, fraction))
+}
+
+/// The currency of a token, which must be built in or added with `currencies add`
+fn currency(symbol: &str) -> color_eyre::Result<&'static crate::money::currencies::Currency> {
+    Ok(crate::money::lookup(symbol)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn token_amounts_are_scaled_and_truncated() {
+        assert_eq!(scale("1500000", 6).unwrap(), dec!(1.5));
+        assert_eq!(scale("42", 18).unwrap(), dec!(0.000000000000000042));
+        assert_eq!(
+            parse_decimal("0.1234567890123456789012345").unwrap(),
+            dec!(0.123456789012345678)
+        );
+    }
+}
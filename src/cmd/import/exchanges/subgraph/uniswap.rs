@@ -0,0 +1,254 @@
+//! Uniswap v3 liquidity positions. The subgraph snapshots a position at each mint, burn and
+//! collect with the running totals of the tokens deposited, withdrawn and collected, so each event
+//! is the change from the previous snapshot of the position.
+//!
+//! Burning liquidity doesn't transfer the tokens, it only makes them owed to the position, and a
+//! collect transfers both the tokens owed and the fees earned. So a collect is split into the
+//! principal returned and the fees, which are income valued when collected. The running total of
+//! fees the subgraph keeps is not used as it has included principal in some of its versions.
+
+use super::{currency, fetch_all, parse_decimal, Entity};
+use crate::{
+    money::zero,
+    transactions::{Movement, Transaction},
+    Money,
+};
+use chrono::NaiveDateTime;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+pub const ENDPOINT: &str = "https://api.thegraph.com/subgraphs/name/uniswap/uniswap-v3";
+
+const QUERY: &str = r#"
+query($owner: Bytes!, $first: Int!, $lastId: ID!) {
+  positionSnapshots(first: $first, orderBy: id, where: { owner: $owner, id_gt: $lastId }) {
+    id
+    timestamp
+    transaction { id }
+    position { id pool { token0 { symbol } token1 { symbol } } }
+    depositedToken0
+    depositedToken1
+    withdrawnToken0
+    withdrawnToken1
+    collectedToken0
+    collectedToken1
+  }
+}"#;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PositionSnapshot {
+    id: String,
+    /// Unix timestamp in seconds, as a BigInt string
+    timestamp: String,
+    transaction: TransactionId,
+    position: Position,
+    deposited_token0: String,
+    deposited_token1: String,
+    withdrawn_token0: String,
+    withdrawn_token1: String,
+    collected_token0: String,
+    collected_token1: String,
+}
+
+impl Entity for PositionSnapshot {
+    fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TransactionId {
+    id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Position {
+    id: String,
+    pool: Pool,
+}
+
+#[derive(Debug, Deserialize)]
+struct Pool {
+    token0: Token,
+    token1: Token,
+}
+
+#[derive(Debug, Deserialize)]
+struct Token {
+    symbol: String,
+}
+
+/// The running totals of a position for one of its tokens, and the tokens burned but not yet
+/// collected
+#[derive(Clone, Copy, Default)]
+struct Totals {
+    deposited: Decimal,
+    withdrawn: Decimal,
+    collected: Decimal,
+    owed: Decimal,
+}
+
+pub fn fetch_transactions<'a>(
+    endpoint: &str,
+    owner: &str,
+) -> color_eyre::Result<Vec<Transaction<'a>>> {
+    let snapshots = fetch_all(endpoint, QUERY, "positionSnapshots", owner)?;
+    to_transactions(snapshots)
+}
+
+/// Liquidity added to a position is withdrawn from the wallet and returned as a deposit when
+/// collected, and the fees collected over the liquidity burned are income.
+fn to_transactions<'a>(
+    mut snapshots: Vec<PositionSnapshot>,
+) -> color_eyre::Result<Vec<Transaction<'a>>> {
+    let timestamp = |snapshot: &PositionSnapshot| snapshot.timestamp.parse::<i64>();
+    snapshots.sort_by_key(|snapshot| timestamp(snapshot).unwrap_or_default());
+
+    let mut totals: HashMap<(String, usize), Totals> = HashMap::new();
+    let mut transactions = Vec::new();
+    for snapshot in snapshots {
+        let date_time = NaiveDateTime::from_timestamp(timestamp(&snapshot)?, 0);
+        let pool = &snapshot.position.pool;
+        let tokens = [
+            (
+                &pool.token0,
+                &snapshot.deposited_token0,
+                &snapshot.withdrawn_token0,
+                &snapshot.collected_token0,
+            ),
+            (
+                &pool.token1,
+                &snapshot.deposited_token1,
+                &snapshot.withdrawn_token1,
+                &snapshot.collected_token1,
+            ),
+        ];
+        for (index, (token, deposited, withdrawn, collected)) in tokens.iter().enumerate() {
+            let currency = currency(&token.symbol)?;
+            let movement = |value: Decimal, event: &str| Movement {
+                date_time,
+                amount: Money::from_decimal(value.round_dp(currency.exponent), currency),
+                fee: zero(currency),
+                exchange: Some("Ethereum".into()),
+                account: None,
+                ownership_changed: false,
+                notes: Some(format!(
+                    "Uniswap v3 position #{} {}; Tx {}",
+                    snapshot.position.id, event, snapshot.transaction.id
+                )),
+            };
+            let key = (snapshot.position.id.clone(), index);
+            let previous = totals.get(&key).cloned().unwrap_or_default();
+            let mut current = Totals {
+                deposited: parse_decimal(deposited)?,
+                withdrawn: parse_decimal(withdrawn)?,
+                collected: parse_decimal(collected)?,
+                owed: previous.owed,
+            };
+
+            let minted = current.deposited - previous.deposited;
+            if minted > Decimal::new(0, 0) {
+                transactions.push(Transaction::Withdrawal(movement(minted, "mint")));
+            }
+            let burned = current.withdrawn - previous.withdrawn;
+            if burned > Decimal::new(0, 0) {
+                tracing::debug!(
+                    "Position #{} burned {} {}, owed until collected",
+                    snapshot.position.id,
+                    burned,
+                    token.symbol
+                );
+                current.owed += burned;
+            }
+            let collected = current.collected - previous.collected;
+            if collected > Decimal::new(0, 0) {
+                let principal = collected.min(current.owed);
+                let fees = collected - principal;
+                current.owed -= principal;
+                if principal > Decimal::new(0, 0) {
+                    transactions.push(Transaction::Deposit(movement(principal, "collect")));
+                }
+                if fees > Decimal::new(0, 0) {
+                    transactions.push(Transaction::Income(movement(fees, "fees")));
+                }
+            }
+            totals.insert(key, current);
+        }
+    }
+    Ok(transactions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::money::currencies::{ETH, USDC};
+    use rust_decimal_macros::dec;
+
+    fn snapshot(
+        timestamp: &str,
+        deposited: (&str, &str),
+        withdrawn: (&str, &str),
+        collected: (&str, &str),
+    ) -> PositionSnapshot {
+        let json = serde_json::json!({
+            "id": format!("1234#{}", timestamp),
+            "timestamp": timestamp,
+            "transaction": { "id": format!("0x{}", timestamp) },
+            "position": { "id": "1234", "pool": { "token0": { "symbol": "USDC" }, "token1": { "symbol": "ETH" } } },
+            "depositedToken0": deposited.0,
+            "depositedToken1": deposited.1,
+            "withdrawnToken0": withdrawn.0,
+            "withdrawnToken1": withdrawn.1,
+            "collectedToken0": collected.0,
+            "collectedToken1": collected.1,
+        });
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn collects_are_split_into_principal_and_fees() {
+        let snapshots = vec![
+            // out of order, as paged by id
+            snapshot(
+                "1627776000",
+                ("2000", "1"),
+                ("2000", "0.9"),
+                ("2020", "0.912"),
+            ),
+            snapshot("1622505600", ("2000", "1"), ("0", "0"), ("0", "0")),
+            // fees collected without removing any liquidity
+            snapshot("1625097600", ("2000", "1"), ("0", "0"), ("15", "0.01")),
+            // liquidity removed, but not collected until later
+            snapshot("1625184000", ("2000", "1"), ("2000", "0.9"), ("15", "0.01")),
+        ];
+
+        let transactions = to_transactions(snapshots).unwrap();
+        let amounts = transactions
+            .iter()
+            .map(|tx| match tx {
+                Transaction::Withdrawal(m) => ("withdrawal", m.amount.clone()),
+                Transaction::Deposit(m) => ("deposit", m.amount.clone()),
+                Transaction::Income(m) => ("income", m.amount.clone()),
+                _ => panic!("Expected a movement in or out of the position"),
+            })
+            .collect::<Vec<_>>();
+        let usdc = |value| Money::from_decimal(value, USDC);
+        let eth = |value| Money::from_decimal(value, ETH);
+
+        assert_eq!(
+            amounts,
+            vec![
+                ("withdrawal", usdc(dec!(2000))),
+                ("withdrawal", eth(dec!(1))),
+                ("income", usdc(dec!(15))),
+                ("income", eth(dec!(0.01))),
+                ("deposit", usdc(dec!(2000))),
+                ("income", usdc(dec!(5))),
+                ("deposit", eth(dec!(0.9))),
+                ("income", eth(dec!(0.002))),
+            ]
+        );
+    }
+}
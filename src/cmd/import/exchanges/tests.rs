@@ -0,0 +1,254 @@
+//! Round trips the anonymised sample exports in `fixtures/exchanges/<exchange>/export.csv`
+//! through each importer, and checks the normalised trades against `expected.csv` in the same
+//! directory.
+//!
+//! To add a new exchange format, add its fixture directory and a test case below.
+
+use super::{
+    binance, bittrex, coinbase, deribit, ethermine, etherscan, kraken, monero, nicehash, otc,
+    poloniex, uphold, zcash,
+};
+use crate::{
+    cmd::import::read_exchange_csv,
+    trades::TradeRecord,
+    transactions::{self, Transaction, TransactionKind},
+    utils, Money,
+};
+use chrono::NaiveDateTime;
+use rust_decimal::Decimal;
+use serde::de::DeserializeOwned;
+use std::{convert::TryInto, fs::File, path::PathBuf};
+
+type Normalised<'a> = (
+    NaiveDateTime,
+    TransactionKind,
+    Option<Money<'a>>,
+    Option<Money<'a>>,
+    Money<'a>,
+    Decimal,
+    Option<String>,
+    Option<String>,
+);
+
+fn normalise<'a>(tx: &Transaction<'a>) -> Normalised<'a> {
+    (
+        tx.date_time(),
+        tx.kind(),
+        tx.buy().cloned(),
+        tx.sell().cloned(),
+        tx.fee().clone(),
+        tx.rate(),
+        tx.exchange().cloned(),
+        tx.account().cloned(),
+    )
+}
+
+fn fixture(exchange: &str, file: &str) -> File {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("fixtures")
+        .join("exchanges")
+        .join(exchange)
+        .join(file);
+    File::open(&path).expect(&format!("Fixture {} should exist", path.display()))
+}
+
+fn assert_fixture<'a, CsvRecord, E>(exchange: &str)
+where
+    CsvRecord: Clone + DeserializeOwned + TryInto<Transaction<'a>, Error = E>,
+    E: std::error::Error + 'static + Send + Sync,
+{
+    let imported = read_exchange_csv::<CsvRecord, E, _>(fixture(exchange, "export.csv"))
+        .expect("Export should be imported");
+    assert_imported(exchange, imported);
+}
+
+fn assert_imported(exchange: &str, imported: Vec<Transaction>) {
+    // round trip through the normalised transactions csv format
+    let records = imported.iter().map(TradeRecord::from).collect();
+    let mut csv = Vec::new();
+    utils::write_csv(records, &mut csv).expect("Transactions should be written");
+    let round_tripped =
+        transactions::read_csv(csv.as_slice()).expect("Transactions should be read");
+
+    let expected = transactions::read_csv(fixture(exchange, "expected.csv"))
+        .expect("Expected transactions should be read");
+
+    assert_eq!(
+        round_tripped.iter().map(normalise).collect::<Vec<_>>(),
+        expected.iter().map(normalise).collect::<Vec<_>>(),
+        "{} transactions",
+        exchange
+    );
+}
+
+#[test]
+fn binance_csv() {
+    assert_fixture::<binance::CsvRecord, _>("binance");
+}
+
+#[test]
+fn binance_earn_csv() {
+    assert_fixture::<binance::EarnCsvRecord, _>("binance-earn");
+}
+
+#[test]
+fn binance_trade_history_csv() {
+    assert_fixture::<binance::TradeHistoryRecord, _>("binance-trade-history");
+}
+
+#[test]
+fn binance_transactions_csv() {
+    let imported = binance::read_transaction_history(fixture("binance-transactions", "export.csv"))
+        .expect("Export should be imported");
+    assert_imported("binance-transactions", imported);
+}
+
+#[test]
+fn bittrex_csv() {
+    assert_fixture::<bittrex::Record, _>("bittrex");
+}
+
+#[test]
+fn bittrex_rejects_commission_in_another_currency() {
+    let export = "\
+OrderUuid,Exchange,Type,Quantity,Limit,CommissionPaid,Price,Opened,Closed,CommissionCurrency
+00000000-0000-0000-0000-000000000001,BTC-ETH,LIMIT_BUY,2.5,0.03,0.0001875,0.075,12/01/2017 9:00:00 AM,12/01/2017 9:01:00 AM,USDT
+";
+    let imported = read_exchange_csv::<bittrex::Record, _, _>(export.as_bytes());
+    assert_eq!(
+        imported
+            .err()
+            .expect("Invalid commission currency")
+            .to_string(),
+        "CommissionCurrency should be either the base or quote currency"
+    );
+}
+
+#[test]
+fn bittrex_opened_csv() {
+    assert_fixture::<bittrex::OpenedRecord, _>("bittrex-opened");
+}
+
+#[test]
+fn binance_rejects_unknown_order_type() {
+    let export = "\
+Date(UTC),Market,Type,Price,Amount,Total,Fee,Fee Coin
+2020-12-01 10:00:00,ETHBTC,STOP_LOSS,0.03,2.5,0.075,0.0025,ETH
+";
+    let imported = read_exchange_csv::<binance::CsvRecord, _, _>(export.as_bytes());
+    assert_eq!(
+        imported.err().expect("Invalid order type").to_string(),
+        "Type should be BUY or SELL"
+    );
+}
+
+#[test]
+fn bittrex_rejects_unknown_order_type() {
+    let export = "\
+OrderUuid,Exchange,Type,Quantity,Limit,CommissionPaid,Price,Opened,Closed,CommissionCurrency
+00000000-0000-0000-0000-000000000001,BTC-ETH,MARKET_BUY,2.5,0.03,0.0001875,0.075,12/01/2017 9:00:00 AM,12/01/2017 9:01:00 AM,
+";
+    let imported = read_exchange_csv::<bittrex::Record, _, _>(export.as_bytes());
+    assert_eq!(
+        imported.err().expect("Invalid order type").to_string(),
+        "Type should be LIMIT_BUY or LIMIT_SELL"
+    );
+}
+
+#[test]
+fn bittrex_fills_csv() {
+    assert_fixture::<bittrex::FillRecord, _>("bittrex-fills");
+}
+
+#[test]
+fn coinbase_csv() {
+    assert_fixture::<coinbase::Record, _>("coinbase");
+}
+
+#[test]
+fn coinbase_history_csv() {
+    assert_fixture::<coinbase::HistoryRecord, _>("coinbase-history");
+}
+
+#[test]
+fn coinbase_unsigned_totals_csv() {
+    assert_fixture::<coinbase::Record, _>("coinbase-unsigned");
+}
+
+#[test]
+fn coinbase_rejects_negative_sell_total() {
+    let export = "\
+trade id,product,side,created at,size,size unit,price,fee,total,price/fee/total unit
+100001,ETH-GBP,SELL,2018-11-20T21:39:45.667Z,5.41307455,ETH,101.86,1.654127320989,-549.721646342011,GBP
+";
+    let imported = read_exchange_csv::<coinbase::Record, _, _>(export.as_bytes());
+    assert!(imported.is_err());
+}
+
+#[test]
+fn deribit_csv() {
+    assert_fixture::<deribit::Record, _>("deribit");
+}
+
+#[test]
+fn etherscan_csv() {
+    assert_fixture::<etherscan::Record, _>("etherscan");
+}
+
+#[test]
+fn ethermine_payouts_json() {
+    let imported =
+        ethermine::read_payouts(fixture("ethermine", "payouts.json"), Some("ethermine:rig2"))
+            .expect("Payouts should be imported");
+    assert_imported("ethermine", imported);
+}
+
+#[test]
+fn kraken_csv() {
+    assert_fixture::<kraken::Record, _>("kraken");
+}
+
+#[test]
+fn kraken_ledgers_csv() {
+    assert_fixture::<kraken::LedgerRecord, _>("kraken-ledgers");
+}
+
+#[test]
+fn monero_csv() {
+    assert_fixture::<monero::Record, _>("monero");
+}
+
+#[test]
+fn nicehash_csv() {
+    assert_fixture::<nicehash::Record, _>("nicehash");
+}
+
+#[test]
+fn otc_csv() {
+    assert_fixture::<otc::Record, _>("otc");
+}
+
+#[test]
+fn poloniex_csv() {
+    assert_fixture::<poloniex::Record, _>("poloniex");
+}
+
+#[test]
+fn uphold_csv() {
+    assert_fixture::<uphold::Record, _>("uphold");
+}
+
+#[test]
+fn zcash_csv() {
+    assert_fixture::<zcash::Record, _>("zcash");
+}
+
+#[test]
+fn zcash_rejects_shielded_movement_without_amount() {
+    let export = "\
+date_time,txid,direction,address,amount,fee,memo
+2021-04-05 16:45:00,7b9d1f3a5c7e9b2d,send,zs1z7rejlpsa98s2rrrfkwmaxu53e4ue0ulcrw0h4x5g8jl04tak0d3mm47vdtahatqrlkngh9sly,,,
+";
+    let imported = read_exchange_csv::<zcash::Record, _, _>(export.as_bytes());
+    assert!(imported.is_err());
+}
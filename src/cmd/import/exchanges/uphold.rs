@@ -5,8 +5,9 @@ use std::convert::TryFrom;
 
 use super::ExchangeError;
 use crate::{
-    money::{amount, currencies},
+    money::amount,
     trades::{Trade, TradeKind},
+    transactions::Transaction,
 };
 
 #[derive(Clone, Debug, Deserialize)]
@@ -28,16 +29,10 @@ pub struct Record {
     destination_commission: String,
 }
 
-impl<'a> TryFrom<Record> for Trade<'a> {
+impl<'a> TryFrom<Record> for Transaction<'a> {
     type Error = ExchangeError;
 
-    fn try_from(value: Record) -> Result<Trade<'a>, Self::Error> {
-        // check to see if this is a crypto trade - either are unknown currencies
-        if currencies::find(&value.origin_currency).is_some()
-            && currencies::find(&value.destination_currency).is_some()
-        {
-            return Err("Either origin or destination currency should be a cryptocurrency".into());
-        }
+    fn try_from(value: Record) -> Result<Transaction<'a>, Self::Error> {
         if value.origin_currency == value.destination_currency {
             return Err("Origin and destination cannot be the same currency".into());
         }
@@ -46,8 +41,8 @@ impl<'a> TryFrom<Record> for Trade<'a> {
             .expect("invalid rcf3339 date")
             .naive_utc();
 
-        let sell = amount(&value.origin_currency, value.origin_amount);
-        let buy = amount(&value.destination_currency, value.destination_amount);
+        let sell = amount(&value.origin_currency, value.origin_amount)?;
+        let buy = amount(&value.destination_currency, value.destination_amount)?;
 
         let (base_currency, _quote_currency) = value.pair.split_at(3);
         let kind = if value.origin_currency == base_currency {
@@ -57,16 +52,62 @@ impl<'a> TryFrom<Record> for Trade<'a> {
         } else {
             panic!("Either source or destination should be the base currency")
         };
-        let fee = amount("£", value.commission_in_GBP);
+        let fee = amount("GBP", value.commission_in_GBP)?;
 
-        Ok(Trade {
+        Ok(Transaction::Trade(Trade {
             date_time,
             buy,
             sell,
             fee,
             rate: value.rate,
             exchange: Some("Uphold".into()),
+            account: None,
+            notes: None,
             kind,
-        })
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(origin: &str, destination: &str) -> Record {
+        Record {
+            date: "2019-03-01T08:00:00.000Z".into(),
+            id: "00000000-0000-0000-0000-000000000001".into(),
+            tx_type: "transfer".into(),
+            value_in_GBP: Decimal::new(100, 0),
+            commission_in_GBP: Decimal::new(50, 2),
+            pair: format!("{}{}", destination, origin),
+            rate: Decimal::new(25, 2),
+            origin_currency: origin.into(),
+            origin_amount: Decimal::new(100, 0),
+            origin_commission: "0".into(),
+            destination_currency: destination.into(),
+            destination_amount: Decimal::new(400, 0),
+            destination_commission: "0".into(),
+        }
+    }
+
+    #[test]
+    fn trades_between_known_currencies_have_a_gbp_fee() {
+        match Transaction::try_from(record("GBP", "XRP")).unwrap() {
+            Transaction::Trade(trade) => {
+                assert_eq!(trade.kind, TradeKind::Buy);
+                assert_eq!(trade.buy, amount("XRP", Decimal::new(400, 0)).unwrap());
+                assert_eq!(trade.sell, amount("GBP", Decimal::new(100, 0)).unwrap());
+                assert_eq!(trade.fee, amount("GBP", Decimal::new(50, 2)).unwrap());
+            }
+            _ => panic!("Expected a trade"),
+        }
+    }
+
+    #[test]
+    fn trades_of_unknown_currencies_are_errors() {
+        assert!(matches!(
+            Transaction::try_from(record("GBP", "XYZ")),
+            Err(ExchangeError::UnknownCurrency(err)) if err.code == "XYZ"
+        ));
     }
 }
@@ -0,0 +1,596 @@
+//! Deposits and withdrawals of an address on Bitcoin and the chains derived from it, fetched from
+//! an [Esplora](https://github.com/Blockstream/esplora/blob/master/API.md) or
+//! [Blockbook](https://github.com/trezor/blockbook/blob/master/docs/api.md) API. Transfers with
+//! addresses in the address book are labelled, and flagged as changing ownership when they are
+//! to or from others.
+
+use crate::{
+    addresses::{self, Address},
+    encryption,
+    money::amount,
+    trades::TradeRecord,
+    transactions::{self, Movement, Transaction},
+};
+use argh::FromArgs;
+use chrono::NaiveDateTime;
+use color_eyre::eyre;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::path::PathBuf;
+
+/// Import the deposits to and withdrawals from the addresses of a wallet on a UTXO chain
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "utxo")]
+pub struct UtxoApiCommand {
+    /// the chain of the addresses: btc, ltc, doge or bch
+    #[argh(option)]
+    chain: Chain,
+    /// an address of the wallet to import the transactions of, repeated for each address
+    /// including change addresses, so change back to the wallet is not counted as sent
+    #[argh(option)]
+    address: Vec<String>,
+    /// the url of an Esplora API to use instead of the default for the chain, e.g. a self hosted
+    /// instance
+    #[argh(option)]
+    esplora: Option<String>,
+    /// the url of a Blockbook server to use instead of the default for the chain
+    #[argh(option)]
+    blockbook: Option<String>,
+    /// the transactions csv, to match the transfers with withdrawals from and deposits to
+    /// exchanges, adding the addresses of the wallet and the exchange deposit addresses to the
+    /// address book as your own
+    #[argh(option)]
+    learn_from: Option<PathBuf>,
+}
+
+impl UtxoApiCommand {
+    pub fn exec(&self) -> color_eyre::Result<()> {
+        crate::utils::write_csv(self.fetch()?, std::io::stdout())
+    }
+
+    /// Download the transactions of the addresses, learning addresses if requested
+    pub fn fetch(&self) -> color_eyre::Result<Vec<TradeRecord>> {
+        let api = match (&self.esplora, &self.blockbook) {
+            (Some(_), Some(_)) => {
+                return Err(eyre::eyre!(
+                    "Only one of --esplora and --blockbook can be given"
+                ))
+            }
+            (Some(url), None) => Api::Esplora(url.clone()),
+            (None, Some(url)) => Api::Blockbook(url.clone()),
+            (None, None) => self.chain.default_api(),
+        };
+        if self.address.is_empty() {
+            return Err(eyre::eyre!("At least one --address is required"));
+        }
+        let mut txs = Vec::new();
+        for address in &self.address {
+            tracing::info!("Fetching {} transactions for {}", self.chain, address);
+            let fetched = api.fetch_transactions(address)?;
+            tracing::info!("Fetched {} transactions", fetched.len());
+            txs.extend(fetched);
+        }
+        // a transaction between addresses of the wallet is fetched for each of them
+        txs.sort_by(|a, b| a.time.cmp(&b.time).then_with(|| a.txid.cmp(&b.txid)));
+        txs.dedup_by(|a, b| a.txid == b.txid);
+
+        let transactions = txs
+            .iter()
+            .filter_map(|tx| Some((tx, tx.to_transaction(self.chain, &self.address)?)))
+            .collect::<Vec<_>>();
+        if let Some(ref path) = self.learn_from {
+            let existing = transactions::read_csv(encryption::open(path)?)?;
+            let learned = self.learn_addresses(&existing, &transactions);
+            tracing::info!(
+                "Added {} addresses to the address book",
+                addresses::learn(learned)?
+            );
+        }
+        let transactions = transactions
+            .into_iter()
+            .map(|(_, tx)| tx)
+            .collect::<Vec<_>>();
+        Ok(transactions.iter().map(TradeRecord::from).collect())
+    }
+}
+
+impl UtxoApiCommand {
+    /// The addresses of the wallet receiving withdrawals from exchanges, and the exchange deposit
+    /// addresses of transfers to exchanges
+    fn learn_addresses(
+        &self,
+        existing: &[Transaction],
+        imported: &[(&UtxoTransaction, Transaction)],
+    ) -> Vec<Address> {
+        let on_exchange = |tx: &Transaction| {
+            tx.exchange().map_or(false, |exchange| {
+                !transactions::account_matches(Some(exchange), &self.chain.to_string())
+            })
+        };
+        let mut learned = Vec::new();
+        for (utxo_tx, tx) in imported {
+            for other in existing.iter().filter(|other| on_exchange(other)) {
+                let exchange = other.exchange().cloned().unwrap_or_default();
+                match (tx, other) {
+                    (Transaction::Deposit(received), Transaction::Withdrawal(sent))
+                        if addresses::is_same_transfer(sent, received) =>
+                    {
+                        learned.extend(
+                            utxo_tx
+                                .outputs
+                                .iter()
+                                .filter(|output| output.is_to_any(&self.address))
+                                .filter_map(|output| output.address.clone())
+                                .map(|address| Address {
+                                    address,
+                                    label: format!("{} wallet", self.chain),
+                                    owned: true,
+                                }),
+                        );
+                    }
+                    (Transaction::Withdrawal(sent), Transaction::Deposit(received))
+                        if addresses::is_same_transfer(sent, received) =>
+                    {
+                        learned.extend(
+                            utxo_tx
+                                .outputs
+                                .iter()
+                                .filter(|output| !output.is_to_any(&self.address))
+                                .filter_map(|output| output.address.clone())
+                                .map(|address| Address {
+                                    address,
+                                    label: format!("{} deposit address", exchange),
+                                    owned: true,
+                                }),
+                        );
+                    }
+                    _ => (),
+                }
+            }
+        }
+        learned.dedup_by(|a, b| a.address == b.address);
+        learned
+    }
+}
+
+/// A chain with the same transaction model as Bitcoin
+#[derive(Debug, Clone, Copy, PartialEq, Eq, derive_more::Display)]
+pub enum Chain {
+    Bitcoin,
+    Litecoin,
+    Dogecoin,
+    #[display(fmt = "Bitcoin Cash")]
+    BitcoinCash,
+}
+
+impl Chain {
+    fn code(&self) -> &'static str {
+        match self {
+            Chain::Bitcoin => "BTC",
+            Chain::Litecoin => "LTC",
+            Chain::Dogecoin => "DOGE",
+            Chain::BitcoinCash => "BCH",
+        }
+    }
+
+    fn default_api(&self) -> Api {
+        match self {
+            Chain::Bitcoin => Api::Esplora("https://blockstream.info/api".into()),
+            Chain::Litecoin => Api::Esplora("https://litecoinspace.org/api".into()),
+            Chain::Dogecoin => Api::Blockbook("https://doge1.trezor.io".into()),
+            Chain::BitcoinCash => Api::Blockbook("https://bch1.trezor.io".into()),
+        }
+    }
+
+    /// The amount of the chain's coin from its smallest unit e.g. satoshis, all of these chains
+    /// have 8 decimal places
+    fn amount<'a>(&self, value: u64) -> crate::Money<'a> {
+        amount(self.code(), Decimal::new(value as i64, 8))
+            .expect("Built in currencies should be known")
+    }
+}
+
+impl std::str::FromStr for Chain {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "btc" => Ok(Chain::Bitcoin),
+            "ltc" => Ok(Chain::Litecoin),
+            "doge" => Ok(Chain::Dogecoin),
+            "bch" => Ok(Chain::BitcoinCash),
+            c => Err(format!(
+                "Invalid chain {}, expected btc, ltc, doge or bch",
+                c
+            )),
+        }
+    }
+}
+
+enum Api {
+    Esplora(String),
+    Blockbook(String),
+}
+
+/// Esplora returns the confirmed transactions of an address 25 at a time, newest first
+const ESPLORA_PAGE_SIZE: usize = 25;
+const BLOCKBOOK_PAGE_SIZE: usize = 1000;
+
+impl Api {
+    fn fetch_transactions(&self, address: &str) -> color_eyre::Result<Vec<UtxoTransaction>> {
+        match self {
+            Api::Esplora(url) => {
+                let mut txs = Vec::new();
+                loop {
+                    // GET /address/:address/txs/chain[/:last_seen_txid]
+                    let page_url = match txs.last() {
+                        None => format!("{}/address/{}/txs/chain", url, address),
+                        Some(EsploraTransaction { txid, .. }) => {
+                            format!("{}/address/{}/txs/chain/{}", url, address, txid)
+                        }
+                    };
+                    let page: Vec<EsploraTransaction> = ureq::get(&page_url).call()?.into_json()?;
+                    let count = page.len();
+                    txs.extend(page);
+                    if count < ESPLORA_PAGE_SIZE {
+                        break;
+                    }
+                }
+                Ok(txs
+                    .into_iter()
+                    .filter_map(UtxoTransaction::from_esplora)
+                    .collect())
+            }
+            Api::Blockbook(url) => {
+                let mut txs = Vec::new();
+                let mut page = 1;
+                loop {
+                    // GET /api/v2/address/:address?details=txs
+                    let page_url = format!(
+                        "{}/api/v2/address/{}?details=txs&page={}&pageSize={}",
+                        url, address, page, BLOCKBOOK_PAGE_SIZE
+                    );
+                    let response: BlockbookAddress = ureq::get(&page_url).call()?.into_json()?;
+                    txs.extend(response.transactions);
+                    if page >= response.total_pages {
+                        break;
+                    }
+                    page += 1;
+                }
+                txs.into_iter()
+                    .filter(|tx| tx.confirmations > 0)
+                    .map(UtxoTransaction::try_from_blockbook)
+                    .collect()
+            }
+        }
+    }
+}
+
+/// The values of a transaction in the smallest unit of the chain e.g. satoshis
+#[derive(Debug)]
+struct UtxoTransaction {
+    txid: String,
+    /// Unix timestamp in seconds of the block
+    time: i64,
+    inputs: Vec<Output>,
+    outputs: Vec<Output>,
+    fee: u64,
+}
+
+/// An output, or the output spent by an input. Coinbase inputs and outputs which don't pay an
+/// address e.g. OP_RETURN have no address.
+#[derive(Debug)]
+struct Output {
+    address: Option<String>,
+    value: u64,
+}
+
+impl Output {
+    fn is_to_any(&self, addresses: &[String]) -> bool {
+        // Bitcoin Cash addresses may or may not have the bitcoincash: prefix
+        let without_prefix = |a: &str| a.rsplit(':').next().unwrap_or_default().to_string();
+        self.address.as_deref().map_or(false, |a| {
+            addresses
+                .iter()
+                .any(|address| without_prefix(a) == without_prefix(address))
+        })
+    }
+}
+
+impl UtxoTransaction {
+    /// The net movement of the transaction for the wallet. Outputs back to any of its addresses
+    /// when spending are change, so only the amount paid to others and the fee are withdrawn
+    /// rather than the whole of the inputs. The fee is only included when the wallet funded all
+    /// of the inputs.
+    fn to_transaction<'a>(&self, chain: Chain, addresses: &[String]) -> Option<Transaction<'a>> {
+        let sum = |outputs: &[Output]| -> u64 {
+            outputs
+                .iter()
+                .filter(|output| output.is_to_any(addresses))
+                .map(|output| output.value)
+                .sum()
+        };
+        let spent = sum(&self.inputs);
+        let received = sum(&self.outputs);
+        // labelled with the counterparties in the address book, changing ownership when they
+        // are all known and any aren't the user's own
+        let movement = |value: u64, fee: u64, direction: &str, counterparties: &[Output]| {
+            let others = counterparties
+                .iter()
+                .filter(|output| !output.is_to_any(addresses))
+                .filter_map(|output| output.address.as_deref())
+                .collect::<Vec<_>>();
+            let known = others
+                .iter()
+                .filter_map(|address| addresses::find(address))
+                .collect::<Vec<_>>();
+            let mut labels = known
+                .iter()
+                .map(|entry| entry.label.as_str())
+                .collect::<Vec<_>>();
+            labels.dedup();
+            Movement {
+                date_time: NaiveDateTime::from_timestamp(self.time, 0),
+                amount: chain.amount(value),
+                fee: chain.amount(fee),
+                exchange: Some(chain.to_string()),
+                account: None,
+                ownership_changed: !others.is_empty()
+                    && known.len() == others.len()
+                    && known.iter().any(|entry| !entry.owned),
+                notes: Some(if labels.is_empty() {
+                    format!("Tx {}", self.txid)
+                } else {
+                    format!("Tx {} {} {}", self.txid, direction, labels.join(", "))
+                }),
+            }
+        };
+
+        if received > spent {
+            Some(Transaction::Deposit(movement(
+                received - spent,
+                0,
+                "from",
+                &self.inputs,
+            )))
+        } else if spent > received {
+            if received > 0 {
+                tracing::debug!(
+                    "Tx {} spent {} with change of {}",
+                    self.txid,
+                    spent,
+                    received
+                );
+            }
+            let funded_all = self.inputs.iter().all(|input| input.is_to_any(addresses));
+            let fee = if funded_all { self.fee } else { 0 };
+            let sent = (spent - received).saturating_sub(fee);
+            Some(Transaction::Withdrawal(movement(
+                sent,
+                fee,
+                "to",
+                &self.outputs,
+            )))
+        } else {
+            None
+        }
+    }
+
+    fn try_from_blockbook(tx: BlockbookTransaction) -> color_eyre::Result<Self> {
+        let outputs = |outputs: Vec<BlockbookOutput>| -> color_eyre::Result<Vec<Output>> {
+            outputs
+                .into_iter()
+                .map(|output| {
+                    Ok(Output {
+                        address: output.addresses.into_iter().next(),
+                        value: output.value.as_deref().unwrap_or("0").parse()?,
+                    })
+                })
+                .collect()
+        };
+        Ok(UtxoTransaction {
+            txid: tx.txid,
+            time: tx.block_time,
+            inputs: outputs(tx.vin)?,
+            outputs: outputs(tx.vout)?,
+            fee: tx.fees.parse()?,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct EsploraTransaction {
+    txid: String,
+    vin: Vec<EsploraInput>,
+    vout: Vec<EsploraOutput>,
+    fee: u64,
+    status: EsploraStatus,
+}
+
+#[derive(Debug, Deserialize)]
+struct EsploraInput {
+    /// None for coinbase inputs
+    prevout: Option<EsploraOutput>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EsploraOutput {
+    scriptpubkey_address: Option<String>,
+    value: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct EsploraStatus {
+    block_time: Option<i64>,
+}
+
+impl UtxoTransaction {
+    /// None if the transaction is unconfirmed
+    fn from_esplora(tx: EsploraTransaction) -> Option<Self> {
+        let output = |output: EsploraOutput| Output {
+            address: output.scriptpubkey_address,
+            value: output.value,
+        };
+        Some(UtxoTransaction {
+            time: tx.status.block_time?,
+            txid: tx.txid,
+            inputs: tx
+                .vin
+                .into_iter()
+                .filter_map(|input| input.prevout.map(output))
+                .collect(),
+            outputs: tx.vout.into_iter().map(output).collect(),
+            fee: tx.fee,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BlockbookAddress {
+    total_pages: u32,
+    /// Omitted when the address has no transactions
+    #[serde(default)]
+    transactions: Vec<BlockbookTransaction>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BlockbookTransaction {
+    txid: String,
+    vin: Vec<BlockbookOutput>,
+    vout: Vec<BlockbookOutput>,
+    block_time: i64,
+    confirmations: u64,
+    /// Values are strings of the amount in the smallest unit
+    fees: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct BlockbookOutput {
+    #[serde(default)]
+    addresses: Vec<String>,
+    /// None for coinbase inputs
+    value: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        money::currencies::{BTC, DOGE},
+        Money,
+    };
+    use rust_decimal_macros::dec;
+
+    const ADDRESS: &str = "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq";
+    const CHANGE_ADDRESS: &str = "bc1qc7slrfxkknqcq2jevvvkdgvrt8080852dfjewde450xdlk4ugp7szw5tk9";
+
+    fn wallet(addresses: &[&str]) -> Vec<String> {
+        addresses.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn esplora_payment_with_change_is_a_withdrawal_of_the_net_amount() {
+        let json = r#"{
+            "txid": "f4184fc5",
+            "vin": [{ "prevout": { "scriptpubkey_address": "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq", "value": 100000000 } }],
+            "vout": [
+                { "scriptpubkey_address": "1BoatSLRHtKNngkdXEeobR76b53LETtpyT", "value": 30000000 },
+                { "scriptpubkey_address": "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq", "value": 69990000 }
+            ],
+            "fee": 10000,
+            "status": { "confirmed": true, "block_time": 1609459200 }
+        }"#;
+        let tx: EsploraTransaction = serde_json::from_str(json).unwrap();
+        let tx = UtxoTransaction::from_esplora(tx).unwrap();
+
+        match tx.to_transaction(Chain::Bitcoin, &wallet(&[ADDRESS])) {
+            Some(Transaction::Withdrawal(movement)) => {
+                assert_eq!(movement.amount, Money::from_decimal(dec!(0.3), BTC));
+                assert_eq!(movement.fee, Money::from_decimal(dec!(0.0001), BTC));
+                assert_eq!(movement.exchange, Some("Bitcoin".into()));
+            }
+            _ => panic!("Expected a withdrawal"),
+        }
+    }
+
+    #[test]
+    fn change_to_another_address_of_the_wallet_is_not_withdrawn() {
+        let json = r#"{
+            "txid": "8d2b6f31",
+            "vin": [
+                { "prevout": { "scriptpubkey_address": "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq", "value": 60000000 } },
+                { "prevout": { "scriptpubkey_address": "bc1qc7slrfxkknqcq2jevvvkdgvrt8080852dfjewde450xdlk4ugp7szw5tk9", "value": 40000000 } }
+            ],
+            "vout": [
+                { "scriptpubkey_address": "1BoatSLRHtKNngkdXEeobR76b53LETtpyT", "value": 25000000 },
+                { "scriptpubkey_address": "bc1qc7slrfxkknqcq2jevvvkdgvrt8080852dfjewde450xdlk4ugp7szw5tk9", "value": 74980000 }
+            ],
+            "fee": 20000,
+            "status": { "confirmed": true, "block_time": 1609459200 }
+        }"#;
+        let tx: EsploraTransaction = serde_json::from_str(json).unwrap();
+        let tx = UtxoTransaction::from_esplora(tx).unwrap();
+
+        match tx.to_transaction(Chain::Bitcoin, &wallet(&[ADDRESS, CHANGE_ADDRESS])) {
+            Some(Transaction::Withdrawal(movement)) => {
+                assert_eq!(movement.amount, Money::from_decimal(dec!(0.25), BTC));
+                assert_eq!(movement.fee, Money::from_decimal(dec!(0.0002), BTC));
+            }
+            _ => panic!("Expected a withdrawal"),
+        }
+
+        // without the change address the wallet only funded some of the inputs, so the whole of
+        // its input is withdrawn
+        match tx.to_transaction(Chain::Bitcoin, &wallet(&[ADDRESS])) {
+            Some(Transaction::Withdrawal(movement)) => {
+                assert_eq!(movement.amount, Money::from_decimal(dec!(0.6), BTC));
+                assert_eq!(movement.fee, Money::from_decimal(dec!(0), BTC));
+            }
+            _ => panic!("Expected a withdrawal"),
+        }
+    }
+
+    #[test]
+    fn esplora_unconfirmed_transactions_are_skipped() {
+        let json = r#"{
+            "txid": "f4184fc5",
+            "vin": [{ "prevout": null }],
+            "vout": [{ "scriptpubkey_address": "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq", "value": 5000 }],
+            "fee": 0,
+            "status": { "confirmed": false }
+        }"#;
+        let tx: EsploraTransaction = serde_json::from_str(json).unwrap();
+
+        assert!(UtxoTransaction::from_esplora(tx).is_none());
+    }
+
+    #[test]
+    fn blockbook_receipt_is_a_deposit() {
+        let json = r#"{
+            "txid": "5c0a1b7e",
+            "vin": [{ "addresses": ["DH5yaieqoZN36fDVciNyRueRGvGLR3mr7L"], "value": "50000000000" }],
+            "vout": [
+                { "addresses": ["DLCDJhnh6aGotar6b182jpzbNEyXb3C361"], "value": "12500000000" },
+                { "addresses": ["DH5yaieqoZN36fDVciNyRueRGvGLR3mr7L"], "value": "37400000000" }
+            ],
+            "blockTime": 1612137600,
+            "confirmations": 120,
+            "fees": "100000000"
+        }"#;
+        let tx: BlockbookTransaction = serde_json::from_str(json).unwrap();
+        let tx = UtxoTransaction::try_from_blockbook(tx).unwrap();
+
+        match tx.to_transaction(
+            Chain::Dogecoin,
+            &wallet(&["DLCDJhnh6aGotar6b182jpzbNEyXb3C361"]),
+        ) {
+            Some(Transaction::Deposit(movement)) => {
+                assert_eq!(movement.amount, Money::from_decimal(dec!(125), DOGE));
+                assert_eq!(movement.fee, Money::from_decimal(dec!(0), DOGE));
+            }
+            _ => panic!("Expected a deposit"),
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use chrono::{DateTime, NaiveDateTime};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::convert::TryFrom;
+
+use super::ExchangeError;
+use crate::{
+    money::amount,
+    transactions::{Movement, Transaction},
+};
+
+/// A row of a Zcash wallet transaction export. Transfers to and from transparent addresses
+/// (t1, t3) are imported as they are. The amounts of shielded movements (zs, zc and u1 addresses)
+/// are often missing from exports by light wallets, so must be filled in from the wallet first.
+///
+/// date_time,txid,direction,address,amount,fee,memo
+#[derive(Clone, Debug, Deserialize)]
+pub struct Record {
+    /// e.g. 2021-01-31 14:00:00 or 2021-01-31T14:00:00+00:00
+    date_time: String,
+    txid: String,
+    /// receive or send
+    direction: String,
+    address: String,
+    #[serde(default)]
+    amount: Option<Decimal>,
+    #[serde(default)]
+    fee: Option<Decimal>,
+    #[serde(default)]
+    memo: String,
+}
+
+impl Record {
+    fn is_shielded(&self) -> bool {
+        ["zs", "zc", "u1"]
+            .iter()
+            .any(|prefix| self.address.starts_with(prefix))
+    }
+}
+
+impl<'a> TryFrom<Record> for Transaction<'a> {
+    type Error = ExchangeError;
+
+    fn try_from(value: Record) -> Result<Transaction<'a>, Self::Error> {
+        let date_time = DateTime::parse_from_rfc3339(&value.date_time)
+            .map(|dt| dt.naive_utc())
+            .or_else(|_| NaiveDateTime::parse_from_str(&value.date_time, "%Y-%m-%d %H:%M:%S"))?;
+        let tx_amount = match value.amount {
+            Some(tx_amount) => tx_amount,
+            None if value.is_shielded() => {
+                tracing::error!(
+                    "Shielded {} of tx {} at {} has no amount, fill in the amount column of the \
+                     export from the wallet and import again",
+                    value.direction,
+                    value.txid,
+                    value.date_time
+                );
+                return Err(ExchangeError::InvalidRecord(
+                    "Shielded movements need the amount filling in",
+                ));
+            }
+            None => return Err(ExchangeError::InvalidRecord("Amount should not be empty")),
+        };
+
+        let notes = [("Memo", &value.memo), ("Tx", &value.txid)]
+            .iter()
+            .filter(|(_, v)| *v != "")
+            .map(|(k, v)| format!("{}: {}", k, v))
+            .collect::<Vec<_>>()
+            .join("; ");
+        let movement = Movement {
+            date_time,
+            amount: amount("ZEC", tx_amount.abs())?,
+            fee: amount("ZEC", value.fee.unwrap_or_default().abs())?,
+            exchange: Some("Zcash".into()),
+            account: None,
+            ownership_changed: false,
+            notes: Some(notes),
+        };
+        match value.direction.to_lowercase().as_ref() {
+            "receive" => Ok(Transaction::Deposit(movement)),
+            "send" => Ok(Transaction::Withdrawal(movement)),
+            _ => Err(ExchangeError::InvalidRecord(
+                "Direction should be either receive or send",
+            )),
+        }
+    }
+}
@@ -1,12 +1,39 @@
-mod exchanges;
+mod bank;
+pub mod chain;
+pub mod exchanges;
+pub mod preview;
+mod vesting;
 
 use crate::{
-    cmd::import::exchanges::{binance::BinanceApiCommand, ExchangeError},
-    trades::{Trade, TradeRecord},
+    cmd::{
+        import::{
+            chain::ethereum::EthereumApiCommand,
+            exchanges::{
+                binance::BinanceApiCommand, bybit::BybitApiCommand, ccxt::CcxtApiCommand,
+                coinbase::CoinbaseApiCommand, ethermine::EthermineApiCommand,
+                gemini::GeminiApiCommand, kraken::KrakenApiCommand, subgraph::SubgraphApiCommand,
+                utxo::UtxoApiCommand, ExchangeError,
+            },
+        },
+        imports,
+        report::gas,
+    },
+    encryption,
+    store::Store,
+    trades::TradeRecord,
+    transactions::{self, Transaction},
+    warnings,
 };
 use argh::FromArgs;
+use color_eyre::eyre;
+use rust_decimal::Decimal;
 use serde::de::DeserializeOwned;
-use std::{convert::TryInto, fs::File, io, path::PathBuf};
+use std::{
+    convert::TryInto,
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+};
 
 /// Import trades from a csv file
 #[derive(FromArgs, PartialEq, Debug)]
@@ -17,9 +44,15 @@ pub struct ImportTradesCommand {
 }
 
 impl ImportTradesCommand {
-    pub fn exec(&self) -> color_eyre::Result<()> {
+    /// Returns the number of problems found reconciling a bank statement
+    pub fn exec(&self) -> color_eyre::Result<usize> {
         self.sub.exec()
     }
+
+    /// Whether the import only previews the transactions, without writing them
+    pub fn dry_run(&self) -> bool {
+        matches!(self.sub, ImportTradesSubCommand::Csv(ref csv) if csv.dry_run)
+    }
 }
 
 /// Import trades from a csv file
@@ -27,14 +60,18 @@ impl ImportTradesCommand {
 #[argh(subcommand)]
 pub enum ImportTradesSubCommand {
     Api(ImportApiCommand),
+    Bank(bank::ImportBankCommand),
     Csv(ImportExchangeCsvCommand),
+    Vesting(vesting::ImportVestingCommand),
 }
 
 impl ImportTradesSubCommand {
-    pub fn exec(&self) -> color_eyre::Result<()> {
+    pub fn exec(&self) -> color_eyre::Result<usize> {
         match self {
-            Self::Api(api) => api.exec(),
-            Self::Csv(csv) => csv.exec(),
+            Self::Api(api) => api.exec().map(|()| 0),
+            Self::Bank(bank) => bank.exec(),
+            Self::Csv(csv) => csv.exec().map(|()| 0),
+            Self::Vesting(vesting) => vesting.exec().map(|()| 0),
         }
     }
 }
@@ -43,13 +80,37 @@ impl ImportTradesSubCommand {
 #[derive(FromArgs, PartialEq, Debug)]
 #[argh(subcommand, name = "api")]
 pub struct ImportApiCommand {
+    /// add the transactions to this SQLite database instead of writing them, skipping those
+    /// already in it from earlier imports, for `report --from-db`
+    #[argh(option)]
+    into_db: Option<PathBuf>,
+    /// append the transactions to this csv file instead of writing to stdout, recording the
+    /// import so it can be undone with `imports undo`
+    #[argh(option)]
+    append: Option<PathBuf>,
     #[argh(subcommand)]
     sub: ImportApiSubCommand,
 }
 
 impl ImportApiCommand {
     pub fn exec(&self) -> color_eyre::Result<()> {
-        self.sub.exec()
+        let source = format!("api {}", self.sub.name());
+        match (&self.into_db, &self.append) {
+            (Some(_), Some(_)) => Err(eyre::eyre!(
+                "Only one of --into-db and --append can be given"
+            )),
+            (Some(db), None) => insert_into_db(db, &self.sub.fetch()?, &source),
+            (None, Some(txs)) => {
+                imports::append_import(txs, source, self.sub.fetch()?)?;
+                Ok(())
+            }
+            (None, None) => self.sub.exec(),
+        }
+    }
+
+    /// Download the transactions without writing them, ignoring any --encrypt
+    pub fn fetch(&self) -> color_eyre::Result<Vec<TradeRecord>> {
+        self.sub.fetch()
     }
 }
 
@@ -58,12 +119,61 @@ impl ImportApiCommand {
 #[argh(subcommand)]
 pub enum ImportApiSubCommand {
     Binance(BinanceApiCommand),
+    Bybit(BybitApiCommand),
+    Ccxt(CcxtApiCommand),
+    Coinbase(CoinbaseApiCommand),
+    Ethereum(EthereumApiCommand),
+    Ethermine(EthermineApiCommand),
+    Gemini(GeminiApiCommand),
+    Kraken(KrakenApiCommand),
+    Utxo(UtxoApiCommand),
+    Subgraph(SubgraphApiCommand),
 }
 
 impl ImportApiSubCommand {
     pub fn exec(&self) -> color_eyre::Result<()> {
         match self {
             Self::Binance(binance) => binance.exec(),
+            Self::Bybit(bybit) => bybit.exec(),
+            Self::Ccxt(ccxt) => ccxt.exec(),
+            Self::Coinbase(coinbase) => coinbase.exec(),
+            Self::Ethereum(ethereum) => ethereum.exec(),
+            Self::Ethermine(ethermine) => ethermine.exec(),
+            Self::Gemini(gemini) => gemini.exec(),
+            Self::Kraken(kraken) => kraken.exec(),
+            Self::Utxo(utxo) => utxo.exec(),
+            Self::Subgraph(subgraph) => subgraph.exec(),
+        }
+    }
+
+    /// The name of the importer, as in the args
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Binance(_) => "binance",
+            Self::Bybit(_) => "bybit",
+            Self::Ccxt(_) => "ccxt",
+            Self::Coinbase(_) => "coinbase",
+            Self::Ethereum(_) => "ethereum",
+            Self::Ethermine(_) => "ethermine",
+            Self::Gemini(_) => "gemini",
+            Self::Kraken(_) => "kraken",
+            Self::Utxo(_) => "utxo",
+            Self::Subgraph(_) => "subgraph",
+        }
+    }
+
+    pub fn fetch(&self) -> color_eyre::Result<Vec<TradeRecord>> {
+        match self {
+            Self::Binance(binance) => binance.fetch(),
+            Self::Bybit(bybit) => bybit.fetch(),
+            Self::Ccxt(ccxt) => ccxt.fetch(),
+            Self::Coinbase(coinbase) => coinbase.fetch(),
+            Self::Ethereum(ethereum) => ethereum.fetch(),
+            Self::Ethermine(ethermine) => ethermine.fetch(),
+            Self::Gemini(gemini) => gemini.fetch(),
+            Self::Kraken(kraken) => kraken.fetch(),
+            Self::Utxo(utxo) => utxo.fetch(),
+            Self::Subgraph(subgraph) => subgraph.fetch(),
         }
     }
 }
@@ -81,44 +191,205 @@ pub struct ImportExchangeCsvCommand {
     /// combines trades on the same pair on the same day into a single trade
     #[argh(switch, short = 'g')]
     group_by_day: bool,
+    /// combines the gas of approvals and other contract interactions on EVM chains which don't
+    /// move any value into a fee per day, keeping transfers, swaps and failed transactions
+    #[argh(switch)]
+    combine_gas: bool,
+    /// label the imported transactions with an account e.g. binance:personal, for users with
+    /// multiple accounts on the same exchange
+    #[argh(option)]
+    account: Option<String>,
+    /// encrypt the output with the passphrase in the TAXC_PASSPHRASE environment variable
+    #[argh(switch)]
+    encrypt: bool,
+    /// print the number of transactions of each kind, a sample of them and the records which
+    /// can't be imported e.g. with unknown currencies or dates, without writing anything
+    #[argh(switch)]
+    dry_run: bool,
+    /// append the transactions to this csv file instead of writing to stdout, recording the
+    /// import so it can be undone with `imports undo`
+    #[argh(option)]
+    append: Option<PathBuf>,
+    /// add the transactions to this SQLite database instead of writing to stdout, skipping those
+    /// already in it from earlier imports, for `report --from-db`
+    #[argh(option)]
+    into_db: Option<PathBuf>,
+    /// recompute the rate of trades from their buy and sell amounts when missing or differing by
+    /// more than --rate-tolerance, logging each correction
+    #[argh(switch)]
+    recompute_rates: bool,
+    /// the proportion by which a rate may differ from the rate implied by the amounts before it
+    /// is recomputed with --recompute-rates, default 0.01
+    #[argh(option)]
+    rate_tolerance: Option<Decimal>,
+    /// for bittrex order history, whether the trade time is when the order was opened or closed
+    /// (the default)
+    #[argh(option, default = "OrderTimestamp::Closed")]
+    timestamp: OrderTimestamp,
+}
+
+/// Which time of an order is used for the trade
+#[derive(PartialEq, Debug)]
+pub enum OrderTimestamp {
+    Opened,
+    Closed,
+}
+
+impl std::str::FromStr for OrderTimestamp {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "opened" => Ok(Self::Opened),
+            "closed" => Ok(Self::Closed),
+            t => Err(format!(
+                "Invalid timestamp {}, expected opened or closed",
+                t
+            )),
+        }
+    }
 }
 
 impl ImportExchangeCsvCommand {
     pub fn exec(&self) -> color_eyre::Result<()> {
         match self.exchange {
             Exchange::Uphold => self.import_csv::<exchanges::uphold::Record, _>(),
+            Exchange::Zcash => self.import_csv::<exchanges::zcash::Record, _>(),
             Exchange::Poloniex => self.import_csv::<exchanges::poloniex::Record, _>(),
-            Exchange::Bittrex => self.import_csv::<exchanges::bittrex::Record, _>(),
+            Exchange::Bittrex => match self.timestamp {
+                OrderTimestamp::Opened => self.import_csv::<exchanges::bittrex::OpenedRecord, _>(),
+                OrderTimestamp::Closed => self.import_csv::<exchanges::bittrex::Record, _>(),
+            },
+            Exchange::BittrexFills => self.import_csv::<exchanges::bittrex::FillRecord, _>(),
             Exchange::Binance => self.import_csv::<exchanges::binance::CsvRecord, _>(),
+            Exchange::BinanceEarn => self.import_csv::<exchanges::binance::EarnCsvRecord, _>(),
+            Exchange::BinanceTradeHistory => {
+                self.import_csv::<exchanges::binance::TradeHistoryRecord, _>()
+            }
+            Exchange::BinanceTransactions => self.import(
+                exchanges::binance::read_transaction_history(File::open(&self.file)?)?,
+            ),
             Exchange::Coinbase => self.import_csv::<exchanges::coinbase::Record, _>(),
+            Exchange::CoinbaseHistory => self.import_csv::<exchanges::coinbase::HistoryRecord, _>(),
+            Exchange::Deribit => self.import_csv::<exchanges::deribit::Record, _>(),
+            Exchange::Etherscan => self.import_csv::<exchanges::etherscan::Record, _>(),
+            Exchange::Kraken => self.import_csv::<exchanges::kraken::Record, _>(),
+            Exchange::KrakenLedgers => self.import_csv::<exchanges::kraken::LedgerRecord, _>(),
+            Exchange::Monero => self.import_csv::<exchanges::monero::Record, _>(),
+            Exchange::NiceHash => self.import_csv::<exchanges::nicehash::Record, _>(),
+            Exchange::Otc => self.import_csv::<exchanges::otc::Record, _>(),
         }
     }
 
     fn import_csv<'a, CsvRecord, E>(&self) -> color_eyre::Result<()>
     where
-        CsvRecord: Clone + DeserializeOwned + TryInto<Trade<'a>, Error = E>,
+        CsvRecord: Clone + DeserializeOwned + TryInto<Transaction<'a>, Error = E>,
         E: std::error::Error + 'static + Send + Sync,
     {
         let file = File::open(&self.file)?;
-        let mut rdr = csv::Reader::from_reader(file);
-        let result: Result<Vec<CsvRecord>, _> = rdr.deserialize().collect();
-        let result = result?;
-        log::info!("Read {} csv records", result.len());
-        let mut trades = result
-            .iter()
-            .cloned()
-            .map(|record: CsvRecord| TryInto::try_into(record).map_err(Into::into))
-            .collect::<color_eyre::Result<Vec<Trade>>>()?;
-        trades.sort_by(|tx1, tx2| tx1.date_time.cmp(&tx2.date_time));
-
-        let trades = if self.group_by_day {
-            crate::trades::group_trades_by_day(&trades)
+        if self.dry_run {
+            let (transactions, problems) = preview::read_exchange_csv::<CsvRecord, E, _>(file)?;
+            let transactions = self.process(transactions);
+            return preview::write_preview(&transactions, &problems, io::stdout());
+        }
+        self.import(read_exchange_csv::<CsvRecord, E, _>(file)?)
+    }
+
+    /// Write out the transactions read from the file, once processed
+    fn import(&self, transactions: Vec<Transaction>) -> color_eyre::Result<()> {
+        let transactions = self.process(transactions);
+        if self.dry_run {
+            return preview::write_preview(&transactions, &[], io::stdout());
+        }
+
+        let trade_records: Vec<_> = transactions.iter().map(TradeRecord::from).collect();
+        if let Some(ref txs) = self.append {
+            if self.encrypt {
+                return Err(eyre::eyre!(
+                    "Appending to an encrypted transactions file is not supported"
+                ));
+            }
+            let source = format!("{:?} {}", self.exchange, self.file.display());
+            imports::append_import(txs, source, trade_records)?;
+            return Ok(());
+        }
+        if let Some(ref db) = self.into_db {
+            let source = format!("{:?} {}", self.exchange, self.file.display());
+            return insert_into_db(db, &trade_records, &source);
+        }
+        encryption::write_stdout(self.encrypt, |out| {
+            crate::utils::write_csv(trade_records, out)
+        })
+    }
+
+    /// Label, correct and combine the transactions read from the file
+    fn process<'a>(&self, mut transactions: Vec<Transaction<'a>>) -> Vec<Transaction<'a>> {
+        if let Some(ref account) = self.account {
+            transactions
+                .iter_mut()
+                .for_each(|tx| tx.set_account(account));
+        }
+        if self.recompute_rates {
+            let tolerance = self.rate_tolerance.unwrap_or_else(warnings::rate_tolerance);
+            recompute_rates(&mut transactions, tolerance);
+        }
+
+        let transactions = if self.group_by_day {
+            transactions::group_trades_by_day(transactions)
         } else {
-            trades
+            transactions
         };
+        if self.combine_gas {
+            gas::combine_by_day(transactions)
+        } else {
+            transactions
+        }
+    }
+}
+
+/// Add the records to the database, skipping those already imported
+fn insert_into_db(db: &Path, records: &[TradeRecord], source: &str) -> color_eyre::Result<()> {
+    let added = Store::open(db)?.insert_transactions(records, source)?;
+    tracing::info!(
+        "Added {} transactions to {}, {} were already imported",
+        added,
+        db.display(),
+        records.len() - added
+    );
+    Ok(())
+}
 
-        let trade_records = trades.iter().map(|t| TradeRecord::from(t)).collect();
-        crate::utils::write_csv(trade_records, io::stdout())
+/// Read transactions from an exchange csv export, sorted by date
+fn read_exchange_csv<'a, CsvRecord, E, R>(reader: R) -> color_eyre::Result<Vec<Transaction<'a>>>
+where
+    CsvRecord: Clone + DeserializeOwned + TryInto<Transaction<'a>, Error = E>,
+    E: std::error::Error + 'static + Send + Sync,
+    R: io::Read,
+{
+    let mut rdr = csv::Reader::from_reader(reader);
+    let result: Result<Vec<CsvRecord>, _> = rdr.deserialize().collect();
+    let result = result?;
+    tracing::info!("Read {} csv records", result.len());
+    let mut transactions = result
+        .iter()
+        .cloned()
+        .map(|record: CsvRecord| TryInto::try_into(record).map_err(Into::into))
+        .collect::<color_eyre::Result<Vec<Transaction>>>()?;
+    transactions.sort_by_key(|tx| tx.date_time());
+    Ok(transactions)
+}
+
+/// Recompute rounded or missing trade rates from the buy and sell amounts, logging corrections
+fn recompute_rates(transactions: &mut [Transaction], tolerance: Decimal) {
+    for tx in transactions.iter_mut() {
+        if let Some(original) = tx.recompute_rate(tolerance) {
+            tracing::warn!(
+                "Corrected rate of trade at {} from {} to {}",
+                tx.date_time(),
+                original,
+                tx.rate()
+            );
+        }
     }
 }
 
@@ -126,10 +397,38 @@ impl ImportExchangeCsvCommand {
 #[derive(PartialEq, Debug)]
 pub enum Exchange {
     Binance,
+    /// Savings, Simple Earn and Launchpool rows of the Binance transaction history statement
+    BinanceEarn,
+    /// Binance Trade History export, with the amounts suffixed with their asset
+    BinanceTradeHistory,
+    /// The full Binance Transaction History statement of deposits, withdrawals, trades, earn and
+    /// distributions
+    BinanceTransactions,
     Bittrex,
+    /// Bittrex executions export, with a row for each fill of an order
+    BittrexFills,
     Coinbase,
+    /// Retail Coinbase transaction history report of buys, sells, conversions, rewards and sends
+    CoinbaseHistory,
+    /// Deribit transaction log of option and futures trades, settlements and transfers
+    Deribit,
+    /// Etherscan export of the transactions of an Ethereum address, including failed ones
+    Etherscan,
+    /// Kraken trades.csv export
+    Kraken,
+    /// Deposits, withdrawals and staking rewards of the Kraken ledgers.csv export, without the
+    /// trade rows which are in trades.csv
+    KrakenLedgers,
+    /// Monero GUI wallet transaction export, or a view key scan in the same format
+    Monero,
+    /// NiceHash BTC wallet report of mining payouts
+    NiceHash,
+    /// Template for OTC and peer to peer trades, see `exchanges::otc::Record`
+    Otc,
     Poloniex,
     Uphold,
+    /// Zcash wallet transactions, see `exchanges::zcash::Record`
+    Zcash,
 }
 
 impl std::str::FromStr for Exchange {
@@ -138,10 +437,23 @@ impl std::str::FromStr for Exchange {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
             "binance" => Ok(Self::Binance),
+            "binance-earn" => Ok(Self::BinanceEarn),
+            "binance-trade-history" => Ok(Self::BinanceTradeHistory),
+            "binance-transactions" => Ok(Self::BinanceTransactions),
             "bittrex" => Ok(Self::Bittrex),
+            "bittrex-fills" => Ok(Self::BittrexFills),
             "coinbase" => Ok(Self::Coinbase),
+            "coinbase-history" => Ok(Self::CoinbaseHistory),
+            "deribit" => Ok(Self::Deribit),
+            "etherscan" => Ok(Self::Etherscan),
+            "kraken" => Ok(Self::Kraken),
+            "kraken-ledgers" => Ok(Self::KrakenLedgers),
+            "monero" => Ok(Self::Monero),
+            "nicehash" => Ok(Self::NiceHash),
+            "otc" => Ok(Self::Otc),
             "poloniex" => Ok(Self::Poloniex),
             "uphold" => Ok(Self::Uphold),
+            "zcash" => Ok(Self::Zcash),
             e => Err(ExchangeError::UnsupportedExchange(e.into())),
         }
     }
@@ -0,0 +1,167 @@
+//! A preview of an import without writing anything, for checking that a new export is read as
+//! expected e.g. `taxc import csv kraken trades.csv --dry-run`.
+//!
+//! Unlike an import, the records which can't be imported don't stop the preview, they are
+//! reported after the transactions which can be.
+
+use super::exchanges::ExchangeError;
+use crate::{money, trades::TradeRecord, transactions::Transaction};
+use serde::de::DeserializeOwned;
+use std::{
+    collections::BTreeMap,
+    convert::TryInto,
+    io::{self, Write},
+};
+
+/// The number of the normalised transactions shown
+const SAMPLE_ROWS: usize = 5;
+
+/// Why a record of the file couldn't be imported
+#[derive(Debug, Clone, PartialEq)]
+pub enum Problem {
+    UnknownCurrency(money::UnknownCurrency),
+    InvalidDate(String),
+    Invalid(String),
+}
+
+impl Problem {
+    fn of_error(err: &(dyn std::error::Error + 'static)) -> Self {
+        match err.downcast_ref::<ExchangeError>() {
+            Some(ExchangeError::UnknownCurrency(err)) => Problem::UnknownCurrency(err.clone()),
+            Some(ExchangeError::DateParse(err)) => Problem::InvalidDate(err.to_string()),
+            _ => Problem::Invalid(err.to_string()),
+        }
+    }
+}
+
+/// Read the transactions of an exchange csv export, sorted by date, with the problems of the
+/// records which couldn't be read by their row in the file, the header being row 1
+pub fn read_exchange_csv<'a, CsvRecord, E, R>(
+    reader: R,
+) -> color_eyre::Result<(Vec<Transaction<'a>>, Vec<(usize, Problem)>)>
+where
+    CsvRecord: DeserializeOwned + TryInto<Transaction<'a>, Error = E>,
+    E: std::error::Error + 'static + Send + Sync,
+    R: io::Read,
+{
+    let mut rdr = csv::Reader::from_reader(reader);
+    let mut transactions = Vec::new();
+    let mut problems = Vec::new();
+    for (i, record) in rdr.deserialize::<CsvRecord>().enumerate() {
+        let row = i + 2;
+        let record = match record {
+            Ok(record) => record,
+            Err(err) => {
+                problems.push((row, Problem::Invalid(err.to_string())));
+                continue;
+            }
+        };
+        match record.try_into() {
+            Ok(tx) => transactions.push(tx),
+            Err(err) => problems.push((row, Problem::of_error(&err))),
+        }
+    }
+    transactions.sort_by_key(|tx| tx.date_time());
+    Ok((transactions, problems))
+}
+
+/// Writes the number of transactions of each kind, a sample of them as they would be imported,
+/// and the problems with the records which couldn't be
+pub fn write_preview<W>(
+    transactions: &[Transaction],
+    problems: &[(usize, Problem)],
+    mut writer: W,
+) -> color_eyre::Result<()>
+where
+    W: Write,
+{
+    let mut kinds = BTreeMap::new();
+    for tx in transactions {
+        *kinds.entry(tx.kind().to_string()).or_insert(0) += 1;
+    }
+    writeln!(
+        writer,
+        "{} transactions, {} records couldn't be imported",
+        transactions.len(),
+        problems.len()
+    )?;
+    for (kind, count) in kinds.iter() {
+        writeln!(writer, "{}: {}", kind, count)?;
+    }
+
+    writeln!(writer)?;
+    let sample = transactions
+        .iter()
+        .take(SAMPLE_ROWS)
+        .map(TradeRecord::from)
+        .collect();
+    crate::utils::write_csv(sample, &mut writer)?;
+
+    let mut unknown_currencies: BTreeMap<&str, (&money::UnknownCurrency, Vec<String>)> =
+        BTreeMap::new();
+    let mut others = Vec::new();
+    for (row, problem) in problems {
+        match problem {
+            Problem::UnknownCurrency(err) => unknown_currencies
+                .entry(&err.code)
+                .or_insert_with(|| (err, Vec::new()))
+                .1
+                .push(row.to_string()),
+            Problem::InvalidDate(err) => {
+                others.push(format!("Invalid date in row {}: {}", row, err))
+            }
+            Problem::Invalid(err) => others.push(format!("Invalid record in row {}: {}", row, err)),
+        }
+    }
+    if !problems.is_empty() {
+        writeln!(writer)?;
+    }
+    for (err, rows) in unknown_currencies.values() {
+        writeln!(writer, "{}, in rows {}", err, rows.join(", "))?;
+    }
+    for other in others {
+        writeln!(writer, "{}", other)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cmd::import::exchanges::binance, transactions::TransactionKind};
+
+    #[test]
+    fn records_which_cant_be_imported_are_reported_by_row() {
+        let export = "\
+Date(UTC),Market,Type,Price,Amount,Total,Fee,Fee Coin
+2020-12-02 11:30:00,DOTGBP,SELL,4.50,10,45.00,0.05,GBP
+2020-12-01 10:00:00,XYZBTC,BUY,0.03,2.5,0.075,0.0025,XYZ
+01/12/2020 10:00,ETHBTC,BUY,0.03,2.5,0.075,0.0025,ETH
+2020-12-01 10:00:00,ETHBTC,BUY,0.03,2.5,0.075,0.0025,ETH
+";
+        let (transactions, problems) =
+            read_exchange_csv::<binance::CsvRecord, _, _>(export.as_bytes()).unwrap();
+
+        assert_eq!(
+            transactions.iter().map(|tx| tx.kind()).collect::<Vec<_>>(),
+            vec![TransactionKind::Buy, TransactionKind::Sell]
+        );
+        assert_eq!(problems.len(), 2);
+        assert_eq!(
+            problems[0],
+            (
+                3,
+                Problem::UnknownCurrency(money::UnknownCurrency::new("XYZ"))
+            )
+        );
+        assert!(matches!(problems[1], (4, Problem::InvalidDate(_))));
+
+        let mut preview = Vec::new();
+        write_preview(&transactions, &problems, &mut preview).unwrap();
+        let preview = String::from_utf8(preview).unwrap();
+        assert!(preview.starts_with("2 transactions, 2 records couldn't be imported\nBuy: 1\n"));
+        assert!(preview.contains(
+            "No currency with code XYZ found, add it with `currencies add XYZ`, in rows 3\n"
+        ));
+    }
+}
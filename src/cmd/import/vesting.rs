@@ -0,0 +1,154 @@
+use crate::{
+    money::{amount, zero},
+    trades::TradeRecord,
+    transactions::{Movement, Transaction},
+};
+use argh::FromArgs;
+use chrono::{Datelike, NaiveDate};
+use color_eyre::eyre;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::{fs::File, io, path::PathBuf};
+
+/// Expand token vesting schedules into an acquisition at each unlock, valued at the price on the
+/// unlock date when reporting
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "vesting")]
+pub struct ImportVestingCommand {
+    /// the csv file containing the vesting schedules, with columns: asset, total_amount,
+    /// start_date, cliff_months, vesting_months, notes
+    #[argh(positional)]
+    file: PathBuf,
+}
+
+impl ImportVestingCommand {
+    pub fn exec(&self) -> color_eyre::Result<()> {
+        let mut rdr = csv::Reader::from_reader(File::open(&self.file)?);
+        let schedules: Vec<VestingSchedule> = rdr.deserialize().collect::<Result<_, _>>()?;
+        let mut unlocks = schedules
+            .iter()
+            .map(VestingSchedule::unlocks)
+            .collect::<color_eyre::Result<Vec<_>>>()?
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+        unlocks.sort_by_key(|tx| tx.date_time());
+        tracing::info!(
+            "Expanded {} vesting schedules into {} unlocks",
+            schedules.len(),
+            unlocks.len()
+        );
+        let trade_records = unlocks.iter().map(TradeRecord::from).collect();
+        crate::utils::write_csv(trade_records, io::stdout())
+    }
+}
+
+/// Tokens which unlock monthly over `vesting_months` from the start date. Nothing unlocks before
+/// the cliff, at which point all the tokens vested so far unlock at once.
+#[derive(Debug, Clone, Deserialize)]
+struct VestingSchedule {
+    asset: String,
+    total_amount: Decimal,
+    start_date: NaiveDate,
+    cliff_months: u32,
+    vesting_months: u32,
+    #[serde(default)]
+    notes: String,
+}
+
+impl VestingSchedule {
+    /// An income transaction for each unlock, the last of which includes any rounding remainder
+    fn unlocks<'a>(&self) -> color_eyre::Result<Vec<Transaction<'a>>> {
+        if self.vesting_months == 0 || self.cliff_months > self.vesting_months {
+            return Err(eyre::eyre!(
+                "Vesting months for {} should be at least one and not before the cliff",
+                self.asset
+            ));
+        }
+        let currency = crate::money::find(&self.asset)
+            .ok_or(eyre::eyre!("Unknown vesting asset {}", self.asset))?;
+        let monthly =
+            (self.total_amount / Decimal::from(self.vesting_months)).round_dp(currency.exponent);
+
+        let first_unlock = std::cmp::max(self.cliff_months, 1);
+        let mut unlocked = Decimal::new(0, 0);
+        (first_unlock..=self.vesting_months)
+            .map(|month| {
+                let unlock = if month == self.vesting_months {
+                    self.total_amount - unlocked
+                } else {
+                    monthly * Decimal::from(month) - unlocked
+                };
+                unlocked += unlock;
+                let date = add_months(self.start_date, month)?;
+                let notes = match self.notes.as_ref() {
+                    "" => format!("Vesting unlock {}/{}", month, self.vesting_months),
+                    notes => format!(
+                        "{}: vesting unlock {}/{}",
+                        notes, month, self.vesting_months
+                    ),
+                };
+                Ok(Transaction::Income(Movement {
+                    date_time: date.and_hms(0, 0, 0),
+                    amount: amount(&self.asset, unlock)?,
+                    fee: zero(currency),
+                    exchange: None,
+                    account: None,
+                    ownership_changed: false,
+                    notes: Some(notes),
+                }))
+            })
+            .collect()
+    }
+}
+
+/// Add calendar months to a date, clamping the day to the end of shorter months
+fn add_months(date: NaiveDate, months: u32) -> color_eyre::Result<NaiveDate> {
+    let month0 = date.month0() + months;
+    let year = date.year() + (month0 / 12) as i32;
+    let month = month0 % 12 + 1;
+    (1..=date.day())
+        .rev()
+        .find_map(|day| NaiveDate::from_ymd_opt(year, month, day))
+        .ok_or(eyre::eyre!("Invalid date {} plus {} months", date, months))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn unlocks_monthly_after_the_cliff() {
+        let schedule = VestingSchedule {
+            asset: "ETH".into(),
+            total_amount: dec!(4800),
+            start_date: NaiveDate::from_ymd(2020, 1, 31),
+            cliff_months: 12,
+            vesting_months: 48,
+            notes: String::new(),
+        };
+        let unlocks = schedule.unlocks().unwrap();
+
+        assert_eq!(unlocks.len(), 37);
+        let amounts = unlocks
+            .iter()
+            .map(|tx| *tx.buy().unwrap().amount())
+            .collect::<Vec<_>>();
+        assert_eq!(amounts[0], dec!(1200));
+        assert_eq!(amounts[1], dec!(100));
+        assert_eq!(amounts.iter().sum::<Decimal>(), dec!(4800));
+        assert_eq!(
+            unlocks[0].date_time().date(),
+            NaiveDate::from_ymd(2021, 1, 31)
+        );
+        assert_eq!(
+            unlocks[1].date_time().date(),
+            NaiveDate::from_ymd(2021, 2, 28)
+        );
+        assert_eq!(
+            unlocks[36].date_time().date(),
+            NaiveDate::from_ymd(2024, 1, 31)
+        );
+    }
+}
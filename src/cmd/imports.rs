@@ -0,0 +1,248 @@
+use crate::{cmd::journal, trades::TradeRecord, utils::replace_file};
+use argh::FromArgs;
+use chrono::{NaiveDateTime, Utc};
+use color_eyre::eyre;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+};
+
+/// An import appended to a transactions file, recorded so that it can be undone
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ImportSession {
+    pub id: u32,
+    pub imported_at: NaiveDateTime,
+    /// The exchange and file or api the transactions were imported from
+    pub source: String,
+    pub records: Vec<TradeRecord>,
+}
+
+/// The import sessions are stored alongside the transactions file e.g. `txs.csv.imports.json`
+pub(crate) fn sessions_path(txs: &Path) -> PathBuf {
+    let mut path = txs.as_os_str().to_os_string();
+    path.push(".imports.json");
+    PathBuf::from(path)
+}
+
+fn read_sessions(txs: &Path) -> color_eyre::Result<Vec<ImportSession>> {
+    let path = sessions_path(txs);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    Ok(serde_json::from_reader(File::open(path)?)?)
+}
+
+fn write_sessions(txs: &Path, sessions: &[ImportSession]) -> color_eyre::Result<()> {
+    replace_file(&sessions_path(txs), |file| {
+        Ok(serde_json::to_writer_pretty(file, sessions)?)
+    })
+}
+
+fn write_records(txs: &Path, records: Vec<TradeRecord>) -> color_eyre::Result<()> {
+    replace_file(txs, |file| crate::utils::write_csv(records, file))
+}
+
+pub fn read_records(txs: &Path) -> color_eyre::Result<Vec<TradeRecord>> {
+    if !txs.exists() {
+        return Ok(Vec::new());
+    }
+    let mut rdr = csv::Reader::from_reader(File::open(txs)?);
+    Ok(rdr.deserialize().collect::<Result<_, _>>()?)
+}
+
+/// Append the records to the transactions file, recording them as a new import session.
+/// Returns the id of the session.
+pub fn append_import(
+    txs: &Path,
+    source: String,
+    records: Vec<TradeRecord>,
+) -> color_eyre::Result<u32> {
+    let mut all_records = read_records(txs)?;
+    all_records.extend(records.iter().cloned());
+    write_records(txs, all_records)?;
+
+    let mut sessions = read_sessions(txs)?;
+    let id = sessions.iter().map(|s| s.id).max().unwrap_or(0) + 1;
+    tracing::info!("Import {}: appended {} transactions", id, records.len());
+    let details = format!("{}: {} transactions from {}", id, records.len(), source);
+    journal::record(txs, "import", details)?;
+    sessions.push(ImportSession {
+        id,
+        imported_at: Utc::now().naive_utc(),
+        source,
+        records,
+    });
+    write_sessions(txs, &sessions)?;
+    Ok(id)
+}
+
+/// List or undo previous imports into a transactions file
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "imports")]
+pub struct ImportsCommand {
+    #[argh(subcommand)]
+    sub: ImportsSubCommand,
+}
+
+impl ImportsCommand {
+    pub fn exec(&self) -> color_eyre::Result<()> {
+        match self.sub {
+            ImportsSubCommand::List(ref list) => list.exec(),
+            ImportsSubCommand::Undo(ref undo) => undo.exec(),
+        }
+    }
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+pub enum ImportsSubCommand {
+    List(ListImportsCommand),
+    Undo(UndoImportCommand),
+}
+
+/// List the imports recorded for a transactions file
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "list")]
+pub struct ListImportsCommand {
+    /// the csv file containing the transactions
+    #[argh(option)]
+    txs: PathBuf,
+}
+
+#[derive(Serialize)]
+struct ImportSessionRecord {
+    id: u32,
+    imported_at: NaiveDateTime,
+    source: String,
+    transactions: usize,
+}
+
+impl ListImportsCommand {
+    pub fn exec(&self) -> color_eyre::Result<()> {
+        let records = read_sessions(&self.txs)?
+            .into_iter()
+            .map(|session| ImportSessionRecord {
+                id: session.id,
+                imported_at: session.imported_at,
+                source: session.source,
+                transactions: session.records.len(),
+            })
+            .collect();
+        crate::utils::write_csv(records, io::stdout())
+    }
+}
+
+/// Remove the transactions added by an import from the transactions file
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "undo")]
+pub struct UndoImportCommand {
+    /// the id of the import to undo, as shown by `imports list`
+    #[argh(positional)]
+    id: u32,
+    /// the csv file containing the transactions
+    #[argh(option)]
+    txs: PathBuf,
+}
+
+impl UndoImportCommand {
+    pub fn exec(&self) -> color_eyre::Result<()> {
+        let mut sessions = read_sessions(&self.txs)?;
+        let index = sessions
+            .iter()
+            .position(|s| s.id == self.id)
+            .ok_or(eyre::eyre!("Import {} not found", self.id))?;
+        let session = sessions.remove(index);
+
+        let mut records = read_records(&self.txs)?;
+        for imported in session.records.iter() {
+            // records may have been edited or removed since the import, so skip any missing
+            match records.iter().position(|r| r == imported) {
+                Some(position) => {
+                    records.remove(position);
+                }
+                None => tracing::warn!("Imported transaction not found: {:?}", imported),
+            }
+        }
+        write_records(&self.txs, records)?;
+        write_sessions(&self.txs, &sessions)?;
+        let details = format!("{} from {}", session.id, session.source);
+        journal::record(&self.txs, "undo-import", details)?;
+        tracing::info!("Undone import {} from {}", session.id, session.source);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::Decimal;
+
+    fn record(date_time: &str, amount: &str) -> TradeRecord {
+        TradeRecord {
+            version: crate::trades::SCHEMA_VERSION,
+            date_time: date_time.into(),
+            kind: "Deposit".into(),
+            buy_asset: "BTC".into(),
+            buy_amount: amount.into(),
+            sell_asset: String::new(),
+            sell_amount: String::new(),
+            fee_asset: String::new(),
+            fee_amount: String::new(),
+            rate: Decimal::new(1, 0),
+            exchange: "Kraken".into(),
+            exchange_id: String::new(),
+            notes: String::new(),
+            ownership_changed: None,
+            received_date_time: None,
+            account: None,
+        }
+    }
+
+    #[test]
+    fn imports_are_appended_and_undone() {
+        let dir = std::env::temp_dir().join(format!("taxc-imports-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let txs = dir.join("txs.csv");
+
+        let first = vec![record("2020-01-01T00:00:00+00:00", "1")];
+        let second = vec![
+            record("2020-02-01T00:00:00+00:00", "2"),
+            record("2020-03-01T00:00:00+00:00", "3"),
+        ];
+        assert_eq!(
+            append_import(&txs, "first".into(), first.clone()).unwrap(),
+            1
+        );
+        assert_eq!(append_import(&txs, "second".into(), second).unwrap(), 2);
+        assert_eq!(read_records(&txs).unwrap().len(), 3);
+        let sessions = read_sessions(&txs).unwrap();
+        assert_eq!(
+            sessions
+                .iter()
+                .map(|s| s.source.as_str())
+                .collect::<Vec<_>>(),
+            vec!["first", "second"]
+        );
+        assert!(!dir.join("txs.csv.tmp").exists());
+
+        let undo = UndoImportCommand {
+            id: 2,
+            txs: txs.clone(),
+        };
+        undo.exec().unwrap();
+        assert_eq!(read_records(&txs).unwrap(), first);
+        assert_eq!(
+            read_sessions(&txs)
+                .unwrap()
+                .iter()
+                .map(|s| s.id)
+                .collect::<Vec<_>>(),
+            vec![1]
+        );
+        assert!(undo.exec().is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,266 @@
+//! An append only log of the changes to a transactions file and the reports generated from it,
+//! for demonstrating when the records were created and that they haven't been altered since
+//! e.g. during an HMRC enquiry.
+//!
+//! Each entry records who did what and when, with the hash of the transactions file afterwards.
+//! The entries are chained by including the hash of the previous entry in the hash of each, so
+//! editing or removing an entry breaks the chain from that point, and the transactions file
+//! being edited outside of taxc shows as a change of its hash between entries.
+
+use argh::FromArgs;
+use chrono::{NaiveDateTime, Utc};
+use color_eyre::eyre;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub seq: u64,
+    pub recorded_at: NaiveDateTime,
+    /// The user name of the account which ran taxc
+    pub user: String,
+    /// e.g. `import`, `undo-import` or `report`
+    pub action: String,
+    pub details: String,
+    /// The hash of the transactions file after the action, empty if there is no file
+    pub transactions_sha256: String,
+    /// The hash of the previous entry, empty for the first
+    pub previous: String,
+    pub hash: String,
+}
+
+impl Entry {
+    fn compute_hash(&self) -> String {
+        let content = format!(
+            "{}|{}|{}|{}|{}|{}|{}",
+            self.seq,
+            self.recorded_at,
+            self.user,
+            self.action,
+            self.details,
+            self.transactions_sha256,
+            self.previous
+        );
+        sha256(content.as_bytes())
+    }
+}
+
+/// The journal is stored alongside the transactions file as json lines e.g.
+/// `txs.csv.journal.jsonl`
+pub(crate) fn journal_path(txs: &Path) -> PathBuf {
+    let mut path = txs.as_os_str().to_os_string();
+    path.push(".journal.jsonl");
+    PathBuf::from(path)
+}
+
+fn read_entries(txs: &Path) -> color_eyre::Result<Vec<Entry>> {
+    let path = journal_path(txs);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    BufReader::new(File::open(path)?)
+        .lines()
+        .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+        .map(|line| Ok(serde_json::from_str(&line?)?))
+        .collect()
+}
+
+fn transactions_sha256(txs: &Path) -> color_eyre::Result<String> {
+    if !txs.exists() {
+        return Ok(String::new());
+    }
+    Ok(sha256(&std::fs::read(txs)?))
+}
+
+fn sha256(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+fn user() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+/// Append an entry for the action to the journal of the transactions file, after the action
+pub fn record(txs: &Path, action: &str, details: String) -> color_eyre::Result<Entry> {
+    let last = read_entries(txs)?.pop();
+    let mut entry = Entry {
+        seq: last.as_ref().map_or(1, |last| last.seq + 1),
+        recorded_at: Utc::now().naive_utc(),
+        user: user(),
+        action: action.to_string(),
+        details,
+        transactions_sha256: transactions_sha256(txs)?,
+        previous: last.map(|last| last.hash).unwrap_or_default(),
+        hash: String::new(),
+    };
+    entry.hash = entry.compute_hash();
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(journal_path(txs))?;
+    writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+    Ok(entry)
+}
+
+/// The problems with the chain of entries, and whether the transactions file has changed since
+/// the last entry
+fn verify(entries: &[Entry], current_sha256: &str) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut previous: Option<&Entry> = None;
+    for entry in entries {
+        if entry.hash != entry.compute_hash() {
+            problems.push(format!("Entry {} has been altered", entry.seq));
+        }
+        let expected_previous = previous.map_or("", |p| p.hash.as_str());
+        if entry.previous != expected_previous {
+            problems.push(format!(
+                "Entry {} doesn't follow the entry before it, entries have been removed or \
+                 reordered",
+                entry.seq
+            ));
+        }
+        if let Some(previous) = previous {
+            // reports don't change the file, so it was edited outside of taxc in between
+            if entry.action == "report" && entry.transactions_sha256 != previous.transactions_sha256
+            {
+                problems.push(format!(
+                    "The transactions file was changed outside of taxc between entries {} and {}",
+                    previous.seq, entry.seq
+                ));
+            }
+        }
+        previous = Some(entry);
+    }
+    if let Some(last) = previous {
+        if last.transactions_sha256 != current_sha256 {
+            problems.push(format!(
+                "The transactions file was changed outside of taxc since entry {}",
+                last.seq
+            ));
+        }
+    }
+    problems
+}
+
+/// List or verify the journal of changes to a transactions file and reports generated from it
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "journal")]
+pub struct JournalCommand {
+    #[argh(subcommand)]
+    sub: JournalSubCommand,
+}
+
+impl JournalCommand {
+    /// Returns the number of problems found with the journal
+    pub fn exec(&self) -> color_eyre::Result<usize> {
+        match self.sub {
+            JournalSubCommand::List(ref list) => list.exec().map(|()| 0),
+            JournalSubCommand::Verify(ref verify) => verify.exec(),
+        }
+    }
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+pub enum JournalSubCommand {
+    List(ListJournalCommand),
+    Verify(VerifyJournalCommand),
+}
+
+/// List the entries of the journal of a transactions file
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "list")]
+pub struct ListJournalCommand {
+    /// the csv file containing the transactions
+    #[argh(option)]
+    txs: PathBuf,
+}
+
+impl ListJournalCommand {
+    pub fn exec(&self) -> color_eyre::Result<()> {
+        crate::utils::write_csv(read_entries(&self.txs)?, io::stdout())
+    }
+}
+
+/// Check the chain of the journal entries and that the transactions file hasn't changed since
+/// the last, outputting the hash of the last entry to keep as a record of the journal so far
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "verify")]
+pub struct VerifyJournalCommand {
+    /// the csv file containing the transactions
+    #[argh(option)]
+    txs: PathBuf,
+}
+
+impl VerifyJournalCommand {
+    pub fn exec(&self) -> color_eyre::Result<usize> {
+        let entries = read_entries(&self.txs)?;
+        let last = entries
+            .last()
+            .ok_or_else(|| eyre::eyre!("No journal found for {}", self.txs.display()))?;
+        let problems = verify(&entries, &transactions_sha256(&self.txs)?);
+        for problem in problems.iter() {
+            tracing::warn!("{}", problem);
+        }
+        tracing::info!("Verified {} journal entries", entries.len());
+        println!("{}", last.hash);
+        Ok(problems.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries() -> Vec<Entry> {
+        let mut entries = Vec::new();
+        let mut previous = String::new();
+        for (seq, (action, file)) in [("import", "a"), ("report", "a"), ("import", "b")]
+            .iter()
+            .enumerate()
+        {
+            let mut entry = Entry {
+                seq: seq as u64 + 1,
+                recorded_at: NaiveDateTime::from_timestamp(1_600_000_000 + seq as i64, 0),
+                user: "alice".into(),
+                action: action.to_string(),
+                details: String::new(),
+                transactions_sha256: sha256(file.as_bytes()),
+                previous: previous.clone(),
+                hash: String::new(),
+            };
+            entry.hash = entry.compute_hash();
+            previous = entry.hash.clone();
+            entries.push(entry);
+        }
+        entries
+    }
+
+    #[test]
+    fn altered_removed_and_outside_changes_break_the_chain() {
+        let current = sha256(b"b");
+        assert!(verify(&entries(), &current).is_empty());
+
+        let mut altered = entries();
+        altered[1].details = "edited".into();
+        assert_eq!(verify(&altered, &current), vec!["Entry 2 has been altered"]);
+
+        let mut removed = entries();
+        removed.remove(1);
+        assert_eq!(verify(&removed, &current).len(), 1);
+
+        let problems = verify(&entries(), &sha256(b"c"));
+        assert_eq!(
+            problems,
+            vec!["The transactions file was changed outside of taxc since entry 3"]
+        );
+    }
+}
@@ -0,0 +1,188 @@
+use crate::{
+    encryption,
+    trades::{TradeRecord, SCHEMA_VERSION},
+};
+use argh::FromArgs;
+use color_eyre::eyre;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::path::PathBuf;
+
+/// Upgrade a transactions csv file to the current schema version
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "migrate")]
+pub struct MigrateCommand {
+    /// the csv file containing the transactions to upgrade
+    #[argh(positional)]
+    file: PathBuf,
+    /// encrypt the output with the passphrase in the TAXC_PASSPHRASE environment variable
+    #[argh(switch)]
+    encrypt: bool,
+}
+
+impl MigrateCommand {
+    pub fn exec(&self) -> color_eyre::Result<()> {
+        let mut rdr = csv::Reader::from_reader(encryption::open(&self.file)?);
+        let headers = rdr.headers()?.clone();
+        // files written before the version column was introduced are version 1
+        let version_column = headers.iter().position(|header| header == "version");
+        let records = rdr
+            .records()
+            .map(|record| {
+                let record = record?;
+                let version = match version_column {
+                    None => 1,
+                    Some(column) => record.get(column).unwrap_or_default().parse()?,
+                };
+                migrate(version, &record, &headers)
+            })
+            .collect::<color_eyre::Result<Vec<_>>>()?;
+        tracing::info!(
+            "Migrated {} transactions to version {}",
+            records.len(),
+            SCHEMA_VERSION
+        );
+        encryption::write_stdout(self.encrypt, |out| crate::utils::write_csv(records, out))
+    }
+}
+
+/// Upgrade a single record from the given version to the current `TradeRecord`
+fn migrate(
+    version: u32,
+    record: &csv::StringRecord,
+    headers: &csv::StringRecord,
+) -> color_eyre::Result<TradeRecord> {
+    match version {
+        1 => Ok(record.deserialize::<TradeRecordV1>(Some(headers))?.into()),
+        // later versions only add columns, which are defaulted when missing
+        2..=SCHEMA_VERSION => {
+            let mut record: TradeRecord = record.deserialize(Some(headers))?;
+            if version < 5 {
+                split_account(&mut record);
+            }
+            record.version = SCHEMA_VERSION;
+            Ok(record)
+        }
+        v => Err(eyre::eyre!("Unsupported transactions csv version {}", v)),
+    }
+}
+
+/// Before version 5 an account label e.g. `binance:personal` replaced the exchange, move it to
+/// the account column and keep the exchange before the `:`
+fn split_account(record: &mut TradeRecord) {
+    if let Some(i) = record.exchange.find(':') {
+        let exchange = record.exchange[..i].to_string();
+        record.account = Some(std::mem::replace(&mut record.exchange, exchange));
+    }
+}
+
+/// The original transactions csv format, without any version column
+#[derive(Debug, Clone, Deserialize)]
+struct TradeRecordV1 {
+    date_time: String,
+    kind: String,
+    buy_asset: String,
+    buy_amount: String,
+    sell_asset: String,
+    sell_amount: String,
+    fee_asset: String,
+    fee_amount: String,
+    rate: Decimal,
+    exchange: String,
+}
+
+impl From<TradeRecordV1> for TradeRecord {
+    fn from(record: TradeRecordV1) -> Self {
+        TradeRecord {
+            version: SCHEMA_VERSION,
+            date_time: record.date_time,
+            kind: record.kind,
+            buy_asset: record.buy_asset,
+            buy_amount: record.buy_amount,
+            sell_asset: record.sell_asset,
+            sell_amount: record.sell_amount,
+            fee_asset: record.fee_asset,
+            fee_amount: record.fee_amount,
+            rate: record.rate,
+            exchange: record.exchange,
+            exchange_id: String::new(),
+            notes: String::new(),
+            ownership_changed: None,
+            received_date_time: None,
+            account: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_without_a_version_are_migrated_from_version_1() {
+        let csv = "\
+date_time,kind,buy_asset,buy_amount,sell_asset,sell_amount,fee_asset,fee_amount,rate,exchange
+2020-01-01T12:00:00+00:00,Buy,ETH,10,GBP,5000,GBP,1,500,Kraken
+";
+        let mut rdr = csv::Reader::from_reader(csv.as_bytes());
+        let headers = rdr.headers().unwrap().clone();
+        let record = rdr.records().next().unwrap().unwrap();
+
+        let migrated = migrate(1, &record, &headers).unwrap();
+        assert_eq!(migrated.version, SCHEMA_VERSION);
+        assert_eq!(
+            (migrated.buy_asset.as_str(), migrated.fee_amount.as_str()),
+            ("ETH", "1")
+        );
+        assert_eq!(migrated.exchange, "Kraken");
+        assert_eq!(migrated.notes, "");
+
+        let mut migrated_csv = Vec::new();
+        crate::utils::write_csv(vec![migrated], &mut migrated_csv).unwrap();
+        assert_eq!(
+            crate::transactions::read_csv(migrated_csv.as_slice())
+                .unwrap()
+                .len(),
+            1
+        );
+        assert!(crate::transactions::read_csv(csv.as_bytes()).is_err());
+        assert_eq!(
+            migrate(SCHEMA_VERSION + 1, &record, &headers)
+                .err()
+                .expect("Unsupported version")
+                .to_string(),
+            format!(
+                "Unsupported transactions csv version {}",
+                SCHEMA_VERSION + 1
+            )
+        );
+    }
+
+    #[test]
+    fn account_labels_are_moved_from_the_exchange() {
+        let csv = "\
+version,date_time,kind,buy_asset,buy_amount,sell_asset,sell_amount,fee_asset,fee_amount,rate,exchange,exchange_id,notes,ownership_changed,received_date_time
+4,2020-01-01T12:00:00+00:00,Buy,ETH,10,GBP,5000,GBP,0,500,binance:personal,,,,
+4,2020-01-02T12:00:00+00:00,Buy,ETH,10,GBP,5000,GBP,0,500,Kraken,,,,
+";
+        let mut rdr = csv::Reader::from_reader(csv.as_bytes());
+        let headers = rdr.headers().unwrap().clone();
+        let migrated = rdr
+            .records()
+            .map(|record| migrate(4, &record.unwrap(), &headers).unwrap())
+            .map(|record| (record.version, record.exchange, record.account))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            migrated,
+            vec![
+                (
+                    SCHEMA_VERSION,
+                    "binance".to_string(),
+                    Some("binance:personal".to_string())
+                ),
+                (SCHEMA_VERSION, "Kraken".to_string(), None),
+            ]
+        );
+    }
+}
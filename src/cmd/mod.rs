@@ -1,3 +1,20 @@
+pub mod add_trade;
+pub mod addresses;
+pub mod audit;
+pub mod backup;
+pub mod crosscheck;
+pub mod currencies;
+pub mod diagnose;
+pub mod explain_price;
+pub mod gui;
+pub mod holdings;
 pub mod import;
+pub mod imports;
+pub mod journal;
+pub mod migrate;
+pub mod notify;
 pub mod prices;
 pub mod report;
+pub mod simulate;
+pub mod sync;
+pub mod verify;
@@ -0,0 +1,372 @@
+//! Notifications of taxable events, for running after each import e.g. from cron. There is no
+//! daemon, so the events already notified are recorded in a state file between runs. The first
+//! run only records the existing events, so that the whole history isn't sent at once.
+
+use crate::{
+    cmd::{
+        prices::Prices,
+        report::{cgt, rates::TaxRates},
+    },
+    currencies::GBP,
+    money::display_amount,
+    transactions, Money,
+};
+use argh::FromArgs;
+use chrono::{Duration, NaiveDate, NaiveDateTime, Utc};
+use color_eyre::eyre;
+use serde::{Deserialize, Serialize};
+use std::{fs::File, path::PathBuf};
+
+/// Send notifications of new disposals, gains exceeding the annual exemption and 30 day matching
+/// windows about to close
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "notify")]
+pub struct NotifyCommand {
+    /// the csv file containing the transactions
+    #[argh(option)]
+    txs: PathBuf,
+    /// optional csv file with prices in GBP for ETH and BTC, instead of fetching from Coingecko.
+    #[argh(option)]
+    prices: Option<PathBuf>,
+    /// the json file recording the events already notified, created if it doesn't exist
+    #[argh(option)]
+    state: PathBuf,
+    /// the url to post notifications to. For telegram the bot api url including the token e.g.
+    /// https://api.telegram.org/bot<token>/sendMessage
+    #[argh(option)]
+    webhook: String,
+    /// the service receiving the webhook: generic (default), slack or telegram
+    #[argh(option, default = "Service::Generic")]
+    service: Service,
+    /// the telegram chat to send notifications to
+    #[argh(option)]
+    telegram_chat_id: Option<String>,
+    /// the annual exempt amount in GBP, instead of that of the current tax year in the tax rates
+    #[argh(option)]
+    exemption: Option<i64>,
+    /// optional toml file of annual exempt amounts and rates, adding to or overriding those built
+    /// in and any in tax_rates.toml in the config directory
+    #[argh(option)]
+    tax_rates: Option<PathBuf>,
+    /// notify when a 30 day matching window closes within this many days, default 3
+    #[argh(option, default = "3")]
+    window_days: i64,
+    /// print the notifications instead of sending them
+    #[argh(switch)]
+    dry_run: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Service {
+    /// Posts `{"event": .., "message": ..}`
+    Generic,
+    Slack,
+    Telegram,
+}
+
+impl std::str::FromStr for Service {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "generic" => Ok(Service::Generic),
+            "slack" => Ok(Service::Slack),
+            "telegram" => Ok(Service::Telegram),
+            s => Err(format!(
+                "Unsupported service {}, expected generic, slack or telegram",
+                s
+            )),
+        }
+    }
+}
+
+/// The events already notified
+#[derive(Default, Serialize, Deserialize)]
+struct State {
+    disposals: Vec<String>,
+    windows: Vec<String>,
+    exemption_exceeded: Vec<i32>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Notification {
+    event: &'static str,
+    message: String,
+}
+
+impl NotifyCommand {
+    pub fn exec(&self) -> color_eyre::Result<()> {
+        let first_run = !self.state.exists();
+        let mut state: State = if first_run {
+            State::default()
+        } else {
+            serde_json::from_reader(File::open(&self.state)?)?
+        };
+        let transactions = transactions::read_csv(crate::encryption::open(&self.txs)?)?;
+        let prices = match self.prices {
+            None => Prices::from_coingecko_api(GBP, false)?,
+            Some(ref path) => Prices::read_csv(File::open(path)?)?,
+        };
+        let options = cgt::Options::default();
+        let report = cgt::calculate(transactions, &prices, &options)?;
+        let today = Utc::now().naive_utc();
+        let year = options.year_start.tax_year(today);
+        let rates = TaxRates::load(self.tax_rates.as_deref())?;
+        let exemption = exemption(self.exemption, &rates, year);
+        if exemption.is_none() {
+            tracing::warn!("No annual exempt amount for {}, use --exemption", year);
+        }
+
+        let notifications = notifications(
+            &report,
+            &mut state,
+            today,
+            year,
+            exemption.as_ref(),
+            self.window_days,
+        );
+        tracing::info!("{} notifications", notifications.len());
+        if first_run {
+            tracing::info!(
+                "No state file yet, recording the existing events without notifying them"
+            );
+        } else {
+            for notification in notifications.iter() {
+                if self.dry_run {
+                    println!("{}: {}", notification.event, notification.message);
+                } else {
+                    self.send(notification)?;
+                }
+            }
+        }
+        if !self.dry_run {
+            serde_json::to_writer_pretty(File::create(&self.state)?, &state)?;
+        }
+        Ok(())
+    }
+
+    fn send(&self, notification: &Notification) -> color_eyre::Result<()> {
+        let body = match self.service {
+            Service::Generic => serde_json::json!({
+                "event": notification.event,
+                "message": notification.message,
+            }),
+            Service::Slack => serde_json::json!({ "text": notification.message }),
+            Service::Telegram => {
+                let chat_id = self.telegram_chat_id.as_ref().ok_or(eyre::eyre!(
+                    "--telegram-chat-id is required for telegram notifications"
+                ))?;
+                serde_json::json!({ "chat_id": chat_id, "text": notification.message })
+            }
+        };
+        ureq::post(&self.webhook).send_json(body)?;
+        tracing::info!("Sent {} notification", notification.event);
+        Ok(())
+    }
+}
+
+/// The annual exempt amount of the tax year, unless overridden
+fn exemption(
+    exemption: Option<i64>,
+    rates: &TaxRates,
+    year: cgt::TaxYear,
+) -> Option<Money<'static>> {
+    match exemption {
+        Some(exemption) => Some(Money::from_major(exemption, GBP)),
+        None => rates
+            .for_year(year)
+            .map(|rate| Money::from_decimal(rate.annual_exempt_amount, GBP)),
+    }
+}
+
+/// The notifications of the events of the report not yet in the state, which are added to it
+fn notifications(
+    report: &cgt::TaxReport,
+    state: &mut State,
+    today: NaiveDateTime,
+    year: cgt::TaxYear,
+    exemption: Option<&Money>,
+    window_days: i64,
+) -> Vec<Notification> {
+    let mut notifications = Vec::new();
+
+    let all = report.gains(None);
+    let new_disposals = all
+        .disposals()
+        .filter(|d| !state.disposals.contains(&disposal_key(d)))
+        .collect::<Vec<_>>();
+    if !new_disposals.is_empty() {
+        let lines = new_disposals
+            .iter()
+            .map(|d| {
+                format!(
+                    "{} {} {} for £{}",
+                    d.trade().date_time.date(),
+                    display_amount(&d.trade().sell),
+                    d.trade().sell.currency().code,
+                    display_amount(d.proceeds())
+                )
+            })
+            .collect::<Vec<_>>();
+        notifications.push(Notification {
+            event: "new_disposals",
+            message: format!(
+                "{} new taxable disposals:\n{}",
+                new_disposals.len(),
+                lines.join("\n")
+            ),
+        });
+        state
+            .disposals
+            .extend(new_disposals.iter().map(|d| disposal_key(d)));
+    }
+
+    let total_gain = report.gains(Some(year)).total_gain();
+    if let Some(exemption) = exemption {
+        if total_gain > *exemption && !state.exemption_exceeded.contains(&year.end_year()) {
+            notifications.push(Notification {
+                event: "exemption_exceeded",
+                message: format!(
+                    "Gains of £{} for the {} tax year exceed the annual exemption of £{}",
+                    display_amount(&total_gain),
+                    year,
+                    display_amount(exemption)
+                ),
+            });
+            state.exemption_exceeded.push(year.end_year());
+        }
+    }
+
+    for disposal in all.disposals() {
+        let closes = window_closes(disposal.trade().date_time.date());
+        let remaining = closes - today.date();
+        let key = disposal_key(disposal);
+        if remaining >= Duration::zero()
+            && remaining <= Duration::days(window_days)
+            && !state.windows.contains(&key)
+        {
+            notifications.push(Notification {
+                event: "window_closing",
+                message: format!(
+                    "The 30 day matching window for the disposal of {} {} on {} closes on {}",
+                    display_amount(&disposal.trade().sell),
+                    disposal.trade().sell.currency().code,
+                    disposal.trade().date_time.date(),
+                    closes
+                ),
+            });
+            state.windows.push(key);
+        }
+    }
+    notifications
+}
+
+/// Identifies a disposal between runs
+fn disposal_key(disposal: &cgt::TaxEvent) -> String {
+    let trade = disposal.trade();
+    format!("{} {} {}", trade.date_time, trade.sell, trade.buy)
+}
+
+/// The last day on which an acquisition is matched with a disposal on the given date
+fn window_closes(disposal_date: NaiveDate) -> NaiveDate {
+    disposal_date + Duration::days(30)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        currencies::BTC,
+        trades::{TradeBuilder, TradeKind},
+        transactions::Transaction,
+    };
+    use rust_decimal_macros::dec;
+
+    fn trade(
+        date: &str,
+        kind: TradeKind,
+        buy: Money<'static>,
+        sell: Money<'static>,
+    ) -> Transaction<'static> {
+        let date_time = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .unwrap()
+            .and_hms(12, 0, 0);
+        let rate = match kind {
+            TradeKind::Buy => *sell.amount() / *buy.amount(),
+            TradeKind::Sell => *buy.amount() / *sell.amount(),
+        };
+        TradeBuilder::new(date_time, kind, buy, sell)
+            .fee(Money::from_major(0, GBP))
+            .rate(rate)
+            .build()
+            .unwrap()
+            .into()
+    }
+
+    /// A gain of £15,000 in the 2020-21 tax year, disposed of on 1 March 2021
+    fn report() -> cgt::TaxReport<'static> {
+        let btc = Money::from_decimal(dec!(1), BTC);
+        let transactions = vec![
+            trade(
+                "2020-01-01",
+                TradeKind::Buy,
+                btc.clone(),
+                Money::from_major(5_000, GBP),
+            ),
+            trade(
+                "2021-03-01",
+                TradeKind::Sell,
+                Money::from_major(20_000, GBP),
+                btc,
+            ),
+        ];
+        cgt::calculate(transactions, &Prices::default(), &cgt::Options::default()).unwrap()
+    }
+
+    fn events(notifications: &[Notification]) -> Vec<&str> {
+        notifications.iter().map(|n| n.event).collect()
+    }
+
+    #[test]
+    fn events_are_notified_once() {
+        let report = report();
+        let today = NaiveDate::from_ymd(2021, 3, 29).and_hms(9, 0, 0);
+        let year = cgt::TaxYear::uk(2021);
+        let exemption = Money::from_major(12_300, GBP);
+        let mut state = State::default();
+
+        let notified = notifications(&report, &mut state, today, year, Some(&exemption), 3);
+        assert_eq!(
+            events(&notified),
+            vec!["new_disposals", "exemption_exceeded", "window_closing"]
+        );
+        // as recorded by the first run without sending them
+        let notified = notifications(&report, &mut state, today, year, Some(&exemption), 3);
+        assert_eq!(notified, vec![]);
+    }
+
+    #[test]
+    fn the_exemption_is_of_the_tax_year() {
+        let rates = TaxRates::embedded();
+        let gbp = |amount| Some(Money::from_major(amount, GBP));
+        assert_eq!(exemption(None, &rates, cgt::TaxYear::uk(2021)), gbp(12_300));
+        assert_eq!(exemption(None, &rates, cgt::TaxYear::uk(2024)), gbp(6_000));
+        assert_eq!(
+            exemption(Some(1_000), &rates, cgt::TaxYear::uk(2024)),
+            gbp(1_000)
+        );
+
+        let report = report();
+        let today = NaiveDate::from_ymd(2021, 3, 29).and_hms(9, 0, 0);
+        let higher = Money::from_major(20_000, GBP);
+        let notified = notifications(
+            &report,
+            &mut State::default(),
+            today,
+            cgt::TaxYear::uk(2021),
+            Some(&higher),
+            3,
+        );
+        assert_eq!(events(&notified), vec!["new_disposals", "window_closing"]);
+    }
+}
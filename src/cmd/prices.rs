@@ -1,7 +1,14 @@
-use std::{collections::HashMap, fmt, io::Read};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+};
 
-use crate::currencies::{self, Currency, BTC, ETH, GBP, USDC};
-use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use crate::currencies::{Currency, BNB, BTC, ETH, GBP, USDC};
+use argh::FromArgs;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
 use color_eyre::eyre;
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
@@ -33,17 +40,21 @@ pub struct Price<'a> {
     pub rate: Decimal,
 }
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct Prices<'a> {
     prices: HashMap<CurrencyPair<'a>, Vec<Price<'a>>>,
+    /// Currencies which are worthless from a date e.g. delisted or rug pulled tokens
+    worthless: HashMap<&'a str, NaiveDate>,
+    /// How many days either side of a date without a price to look for the nearest one
+    nearest_within: i64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-struct Record {
-    base_currency: String,
-    quote_currency: String,
-    date_time: String,
-    rate: Decimal,
+pub(crate) struct Record {
+    pub(crate) base_currency: String,
+    pub(crate) quote_currency: String,
+    pub(crate) date_time: String,
+    pub(crate) rate: Decimal,
 }
 
 #[derive(Debug, Deserialize)]
@@ -57,37 +68,261 @@ pub struct CoingeckoPrice {
     price: Decimal,
 }
 
+impl CoingeckoPrices {
+    fn to_prices<'a>(&self, pair: &CurrencyPair<'a>) -> Vec<Price<'a>> {
+        self.prices
+            .iter()
+            .map(|price| {
+                let unix_time_secs = price.timestamp / 1000;
+                Price {
+                    pair: pair.clone(),
+                    date_time: NaiveDateTime::from_timestamp(unix_time_secs, 0),
+                    rate: price.price,
+                }
+            })
+            .collect()
+    }
+}
+
+/// The CoinGecko id of the coin for a currency
+pub(crate) fn coingecko_id(currency: &Currency) -> Option<&'static str> {
+    match currency.code {
+        "BTC" => Some("bitcoin"),
+        "ETH" => Some("ethereum"),
+        "ETC" => Some("ethereum-classic"),
+        "ATOM" => Some("cosmos"),
+        "XRP" => Some("ripple"),
+        "REP" => Some("augur"),
+        "OMG" => Some("omisego"),
+        "DOT" => Some("polkadot"),
+        "USDC" => Some("usd-coin"),
+        "USDT" => Some("tether"),
+        "BUSD" => Some("binance-usd"),
+        "BNB" => Some("binancecoin"),
+        "SHIB" => Some("shiba-inu"),
+        "XMR" => Some("monero"),
+        "ZEC" => Some("zcash"),
+        "LTC" => Some("litecoin"),
+        "DOGE" => Some("dogecoin"),
+        "BCH" => Some("bitcoin-cash"),
+        code => crate::money::user_coingecko_id(code),
+    }
+}
+
+/// Environment variable for a CoinGecko Pro API key, which uses the paid endpoints
+const COINGECKO_PRO_API_KEY: &str = "COINGECKO_PRO_API_KEY";
+/// Environment variable for a CoinGecko Demo API key
+const COINGECKO_DEMO_API_KEY: &str = "COINGECKO_DEMO_API_KEY";
+
+/// Client for the CoinGecko API, using the public, demo or pro endpoints depending on which API
+/// key is configured.
+struct CoinGecko {
+    host: &'static str,
+    /// The header and value of the API key
+    api_key: Option<(&'static str, String)>,
+}
+
+impl CoinGecko {
+    fn from_env() -> Self {
+        Self::with_keys(
+            std::env::var(COINGECKO_PRO_API_KEY).ok(),
+            std::env::var(COINGECKO_DEMO_API_KEY).ok(),
+        )
+    }
+
+    /// The pro endpoints if there's a pro key, otherwise the public endpoints with the demo key
+    /// if any
+    fn with_keys(pro: Option<String>, demo: Option<String>) -> Self {
+        if let Some(key) = pro {
+            CoinGecko {
+                host: "https://pro-api.coingecko.com",
+                api_key: Some(("x-cg-pro-api-key", key)),
+            }
+        } else if let Some(key) = demo {
+            CoinGecko {
+                host: "https://api.coingecko.com",
+                api_key: Some(("x-cg-demo-api-key", key)),
+            }
+        } else {
+            CoinGecko {
+                host: "https://api.coingecko.com",
+                api_key: None,
+            }
+        }
+    }
+
+    fn is_paid(&self) -> bool {
+        self.host.starts_with("https://pro-api")
+    }
+
+    fn get(&self, path: &str, query: &[(&str, String)]) -> eyre::Result<CoingeckoPrices> {
+        let mut request = ureq::get(&format!("{}/api/v3{}", self.host, path));
+        if let Some((header, ref key)) = self.api_key {
+            request = request.set(header, key);
+        }
+        for (param, value) in query {
+            request = request.query(param, value);
+        }
+        Ok(request.call()?.into_json()?)
+    }
+
+    /// Daily prices for the full history of the coin
+    fn market_chart(&self, coin: &str, quote: &Currency) -> eyre::Result<CoingeckoPrices> {
+        self.get(
+            &format!("/coins/{}/market_chart", coin),
+            &[
+                ("vs_currency", quote.code.to_string()),
+                ("interval", "daily".into()),
+                ("days", "max".into()),
+            ],
+        )
+    }
+
+    /// Prices between the start of the `from` date and the end of the `to` date, which are more
+    /// granular than daily for short ranges
+    fn market_chart_range(
+        &self,
+        coin: &str,
+        quote: &Currency,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> eyre::Result<CoingeckoPrices> {
+        tracing::info!("Fetching {} prices from {} to {}", coin, from, to);
+        self.get(
+            &format!("/coins/{}/market_chart/range", coin),
+            &[
+                ("vs_currency", quote.code.to_string()),
+                ("from", from.and_hms(0, 0, 0).timestamp().to_string()),
+                ("to", to.and_hms(23, 59, 59).timestamp().to_string()),
+            ],
+        )
+    }
+}
+
+/// Fetch the ranges of any missing days between the daily prices, keeping the first price of each
+/// missing day
+fn fill_gaps<'a, F>(
+    pair: &CurrencyPair<'a>,
+    prices: &mut Vec<Price<'a>>,
+    mut fetch_range: F,
+) -> eyre::Result<()>
+where
+    F: FnMut(NaiveDate, NaiveDate) -> eyre::Result<CoingeckoPrices>,
+{
+    prices.sort_by_key(|p| p.date_time);
+    let gaps = prices
+        .windows(2)
+        .map(|w| (w[0].date_time.date(), w[1].date_time.date()))
+        .filter(|(prev, next)| *next - *prev > chrono::Duration::days(1))
+        .collect::<Vec<_>>();
+    for (prev, next) in gaps {
+        let range = fetch_range(prev.succ(), next.pred())?;
+        let mut filled = range.to_prices(pair);
+        filled.dedup_by_key(|p| p.date_time.date());
+        prices.extend(filled);
+    }
+    prices.sort_by_key(|p| p.date_time);
+    Ok(())
+}
+
+/// The prices fetched from CoinGecko are cached per quote currency in the prices csv format
+fn cache_path(quote_currency: &Currency) -> Option<PathBuf> {
+    Some(crate::utils::cache_dir()?.join(format!("coingecko-{}.csv", quote_currency.code)))
+}
+
+/// Whether the cache was written today, since CoinGecko only adds a daily price once a day
+fn is_fresh(path: &Path) -> bool {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .map(|modified| DateTime::<Utc>::from(modified).date() == Utc::today())
+        .unwrap_or(false)
+}
+
 impl<'a> Prices<'a> {
-    /// Initializes the prices database from the coingecko api
-    pub fn from_coingecko_api(quote_currency: &Currency) -> eyre::Result<Prices<'a>> {
+    /// Initializes the prices database from the coingecko api, or the prices cached from it
+    /// today unless `refresh`. The cache is also used if the api can't be reached, however old.
+    pub fn from_coingecko_api(
+        quote_currency: &Currency,
+        refresh: bool,
+    ) -> eyre::Result<Prices<'a>> {
+        Self::cached(cache_path(quote_currency).as_deref(), refresh, || {
+            Self::fetch_coingecko_api(quote_currency)
+        })
+    }
+
+    /// The prices cached today unless `refresh`, otherwise those fetched, which are cached. The
+    /// cache is also used if they can't be fetched, however old.
+    fn cached<F>(cache: Option<&Path>, refresh: bool, fetch: F) -> eyre::Result<Prices<'a>>
+    where
+        F: FnOnce() -> eyre::Result<Prices<'a>>,
+    {
+        if let Some(path) = cache {
+            if !refresh && is_fresh(path) {
+                tracing::info!("Using the prices cached at {}", path.display());
+                return Self::read_csv(File::open(path)?);
+            }
+        }
+        match fetch() {
+            Ok(prices) => {
+                if let Some(path) = cache {
+                    if let Err(err) = prices.write_cache(path) {
+                        tracing::warn!("Failed to cache the prices at {}: {}", path.display(), err);
+                    }
+                }
+                Ok(prices)
+            }
+            Err(err) => match cache {
+                Some(path) if !refresh && path.exists() => {
+                    tracing::warn!(
+                        "Failed to fetch prices from CoinGecko, using the prices cached at {}: {}",
+                        path.display(),
+                        err
+                    );
+                    Self::read_csv(File::open(path)?)
+                }
+                _ => Err(err),
+            },
+        }
+    }
+
+    fn write_cache(&self, path: &Path) -> color_eyre::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        crate::utils::write_csv(self.records(), File::create(path)?)
+    }
+
+    /// The prices as the records of a prices csv, sorted by date
+    pub(crate) fn records(&self) -> Vec<Record> {
+        let mut records = self
+            .prices
+            .values()
+            .flatten()
+            .map(|price| Record {
+                base_currency: price.pair.base.code.to_string(),
+                quote_currency: price.pair.quote.code.to_string(),
+                date_time: DateTime::<Utc>::from_utc(price.date_time, Utc).to_rfc3339(),
+                rate: price.rate,
+            })
+            .collect::<Vec<_>>();
+        records.sort_by(|r1, r2| r1.date_time.cmp(&r2.date_time));
+        records
+    }
+
+    fn fetch_coingecko_api(quote_currency: &Currency) -> eyre::Result<Prices<'a>> {
+        let coingecko = CoinGecko::from_env();
         let mut prices = HashMap::new();
 
         let mut fetch_prices = |coin, base| -> eyre::Result<()> {
-            let url = format!(
-                "https://api.coingecko.com/api/v3/coins/{}/market_chart",
-                coin
-            );
-            let response = ureq::get(&url)
-                .query("vs_currency", quote_currency.code)
-                .query("interval", "daily")
-                .query("days", "max")
-                .call()?;
-
-            let coingecko_prices: CoingeckoPrices = response.into_json()?;
-            log::info!("{} {} prices fetched", coingecko_prices.prices.len(), coin);
             let pair = CurrencyPair { base, quote: GBP };
-            let pair_prices = coingecko_prices
-                .prices
-                .iter()
-                .map(|price| {
-                    let unix_time_secs = price.timestamp / 1000;
-                    Price {
-                        pair: pair.clone(),
-                        date_time: NaiveDateTime::from_timestamp(unix_time_secs, 0).into(),
-                        rate: price.price,
-                    }
-                })
-                .collect();
+            let coingecko_prices = coingecko.market_chart(coin, quote_currency)?;
+            tracing::info!("{} {} prices fetched", coingecko_prices.prices.len(), coin);
+            let mut pair_prices = coingecko_prices.to_prices(&pair);
+            if coingecko.is_paid() {
+                fill_gaps(&pair, &mut pair_prices, |from, to| {
+                    coingecko.market_chart_range(coin, quote_currency, from, to)
+                })?;
+            }
             prices.insert(pair, pair_prices);
             Ok(())
         };
@@ -95,8 +330,12 @@ impl<'a> Prices<'a> {
         fetch_prices("bitcoin", BTC)?;
         fetch_prices("ethereum", ETH)?;
         fetch_prices("usd-coin", USDC)?;
+        fetch_prices("binancecoin", BNB)?;
 
-        Ok(Prices { prices })
+        Ok(Prices {
+            prices,
+            ..Default::default()
+        })
     }
 
     /// Initialize the prices database from the supplied CSV file
@@ -106,11 +345,16 @@ impl<'a> Prices<'a> {
     {
         let mut rdr = csv::Reader::from_reader(reader);
         let result: Result<Vec<_>, _> = rdr.deserialize::<Record>().collect();
+        Ok(Self::from_records(result?))
+    }
+
+    /// The prices of the records of a prices csv
+    pub(crate) fn from_records(records: Vec<Record>) -> Prices<'a> {
         let mut prices = HashMap::new();
-        for record in result? {
-            let base = currencies::find(&record.base_currency)
+        for record in records {
+            let base = crate::money::find(&record.base_currency)
                 .expect(format!("invalid base currency {}", record.base_currency).as_ref());
-            let quote = currencies::find(&record.quote_currency)
+            let quote = crate::money::find(&record.quote_currency)
                 .expect(format!("invalid quote currency {}", record.quote_currency).as_ref());
             let date_time = parse_date(&record.date_time);
             let pair = CurrencyPair { base, quote };
@@ -123,17 +367,64 @@ impl<'a> Prices<'a> {
             pair_prices.push(price);
         }
 
-        Ok(Prices { prices })
+        Prices {
+            prices,
+            ..Default::default()
+        }
+    }
+
+    /// The number of prices for the pair, and the dates of the first and last price
+    pub fn coverage(&self, pair: &CurrencyPair<'a>) -> Option<(usize, NaiveDate, NaiveDate)> {
+        let prices = self.prices.get(pair)?;
+        let first = prices.iter().map(|p| p.date_time.date()).min()?;
+        let last = prices.iter().map(|p| p.date_time.date()).max()?;
+        Some((prices.len(), first, last))
     }
 
-    /// gets daily price if exists
+    /// Whether there are prices of every pair up to the date
+    pub fn covers(&self, date: NaiveDate) -> bool {
+        !self.prices.is_empty()
+            && self
+                .prices
+                .values()
+                .all(|prices| prices.iter().any(|price| price.date_time.date() >= date))
+    }
+
+    /// Price the currency at zero from the given date, instead of looking up its prices
+    pub fn mark_worthless(&mut self, currency: &'a Currency, from: NaiveDate) {
+        self.worthless.insert(currency.code, from);
+    }
+
+    /// Use the nearest price up to the given number of days before or after a date without a
+    /// price, instead of none. The price keeps its own date, so the approximation can be seen.
+    pub fn set_nearest_within(&mut self, days: u32) {
+        self.nearest_within = i64::from(days);
+    }
+
+    /// gets daily price if exists, or the nearest within the days set by `set_nearest_within`,
+    /// the earlier if two are as near
     pub fn get(&self, pair: CurrencyPair<'a>, at: NaiveDate) -> Option<Price<'a>> {
-        self.prices.get(&pair).and_then(|prices| {
-            prices
-                .iter()
-                .find(|price| price.date_time.date() == at)
-                .cloned()
-        })
+        if let Some(from) = self.worthless.get(pair.base.code) {
+            if at >= *from {
+                return Some(Price {
+                    pair,
+                    date_time: at.and_hms(0, 0, 0),
+                    rate: Decimal::new(0, 0),
+                });
+            }
+        }
+        let prices = self.prices.get(&pair)?;
+        let exact = prices.iter().find(|price| price.date_time.date() == at);
+        exact
+            .or_else(|| {
+                prices
+                    .iter()
+                    .map(|price| (price, (price.date_time.date() - at).num_days()))
+                    .filter(|(_, days)| days.abs() <= self.nearest_within)
+                    .min_by_key(|(_, days)| (days.abs(), *days))
+                    .map(|(price, _)| price)
+            })
+            .cloned()
     }
 }
 
@@ -142,3 +433,484 @@ fn parse_date(s: &str) -> NaiveDateTime {
         .expect(format!("Invalid date_time {}", s).as_ref())
         .naive_utc()
 }
+
+/// Manage the prices csv file
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "prices")]
+pub struct PricesCommand {
+    #[argh(subcommand)]
+    sub: PricesSubCommand,
+}
+
+impl PricesCommand {
+    /// Returns the number of outliers found by `prices check`
+    pub fn exec(&self) -> color_eyre::Result<usize> {
+        match self.sub {
+            PricesSubCommand::Backfill(ref backfill) => backfill.exec().map(|()| 0),
+            PricesSubCommand::Check(ref check) => check.exec(),
+            PricesSubCommand::Set(ref set) => set.exec().map(|()| 0),
+        }
+    }
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+pub enum PricesSubCommand {
+    Backfill(BackfillCommand),
+    Check(CheckCommand),
+    Set(SetCommand),
+}
+
+/// Fetch daily prices for a pair over a date range from CoinGecko into a prices csv file,
+/// replacing any existing prices for the pair in that range
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "backfill")]
+pub struct BackfillCommand {
+    /// the pair to fetch e.g. DOT/GBP
+    #[argh(option)]
+    pair: String,
+    /// the first date to fetch e.g. 2020-08-01
+    #[argh(option)]
+    from: NaiveDate,
+    /// the last date to fetch e.g. 2021-04-05
+    #[argh(option)]
+    to: NaiveDate,
+    /// the prices csv file to add the prices to, created if it doesn't exist
+    #[argh(option)]
+    file: PathBuf,
+    /// the CoinGecko id of the base currency, if not one of the known coins e.g. polkadot
+    #[argh(option)]
+    coin: Option<String>,
+}
+
+impl BackfillCommand {
+    pub fn exec(&self) -> color_eyre::Result<()> {
+        let pair = parse_pair(&self.pair)?;
+        if self.from > self.to {
+            return Err(eyre::eyre!("{} is after {}", self.from, self.to));
+        }
+        let coin = match self.coin {
+            Some(ref coin) => coin.as_str(),
+            None => coingecko_id(pair.base).ok_or(eyre::eyre!(
+                "Unknown CoinGecko id for {}, specify it with --coin",
+                pair.base.code
+            ))?,
+        };
+
+        let fetched = CoinGecko::from_env()
+            .market_chart_range(coin, pair.quote, self.from, self.to)?
+            .to_prices(&pair);
+        let records = self.replace_range(&pair, read_records(&self.file)?, fetched);
+        crate::utils::write_csv(records, File::create(&self.file)?)
+    }
+
+    /// Replace the records of the pair in the range with the first fetched price of each day
+    fn replace_range(
+        &self,
+        pair: &CurrencyPair,
+        mut records: Vec<Record>,
+        mut fetched: Vec<Price>,
+    ) -> Vec<Record> {
+        // ranges of less than 90 days are hourly, so keep the first price of each day
+        fetched.sort_by_key(|p| p.date_time);
+        fetched.dedup_by_key(|p| p.date_time.date());
+        tracing::info!("Fetched {} daily {} prices", fetched.len(), pair);
+
+        records.retain(|r| {
+            let in_range = || {
+                let date = parse_date(&r.date_time).date();
+                date >= self.from && date <= self.to
+            };
+            r.base_currency != pair.base.code || r.quote_currency != pair.quote.code || !in_range()
+        });
+        records.extend(fetched.iter().map(|price| Record {
+            base_currency: pair.base.code.to_string(),
+            quote_currency: pair.quote.code.to_string(),
+            date_time: DateTime::<Utc>::from_utc(price.date_time, Utc).to_rfc3339(),
+            rate: price.rate,
+        }));
+        records.sort_by(|r1, r2| r1.date_time.cmp(&r2.date_time));
+        records
+    }
+}
+
+/// Flag day over day price moves beyond a threshold, which are likely bad data or unit errors
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "check")]
+pub struct CheckCommand {
+    /// the prices csv file to check
+    #[argh(option)]
+    file: PathBuf,
+    /// the proportional move from the previous price above which a price is flagged, default
+    /// 0.5 i.e. 50%
+    #[argh(option, default = "Decimal::new(5, 1)")]
+    threshold: Decimal,
+    /// remove the flagged prices from the file, correct prices can then be added with `prices set`
+    #[argh(switch)]
+    exclude: bool,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+struct OutlierRecord {
+    pair: String,
+    previous_date_time: String,
+    previous_rate: Decimal,
+    date_time: String,
+    rate: Decimal,
+    change: Decimal,
+}
+
+/// The prices of the records, sorted by date, which moved by more than the threshold from the
+/// previous price of their pair, with the indexes of the records to exclude if excluding. An
+/// excluded price isn't the previous price of the next one, which is compared against the last
+/// good price instead.
+fn outliers(
+    records: &[Record],
+    threshold: Decimal,
+    exclude: bool,
+) -> (Vec<OutlierRecord>, HashSet<usize>) {
+    let mut previous: HashMap<(&str, &str), &Record> = HashMap::new();
+    let mut outliers = Vec::new();
+    let mut excluded = HashSet::new();
+    for (index, record) in records.iter().enumerate() {
+        let key = (
+            record.base_currency.as_str(),
+            record.quote_currency.as_str(),
+        );
+        if let Some(prev) = previous.get(&key) {
+            let change = (record.rate - prev.rate).checked_div(prev.rate);
+            if let Some(change) = change.filter(|c| c.abs() > threshold) {
+                outliers.push(OutlierRecord {
+                    pair: format!("{}/{}", record.base_currency, record.quote_currency),
+                    previous_date_time: prev.date_time.clone(),
+                    previous_rate: prev.rate,
+                    date_time: record.date_time.clone(),
+                    rate: record.rate,
+                    change: change.round_dp(4),
+                });
+                if exclude {
+                    excluded.insert(index);
+                    continue;
+                }
+            }
+        }
+        previous.insert(key, record);
+    }
+    (outliers, excluded)
+}
+
+impl CheckCommand {
+    pub fn exec(&self) -> color_eyre::Result<usize> {
+        let mut records = read_records(&self.file)?;
+        records.sort_by(|r1, r2| r1.date_time.cmp(&r2.date_time));
+
+        let (outliers, excluded) = outliers(&records, self.threshold, self.exclude);
+        tracing::info!("{} prices flagged as outliers", outliers.len());
+
+        if self.exclude {
+            let remaining = records
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| !excluded.contains(index))
+                .map(|(_, record)| record)
+                .collect::<Vec<_>>();
+            crate::utils::write_csv(remaining, File::create(&self.file)?)?;
+        }
+        let found = outliers.len();
+        crate::utils::write_csv(outliers, std::io::stdout())?;
+        Ok(found)
+    }
+}
+
+/// Override the price of a pair on a date in a prices csv file
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "set")]
+pub struct SetCommand {
+    /// the prices csv file, created if it doesn't exist
+    #[argh(option)]
+    file: PathBuf,
+    /// the pair e.g. DOT/GBP
+    #[argh(option)]
+    pair: String,
+    /// the date of the price e.g. 2021-01-31
+    #[argh(option)]
+    date: NaiveDate,
+    /// the price of one unit of the base currency in the quote currency
+    #[argh(option)]
+    rate: Decimal,
+}
+
+impl SetCommand {
+    pub fn exec(&self) -> color_eyre::Result<()> {
+        let pair = parse_pair(&self.pair)?;
+        let mut records = read_records(&self.file)?;
+        records.retain(|r| {
+            r.base_currency != pair.base.code
+                || r.quote_currency != pair.quote.code
+                || parse_date(&r.date_time).date() != self.date
+        });
+        records.push(Record {
+            base_currency: pair.base.code.to_string(),
+            quote_currency: pair.quote.code.to_string(),
+            date_time: DateTime::<Utc>::from_utc(self.date.and_hms(0, 0, 0), Utc).to_rfc3339(),
+            rate: self.rate,
+        });
+        records.sort_by(|r1, r2| r1.date_time.cmp(&r2.date_time));
+        crate::utils::write_csv(records, File::create(&self.file)?)
+    }
+}
+
+/// Parses a pair of known currencies e.g. DOT/GBP or DOT-GBP
+pub(crate) fn parse_pair(s: &str) -> color_eyre::Result<CurrencyPair<'static>> {
+    let mut codes = s.split(|c: char| c == '/' || c == '-');
+    let mut currency = || {
+        codes
+            .next()
+            .and_then(|code| crate::money::find(&code.to_uppercase()))
+            .ok_or(eyre::eyre!("Invalid pair {}, expected e.g. DOT/GBP", s))
+    };
+    Ok(CurrencyPair {
+        base: currency()?,
+        quote: currency()?,
+    })
+}
+
+/// Read the records of a prices csv file, if it exists
+pub(crate) fn read_records(file: &Path) -> color_eyre::Result<Vec<Record>> {
+    if !file.exists() {
+        return Ok(Vec::new());
+    }
+    let mut rdr = csv::Reader::from_reader(File::open(file)?);
+    Ok(rdr.deserialize::<Record>().collect::<Result<Vec<_>, _>>()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(pair: &str, date: &str, rate: i64) -> Record {
+        let mut codes = pair.split('/');
+        Record {
+            base_currency: codes.next().unwrap().to_string(),
+            quote_currency: codes.next().unwrap().to_string(),
+            date_time: format!("{}T00:00:00+00:00", date),
+            rate: Decimal::new(rate, 0),
+        }
+    }
+
+    #[test]
+    fn moves_beyond_the_threshold_are_flagged_per_pair() {
+        let records = vec![
+            record("BTC/GBP", "2021-01-01", 100),
+            record("ETH/GBP", "2021-01-01", 10),
+            record("BTC/GBP", "2021-01-02", 150),
+            record("ETH/GBP", "2021-01-02", 16),
+            record("BTC/GBP", "2021-01-03", 15000),
+            record("BTC/GBP", "2021-01-04", 160),
+        ];
+        let threshold = Decimal::new(5, 1);
+
+        let (flagged, excluded) = outliers(&records, threshold, false);
+        assert_eq!(
+            flagged
+                .iter()
+                .map(|o| (o.pair.as_str(), o.rate, o.change))
+                .collect::<Vec<_>>(),
+            vec![
+                ("ETH/GBP", Decimal::new(16, 0), Decimal::new(6, 1)),
+                ("BTC/GBP", Decimal::new(15000, 0), Decimal::new(99, 0)),
+                ("BTC/GBP", Decimal::new(160, 0), Decimal::new(-9893, 4)),
+            ]
+        );
+        assert!(excluded.is_empty());
+
+        let (flagged, excluded) = outliers(&records, threshold, true);
+        assert_eq!(flagged.len(), 2);
+        assert_eq!(excluded, vec![3, 4].into_iter().collect());
+    }
+
+    #[test]
+    fn api_keys_select_the_coingecko_endpoints() {
+        let pro = CoinGecko::with_keys(Some("pro".into()), Some("demo".into()));
+        assert_eq!(pro.host, "https://pro-api.coingecko.com");
+        assert_eq!(pro.api_key, Some(("x-cg-pro-api-key", "pro".to_string())));
+        assert!(pro.is_paid());
+
+        let demo = CoinGecko::with_keys(None, Some("demo".into()));
+        assert_eq!(demo.host, "https://api.coingecko.com");
+        assert_eq!(
+            demo.api_key,
+            Some(("x-cg-demo-api-key", "demo".to_string()))
+        );
+        assert!(!demo.is_paid());
+
+        let public = CoinGecko::with_keys(None, None);
+        assert_eq!(public.api_key, None);
+        assert!(!public.is_paid());
+    }
+
+    #[test]
+    fn missing_days_are_filled_with_the_first_price_of_each_day() {
+        let pair = CurrencyPair {
+            base: BTC,
+            quote: GBP,
+        };
+        let day = |d| NaiveDate::from_ymd(2021, 1, d);
+        let price = |d, hour, rate| Price {
+            pair: pair.clone(),
+            date_time: day(d).and_hms(hour, 0, 0),
+            rate: Decimal::new(rate, 0),
+        };
+        let coingecko_price = |d, hour, rate| CoingeckoPrice {
+            timestamp: day(d).and_hms(hour, 0, 0).timestamp() * 1000,
+            price: Decimal::new(rate, 0),
+        };
+        let mut prices = vec![price(5, 0, 50), price(1, 0, 10), price(2, 0, 20)];
+        let mut ranges = Vec::new();
+        fill_gaps(&pair, &mut prices, |from, to| {
+            ranges.push((from, to));
+            Ok(CoingeckoPrices {
+                prices: vec![
+                    coingecko_price(3, 1, 30),
+                    coingecko_price(3, 13, 35),
+                    coingecko_price(4, 1, 40),
+                ],
+            })
+        })
+        .unwrap();
+
+        assert_eq!(ranges, vec![(day(3), day(4))]);
+        assert_eq!(
+            prices
+                .iter()
+                .map(|p| (p.date_time, p.rate))
+                .collect::<Vec<_>>(),
+            vec![
+                (day(1).and_hms(0, 0, 0), Decimal::new(10, 0)),
+                (day(2).and_hms(0, 0, 0), Decimal::new(20, 0)),
+                (day(3).and_hms(1, 0, 0), Decimal::new(30, 0)),
+                (day(4).and_hms(1, 0, 0), Decimal::new(40, 0)),
+                (day(5).and_hms(0, 0, 0), Decimal::new(50, 0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn backfilled_prices_replace_those_of_the_pair_in_the_range() {
+        let backfill = BackfillCommand::from_args(
+            &["backfill"],
+            &[
+                "--pair",
+                "btc-gbp",
+                "--from",
+                "2021-01-02",
+                "--to",
+                "2021-01-03",
+                "--file",
+                "prices.csv",
+            ],
+        )
+        .unwrap();
+        let pair = parse_pair(&backfill.pair).unwrap();
+        let price = |d, hour, rate| Price {
+            pair: pair.clone(),
+            date_time: NaiveDate::from_ymd(2021, 1, d).and_hms(hour, 0, 0),
+            rate: Decimal::new(rate, 0),
+        };
+        let records = vec![
+            record("BTC/GBP", "2021-01-01", 100),
+            record("BTC/GBP", "2021-01-02", 999),
+            record("ETH/GBP", "2021-01-02", 10),
+            record("BTC/GBP", "2021-01-04", 130),
+        ];
+        let fetched = vec![
+            price(3, 0, 120),
+            price(2, 1, 110),
+            price(2, 0, 105),
+            price(3, 1, 125),
+        ];
+
+        let records = backfill.replace_range(&pair, records, fetched);
+        assert_eq!(
+            records
+                .iter()
+                .map(|r| (r.base_currency.as_str(), &r.date_time[..10], r.rate))
+                .collect::<Vec<_>>(),
+            vec![
+                ("BTC", "2021-01-01", Decimal::new(100, 0)),
+                ("ETH", "2021-01-02", Decimal::new(10, 0)),
+                ("BTC", "2021-01-02", Decimal::new(105, 0)),
+                ("BTC", "2021-01-03", Decimal::new(120, 0)),
+                ("BTC", "2021-01-04", Decimal::new(130, 0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn cached_prices_are_used_for_the_day_or_when_offline() {
+        let dir = std::env::temp_dir().join(format!("taxc-prices-{}", std::process::id()));
+        let path = dir.join("coingecko-GBP.csv");
+        let fetched = |rate| {
+            move || -> eyre::Result<Prices<'static>> {
+                Ok(Prices::from_records(vec![record(
+                    "BTC/GBP",
+                    "2021-01-01",
+                    rate,
+                )]))
+            }
+        };
+        let offline = || -> eyre::Result<Prices<'static>> { Err(eyre::eyre!("offline")) };
+        let rate = |prices: eyre::Result<Prices>| {
+            prices
+                .unwrap()
+                .get(
+                    CurrencyPair {
+                        base: BTC,
+                        quote: GBP,
+                    },
+                    NaiveDate::from_ymd(2021, 1, 1),
+                )
+                .map(|price| price.rate)
+        };
+
+        // fetched and cached, in a directory created for the cache
+        assert_eq!(
+            rate(Prices::cached(Some(&path), false, fetched(100))),
+            Some(Decimal::new(100, 0))
+        );
+        // the cache was written today
+        assert_eq!(
+            rate(Prices::cached(Some(&path), false, fetched(200))),
+            Some(Decimal::new(100, 0))
+        );
+        // unless refreshed
+        assert_eq!(
+            rate(Prices::cached(Some(&path), true, fetched(200))),
+            Some(Decimal::new(200, 0))
+        );
+        assert!(Prices::cached(Some(&path), true, offline).is_err());
+
+        // a stale cache is fetched again, but still used when offline
+        let yesterday = std::time::SystemTime::now() - std::time::Duration::from_secs(24 * 60 * 60);
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(yesterday)
+            .unwrap();
+        assert!(!is_fresh(&path));
+        assert_eq!(
+            rate(Prices::cached(Some(&path), false, offline)),
+            Some(Decimal::new(200, 0))
+        );
+        assert_eq!(
+            rate(Prices::cached(Some(&path), false, fetched(300))),
+            Some(Decimal::new(300, 0))
+        );
+        assert!(is_fresh(&path));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(Prices::cached(Some(&path), false, offline).is_err());
+        assert!(Prices::cached(None, false, fetched(100)).is_ok());
+    }
+}
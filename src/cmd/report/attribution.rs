@@ -0,0 +1,119 @@
+//! Attribution of each year's allowable costs to the HMRC share matching rules, for sanity
+//! checking results and explaining them to HMRC.
+
+use super::cgt::{MatchingRule, TaxReport, TaxYear};
+use crate::money::display_amount;
+use serde::Serialize;
+use std::io::Write;
+
+#[derive(Serialize)]
+struct AttributionRecord {
+    tax_year: TaxYear,
+    disposals: usize,
+    same_day_costs: String,
+    thirty_day_costs: String,
+    pool_costs: String,
+    allowable_costs: String,
+}
+
+/// Writes the allowable costs matched by each rule for every tax year as csv
+pub fn write_attribution<W>(report: &TaxReport, writer: W) -> color_eyre::Result<()>
+where
+    W: Write,
+{
+    let mut years = report.years.keys().cloned().collect::<Vec<_>>();
+    years.sort();
+    let records = years
+        .into_iter()
+        .map(|year| {
+            let gains = report.gains(Some(year));
+            AttributionRecord {
+                tax_year: year,
+                disposals: gains.disposal_count(),
+                same_day_costs: display_amount(
+                    &gains.total_costs_matched_by(MatchingRule::SameDay),
+                ),
+                thirty_day_costs: display_amount(
+                    &gains.total_costs_matched_by(MatchingRule::ThirtyDay),
+                ),
+                pool_costs: display_amount(&gains.total_costs_matched_by(MatchingRule::Pool)),
+                allowable_costs: display_amount(&gains.total_allowable_costs()),
+            }
+        })
+        .collect();
+    crate::utils::write_csv(records, writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cmd::{prices::Prices, report::cgt},
+        currencies::{BTC, GBP},
+        trades::{TradeBuilder, TradeKind},
+        transactions::Transaction,
+        Money,
+    };
+    use chrono::NaiveDate;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn allowable_costs_are_attributed_to_the_rule_matching_them() {
+        let trade = |date: &str, kind, sell, buy, rate| {
+            let date_time = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                .unwrap()
+                .and_hms(12, 0, 0);
+            Transaction::Trade(
+                TradeBuilder::new(date_time, kind, buy, sell)
+                    .fee(Money::from_decimal(dec!(0), GBP))
+                    .rate(rate)
+                    .build()
+                    .unwrap(),
+            )
+        };
+        let gbp = |amount| Money::from_decimal(amount, GBP);
+        let btc = |amount| Money::from_decimal(amount, BTC);
+        let transactions = vec![
+            trade(
+                "2018-05-01",
+                TradeKind::Buy,
+                gbp(dec!(100)),
+                btc(dec!(10)),
+                dec!(10),
+            ),
+            trade(
+                "2018-06-01",
+                TradeKind::Buy,
+                gbp(dec!(20)),
+                btc(dec!(1)),
+                dec!(20),
+            ),
+            trade(
+                "2018-06-01",
+                TradeKind::Sell,
+                btc(dec!(3)),
+                gbp(dec!(90)),
+                dec!(30),
+            ),
+            trade(
+                "2018-06-10",
+                TradeKind::Buy,
+                gbp(dec!(40)),
+                btc(dec!(1)),
+                dec!(40),
+            ),
+        ];
+        let report =
+            cgt::calculate(transactions, &Prices::default(), &cgt::Options::default()).unwrap();
+
+        let mut output = Vec::new();
+        write_attribution(&report, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "\
+tax_year,disposals,same_day_costs,thirty_day_costs,pool_costs,allowable_costs
+2019,1,20.00,40.00,10.00,70.00
+"
+        );
+    }
+}
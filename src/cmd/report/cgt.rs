@@ -1,24 +1,146 @@
+use super::{gas, invariants, rates::TaxRates};
 use crate::{
     cmd::prices::{CurrencyPair, Price, Prices},
     currencies::{Currency, GBP},
-    money::display_amount,
+    money::{self, display_amount, Instrument},
     trades::{Trade, TradeKey, TradeKind, TradeRecord},
+    transactions::{self, Movement, Transaction, TransactionKind},
     Money,
 };
 use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime};
+use color_eyre::eyre;
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, fmt, io::Write};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    io::Write,
+};
+
+/// A tax year, identified by the calendar year in which it ends, and the day it starts on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TaxYear {
+    end: i32,
+    start: YearStart,
+}
+
+impl TaxYear {
+    pub fn new(end: i32, start: YearStart) -> Self {
+        TaxYear { end, start }
+    }
+
+    /// The UK tax year ending on 5 April of the given year
+    pub fn uk(end: i32) -> Self {
+        TaxYear::new(end, YearStart::UK)
+    }
+
+    /// The tax year containing the date
+    pub fn containing(date: NaiveDate, start: YearStart) -> Self {
+        let year = date.year();
+        if start != YearStart::CALENDAR && (date.month(), date.day()) >= (start.month, start.day) {
+            TaxYear::new(year + 1, start)
+        } else {
+            TaxYear::new(year, start)
+        }
+    }
+
+    /// The same year, starting on another day e.g. a year parsed from the command line
+    pub fn with_start(self, start: YearStart) -> Self {
+        TaxYear::new(self.end, start)
+    }
+
+    /// The calendar year in which the tax year ends
+    pub fn end_year(&self) -> i32 {
+        self.end
+    }
+
+    pub fn first_day(&self) -> NaiveDate {
+        if self.start == YearStart::CALENDAR {
+            NaiveDate::from_ymd(self.end, 1, 1)
+        } else {
+            NaiveDate::from_ymd(self.end - 1, self.start.month, self.start.day)
+        }
+    }
+
+    pub fn last_day(&self) -> NaiveDate {
+        self.next().first_day().pred()
+    }
 
-pub type Year = i32;
+    pub fn previous(&self) -> Self {
+        TaxYear::new(self.end - 1, self.start)
+    }
+
+    pub fn next(&self) -> Self {
+        TaxYear::new(self.end + 1, self.start)
+    }
 
-pub struct TaxYear<'a> {
-    pub year: Year,
+    pub fn contains(&self, date: NaiveDate) -> bool {
+        date >= self.first_day() && date <= self.last_day()
+    }
+
+    /// Each day of the tax year, in order
+    pub fn days(&self) -> impl Iterator<Item = NaiveDate> {
+        let last_day = self.last_day();
+        std::iter::successors(Some(self.first_day()), |day| day.succ_opt())
+            .take_while(move |day| *day <= last_day)
+    }
+}
+
+/// Years which span two calendar years are shown as e.g. `2020/21`, calendar years as `2021`
+impl fmt::Display for TaxYear {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.start == YearStart::CALENDAR {
+            write!(f, "{}", self.end)
+        } else {
+            write!(f, "{}/{:02}", self.end - 1, self.end % 100)
+        }
+    }
+}
+
+/// Written to csv and json as the calendar year in which it ends
+impl Serialize for TaxYear {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_i32(self.end)
+    }
+}
+
+impl std::str::FromStr for TaxYear {
+    type Err = String;
+
+    /// Parses a UK tax year `2020-21`, `2020/21` or `2021` (the year ending in April 2021)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid tax year {}, expected e.g. 2020-21 or 2021", s);
+        let mut parts = s.splitn(2, |c: char| c == '-' || c == '/');
+        let year: i32 = parts
+            .next()
+            .and_then(|y| y.parse().ok())
+            .ok_or_else(invalid)?;
+        match parts.next() {
+            None => Ok(TaxYear::uk(year)),
+            Some(end) => {
+                let end: i32 = end.parse().map_err(|_| invalid())?;
+                let expected_end = (year + 1) % 100;
+                if end == expected_end || end == year + 1 {
+                    Ok(TaxYear::uk(year + 1))
+                } else {
+                    Err(format!(
+                        "Invalid year {}, a tax year starting in {} should end in {:02}",
+                        s, year, expected_end
+                    ))
+                }
+            }
+        }
+    }
+}
+
+/// The taxable events of a tax year
+pub struct TaxYearEvents<'a> {
+    pub year: TaxYear,
     pub events: Vec<TaxEvent<'a>>,
 }
-impl<'a> TaxYear<'a> {
-    fn new(year: Year) -> Self {
-        TaxYear {
+impl<'a> TaxYearEvents<'a> {
+    fn new(year: TaxYear) -> Self {
+        TaxYearEvents {
             year,
             events: Vec::new(),
         }
@@ -26,46 +148,491 @@ impl<'a> TaxYear<'a> {
 }
 
 pub struct TaxReport<'a> {
-    pub trades: Vec<Trade<'a>>,
-    pub years: HashMap<Year, TaxYear<'a>>,
-    pub pools: HashMap<String, Pool<'a>>,
+    pub transactions: Vec<Transaction<'a>>,
+    pub years: BTreeMap<TaxYear, TaxYearEvents<'a>>,
+    pub pools: BTreeMap<String, Pool<'a>>,
 }
 
 impl<'a> TaxReport<'a> {
     fn new(
-        trades: Vec<Trade<'a>>,
+        transactions: Vec<Transaction<'a>>,
         gains: Vec<TaxEvent<'a>>,
-        pools: HashMap<String, Pool<'a>>,
+        pools: BTreeMap<String, Pool<'a>>,
     ) -> Self {
-        let mut tax_years = HashMap::new();
+        let mut tax_years = BTreeMap::new();
         for gain in gains.iter() {
             let year = gain.tax_year;
-            let ty = tax_years.entry(year).or_insert(TaxYear::new(year));
+            let ty = tax_years.entry(year).or_insert(TaxYearEvents::new(year));
             ty.events.push(gain.clone())
         }
         Self {
-            trades: trades.to_vec(),
+            transactions,
             years: tax_years,
             pools,
         }
     }
 
-    pub(crate) fn gains(&self, year: Option<Year>) -> Gains {
-        let mut gains = year
-            .and_then(|y| self.years.get(&y).map(|ty| ty.events.clone()))
-            .unwrap_or(
-                self.years
-                    .iter()
-                    .flat_map(|(_, y)| y.events.clone())
-                    .collect::<Vec<_>>(),
-            );
-        gains.sort_by(|g1, g2| g1.trade.date_time.cmp(&g2.trade.date_time));
+    pub fn gains(&self, year: Option<TaxYear>) -> Gains {
+        let mut gains = match year {
+            Some(y) => self
+                .years
+                .get(&y)
+                .map(|ty| ty.events.clone())
+                .unwrap_or_default(),
+            None => self.all_events(),
+        };
+        gains.sort_by_key(|g| (g.trade.date_time, g.id));
         Gains { year, gains }
     }
+
+    /// Gains for all events between the `from` and `to` dates inclusive
+    pub fn gains_between(&self, from: NaiveDate, to: NaiveDate) -> Gains {
+        let mut gains = self
+            .all_events()
+            .into_iter()
+            .filter(|g| {
+                let date = g.trade.date_time.date();
+                date >= from && date <= to
+            })
+            .collect::<Vec<_>>();
+        gains.sort_by_key(|g| (g.trade.date_time, g.id));
+        Gains { year: None, gains }
+    }
+
+    fn all_events(&self) -> Vec<TaxEvent<'a>> {
+        self.years
+            .iter()
+            .flat_map(|(_, y)| y.events.clone())
+            .collect()
+    }
+}
+
+/// The period to report gains for
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReportPeriod {
+    /// The tax year containing today's date
+    Current,
+    /// A tax year, starting on the year start of the report
+    TaxYear(TaxYear),
+    /// All events between two dates inclusive
+    DateRange(NaiveDate, NaiveDate),
+}
+
+/// The day and month on which each tax year starts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct YearStart {
+    month: u32,
+    day: u32,
+}
+
+impl YearStart {
+    /// The UK tax year, 6 April to 5 April
+    pub const UK: YearStart = YearStart { month: 4, day: 6 };
+    /// The calendar year, 1 January to 31 December
+    pub const CALENDAR: YearStart = YearStart { month: 1, day: 1 };
+    /// 1 July to 30 June, as used in e.g. Australia
+    pub const JULY: YearStart = YearStart { month: 7, day: 1 };
+
+    /// The tax year containing the given date
+    pub fn tax_year(&self, date_time: NaiveDateTime) -> TaxYear {
+        TaxYear::containing(date_time.date(), *self)
+    }
+}
+
+impl Default for YearStart {
+    fn default() -> Self {
+        YearStart::UK
+    }
+}
+
+impl std::str::FromStr for YearStart {
+    type Err = String;
+
+    /// Parses `uk`, `calendar`, `july` or a custom start date in the format `MM-DD`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "uk" => Ok(YearStart::UK),
+            "calendar" => Ok(YearStart::CALENDAR),
+            "july" => Ok(YearStart::JULY),
+            custom => {
+                // use a non leap year, a year can't start on 29 February
+                let date = NaiveDate::parse_from_str(&format!("2001-{}", custom), "%Y-%m-%d")
+                    .map_err(|_| {
+                        format!(
+                            "Invalid year start {}, expected uk, calendar, july or MM-DD",
+                            custom
+                        )
+                    })?;
+                Ok(YearStart {
+                    month: date.month(),
+                    day: date.day(),
+                })
+            }
+        }
+    }
+}
+
+/// Options for the calculation of gains
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// When each tax year starts, for bucketing disposals into years
+    pub year_start: YearStart,
+    /// How to treat transfers which change the beneficial ownership of an asset
+    pub ownership_transfers: OwnershipTransferPolicy,
+    /// Whether to value trades with a fiat leg by their consideration or the market price
+    pub valuation: Valuation,
+    /// Tokens which became worthless, the prices must also be marked as worthless
+    pub worthless: Vec<Worthless>,
+    /// Existing Section 104 holdings to start the pools with
+    pub opening_pools: Vec<OpeningPool>,
+    /// How to treat disposals of more than is held, acquired before the imported history
+    pub unknown_basis: UnknownBasis,
+    /// Add the gas paid for swaps on EVM chains to the cost of the acquisition, instead of
+    /// deducting it as a cost of the disposal
+    pub capitalise_swap_gas: bool,
+    /// Whether the fees of disposals are allowable costs or deducted from the proceeds
+    pub disposal_fees: DisposalFees,
+}
+
+/// A Section 104 holding carried over e.g. from another tool, so that the history before it
+/// doesn't have to be imported trade by trade
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpeningPool {
+    pub currency: &'static Currency,
+    pub quantity: Decimal,
+    /// The pooled allowable costs in GBP
+    pub total_cost: Decimal,
+    /// The date of the holding, transactions of the asset should only be imported after it
+    pub as_of: NaiveDate,
+}
+
+/// asset,quantity,total_cost,as_of
+#[derive(Deserialize)]
+struct OpeningPoolRecord {
+    asset: String,
+    quantity: Decimal,
+    total_cost: Decimal,
+    /// e.g. 2020-04-05
+    as_of: String,
+}
+
+impl OpeningPool {
+    pub fn read_csv<R>(reader: R) -> color_eyre::Result<Vec<OpeningPool>>
+    where
+        R: std::io::Read,
+    {
+        let mut rdr = csv::Reader::from_reader(reader);
+        rdr.deserialize::<OpeningPoolRecord>()
+            .map(|record| -> color_eyre::Result<OpeningPool> {
+                let record = record?;
+                let currency = crate::money::find(&record.asset)
+                    .ok_or_else(|| eyre::eyre!("Unknown opening pool asset {}", record.asset))?;
+                let as_of = NaiveDate::parse_from_str(&record.as_of, "%Y-%m-%d")?;
+                Ok(OpeningPool {
+                    currency,
+                    quantity: record.quantity,
+                    total_cost: record.total_cost,
+                    as_of,
+                })
+            })
+            .collect()
+    }
+}
+
+/// A token which is worthless from a date e.g. when delisted or rug pulled
+#[derive(Debug, Clone, PartialEq)]
+pub struct Worthless {
+    pub currency: &'static Currency,
+    pub from: NaiveDate,
+    /// Whether to make a negligible value claim, crystallising the loss on the date
+    pub claim: bool,
+}
+
+impl std::str::FromStr for Worthless {
+    type Err = String;
+
+    /// Parses `CODE:YYYY-MM-DD`, with a `:claim` suffix to make a negligible value claim
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "Invalid worthless token {}, expected e.g. LUNA:2022-05-13 or LUNA:2022-05-13:claim",
+                s
+            )
+        };
+        let parts = s.split(':').collect::<Vec<_>>();
+        let (code, date, claim) = match parts[..] {
+            [code, date] => (code, date, false),
+            [code, date, "claim"] => (code, date, true),
+            _ => return Err(invalid()),
+        };
+        let currency =
+            crate::money::find(code).ok_or_else(|| format!("Unknown currency {}", code))?;
+        let from = NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|_| invalid())?;
+        Ok(Worthless {
+            currency,
+            from,
+            claim,
+        })
+    }
+}
+
+/// Treatment of transfers flagged as changing beneficial ownership e.g. collateral posted to a
+/// lender. HMRC treatment depends on the terms of the arrangement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OwnershipTransferPolicy {
+    /// Withdrawals are disposals and deposits acquisitions, at market value
+    Disposal,
+    /// Transfers are not taxable events, regardless of the flag
+    Ignore,
+}
+
+impl Default for OwnershipTransferPolicy {
+    fn default() -> Self {
+        OwnershipTransferPolicy::Disposal
+    }
+}
+
+impl std::str::FromStr for OwnershipTransferPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "disposal" => Ok(OwnershipTransferPolicy::Disposal),
+            "ignore" => Ok(OwnershipTransferPolicy::Ignore),
+            p => Err(format!(
+                "Invalid ownership transfer policy {}, expected disposal or ignore",
+                p
+            )),
+        }
+    }
+}
+
+/// Which price takes precedence when valuing a trade with a fiat leg
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Valuation {
+    /// The actual consideration of the fiat leg, converted to GBP if in EUR or USD
+    Consideration,
+    /// The external daily market price of the asset
+    Market,
+}
+
+impl Default for Valuation {
+    fn default() -> Self {
+        Valuation::Consideration
+    }
+}
+
+impl std::str::FromStr for Valuation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "consideration" => Ok(Valuation::Consideration),
+            "market" => Ok(Valuation::Market),
+            v => Err(format!(
+                "Invalid valuation {}, expected consideration or market",
+                v
+            )),
+        }
+    }
+}
+
+/// How to treat the part of a disposal exceeding the pool, when the history is incomplete and
+/// the asset was acquired before the first imported transaction. Either way the disposal is
+/// flagged with an unknown basis match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnknownBasis {
+    /// Assume it was acquired for nothing, so all of its proceeds are a gain
+    ZeroCost,
+    /// Leave its proceeds out of the gain, until the missing history is imported
+    Exclude,
+}
+
+impl Default for UnknownBasis {
+    fn default() -> Self {
+        UnknownBasis::ZeroCost
+    }
+}
+
+impl std::str::FromStr for UnknownBasis {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "zero" => Ok(UnknownBasis::ZeroCost),
+            "exclude" => Ok(UnknownBasis::Exclude),
+            b => Err(format!(
+                "Invalid unknown basis {}, expected zero or exclude",
+                b
+            )),
+        }
+    }
+}
+
+/// How the fees paid on disposals are presented. Either way they're deducted from the gain, as
+/// incidental costs of the disposal (TCGA 1992 s38(1)(c)). The fees paid on acquisitions for GBP
+/// are always added to the allowable costs of the asset acquired.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisposalFees {
+    /// Included in the allowable costs, as in the allowable costs box of the SA108
+    Costs,
+    /// Deducted from the proceeds, for reconciling with the net amounts received from exchanges
+    Proceeds,
+}
+
+impl Default for DisposalFees {
+    fn default() -> Self {
+        DisposalFees::Costs
+    }
+}
+
+impl std::str::FromStr for DisposalFees {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "costs" => Ok(DisposalFees::Costs),
+            "proceeds" => Ok(DisposalFees::Proceeds),
+            f => Err(format!(
+                "Invalid disposal fees {}, expected costs or proceeds",
+                f
+            )),
+        }
+    }
+}
+
+/// How the figures of the report are rounded when presented. The gains are always calculated
+/// from the exact figures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rounding {
+    /// To whole pounds in the taxpayer's favour, as HMRC allow: proceeds down and costs up
+    Hmrc,
+    /// To the penny
+    Exact,
+}
+
+impl Rounding {
+    pub fn proceeds<'a>(&self, proceeds: &Money<'a>) -> Money<'a> {
+        match self {
+            Rounding::Hmrc => Money::from_decimal(proceeds.amount().floor(), proceeds.currency()),
+            Rounding::Exact => proceeds.clone(),
+        }
+    }
+
+    pub fn costs<'a>(&self, costs: &Money<'a>) -> Money<'a> {
+        match self {
+            Rounding::Hmrc => Money::from_decimal(costs.amount().ceil(), costs.currency()),
+            Rounding::Exact => costs.clone(),
+        }
+    }
+
+    /// The gain from the rounded proceeds less the rounded costs, including any fees
+    pub fn gain<'a>(&self, proceeds: &Money<'a>, gain: &Money<'a>) -> Money<'a> {
+        let costs = proceeds.clone() - gain.clone();
+        self.proceeds(proceeds) - self.costs(&costs)
+    }
+}
+
+impl Default for Rounding {
+    fn default() -> Self {
+        Rounding::Exact
+    }
+}
+
+impl fmt::Display for Rounding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rounding::Hmrc => write!(f, "hmrc"),
+            Rounding::Exact => write!(f, "exact"),
+        }
+    }
+}
+
+impl std::str::FromStr for Rounding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hmrc" => Ok(Rounding::Hmrc),
+            "exact" => Ok(Rounding::Exact),
+            r => Err(format!("Invalid rounding {}, expected hmrc or exact", r)),
+        }
+    }
+}
+
+/// The basis on which the GBP value of a trade was determined
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PriceBasis {
+    /// The GBP consideration of the trade itself
+    Consideration,
+    /// The EUR or USD consideration of the trade, converted at the daily exchange rate
+    ExchangeRate,
+    /// The external daily market price
+    Market,
+}
+
+impl fmt::Display for PriceBasis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PriceBasis::Consideration => write!(f, "consideration"),
+            PriceBasis::ExchangeRate => write!(f, "exchange rate"),
+            PriceBasis::Market => write!(f, "market"),
+        }
+    }
+}
+
+impl ReportPeriod {
+    /// Whether the period contains the given date
+    pub fn contains(&self, date_time: NaiveDateTime, year_start: &YearStart) -> bool {
+        match self {
+            ReportPeriod::Current => {
+                let today = chrono::Utc::now().naive_utc();
+                year_start.tax_year(date_time) == year_start.tax_year(today)
+            }
+            ReportPeriod::TaxYear(year) => year.with_start(*year_start).contains(date_time.date()),
+            ReportPeriod::DateRange(from, to) => {
+                date_time.date() >= *from && date_time.date() <= *to
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for ReportPeriod {
+    type Err = String;
+
+    /// Parses either a tax year, `2020-21`, `2020/21`, `2021` (the year ending in April 2021) or
+    /// `current`, or a date range `2020-06-01..2020-12-31`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "Invalid year {}, expected e.g. 2020-21, 2021, current or 2020-06-01..2020-12-31",
+                s
+            )
+        };
+        if s == "current" {
+            return Ok(ReportPeriod::Current);
+        }
+        if let Some(range) = s.find("..") {
+            let parse = |d: &str| NaiveDate::parse_from_str(d, "%Y-%m-%d").map_err(|_| invalid());
+            let from = parse(&s[..range])?;
+            let to = parse(&s[range + 2..])?;
+            if from > to {
+                return Err(format!(
+                    "Invalid date range {}, {} is after {}",
+                    s, from, to
+                ));
+            }
+            return Ok(ReportPeriod::DateRange(from, to));
+        }
+        if !s.starts_with(|c: char| c.is_ascii_digit()) {
+            return Err(invalid());
+        }
+        s.parse().map(ReportPeriod::TaxYear)
+    }
 }
 
 pub struct Gains<'a> {
-    pub year: Option<Year>,
+    pub year: Option<TaxYear>,
     pub gains: Vec<TaxEvent<'a>>,
 }
 
@@ -79,77 +646,513 @@ impl<'a> IntoIterator for Gains<'a> {
 }
 
 impl<'a> Gains<'a> {
-    pub(crate) fn len(&self) -> usize {
-        self.gains.len()
+    /// Only the events on the given exchange or account, see [`transactions::venue_matches`]
+    pub(crate) fn for_account(self, account: &str) -> Self {
+        let gains = self
+            .gains
+            .into_iter()
+            .filter(|g| {
+                let (exchange, label) = (g.trade.exchange.as_deref(), g.trade.account.as_deref());
+                transactions::venue_matches(exchange, label, account)
+            })
+            .collect();
+        Gains {
+            year: self.year,
+            gains,
+        }
+    }
+
+    /// Only the events of the kind of asset, disposed of or acquired for GBP, so that securities
+    /// can be reported separately from crypto
+    pub fn for_instrument(self, instrument: Instrument) -> Self {
+        let gains = self
+            .gains
+            .into_iter()
+            .filter(|g| g.instrument() == instrument)
+            .collect();
+        Gains {
+            year: self.year,
+            gains,
+        }
+    }
+
+    /// The events which are disposals, excluding acquisitions for GBP
+    pub fn disposals(&self) -> impl Iterator<Item = &TaxEvent<'a>> {
+        self.gains.iter().filter(|g| g.is_disposal())
+    }
+
+    pub fn disposal_count(&self) -> usize {
+        self.disposals().count()
     }
 
-    pub(crate) fn total_proceeds(&self) -> Money<'a> {
-        self.gains.iter().fold(Money::from_major(0, GBP), |acc, g| {
+    pub fn total_proceeds(&self) -> Money<'a> {
+        self.disposals().fold(Money::from_major(0, GBP), |acc, g| {
             acc + g.proceeds().clone()
         })
     }
 
-    pub(crate) fn total_allowable_costs(&self) -> Money<'a> {
-        self.gains.iter().fold(Money::from_major(0, GBP), |acc, g| {
+    pub fn total_allowable_costs(&self) -> Money<'a> {
+        self.disposals().fold(Money::from_major(0, GBP), |acc, g| {
             acc + g.allowable_costs().clone()
         })
     }
 
-    pub(crate) fn total_gain(&self) -> Money<'a> {
-        self.gains
-            .iter()
+    pub fn total_gain(&self) -> Money<'a> {
+        self.disposals()
             .fold(Money::from_major(0, GBP), |acc, g| acc + g.gain())
     }
+
+    /// Total allowable costs of the disposals from acquisitions matched by the given rule
+    pub(crate) fn total_costs_matched_by(&self, rule: MatchingRule) -> Money<'a> {
+        self.disposals()
+            .flat_map(|g| g.matches.iter())
+            .filter(|m| m.rule == rule)
+            .fold(Money::from_major(0, GBP), |acc, m| acc + m.costs.clone())
+    }
+
+    /// Total GBP value of the parts of disposals exceeding the pool, acquired before the imported
+    /// history
+    pub(crate) fn total_unknown_basis(&self) -> Money<'a> {
+        self.disposals()
+            .filter_map(|g| g.unknown_basis.clone())
+            .fold(Money::from_major(0, GBP), |acc, value| acc + value)
+    }
+
+    /// Tax due on the net gain of each tax year above its annual exempt amount, estimated at the
+    /// higher rate
+    pub(crate) fn estimated_liability(&self, rates: &TaxRates) -> Money<'a> {
+        let zero = Money::from_major(0, GBP);
+        let mut years = BTreeMap::new();
+        for disposal in self.disposals() {
+            let gain = years.entry(disposal.tax_year).or_insert(zero.clone());
+            *gain = gain.clone() + disposal.gain();
+        }
+        years.into_iter().fold(zero.clone(), |acc, (year, gain)| {
+            let rate = match rates.for_year(year) {
+                Some(rate) => rate,
+                None => {
+                    tracing::warn!("No tax rates for {}, the tax due is not estimated", year);
+                    return acc;
+                }
+            };
+            let taxable = gain - Money::from_decimal(rate.annual_exempt_amount, GBP);
+            if taxable > zero {
+                acc + taxable * (rate.higher_rate / Decimal::new(100, 0))
+            } else {
+                acc
+            }
+        })
+    }
+
+    /// Total of all disposals made at a gain
+    pub fn total_gains(&self) -> Money<'a> {
+        let zero = Money::from_major(0, GBP);
+        self.disposals()
+            .map(|g| g.gain())
+            .filter(|gain| *gain > zero)
+            .fold(zero.clone(), |acc, gain| acc + gain)
+    }
+
+    /// Total of all disposals made at a loss, as a positive amount
+    pub fn total_losses(&self) -> Money<'a> {
+        let zero = Money::from_major(0, GBP);
+        self.disposals()
+            .map(|g| g.gain())
+            .filter(|gain| *gain < zero)
+            .fold(zero.clone(), |acc, loss| acc - loss)
+    }
 }
 
 #[derive(Clone)]
 pub struct TaxEvent<'a> {
+    id: usize,
+    kind: TransactionKind,
     trade: Trade<'a>,
-    tax_year: Year,
+    tax_year: TaxYear,
     buy_value: Money<'a>,
     sell_value: Money<'a>,
     fee_value: Money<'a>,
+    /// The value of the asset disposed of, less the fee when deducted from the proceeds
+    proceeds: Money<'a>,
     price: Price<'a>,
+    price_basis: PriceBasis,
+    /// The date of the price when there was none on the day of the trade, and the nearest was
+    /// used instead
+    price_date: Option<NaiveDate>,
+    /// The price of the asset in the fiat currency of the trade, when valued in two steps by
+    /// converting the fiat leg to GBP
+    asset_price: Option<Price<'a>>,
+    fee_price: Option<Price<'a>>,
+    /// The costs of the matched acquisitions, plus the fee when counted as an allowable cost
     allowable_costs: Money<'a>,
     buy_pool: Option<Pool<'a>>,
     sell_pool: Option<Pool<'a>>,
+    matches: Vec<MatchedAcquisition<'a>>,
+    /// The GBP value of the part of the disposal exceeding the pool
+    unknown_basis: Option<Money<'a>>,
+    /// The cost of the whole amount acquired, before any is matched with earlier disposals
+    acquisition_cost: Option<Money<'a>>,
+    year_boundary: Option<YearBoundary>,
+    /// Which side of a trade of one asset for another the event is, `None` for trades with GBP
+    leg: Option<Leg>,
 }
 impl<'a> TaxEvent<'a> {
+    /// The id of the event, as shown in the report output
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn kind(&self) -> TransactionKind {
+        self.kind
+    }
+
+    /// The tax year of the event
+    pub fn tax_year(&self) -> TaxYear {
+        self.tax_year
+    }
+
+    /// Whether the event is at the edge of its tax year, where the year it falls in is easy to
+    /// get wrong and worth checking
+    pub fn year_boundary(&self) -> Option<YearBoundary> {
+        self.year_boundary
+    }
+
+    /// Which side of a trade of one asset for another the event is, `None` for trades with GBP
+    pub fn leg(&self) -> Option<Leg> {
+        self.leg
+    }
+
+    /// The date of the price used when it's from a nearby day, with no price on the day itself
+    pub fn price_date(&self) -> Option<NaiveDate> {
+        self.price_date
+    }
+
+    /// Whether the event disposes of an asset, rather than only acquiring one for GBP or as the
+    /// acquisition side of a trade of one asset for another
+    pub fn is_disposal(&self) -> bool {
+        self.leg != Some(Leg::Acquisition) && self.trade.sell.currency() != GBP
+    }
+
+    /// Splits the event of a trade of one asset for another into its disposal, with the matches,
+    /// costs and fee, followed by its acquisition, with the pool of the asset acquired. Both have
+    /// the id of the trade, which links them. Events of trades with GBP are returned unchanged.
+    fn split(self) -> Vec<TaxEvent<'a>> {
+        if self.trade.buy.currency() == GBP || self.trade.sell.currency() == GBP {
+            return vec![self];
+        }
+        let zero = Money::from_major(0, GBP);
+        let acquisition = TaxEvent {
+            fee_value: zero.clone(),
+            fee_price: None,
+            proceeds: self.sell_value.clone(),
+            allowable_costs: zero,
+            sell_pool: None,
+            matches: Vec::new(),
+            unknown_basis: None,
+            year_boundary: YearBoundary::of(self.trade.date_time.date(), self.tax_year, &[]),
+            leg: Some(Leg::Acquisition),
+            ..self.clone()
+        };
+        let disposal = TaxEvent {
+            buy_pool: None,
+            acquisition_cost: None,
+            leg: Some(Leg::Disposal),
+            ..self
+        };
+        vec![disposal, acquisition]
+    }
+
+    /// The proceeds of a disposal, net of the fee if deducted from them, see [`DisposalFees`]
     pub fn proceeds(&self) -> &Money<'a> {
-        &self.sell_value // todo: fees
+        &self.proceeds
+    }
+
+    /// The GBP value of the asset disposed of, before any fee
+    pub fn sell_value(&self) -> &Money<'a> {
+        &self.sell_value
     }
 
     pub fn allowable_costs(&self) -> &Money<'a> {
         &self.allowable_costs
     }
 
+    pub fn trade(&self) -> &Trade<'a> {
+        &self.trade
+    }
+
     pub fn fee(&self) -> &Money<'a> {
         &self.fee_value
     }
 
     pub fn gain(&self) -> Money<'a> {
-        self.sell_value.clone() - self.allowable_costs.clone() - self.fee().clone()
+        self.proceeds.clone() - self.allowable_costs.clone()
     }
 
-    pub fn write_csv<E, W>(tax_events: E, writer: W) -> color_eyre::Result<()>
+    /// The cost of the asset acquired by the event, none for disposals for GBP. Part of it may
+    /// be matched with earlier disposals by the same day or 30 day rules, rather than pooled.
+    pub fn acquisition_cost(&self) -> Option<&Money<'a>> {
+        self.acquisition_cost.as_ref()
+    }
+
+    /// The acquisitions matched against a disposal
+    pub fn matches(&self) -> &[MatchedAcquisition<'a>] {
+        &self.matches
+    }
+
+    /// The kind of asset disposed of, or acquired by an acquisition
+    pub fn instrument(&self) -> Instrument {
+        if self.is_disposal() {
+            money::instrument(self.trade.sell.currency())
+        } else {
+            money::instrument(self.trade.buy.currency())
+        }
+    }
+
+    /// The pools of the currencies bought and sold, as they were after the event
+    pub fn pools(&self) -> impl Iterator<Item = &Pool<'a>> {
+        self.buy_pool.iter().chain(self.sell_pool.iter())
+    }
+
+    /// Writes the events as csv, with the proceeds, allowable costs and gains of disposals rounded
+    pub fn write_csv<E, W>(tax_events: E, rounding: Rounding, writer: W) -> color_eyre::Result<()>
     where
         E: IntoIterator<Item = TaxEvent<'a>>,
         W: Write,
     {
         let mut wtr = csv::Writer::from_writer(writer);
         for tax_event in tax_events.into_iter() {
-            let record: TaxEventRecord = tax_event.into();
+            let rounded = RoundedRecord::new(&tax_event, rounding);
+            let mut record: TaxEventRecord = tax_event.into();
+            if let Some(rounded) = rounded {
+                record.sell_gbp = rounded.proceeds;
+                record.allowable_cost = rounded.allowable_cost;
+                record.gain = rounded.gain;
+            }
             wtr.serialize(record)?;
         }
         wtr.flush()?;
         Ok(())
     }
+
+    /// Writes the events as json with the exact figures, and the rounded figures of disposals
+    /// alongside them unless the rounding is exact
+    pub fn write_json<E, W>(tax_events: E, rounding: Rounding, writer: W) -> color_eyre::Result<()>
+    where
+        E: IntoIterator<Item = TaxEvent<'a>>,
+        W: Write,
+    {
+        let records = tax_events
+            .into_iter()
+            .map(|tax_event| {
+                let matches = tax_event.matches.iter().map(Into::into).collect();
+                let rounded = RoundedRecord::new(&tax_event, rounding);
+                TaxEventJson {
+                    record: tax_event.into(),
+                    matches,
+                    rounded,
+                }
+            })
+            .collect::<Vec<_>>();
+        serde_json::to_writer_pretty(writer, &records)?;
+        Ok(())
+    }
+}
+
+/// An acquisition matched against a disposal, determining part of its allowable costs
+#[derive(Clone)]
+pub struct MatchedAcquisition<'a> {
+    rule: MatchingRule,
+    /// The id of the acquiring trade, `None` when matched against the pool
+    trade_id: Option<usize>,
+    date_time: Option<NaiveDateTime>,
+    amount: Money<'a>,
+    costs: Money<'a>,
+}
+
+impl<'a> MatchedAcquisition<'a> {
+    pub fn rule(&self) -> MatchingRule {
+        self.rule
+    }
+
+    /// The id of the acquiring trade, `None` when matched against the pool
+    pub fn trade_id(&self) -> Option<usize> {
+        self.trade_id
+    }
+
+    /// When the acquiring trade was, `None` when matched against the pool
+    pub fn date_time(&self) -> Option<NaiveDateTime> {
+        self.date_time
+    }
+
+    pub fn amount(&self) -> &Money<'a> {
+        &self.amount
+    }
+
+    pub fn costs(&self) -> &Money<'a> {
+        &self.costs
+    }
+}
+
+impl<'a> fmt::Display for MatchedAcquisition<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.rule)?;
+        if let Some(trade_id) = self.trade_id {
+            write!(f, " #{}", trade_id)?;
+        }
+        write!(
+            f,
+            " {} {} for {} {}",
+            display_amount(&self.amount),
+            self.amount.currency().code,
+            display_amount(&self.costs),
+            self.costs.currency().code
+        )
+    }
+}
+
+/// The HMRC share matching rule used to match an acquisition to a disposal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchingRule {
+    /// Acquisitions on the same day as the disposal
+    SameDay,
+    /// Acquisitions in the 30 days following the disposal
+    ThirtyDay,
+    /// The Section 104 holding
+    Pool,
+    /// More than the pool holds, acquired before the imported history
+    Unknown,
+}
+
+impl fmt::Display for MatchingRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MatchingRule::SameDay => write!(f, "same-day"),
+            MatchingRule::ThirtyDay => write!(f, "30-day"),
+            MatchingRule::Pool => write!(f, "s104"),
+            MatchingRule::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+/// A side of a trade of one asset for another, which for CGT is both a disposal of the asset
+/// given and an acquisition of the asset received at the same value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Leg {
+    Disposal,
+    Acquisition,
+}
+
+impl fmt::Display for Leg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Leg::Disposal => write!(f, "disposal"),
+            Leg::Acquisition => write!(f, "acquisition"),
+        }
+    }
+}
+
+/// Why an event is flagged as at the edge of its tax year
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum YearBoundary {
+    /// On the first day of the tax year e.g. 6 April
+    FirstDay,
+    /// On the last day of the tax year e.g. 5 April
+    LastDay,
+    /// A disposal matched by the 30 day rule against an acquisition in the next tax year
+    ThirtyDayAcrossYearEnd,
+}
+
+impl YearBoundary {
+    fn of(date: NaiveDate, tax_year: TaxYear, matches: &[MatchedAcquisition]) -> Option<Self> {
+        let across_year_end = matches.iter().any(|m| {
+            m.rule == MatchingRule::ThirtyDay
+                && m.date_time
+                    .map_or(false, |date_time| !tax_year.contains(date_time.date()))
+        });
+        if across_year_end {
+            Some(YearBoundary::ThirtyDayAcrossYearEnd)
+        } else if date == tax_year.first_day() {
+            Some(YearBoundary::FirstDay)
+        } else if date == tax_year.last_day() {
+            Some(YearBoundary::LastDay)
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for YearBoundary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            YearBoundary::FirstDay => write!(f, "first-day"),
+            YearBoundary::LastDay => write!(f, "last-day"),
+            YearBoundary::ThirtyDayAcrossYearEnd => write!(f, "30-day-across-year-end"),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct TaxEventJson {
+    #[serde(flatten)]
+    record: TaxEventRecord,
+    matches: Vec<MatchedAcquisitionRecord>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rounded: Option<RoundedRecord>,
+}
+
+/// The figures of a disposal as presented, when not exact
+#[derive(Serialize)]
+struct RoundedRecord {
+    rounding: String,
+    proceeds: String,
+    allowable_cost: String,
+    gain: String,
+}
+
+impl RoundedRecord {
+    fn new(tax_event: &TaxEvent, rounding: Rounding) -> Option<Self> {
+        if rounding == Rounding::Exact || !tax_event.is_disposal() {
+            return None;
+        }
+        Some(RoundedRecord {
+            rounding: rounding.to_string(),
+            proceeds: display_amount(&rounding.proceeds(tax_event.proceeds())),
+            allowable_cost: display_amount(&rounding.costs(tax_event.allowable_costs())),
+            gain: display_amount(&rounding.gain(tax_event.proceeds(), &tax_event.gain())),
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct MatchedAcquisitionRecord {
+    rule: String,
+    trade_id: Option<usize>,
+    date_time: Option<String>,
+    asset: String,
+    amount: String,
+    costs: String,
+}
+
+impl<'a, 'b> From<&'b MatchedAcquisition<'a>> for MatchedAcquisitionRecord {
+    fn from(matched: &'b MatchedAcquisition<'a>) -> Self {
+        MatchedAcquisitionRecord {
+            rule: matched.rule.to_string(),
+            trade_id: matched.trade_id,
+            date_time: matched.date_time.map(|dt| dt.to_string()),
+            asset: matched.amount.currency().code.to_string(),
+            amount: display_amount(&matched.amount),
+            costs: display_amount(&matched.costs),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 struct TaxEventRecord {
+    id: usize,
     date_time: String,
-    tax_year: Year,
+    /// The calendar year in which the tax year ends
+    tax_year: i32,
+    kind: String,
     exchange: String,
     buy_asset: String,
     buy_amt: String,
@@ -157,21 +1160,33 @@ struct TaxEventRecord {
     sell_amt: String,
     price: String,
     rate: String,
+    price_basis: String,
+    /// Set when the price is from the nearest day with a price, not the day of the trade
+    price_date: String,
+    asset_price: String,
     buy_gbp: String,
     sell_gbp: String,
     fee: String,
+    fee_price: String,
     allowable_cost: String,
     gain: String,
     buy_pool_total: String,
     buy_pool_cost: String,
     sell_pool_total: String,
     sell_pool_cost: String,
+    matched: String,
+    /// Set for events at the edge of their tax year
+    year_boundary: String,
+    /// Set for the disposal and acquisition of a trade of one asset for another
+    leg: String,
 }
 impl<'a> From<TaxEvent<'a>> for TaxEventRecord {
     fn from(tax_event: TaxEvent) -> Self {
         TaxEventRecord {
+            id: tax_event.id,
             date_time: tax_event.trade.date_time.date().to_string(),
-            tax_year: tax_event.tax_year,
+            tax_year: tax_event.tax_year.end_year(),
+            kind: tax_event.kind.to_string(),
             exchange: tax_event.trade.exchange.clone().unwrap_or(String::new()),
             buy_asset: tax_event.trade.buy.currency().code.to_string(),
             buy_amt: display_amount(&tax_event.trade.buy),
@@ -179,9 +1194,21 @@ impl<'a> From<TaxEvent<'a>> for TaxEventRecord {
             sell_amt: display_amount(&tax_event.trade.sell),
             price: tax_event.price.pair.to_string(),
             rate: tax_event.price.rate.to_string(),
+            price_basis: tax_event.price_basis.to_string(),
+            price_date: tax_event
+                .price_date
+                .map_or(String::new(), |date| date.to_string()),
+            asset_price: tax_event
+                .asset_price
+                .as_ref()
+                .map_or("".to_string(), |p| format!("{} {}", p.pair, p.rate)),
             buy_gbp: display_amount(&tax_event.buy_value),
             sell_gbp: display_amount(&tax_event.sell_value),
             fee: display_amount(tax_event.fee()),
+            fee_price: tax_event
+                .fee_price
+                .as_ref()
+                .map_or("".to_string(), |p| format!("{} {}", p.pair, p.rate)),
             allowable_cost: display_amount(tax_event.allowable_costs()),
             gain: display_amount(&tax_event.gain()),
             buy_pool_total: tax_event
@@ -200,6 +1227,16 @@ impl<'a> From<TaxEvent<'a>> for TaxEventRecord {
                 .sell_pool
                 .as_ref()
                 .map_or("".to_string(), |p| format!("{:.2}", &p.cost_basis())),
+            matched: tax_event
+                .matches
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join("; "),
+            year_boundary: tax_event
+                .year_boundary
+                .map_or(String::new(), |boundary| boundary.to_string()),
+            leg: tax_event.leg.map_or(String::new(), |leg| leg.to_string()),
         }
     }
 }
@@ -209,6 +1246,7 @@ pub struct Pool<'a> {
     currency: &'a Currency,
     total: Money<'a>,
     costs: Money<'a>,
+    acquisition_costs: Money<'a>,
 }
 impl<'a> Pool<'a> {
     fn new(currency: &'a Currency) -> Self {
@@ -216,23 +1254,25 @@ impl<'a> Pool<'a> {
             currency,
             total: Money::from_major(0, currency),
             costs: Money::from_major(0, GBP),
+            acquisition_costs: Money::from_major(0, GBP),
         }
     }
 
     fn buy(&mut self, buy: &Money<'a>, costs: &Money<'a>) {
         self.total = self.total.clone() + buy.clone();
         self.costs = self.costs.clone() + costs.clone();
-        log::debug!(
-            "Pool BUY {}, costs: {}",
-            display_amount(&buy),
-            display_amount(&costs)
+        self.acquisition_costs = self.acquisition_costs.clone() + costs.clone();
+        tracing::debug!(
+            amount = %display_amount(&buy),
+            costs = %display_amount(&costs),
+            pool = ?self,
+            "Pool BUY"
         );
-        log::debug!("Pool: {:?}", self);
     }
 
     fn sell(&mut self, sell: Money<'a>) -> Money<'a> {
-        let (costs, new_total, new_costs) = if sell > self.total {
-            // selling more than is in the pool
+        let (costs, new_total, new_costs) = if sell >= self.total {
+            // selling everything, or more than is in the pool
             (
                 self.costs.clone(),
                 Money::from_major(0, &self.currency),
@@ -247,15 +1287,35 @@ impl<'a> Pool<'a> {
         };
         self.total = new_total;
         self.costs = new_costs;
-        log::debug!(
-            "Pool SELL {}, costs: {}",
-            display_amount(&sell),
-            display_amount(&costs)
+        tracing::debug!(
+            amount = %display_amount(&sell),
+            costs = %display_amount(&costs),
+            pool = ?self,
+            "Pool SELL"
         );
-        log::debug!("Pool: {:?}", self);
         costs
     }
 
+    /// The amount of the currency held in the pool
+    pub fn total(&self) -> &Money<'a> {
+        &self.total
+    }
+
+    /// The pooled allowable costs in GBP
+    pub fn costs(&self) -> &Money<'a> {
+        &self.costs
+    }
+
+    /// The total allowable costs in GBP of every acquisition added to the pool, before any were
+    /// allocated to disposals
+    pub fn acquisition_costs(&self) -> &Money<'a> {
+        &self.acquisition_costs
+    }
+
+    pub fn currency(&self) -> &'a Currency {
+        self.currency
+    }
+
     fn cost_basis(&self) -> Decimal {
         use rust_decimal::prelude::Zero;
         self.costs
@@ -277,40 +1337,149 @@ impl<'a> fmt::Debug for Pool<'a> {
     }
 }
 
+/// The log filter directive for only the computation path of the trade with the given id, whose
+/// events are all in its `trade` span
+pub fn trace_trade_filter(id: usize) -> String {
+    format!("[trade{{id={}}}]=trace", id)
+}
+
 pub fn calculate<'a>(
-    mut trades: Vec<Trade<'a>>,
-    prices: &'a Prices<'a>,
+    transactions: Vec<Transaction<'a>>,
+    prices: &Prices<'a>,
+    options: &Options,
 ) -> color_eyre::Result<TaxReport<'a>> {
-    let mut pools = HashMap::new();
+    let mut pools = BTreeMap::new();
+    for opening in options.opening_pools.iter() {
+        let pool = pools
+            .entry(opening.currency.code.to_string())
+            .or_insert(Pool::new(opening.currency));
+        pool.buy(
+            &Money::from_decimal(opening.quantity, opening.currency),
+            &Money::from_decimal(opening.total_cost, GBP),
+        );
+    }
 
-    trades.sort_by_key(|trade| trade.date_time);
+    // priced at zero from when they became worthless, whether or not the prices are marked
+    let marked;
+    let prices = if options.worthless.is_empty() {
+        prices
+    } else {
+        let mut worthless_prices = prices.clone();
+        for worthless in options.worthless.iter() {
+            worthless_prices.mark_worthless(worthless.currency, worthless.from);
+        }
+        marked = worthless_prices;
+        &marked
+    };
+
+    let mut trades = transactions
+        .iter()
+        .map(|tx| taxable_trade(tx, prices, options))
+        .collect::<color_eyre::Result<Vec<_>>>()?
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+    for worthless in options.worthless.iter().filter(|w| w.claim) {
+        let claim = negligible_value_claim(&trades, &options.opening_pools, worthless);
+        trades.extend(claim);
+    }
+    trades.sort_by_key(|(_, trade)| trade.date_time);
+    for opening in options.opening_pools.iter() {
+        let earlier = trades
+            .iter()
+            .filter(|(_, t)| {
+                (t.buy.currency() == opening.currency || t.sell.currency() == opening.currency)
+                    && t.date_time.date() <= opening.as_of
+            })
+            .count();
+        if earlier > 0 {
+            tracing::warn!(
+                "{} transactions of {} on or before the opening pool date {} are counted in \
+                 addition to the opening pool",
+                earlier,
+                opening.currency.code,
+                opening.as_of
+            );
+        }
+    }
     let trades_with_prices = trades
         .iter()
-        .map(|trade| {
-            let price = get_price(trade, &prices).expect(&format!(
-                "Should have price for buy: {} sell: {} at {}",
-                trade.buy, trade.sell, trade.date_time
-            ));
-            (trade, price)
+        .enumerate()
+        .map(|(index, (kind, trade))| {
+            let (price, price_basis) =
+                get_price(trade, &prices, options.valuation).ok_or_else(|| {
+                    eyre::eyre!(
+                        "Should have price for buy: {} sell: {} at {}, or within the days of \
+                         --price-tolerance",
+                        trade.buy,
+                        trade.sell,
+                        trade.date_time
+                    )
+                })?;
+            Ok((trade, price, price_basis, *kind, index + 1))
         })
-        .collect::<Vec<_>>();
+        .collect::<color_eyre::Result<Vec<_>>>()?;
+
+    let fee_values = trades_with_prices
+        .iter()
+        .map(|(trade, price, _, _, _)| value_fee(trade, price, prices))
+        .collect::<color_eyre::Result<Vec<_>>>()?;
 
+    // same day matches take priority over the 30 day rule, so are made before any disposal is
+    // matched with a later acquisition, leaving only the remainder of those acquisitions
+    // the disposals of negligible value claims are matched with the pool only
+    let same_day = same_day_matches(
+        &trades_with_prices
+            .iter()
+            .filter(|(trade, _, _, kind, _)| !is_claim(trade, *kind, options))
+            .map(|(trade, _, _, _, id)| (*trade, *id))
+            .collect::<Vec<_>>(),
+    );
     let mut special_buys: HashMap<TradeKey, Money> = HashMap::new();
+    for (buy_id, amount) in same_day.values().flatten() {
+        let (buy, _, _, _, _) = &trades_with_prices[buy_id - 1];
+        let remaining = special_buys
+            .entry(buy.key(*buy_id))
+            .or_insert(buy.buy.clone());
+        *remaining = remaining.clone() - amount.clone();
+    }
 
     let gains = trades_with_prices
         .iter()
         .cloned()
-        .map(|(trade, price)| {
+        .map(|(trade, price, price_basis, kind, id)| {
+            let span = tracing::debug_span!("trade", id, %kind);
+            let _trade = span.enter();
             let trade_record: TradeRecord = trade.into();
-            log::debug!("Trade: {:?}", trade_record);
+            tracing::debug!(trade = ?trade_record, price = %price.rate, "Trade");
             let mut buy_pool: Option<Pool> = None;
             let mut sell_pool: Option<Pool> = None;
             let mut allowable_costs = Money::from_major(0, GBP);
+            let mut matches = Vec::new();
+            let mut unknown_amount = None;
+            let mut acquisition_cost = None;
+
+            let (mut fee_value, fee_price) = fee_values[id - 1].clone();
+
+            let claim = is_claim(trade, kind, options);
+            let capitalise_gas = options.capitalise_swap_gas
+                && trade.buy.currency() != GBP
+                && gas::is_swap_gas(kind, trade);
 
             if trade.buy.currency() != GBP {
+                let _acquisition = tracing::debug_span!("acquisition").entered();
                 let _zero = Money::from_major(0, trade.buy.currency());
-                let buy_amount = special_buys.get(&trade.key()).unwrap_or(&trade.buy);
-                let costs = convert_to_gbp(buy_amount.clone(), &price, trade.rate)?;
+                let buy_amount = special_buys.get(&trade.key(id)).unwrap_or(&trade.buy);
+                let mut costs = acquisition_costs(trade, kind, &price, &fee_value, buy_amount)?;
+                let mut whole_costs =
+                    acquisition_costs(trade, kind, &price, &fee_value, &trade.buy)?;
+                if capitalise_gas {
+                    tracing::debug!(gas = %display_amount(&fee_value), "Capitalised swap gas");
+                    costs = costs + fee_value.clone();
+                    whole_costs = whole_costs + fee_value.clone();
+                    fee_value = Money::from_major(0, GBP);
+                }
+                acquisition_cost = Some(whole_costs);
                 let pool = pools
                     .entry(trade.buy.currency().code.to_string())
                     .or_insert(Pool::new(trade.buy.currency()));
@@ -319,23 +1488,59 @@ pub fn calculate<'a>(
             }
 
             if trade.sell.currency() != GBP {
-                // find any buys of this asset within the next 30 days
+                let mut main_pool_sell = trade.sell.clone();
+                let mut special_allowable_costs = Money::from_major(0, GBP);
+
+                let same_day_span = tracing::debug_span!("same_day").entered();
+                for (buy_id, amount) in same_day.get(&id).into_iter().flatten() {
+                    let (same_day_buy, buy_price, _, buy_kind, _) = &trades_with_prices[buy_id - 1];
+                    let costs = acquisition_costs(
+                        same_day_buy,
+                        *buy_kind,
+                        buy_price,
+                        &fee_values[buy_id - 1].0,
+                        amount,
+                    )?;
+                    tracing::debug!(
+                        amount = %display_amount(amount),
+                        buy_id = *buy_id,
+                        buy_date_time = %same_day_buy.date_time,
+                        costs = %display_amount(&costs),
+                        "Matching SELL with same day BUY"
+                    );
+                    main_pool_sell = main_pool_sell - amount.clone();
+                    special_allowable_costs = special_allowable_costs + costs.clone();
+                    matches.push(MatchedAcquisition {
+                        rule: MatchingRule::SameDay,
+                        trade_id: Some(*buy_id),
+                        date_time: Some(same_day_buy.date_time),
+                        amount: amount.clone(),
+                        costs,
+                    });
+                }
+                drop(same_day_span);
+
+                let thirty_day = tracing::debug_span!("thirty_day").entered();
+                // find any buys of this asset within the next 30 days, those on the same day
+                // having already been matched
                 let special_rules_buy = trades_with_prices
                     .iter()
-                    .filter(|(t, _)| {
-                        t.buy.currency() == trade.sell.currency()
-                            && t.date_time.date() >= trade.date_time.date()
-                            && t.date_time < trade.date_time + Duration::days(30)
+                    .filter(|(t, _, _, _, _)| {
+                        !claim
+                            && t.buy.currency() == trade.sell.currency()
+                            && t.date_time.date() != trade.date_time.date()
+                            && matching_window_contains(
+                                trade.date_time.date(),
+                                t.date_time.date(),
+                                THIRTY_DAY_WINDOW_DAYS,
+                            )
                     })
                     .cloned()
                     .collect::<Vec<_>>();
 
-                let mut main_pool_sell = trade.sell.clone();
-                let mut special_allowable_costs = Money::from_major(0, GBP);
-
-                for (future_buy, buy_price) in special_rules_buy {
+                for (future_buy, buy_price, _, buy_kind, future_id) in special_rules_buy {
                     let remaining_buy_amount = special_buys
-                        .entry(future_buy.key())
+                        .entry(future_buy.key(future_id))
                         .or_insert(future_buy.buy.clone());
 
                     if *remaining_buy_amount > Money::from_major(0, remaining_buy_amount.currency())
@@ -350,32 +1555,93 @@ pub fn calculate<'a>(
                         };
                         *remaining_buy_amount =
                             remaining_buy_amount.clone() - special_buy_amt.clone();
-                        let costs =
-                            convert_to_gbp(special_buy_amt.clone(), &buy_price, future_buy.rate)?;
-                        log::debug!(
-                            "Deducting SELL of {} from future BUY at {}, cost: {}",
-                            display_amount(&special_buy_amt),
-                            future_buy.date_time,
-                            display_amount(&costs)
+                        let costs = acquisition_costs(
+                            future_buy,
+                            buy_kind,
+                            &buy_price,
+                            &fee_values[future_id - 1].0,
+                            &special_buy_amt,
+                        )?;
+                        tracing::debug!(
+                            amount = %display_amount(&special_buy_amt),
+                            buy_id = future_id,
+                            buy_date_time = %future_buy.date_time,
+                            costs = %display_amount(&costs),
+                            "Deducting SELL from future BUY"
                         );
                         main_pool_sell = sell;
-                        special_allowable_costs = special_allowable_costs + costs;
+                        special_allowable_costs = special_allowable_costs + costs.clone();
+                        matches.push(MatchedAcquisition {
+                            rule: MatchingRule::ThirtyDay,
+                            trade_id: Some(future_id),
+                            date_time: Some(future_buy.date_time),
+                            amount: special_buy_amt,
+                            costs,
+                        });
                     }
                 }
 
+                drop(thirty_day);
+
+                let _pool = tracing::debug_span!("pool").entered();
                 let pool = pools
                     .entry(trade.sell.currency().code.to_string())
                     .or_insert(Pool::new(trade.sell.currency()));
-                let main_pool_costs = pool.sell(main_pool_sell);
+                let zero = Money::from_major(0, main_pool_sell.currency());
+                let unknown = if main_pool_sell > pool.total {
+                    main_pool_sell.clone() - pool.total.clone()
+                } else {
+                    zero.clone()
+                };
+                let main_pool_costs = pool.sell(main_pool_sell.clone());
+                let pool_amount = main_pool_sell - unknown.clone();
+                if pool_amount > zero {
+                    matches.push(MatchedAcquisition {
+                        rule: MatchingRule::Pool,
+                        trade_id: None,
+                        date_time: None,
+                        amount: pool_amount,
+                        costs: main_pool_costs.clone(),
+                    });
+                }
+                if unknown > zero {
+                    tracing::warn!(
+                        "Disposal of {} {} at {} exceeds the pool by {}, acquired before the \
+                         imported history",
+                        display_amount(&trade.sell),
+                        trade.sell.currency().code,
+                        trade.date_time,
+                        display_amount(&unknown)
+                    );
+                    matches.push(MatchedAcquisition {
+                        rule: MatchingRule::Unknown,
+                        trade_id: None,
+                        date_time: None,
+                        amount: unknown.clone(),
+                        costs: Money::from_major(0, GBP),
+                    });
+                    unknown_amount = Some(unknown);
+                }
                 allowable_costs = main_pool_costs + special_allowable_costs;
+                if claim {
+                    // deemed to be reacquired straight away at nil (TCGA 1992 s24(2))
+                    pool.buy(&trade.sell, &Money::from_major(0, GBP));
+                }
                 sell_pool = Some(pool.clone());
             }
 
-            let sell_value = if trade.sell.currency() == GBP {
+            let mut sell_value = if trade.sell.currency() == GBP {
                 trade.sell.clone()
             } else {
                 convert_to_gbp(trade.sell.clone(), &price, trade.rate)?
             };
+            let unknown_basis = match unknown_amount {
+                Some(amount) => Some(convert_to_gbp(amount, &price, trade.rate)?),
+                None => None,
+            };
+            if let (Some(value), UnknownBasis::Exclude) = (&unknown_basis, options.unknown_basis) {
+                sell_value = sell_value - value.clone();
+            }
 
             let buy_value = if trade.buy.currency() == GBP {
                 trade.buy.clone()
@@ -383,31 +1649,271 @@ pub fn calculate<'a>(
                 convert_to_gbp(trade.buy.clone(), &price, trade.rate)?
             };
 
-            let fee_value = if trade.fee.currency() == GBP {
-                trade.fee.clone()
+            let asset_price = fiat_asset_price(trade, &price);
+            if let Some(ref asset_price) = asset_price {
+                tracing::debug!(
+                    asset_price = %asset_price.rate,
+                    fiat_price = %price.rate,
+                    pair = %asset_price.pair,
+                    "Converted via fiat"
+                );
+            }
+
+            let tax_year = options.year_start.tax_year(trade.date_time);
+            let year_boundary = YearBoundary::of(trade.date_time.date(), tax_year, &matches);
+            if let Some(boundary) = year_boundary {
+                tracing::debug!(%boundary, %tax_year, "At the edge of the tax year");
+            }
+            tracing::debug!(
+                buy_value = %display_amount(&buy_value),
+                sell_value = %display_amount(&sell_value),
+                fee_value = %display_amount(&fee_value),
+                allowable_costs = %display_amount(&allowable_costs),
+                "Valuation"
+            );
+
+            let (proceeds, allowable_costs) = if trade.sell.currency() == GBP {
+                (sell_value.clone(), allowable_costs)
             } else {
-                convert_to_gbp(trade.fee.clone(), &price, trade.rate)?
+                match options.disposal_fees {
+                    DisposalFees::Costs => {
+                        (sell_value.clone(), allowable_costs + fee_value.clone())
+                    }
+                    DisposalFees::Proceeds => {
+                        (sell_value.clone() - fee_value.clone(), allowable_costs)
+                    }
+                }
             };
 
-            let tax_year = uk_tax_year(trade.date_time);
-
             Ok(TaxEvent {
+                id,
+                kind,
                 trade: trade.clone(),
                 buy_value,
                 sell_value,
                 fee_value,
+                proceeds,
                 price: price.clone(),
+                price_basis,
+                price_date: Some(price.date_time.date())
+                    .filter(|date| *date != trade.date_time.date()),
+                asset_price,
+                fee_price,
                 allowable_costs,
                 tax_year,
                 sell_pool,
                 buy_pool,
+                matches,
+                unknown_basis,
+                acquisition_cost,
+                year_boundary,
+                leg: None,
             })
         })
-        .collect::<color_eyre::Result<Vec<_>>>()?;
-    let report = TaxReport::new(trades, gains, pools);
+        .collect::<color_eyre::Result<Vec<_>>>()?
+        .into_iter()
+        .flat_map(TaxEvent::split)
+        .collect();
+    let report = TaxReport::new(transactions, gains, pools);
+    #[cfg(debug_assertions)]
+    {
+        let violations = invariants::check(&report);
+        debug_assert!(
+            violations.is_empty(),
+            "Report invariants violated: {:?}",
+            violations
+        );
+    }
     Ok(report)
 }
 
+/// The number of days following a disposal in which acquisitions are matched by the 30 day rule
+const THIRTY_DAY_WINDOW_DAYS: i64 = 30;
+
+/// Whether an acquisition is matched against a disposal by the same day or 30 day rules.
+///
+/// The window is in calendar days, not a duration from the time of the disposal: HMRC match
+/// acquisitions on the day of the disposal, then those "within 30 days after" it, so a disposal on
+/// 1 March matches acquisitions up to and including 31 March, whatever the time of day.
+fn matching_window_contains(disposal: NaiveDate, acquisition: NaiveDate, days: i64) -> bool {
+    acquisition >= disposal && acquisition <= disposal + Duration::days(days)
+}
+
+/// Matches each disposal with acquisitions of the asset on the same day, returning the ids and
+/// amounts of the acquisitions matched by the id of each disposal.
+///
+/// All the disposals of an asset on a day are treated as a single disposal, and all the
+/// acquisitions as a single acquisition (TCGA 1992 s105), so the times within the day don't
+/// matter: an acquisition in the morning is matched with a disposal in the afternoon rather than
+/// added to the pool. The amounts are allocated to the disposals in order, which gives the same
+/// total gain for the day as apportioning them.
+fn same_day_matches<'a>(trades: &[(&Trade<'a>, usize)]) -> HashMap<usize, Vec<(usize, Money<'a>)>> {
+    let mut matches: HashMap<usize, Vec<(usize, Money<'a>)>> = HashMap::new();
+    let mut remaining: HashMap<usize, Money<'a>> = HashMap::new();
+    for (disposal, disposal_id) in trades.iter().filter(|(t, _)| t.sell.currency() != GBP) {
+        let zero = Money::from_major(0, disposal.sell.currency());
+        let mut unmatched = disposal.sell.clone();
+        let acquisitions = trades.iter().filter(|(t, _)| {
+            t.buy.currency() == disposal.sell.currency()
+                && t.date_time.date() == disposal.date_time.date()
+        });
+        for (acquisition, acquisition_id) in acquisitions {
+            let available = remaining
+                .entry(*acquisition_id)
+                .or_insert(acquisition.buy.clone());
+            let amount = if *available < unmatched {
+                available.clone()
+            } else {
+                unmatched.clone()
+            };
+            if amount > zero {
+                *available = available.clone() - amount.clone();
+                unmatched = unmatched - amount.clone();
+                matches
+                    .entry(*disposal_id)
+                    .or_default()
+                    .push((*acquisition_id, amount));
+            }
+        }
+    }
+    matches
+}
+
+/// Converts a transaction into the equivalent trade against GBP for the purposes of matching.
+///
+/// Income, forks and airdrops are acquisitions at their market value (forks at zero cost), while
+/// spending, expenses, gifts and fees are disposals at their market value. Losses are disposals for nil
+/// consideration. Transfers between accounts and movements of GBP are not taxable, unless the
+/// transfer changes beneficial ownership and the policy treats those as disposals.
+fn taxable_trade<'a>(
+    tx: &Transaction<'a>,
+    prices: &Prices<'a>,
+    options: &Options,
+) -> color_eyre::Result<Option<(TransactionKind, Trade<'a>)>> {
+    let kind = tx.kind();
+    let movement = match tx {
+        Transaction::Trade(trade) => return Ok(Some((kind, trade.clone()))),
+        // acquired at the time of the contribution, at the value of the contribution
+        Transaction::Contribution(contribution) => {
+            return Ok(Some((kind, contribution.trade.clone())))
+        }
+        Transaction::Deposit(m) | Transaction::Withdrawal(m) => {
+            if m.ownership_changed
+                && options.ownership_transfers == OwnershipTransferPolicy::Disposal
+            {
+                m
+            } else {
+                return Ok(None);
+            }
+        }
+        tx => tx.movement().expect("Non trade is a movement"),
+    };
+    if movement.amount.currency() == GBP {
+        return Ok(None);
+    }
+    let rate = match kind {
+        TransactionKind::Fork | TransactionKind::Loss => Decimal::new(0, 0),
+        _ => market_rate(kind, movement, prices)?,
+    };
+    let value = Money::from_decimal(*movement.amount.amount() * rate, GBP);
+    let (trade_kind, buy, sell) = if kind.is_inflow() {
+        (TradeKind::Buy, movement.amount.clone(), value)
+    } else {
+        (TradeKind::Sell, value, movement.amount.clone())
+    };
+    let trade = Trade {
+        date_time: movement.date_time,
+        kind: trade_kind,
+        buy,
+        sell,
+        fee: movement.fee.clone(),
+        rate,
+        exchange: movement.exchange.clone(),
+        account: movement.account.clone(),
+        notes: movement.notes.clone(),
+    };
+    Ok(Some((kind, trade)))
+}
+
+/// A disposal for nil consideration at the start of the day the token became worthless, of the
+/// amount held at that time including any opening pool. The token is deemed to be reacquired at
+/// nil straight away, which is added to the pool when the claim is matched, so later disposals
+/// have no allowable costs.
+fn negligible_value_claim<'a>(
+    trades: &[(TransactionKind, Trade<'a>)],
+    opening_pools: &[OpeningPool],
+    worthless: &Worthless,
+) -> Option<(TransactionKind, Trade<'a>)> {
+    let date_time = worthless.from.and_hms(0, 0, 0);
+    let opening = opening_pools
+        .iter()
+        .filter(|opening| opening.currency == worthless.currency && opening.as_of < worthless.from)
+        .map(|opening| opening.quantity)
+        .sum::<Decimal>();
+    let holding = trades
+        .iter()
+        .filter(|(_, trade)| trade.date_time < date_time)
+        .fold(opening, |mut holding, (_, trade)| {
+            if trade.buy.currency() == worthless.currency {
+                holding += *trade.buy.amount();
+            }
+            if trade.sell.currency() == worthless.currency {
+                holding -= *trade.sell.amount();
+            }
+            if trade.fee.currency() == worthless.currency {
+                holding -= *trade.fee.amount();
+            }
+            holding
+        });
+    if holding <= Decimal::new(0, 0) {
+        return None;
+    }
+    let trade = Trade {
+        date_time,
+        kind: TradeKind::Sell,
+        buy: Money::from_major(0, GBP),
+        sell: Money::from_decimal(holding, worthless.currency),
+        fee: Money::from_major(0, GBP),
+        rate: Decimal::new(0, 0),
+        exchange: None,
+        account: None,
+        notes: Some("Negligible value claim".into()),
+    };
+    Some((TransactionKind::Loss, trade))
+}
+
+/// Whether the trade is the disposal of a negligible value claim, which isn't matched with
+/// acquisitions on the same day or in the next 30 days
+fn is_claim(trade: &Trade, kind: TransactionKind, options: &Options) -> bool {
+    kind == TransactionKind::Loss
+        && options.worthless.iter().any(|worthless| {
+            worthless.claim
+                && worthless.currency == trade.sell.currency()
+                && worthless.from.and_hms(0, 0, 0) == trade.date_time
+        })
+}
+
+/// The GBP price of the moved currency at the time of the movement
+pub(crate) fn market_rate<'a>(
+    kind: TransactionKind,
+    movement: &Movement<'a>,
+    prices: &Prices<'a>,
+) -> color_eyre::Result<Decimal> {
+    let pair = CurrencyPair {
+        base: movement.amount.currency(),
+        quote: GBP,
+    };
+    let price = prices
+        .get(pair, movement.date_time.date())
+        .ok_or(eyre::eyre!(
+            "Should have price for {}: {} at {}",
+            kind,
+            movement.amount,
+            movement.date_time
+        ))?;
+    Ok(price.rate)
+}
+
 fn convert_to_gbp<'a>(
     money: Money<'a>,
     price: &Price<'a>,
@@ -440,46 +1946,158 @@ fn convert_to_gbp<'a>(
     }
 }
 
-fn get_price<'a>(trade: &Trade<'a>, prices: &'a Prices<'a>) -> Option<Price<'a>> {
+/// The GBP price used to value the trade, and the basis on which it was determined.
+///
+/// A trade with a GBP, EUR or USD leg is valued by its consideration, converted at the daily
+/// exchange rate for EUR and USD, unless the market price is preferred and available.
+fn get_price<'a>(
+    trade: &Trade<'a>,
+    prices: &Prices<'a>,
+    valuation: Valuation,
+) -> Option<(Price<'a>, PriceBasis)> {
     // todo - extract and dedup this logic
     let (quote, base) = match trade.kind {
         TradeKind::Buy => (trade.sell.currency(), trade.buy.currency()),
         TradeKind::Sell => (trade.buy.currency(), trade.sell.currency()),
     };
+    let date = trade.date_time.date();
+    let market = || prices.get(CurrencyPair { base, quote: GBP }, date);
 
     if quote == GBP {
-        return Some(Price {
+        if valuation == Valuation::Market {
+            // the consideration is better than the market price of a nearby day
+            if let Some(price) = market().filter(|price| price.date_time.date() == date) {
+                return Some((price, PriceBasis::Market));
+            }
+        }
+        let price = Price {
             pair: CurrencyPair { base, quote: GBP },
             date_time: trade.date_time,
             rate: trade.rate,
-        });
+        };
+        return Some((price, PriceBasis::Consideration));
     }
 
     let pair = CurrencyPair {
         base: &quote,
         quote: GBP,
     };
-    prices.get(pair, trade.date_time.date())
+    let quote_price = prices.get(pair, date);
+    if !is_fiat(quote) {
+        return quote_price.map(|price| (price, PriceBasis::Market));
+    }
+
+    // the market price of the asset, expressed as the price of the fiat leg implied by the rate
+    let implied_market = || {
+        market().and_then(|market| {
+            let rate = market.rate.checked_div(trade.rate)?;
+            let price = Price {
+                pair: CurrencyPair {
+                    base: quote,
+                    quote: GBP,
+                },
+                // the date of the market price, which may be a nearby day
+                date_time: market.date_time,
+                rate,
+            };
+            Some((price, PriceBasis::Market))
+        })
+    };
+    let exchange_rate = quote_price.map(|price| (price, PriceBasis::ExchangeRate));
+    match valuation {
+        Valuation::Consideration => exchange_rate.or_else(implied_market),
+        Valuation::Market => implied_market().or(exchange_rate),
+    }
 }
 
-fn uk_tax_year(date_time: NaiveDateTime) -> Year {
-    let date = date_time.date();
-    let year = date.year();
-    if date > ymd(year, 4, 5) && date <= ymd(year, 12, 31) {
-        year + 1
+/// The price of the asset in the fiat currency of a trade valued by its fiat leg e.g. ETH/EUR,
+/// the first step of converting the asset to GBP, with the price of the fiat leg the second.
+fn fiat_asset_price<'a>(trade: &Trade<'a>, price: &Price<'a>) -> Option<Price<'a>> {
+    let fiat = price.pair.base;
+    if !is_fiat(fiat) {
+        return None;
+    }
+    let asset = if trade.buy.currency() == fiat {
+        trade.sell.currency()
+    } else if trade.sell.currency() == fiat {
+        trade.buy.currency()
     } else {
-        year
+        return None;
+    };
+    Some(Price {
+        pair: CurrencyPair {
+            base: asset,
+            quote: fiat,
+        },
+        date_time: trade.date_time,
+        rate: trade.rate,
+    })
+}
+
+/// Fiat currencies other than GBP, whose legs are valued at the daily exchange rate
+fn is_fiat(currency: &Currency) -> bool {
+    currency.code == "EUR" || currency.code == "USD"
+}
+
+/// The GBP value of the fee of the trade, with the price of the fee currency if it's not one of
+/// the currencies traded
+fn value_fee<'a>(
+    trade: &Trade<'a>,
+    price: &Price<'a>,
+    prices: &Prices<'a>,
+) -> color_eyre::Result<(Money<'a>, Option<Price<'a>>)> {
+    if trade.fee.currency() == GBP {
+        Ok((trade.fee.clone(), None))
+    } else if trade.fee.currency() == trade.buy.currency()
+        || trade.fee.currency() == trade.sell.currency()
+    {
+        Ok((convert_to_gbp(trade.fee.clone(), price, trade.rate)?, None))
+    } else {
+        // fee paid in a currency not part of the trade e.g. BNB on Binance, so it must be
+        // valued with its own GBP price at the time of the trade
+        let price = get_fee_price(trade, prices).ok_or(eyre::eyre!(
+            "Should have price for fee: {} at {}",
+            trade.fee,
+            trade.date_time
+        ))?;
+        let fee_value = convert_to_gbp(trade.fee.clone(), &price, trade.rate)?;
+        Ok((fee_value, Some(price)))
     }
 }
 
-fn ymd(y: Year, m: u32, d: u32) -> NaiveDate {
-    NaiveDate::from_ymd(y, m, d)
+/// The allowable costs in GBP of an amount of the asset acquired by the trade: its value, plus
+/// the same share of the fee if the trade is a purchase for GBP, the fee then being an incidental
+/// cost of the acquisition (TCGA 1992 s38(1)(a) and (b)). The fees of trades of one asset for
+/// another are deducted from the disposal instead.
+fn acquisition_costs<'a>(
+    trade: &Trade<'a>,
+    kind: TransactionKind,
+    price: &Price<'a>,
+    fee_value: &Money<'a>,
+    amount: &Money<'a>,
+) -> color_eyre::Result<Money<'a>> {
+    let value = convert_to_gbp(amount.clone(), price, trade.rate)?;
+    let is_purchase = matches!(kind, TransactionKind::Buy | TransactionKind::Sell)
+        && trade.sell.currency() == GBP;
+    if !is_purchase || trade.buy.amount().is_zero() {
+        return Ok(value);
+    }
+    let fee = *fee_value.amount() * *amount.amount() / *trade.buy.amount();
+    Ok(value + Money::from_decimal(fee, GBP))
+}
+
+fn get_fee_price<'a>(trade: &Trade<'a>, prices: &Prices<'a>) -> Option<Price<'a>> {
+    let pair = CurrencyPair {
+        base: trade.fee.currency(),
+        quote: GBP,
+    };
+    prices.get(pair, trade.date_time.date())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{currencies::BTC, trades::Trade};
+    use crate::{currencies::BTC, trades::TradeBuilder};
     use chrono::NaiveDate;
     use rust_decimal_macros::dec;
 
@@ -510,24 +2128,20 @@ mod tests {
         sell: Money<'a>,
         buy: Money<'a>,
         rate: D,
-    ) -> Trade<'a>
+    ) -> Transaction<'a>
     where
         D: Into<Decimal>,
     {
         let date_time = NaiveDate::parse_from_str(dt, "%Y-%m-%d")
             .expect("DateTime string should match pattern")
             .and_hms(23, 59, 59);
-        let rate = rate.into();
 
-        Trade {
-            date_time,
-            kind,
-            sell,
-            buy,
-            rate,
-            fee: gbp!(0),
-            exchange: None,
-        }
+        TradeBuilder::new(date_time, kind, buy, sell)
+            .fee(gbp!(0))
+            .rate(rate.into())
+            .build()
+            .expect("Test trade should be valid")
+            .into()
     }
 
     #[test]
@@ -544,9 +2158,9 @@ mod tests {
 
         let trades = vec![acq1, acq2, disp];
         let prices = Prices::default();
-        let report = calculate(trades, &prices).unwrap();
+        let report = calculate(trades, &prices, &Options::default()).unwrap();
 
-        let gains_2018 = report.gains(Some(2018));
+        let gains_2018 = report.gains(Some(TaxYear::uk(2018)));
 
         assert_money_eq!(gains_2018.total_proceeds(), gbp!(300_000.00));
         assert_money_eq!(gains_2018.total_allowable_costs(), gbp!(42_000.00));
@@ -567,9 +2181,9 @@ mod tests {
 
         let trades = vec![disp, acq2, acq1];
         let prices = Prices::default();
-        let report = calculate(trades, &prices).unwrap();
+        let report = calculate(trades, &prices, &Options::default()).unwrap();
 
-        let gains_2018 = report.gains(Some(2018));
+        let gains_2018 = report.gains(Some(TaxYear::uk(2018)));
 
         assert_money_eq!(gains_2018.total_proceeds(), gbp!(300_000.00));
         assert_money_eq!(gains_2018.total_allowable_costs(), gbp!(42_000.00));
@@ -590,9 +2204,9 @@ mod tests {
 
         let trades = vec![buy1, sell, buy2];
         let prices = Prices::default();
-        let report = calculate(trades, &prices).unwrap();
+        let report = calculate(trades, &prices, &Options::default()).unwrap();
 
-        let gains_2019 = report.gains(Some(2019));
+        let gains_2019 = report.gains(Some(TaxYear::uk(2019)));
         let gain = gains_2019.gains.get(0).unwrap();
 
         assert_money_eq!(gain.proceeds(), gbp!(160_000), "Consideration");
@@ -609,6 +2223,192 @@ mod tests {
         );
     }
 
+    /// HS284 Shares and Capital Gains Tax, Example 3: the shares bought on the day of the
+    /// disposal are matched first, then the rest comes from the section 104 holding
+    #[test]
+    fn hmrc_same_day_example() {
+        let buy1 = trade(
+            "2010-01-01",
+            TradeKind::Buy,
+            gbp!(1850),
+            btc!(9500),
+            dec!(0.194736842),
+        );
+        let buy2 = trade(
+            "2014-01-01",
+            TradeKind::Buy,
+            gbp!(850),
+            btc!(500),
+            dec!(1.7),
+        );
+        let sell = trade(
+            "2015-08-30",
+            TradeKind::Sell,
+            btc!(4000),
+            gbp!(6000),
+            dec!(1.5),
+        );
+        let buy3 = trade(
+            "2015-08-30",
+            TradeKind::Buy,
+            gbp!(850),
+            btc!(500),
+            dec!(1.7),
+        );
+
+        let trades = vec![buy1, buy2, sell, buy3];
+        let prices = Prices::default();
+        let report = calculate(trades, &prices, &Options::default()).unwrap();
+
+        let gains_2016 = report.gains(Some(TaxYear::uk(2016)));
+        let disposal = gains_2016.disposals().next().unwrap();
+
+        assert_eq!(disposal.matches()[0].rule(), MatchingRule::SameDay);
+        assert_money_eq!(disposal.matches()[0].amount(), btc!(500), "Same day");
+        assert_money_eq!(
+            disposal.matches()[0].costs(),
+            gbp!(850.00),
+            "Same day costs"
+        );
+        assert_eq!(disposal.matches()[1].rule(), MatchingRule::Pool);
+        assert_money_eq!(
+            disposal.matches()[1].amount(),
+            btc!(3500),
+            "From the holding"
+        );
+        assert_money_eq!(disposal.matches()[1].costs(), gbp!(945.00), "Holding costs");
+        assert_money_eq!(disposal.proceeds(), gbp!(6000), "Consideration");
+        assert_money_eq!(disposal.allowable_costs, gbp!(1795.00), "Allowable costs");
+        assert_money_eq!(disposal.gain(), gbp!(4205.00), "Gain");
+
+        let btc_pool = report.pools.get("BTC").expect("BTC should have a Pool");
+
+        assert_money_eq!(btc_pool.total, btc!(6500), "Remaining in holding");
+        assert_money_eq!(btc_pool.costs, gbp!(1755.00), "Remaining allowable costs");
+    }
+
+    #[test]
+    fn identical_acquisitions_within_30_days_are_matched_separately() {
+        let buy1 = trade("2018-01-01", TradeKind::Buy, gbp!(1000), btc!(10), 100);
+        let sell = trade("2018-02-01", TradeKind::Sell, btc!(3), gbp!(1500), 500);
+        // two fills at the same time for the same amounts
+        let buy2 = trade("2018-02-10", TradeKind::Buy, gbp!(800), btc!(2), 400);
+        let buy3 = trade("2018-02-10", TradeKind::Buy, gbp!(800), btc!(2), 400);
+
+        let trades = vec![buy1, sell, buy2, buy3];
+        let prices = Prices::default();
+        let report = calculate(trades, &prices, &Options::default()).unwrap();
+
+        let gains_2018 = report.gains(Some(TaxYear::uk(2018)));
+        let gain = gains_2018.gains.get(0).unwrap();
+
+        assert_money_eq!(gain.allowable_costs, gbp!(1200.00), "Allowable costs");
+        assert_money_eq!(gain.gain(), gbp!(300.00), "Gain 30 days");
+
+        let btc_pool = report.pools.get("BTC").expect("BTC should have a Pool");
+
+        assert_money_eq!(btc_pool.total, btc!(11), "Remaining in pool");
+        assert_money_eq!(btc_pool.costs, gbp!(1400.00), "Remaining allowable costs");
+    }
+
+    #[test]
+    fn thirty_day_window_is_in_calendar_days() {
+        let date = |d: &str| NaiveDate::parse_from_str(d, "%Y-%m-%d").unwrap();
+        let disposal = date("2018-03-01");
+
+        assert!(!matching_window_contains(disposal, date("2018-02-28"), 30));
+        assert!(matching_window_contains(disposal, date("2018-03-01"), 30));
+        assert!(matching_window_contains(disposal, date("2018-03-31"), 30));
+        assert!(!matching_window_contains(disposal, date("2018-04-01"), 30));
+        assert!(matching_window_contains(disposal, date("2018-03-02"), 1));
+        assert!(!matching_window_contains(disposal, date("2018-03-03"), 1));
+    }
+
+    #[test]
+    fn acquisitions_on_day_30_are_matched_but_not_day_31() {
+        let matched_by = |buy_date: &str| {
+            let buy1 = trade("2017-01-01", TradeKind::Buy, gbp!(1000), btc!(10), 100);
+            // the disposal is early in the day, the acquisitions late, as in the same day
+            let mut sell = trade("2018-03-01", TradeKind::Sell, btc!(1), gbp!(500), 500);
+            if let Transaction::Trade(ref mut sell) = sell {
+                sell.date_time = sell.date_time.date().and_hms(0, 0, 1);
+            }
+            let buy2 = trade(buy_date, TradeKind::Buy, gbp!(400), btc!(1), 400);
+            let prices = Prices::default();
+            let report = calculate(vec![buy1, sell, buy2], &prices, &Options::default()).unwrap();
+            let gains = report.gains(Some(TaxYear::uk(2018)));
+            let disposal = gains.disposals().next().unwrap();
+            disposal.matches()[0].rule()
+        };
+
+        assert_eq!(matched_by("2018-03-31"), MatchingRule::ThirtyDay);
+        assert_eq!(matched_by("2018-04-01"), MatchingRule::Pool);
+    }
+
+    #[test]
+    fn same_day_acquisition_before_the_disposal_is_matched_not_pooled() {
+        let at = |mut tx: Transaction<'static>, hour| {
+            if let Transaction::Trade(ref mut trade) = tx {
+                trade.date_time = trade.date_time.date().and_hms(hour, 0, 0);
+            }
+            tx
+        };
+        let buy1 = trade("2016-01-01", TradeKind::Buy, gbp!(10_000), btc!(100), 100);
+        let buy2 = at(
+            trade("2018-05-01", TradeKind::Buy, gbp!(3000), btc!(10), 300),
+            9,
+        );
+        let sell = at(
+            trade("2018-05-01", TradeKind::Sell, btc!(20), gbp!(5000), 250),
+            15,
+        );
+
+        let prices = Prices::default();
+        let report = calculate(vec![buy1, buy2, sell], &prices, &Options::default()).unwrap();
+        let gains = report.gains(Some(TaxYear::uk(2019)));
+        let disposal = gains.disposals().next().unwrap();
+
+        assert_eq!(disposal.matches()[0].rule(), MatchingRule::SameDay);
+        assert_eq!(disposal.matches()[1].rule(), MatchingRule::Pool);
+        assert_money_eq!(disposal.allowable_costs, gbp!(4000.00), "Allowable costs");
+        assert_money_eq!(disposal.gain(), gbp!(1000.00), "Gain");
+
+        let btc_pool = report.pools.get("BTC").expect("BTC should have a Pool");
+
+        assert_money_eq!(btc_pool.total, btc!(90), "Remaining in pool");
+        assert_money_eq!(btc_pool.costs, gbp!(9000.00), "Remaining allowable costs");
+    }
+
+    #[test]
+    fn same_day_matching_takes_priority_over_30_days() {
+        let buy1 = trade("2016-01-01", TradeKind::Buy, gbp!(10_000), btc!(100), 100);
+        let sell1 = trade("2018-05-01", TradeKind::Sell, btc!(10), gbp!(4000), 400);
+        let sell2 = trade("2018-05-10", TradeKind::Sell, btc!(10), gbp!(4000), 400);
+        // within 30 days of the first disposal, but on the same day as the second
+        let buy2 = trade("2018-05-10", TradeKind::Buy, gbp!(3000), btc!(10), 300);
+
+        let prices = Prices::default();
+        let report =
+            calculate(vec![buy1, sell1, sell2, buy2], &prices, &Options::default()).unwrap();
+        let gains = report.gains(Some(TaxYear::uk(2019)));
+        let mut disposals = gains.disposals();
+        let disposal1 = disposals.next().unwrap();
+        let disposal2 = disposals.next().unwrap();
+
+        assert_eq!(disposal1.matches()[0].rule(), MatchingRule::Pool);
+        assert_money_eq!(disposal1.allowable_costs, gbp!(1000.00), "Pool costs");
+        assert_money_eq!(disposal1.gain(), gbp!(3000.00), "Gain");
+
+        assert_eq!(disposal2.matches()[0].rule(), MatchingRule::SameDay);
+        assert_money_eq!(disposal2.allowable_costs, gbp!(3000.00), "Same day costs");
+        assert_money_eq!(disposal2.gain(), gbp!(1000.00), "Gain");
+
+        let btc_pool = report.pools.get("BTC").expect("BTC should have a Pool");
+
+        assert_money_eq!(btc_pool.total, btc!(90), "Remaining in pool");
+        assert_money_eq!(btc_pool.costs, gbp!(9000.00), "Remaining allowable costs");
+    }
+
     #[test]
     fn multiple_acquisitions_within_30_days() {
         let buy1 = trade(
@@ -624,9 +2424,9 @@ mod tests {
 
         let trades = vec![buy1, sell, buy2, buy3];
         let prices = Prices::default();
-        let report = calculate(trades, &prices).unwrap();
+        let report = calculate(trades, &prices, &Options::default()).unwrap();
 
-        let gains_2019 = report.gains(Some(2019));
+        let gains_2019 = report.gains(Some(TaxYear::uk(2019)));
         let gain = gains_2019.gains.get(0).unwrap();
 
         assert_money_eq!(gain.proceeds(), gbp!(160_000), "Consideration");
@@ -652,9 +2452,9 @@ mod tests {
 
         let trades = vec![buy1, sell1, sell2, buy2];
         let prices = Prices::default();
-        let report = calculate(trades, &prices).unwrap();
+        let report = calculate(trades, &prices, &Options::default()).unwrap();
 
-        let gains_2019 = report.gains(Some(2019));
+        let gains_2019 = report.gains(Some(TaxYear::uk(2019)));
         let gain1 = gains_2019.gains.get(0).unwrap();
 
         assert_money_eq!(gain1.proceeds(), gbp!(40_000), "Consideration");
@@ -681,9 +2481,9 @@ mod tests {
 
         let trades = vec![buy1, sell, buy2];
         let prices = Prices::default();
-        let report = calculate(trades, &prices).unwrap();
+        let report = calculate(trades, &prices, &Options::default()).unwrap();
 
-        let gains_2019 = report.gains(Some(2019));
+        let gains_2019 = report.gains(Some(TaxYear::uk(2019)));
         println!(
             "GAINS {}",
             gains_2019
@@ -716,16 +2516,846 @@ mod tests {
 
         let trades = vec![acq1, disp];
         let prices = Prices::default();
-        let report = calculate(trades, &prices).unwrap();
+        let report = calculate(trades, &prices, &Options::default()).unwrap();
 
-        let gains_2018 = report.gains(Some(2018));
+        let gains_2018 = report.gains(Some(TaxYear::uk(2018)));
 
         assert_money_eq!(gains_2018.total_proceeds(), gbp!(2000));
         assert_money_eq!(gains_2018.total_allowable_costs(), gbp!(1000));
         assert_money_eq!(gains_2018.total_gain(), gbp!(1000));
     }
 
-    // todo: test crypto -> crypto trade, should be both a sale and a purchase and require a price
+    #[test]
+    fn totals_are_of_the_disposals_not_the_purchases_for_gbp() {
+        let buy = trade("2017-06-01", TradeKind::Buy, gbp!(1000), btc!(10), 100);
+        let sell = trade("2018-01-01", TradeKind::Sell, btc!(5), gbp!(2000), 400);
+
+        let prices = Prices::default();
+        let report = calculate(vec![buy, sell], &prices, &Options::default()).unwrap();
+        let gains_2018 = report.gains(Some(TaxYear::uk(2018)));
+
+        assert_eq!(gains_2018.disposal_count(), 1);
+        assert_money_eq!(gains_2018.total_proceeds(), gbp!(2000));
+        assert_money_eq!(gains_2018.total_allowable_costs(), gbp!(500));
+        assert_money_eq!(gains_2018.total_gain(), gbp!(1500));
+        assert_money_eq!(gains_2018.total_gains(), gbp!(1500));
+        assert_money_eq!(gains_2018.total_losses(), gbp!(0));
+
+        // summing all the events of the year, as the totals did before, counts the GBP spent on
+        // the purchase as proceeds with no allowable costs, i.e. as a gain
+        let zero = gbp!(0);
+        let (count, proceeds, costs, gain) = gains_2018.into_iter().fold(
+            (0, zero.clone(), zero.clone(), zero.clone()),
+            |(count, proceeds, costs, gain), g| {
+                (
+                    count + 1,
+                    proceeds + g.proceeds().clone(),
+                    costs + g.allowable_costs().clone(),
+                    gain + g.gain(),
+                )
+            },
+        );
+        assert_eq!(count, 2);
+        assert_money_eq!(proceeds, gbp!(3000));
+        assert_money_eq!(costs, gbp!(500));
+        assert_money_eq!(gain, gbp!(2500));
+    }
+
+    /// The output of a subscriber, shared with the test
+    #[derive(Clone, Default)]
+    struct Captured(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn tracing_a_trade_logs_only_its_span() {
+        let buy = trade("2017-06-01", TradeKind::Buy, gbp!(1000), btc!(10), 100);
+        let sell = trade("2018-01-01", TradeKind::Sell, btc!(5), gbp!(2000), 400);
+
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_env_filter(tracing_subscriber::EnvFilter::new(trace_trade_filter(2)))
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .finish();
+        tracing::subscriber::with_default(subscriber, || {
+            let prices = Prices::default();
+            calculate(vec![buy, sell], &prices, &Options::default()).unwrap();
+        });
+
+        let output = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        let lines = output.lines().collect::<Vec<_>>();
+        assert!(
+            lines.iter().any(|line| line.contains(":pool:")),
+            "{}",
+            output
+        );
+        assert!(
+            lines.iter().all(|line| line.contains("trade{id=2 ")),
+            "{}",
+            output
+        );
+    }
+
+    #[test]
+    fn parse_report_period() {
+        let parse = |s: &str| s.parse::<ReportPeriod>();
+
+        let year = |end| Ok(ReportPeriod::TaxYear(TaxYear::uk(end)));
+        assert_eq!(parse("2020-21"), year(2021));
+        assert_eq!(parse("2020/21"), year(2021));
+        assert_eq!(parse("2020-2021"), year(2021));
+        assert_eq!(parse("2099-00"), year(2100));
+        assert_eq!(parse("2021"), year(2021));
+        assert_eq!(
+            parse("2020-06-01..2020-12-31"),
+            Ok(ReportPeriod::DateRange(
+                NaiveDate::from_ymd(2020, 6, 1),
+                NaiveDate::from_ymd(2020, 12, 31)
+            ))
+        );
+        assert_eq!(parse("current"), Ok(ReportPeriod::Current));
+        assert!(parse("2020-22").is_err());
+        assert!(parse("2020-12-31..2020-06-01").is_err());
+        assert!(parse("last year").is_err());
+    }
+
+    #[test]
+    fn year_start_buckets_dates_into_years() {
+        let date = |dt: &str| {
+            NaiveDate::parse_from_str(dt, "%Y-%m-%d")
+                .unwrap()
+                .and_hms(12, 0, 0)
+        };
+
+        assert_eq!(YearStart::UK.tax_year(date("2021-04-05")).end_year(), 2021);
+        assert_eq!(YearStart::UK.tax_year(date("2021-04-06")).end_year(), 2022);
+        assert_eq!(YearStart::UK.tax_year(date("2021-12-31")).end_year(), 2022);
+        assert_eq!(YearStart::UK.tax_year(date("2022-01-01")).end_year(), 2022);
+
+        assert_eq!(
+            YearStart::CALENDAR.tax_year(date("2021-01-01")).end_year(),
+            2021
+        );
+        assert_eq!(
+            YearStart::CALENDAR.tax_year(date("2021-12-31")).end_year(),
+            2021
+        );
+
+        assert_eq!(
+            YearStart::JULY.tax_year(date("2021-06-30")).end_year(),
+            2021
+        );
+        assert_eq!(
+            YearStart::JULY.tax_year(date("2021-07-01")).end_year(),
+            2022
+        );
+
+        let custom: YearStart = "10-01".parse().unwrap();
+        assert_eq!(custom.tax_year(date("2021-09-30")).end_year(), 2021);
+        assert_eq!(custom.tax_year(date("2021-10-01")).end_year(), 2022);
+
+        assert!("02-29".parse::<YearStart>().is_err());
+        assert!("13-01".parse::<YearStart>().is_err());
+    }
+
+    #[test]
+    fn tax_year_knows_its_days() {
+        let date = |dt: &str| NaiveDate::parse_from_str(dt, "%Y-%m-%d").unwrap();
+        let year: TaxYear = "2020/21".parse().unwrap();
+
+        assert_eq!(year, TaxYear::uk(2021));
+        assert_eq!(year.to_string(), "2020/21");
+        assert_eq!(year.first_day(), date("2020-04-06"));
+        assert_eq!(year.last_day(), date("2021-04-05"));
+        assert!(!year.contains(date("2020-04-05")));
+        assert!(year.contains(date("2020-04-06")));
+        assert!(year.contains(date("2021-04-05")));
+        assert!(!year.contains(date("2021-04-06")));
+        assert_eq!(year.days().count(), 365);
+        assert_eq!(year.next().days().count(), 365);
+        assert_eq!(
+            year.previous().days().count(),
+            366,
+            "2019/20 has 29 February"
+        );
+        assert_eq!(
+            TaxYear::containing(date("2020-04-06"), YearStart::UK),
+            year,
+            "The first day is in the year"
+        );
+
+        let calendar = year.with_start(YearStart::CALENDAR);
+        assert_eq!(calendar.to_string(), "2021");
+        assert_eq!(calendar.first_day(), date("2021-01-01"));
+        assert_eq!(calendar.last_day(), date("2021-12-31"));
+    }
+
+    #[test]
+    fn every_day_is_in_exactly_one_tax_year() {
+        let starts = [
+            YearStart::UK,
+            YearStart::CALENDAR,
+            YearStart::JULY,
+            "02-28".parse().unwrap(),
+        ];
+        for start in starts.iter().cloned() {
+            let mut year = TaxYear::containing(NaiveDate::from_ymd(1999, 12, 31), start);
+            let mut day = year.first_day();
+            while year.end_year() <= 2041 {
+                assert_eq!(year.days().next(), Some(day));
+                for date in year.days() {
+                    assert_eq!(date, day, "{} has no gaps", year);
+                    assert!(year.contains(date));
+                    assert!(!year.previous().contains(date) && !year.next().contains(date));
+                    assert_eq!(TaxYear::containing(date, start), year);
+                    // a leap second at the end of the day is still on that day
+                    let leap_second = date.and_hms_milli(23, 59, 59, 1_500);
+                    assert_eq!(start.tax_year(leap_second), year);
+                    day = day.succ();
+                }
+                assert_eq!(year.last_day().succ(), year.next().first_day());
+                year = year.next();
+            }
+        }
+    }
+
+    #[test]
+    fn events_at_the_edge_of_the_tax_year_are_flagged() {
+        let buy1 = trade("2017-01-01", TradeKind::Buy, gbp!(1000), btc!(10), 100);
+        let sell1 = trade("2018-04-05", TradeKind::Sell, btc!(2), gbp!(400), 200);
+        let buy2 = trade("2018-04-06", TradeKind::Buy, gbp!(300), btc!(1), 300);
+        let sell2 = trade("2018-04-20", TradeKind::Sell, btc!(1), gbp!(300), 300);
+        let buy3 = trade("2018-05-01", TradeKind::Buy, gbp!(250), btc!(1), 250);
+
+        let trades = vec![buy1, sell1, buy2, sell2, buy3];
+        let prices = Prices::default();
+        let report = calculate(trades, &prices, &Options::default()).unwrap();
+
+        let boundaries = report
+            .gains(None)
+            .gains
+            .iter()
+            .map(|event| (event.tax_year().end_year(), event.year_boundary()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            boundaries,
+            vec![
+                (2017, None),
+                (2018, Some(YearBoundary::ThirtyDayAcrossYearEnd)),
+                (2019, Some(YearBoundary::FirstDay)),
+                (2019, None),
+                (2019, None),
+            ]
+        );
+
+        let last_day = trade("2018-04-05", TradeKind::Buy, gbp!(100), btc!(1), 100);
+        let report = calculate(vec![last_day], &prices, &Options::default()).unwrap();
+        let event = &report.gains(None).gains[0];
+        assert_eq!(event.year_boundary(), Some(YearBoundary::LastDay));
+    }
+
+    #[test]
+    fn ico_contribution_is_acquired_at_the_value_of_the_contribution() {
+        let txs = "\
+version,date_time,kind,buy_asset,buy_amount,sell_asset,sell_amount,fee_asset,fee_amount,rate,exchange,exchange_id,notes,ownership_changed,received_date_time
+5,2017-01-01T12:00:00+00:00,Buy,ETH,10,GBP,1000,GBP,0,100,,,,,
+5,2017-06-01T12:00:00+00:00,Contribution,BTC,0.5,ETH,10,ETH,0,20,,,,,2017-08-01T12:00:00+00:00
+5,2018-01-01T12:00:00+00:00,Sell,GBP,5000,BTC,0.5,GBP,0,10000,,,,,
+";
+        let prices = "\
+base_currency,quote_currency,date_time,rate
+ETH,GBP,2017-06-01T00:00:00+00:00,200
+";
+        let transactions = transactions::read_csv(txs.as_bytes()).unwrap();
+        let prices = Prices::read_csv(prices.as_bytes()).unwrap();
+        let report = calculate(transactions, &prices, &Options::default()).unwrap();
+
+        let gains_2018 = report.gains(Some(TaxYear::uk(2018)));
+
+        // the ETH contributed is disposed of, and the tokens acquired, at its value of £2000
+        assert_money_eq!(gains_2018.total_proceeds(), gbp!(7000));
+        assert_money_eq!(gains_2018.total_allowable_costs(), gbp!(3000));
+        assert_money_eq!(gains_2018.total_gain(), gbp!(4000));
+    }
+
+    #[test]
+    fn fiat_leg_is_valued_by_consideration_or_market_price() {
+        let txs = "\
+version,date_time,kind,buy_asset,buy_amount,sell_asset,sell_amount,fee_asset,fee_amount,rate,exchange,exchange_id,notes,ownership_changed,received_date_time
+5,2017-01-01T12:00:00+00:00,Buy,BTC,1,GBP,500,GBP,0,500,,,,,
+5,2018-01-01T12:00:00+00:00,Sell,EUR,1000,BTC,1,EUR,0,1000,,,,,
+";
+        let prices = "\
+base_currency,quote_currency,date_time,rate
+EUR,GBP,2018-01-01T00:00:00+00:00,0.9
+BTC,GBP,2018-01-01T00:00:00+00:00,950
+";
+        let prices = Prices::read_csv(prices.as_bytes()).unwrap();
+        let proceeds = |valuation| {
+            let transactions = transactions::read_csv(txs.as_bytes()).unwrap();
+            let options = Options {
+                valuation,
+                ..Default::default()
+            };
+            let report = calculate(transactions, &prices, &options).unwrap();
+            let gains = report.gains(Some(TaxYear::uk(2018)));
+            let basis = gains.gains[0].price_basis;
+            (gains.total_proceeds(), basis)
+        };
+
+        let (consideration, basis) = proceeds(Valuation::Consideration);
+        assert_money_eq!(consideration, gbp!(900));
+        assert_eq!(basis, PriceBasis::ExchangeRate);
+
+        let (market, basis) = proceeds(Valuation::Market);
+        assert_money_eq!(market, gbp!(950));
+        assert_eq!(basis, PriceBasis::Market);
+    }
+
+    #[test]
+    fn nearest_price_within_the_tolerance_is_used_and_recorded() {
+        let txs = "\
+version,date_time,kind,buy_asset,buy_amount,sell_asset,sell_amount,fee_asset,fee_amount,rate,exchange,exchange_id,notes,ownership_changed,received_date_time
+5,2017-01-01T12:00:00+00:00,Buy,BTC,1,GBP,1000,GBP,0,1000,,,,,
+5,2018-01-05T12:00:00+00:00,Buy,ETH,10,BTC,0.5,BTC,0,0.05,,,,,
+";
+        let prices = "\
+base_currency,quote_currency,date_time,rate
+BTC,GBP,2018-01-01T00:00:00+00:00,9000
+BTC,GBP,2018-01-07T00:00:00+00:00,12000
+";
+        let mut prices = Prices::read_csv(prices.as_bytes()).unwrap();
+        let calculate_with = |prices: &Prices| {
+            let transactions = transactions::read_csv(txs.as_bytes()).unwrap();
+            calculate(transactions, prices, &Options::default()).map(|report| {
+                let gains = report.gains(Some(TaxYear::uk(2018)));
+                let disposal = &gains.gains[0];
+                (disposal.price_date(), gains.total_proceeds())
+            })
+        };
+
+        assert!(calculate_with(&prices).is_err(), "No price on the day");
+
+        prices.set_nearest_within(3);
+        let (price_date, proceeds) = calculate_with(&prices).unwrap();
+        assert_eq!(price_date, Some(NaiveDate::from_ymd(2018, 1, 7)));
+        assert_money_eq!(proceeds, gbp!(6000));
+    }
+
+    #[test]
+    fn fiat_leg_records_both_conversion_rates() {
+        let txs = "\
+version,date_time,kind,buy_asset,buy_amount,sell_asset,sell_amount,fee_asset,fee_amount,rate,exchange,exchange_id,notes,ownership_changed,received_date_time
+5,2017-01-01T12:00:00+00:00,Buy,ETH,10,GBP,1000,GBP,0,100,,,,,
+5,2018-01-01T12:00:00+00:00,Sell,EUR,2000,ETH,2,EUR,0,1000,,,,,
+";
+        let prices = "\
+base_currency,quote_currency,date_time,rate
+EUR,GBP,2018-01-01T00:00:00+00:00,0.9
+";
+        let transactions = transactions::read_csv(txs.as_bytes()).unwrap();
+        let prices = Prices::read_csv(prices.as_bytes()).unwrap();
+        let report = calculate(transactions, &prices, &Options::default()).unwrap();
+
+        let gains = report.gains(Some(TaxYear::uk(2018)));
+        let disposal = &gains.gains[0];
+        let asset_price = disposal
+            .asset_price
+            .as_ref()
+            .expect("Should have ETH/EUR price");
+
+        assert_eq!(asset_price.pair.to_string(), "ETH/EUR");
+        assert_eq!(asset_price.rate, dec!(1000));
+        assert_eq!(disposal.price.pair.to_string(), "EUR/GBP");
+        assert_money_eq!(gains.total_proceeds(), gbp!(1800));
+    }
+
+    #[test]
+    fn report_output_is_identical_across_runs() {
+        let txs = "\
+version,date_time,kind,buy_asset,buy_amount,sell_asset,sell_amount,fee_asset,fee_amount,rate,exchange,exchange_id,notes,ownership_changed,received_date_time
+5,2017-01-01T12:00:00+00:00,Buy,BTC,1,GBP,1000,GBP,0,1000,,,,,
+5,2017-01-01T12:00:00+00:00,Buy,ETH,10,GBP,100,GBP,0,10,,,,,
+5,2018-01-01T12:00:00+00:00,Sell,GBP,2000,BTC,0.5,GBP,0,4000,,,,,
+5,2018-01-01T12:00:00+00:00,Sell,GBP,500,ETH,5,GBP,0,100,,,,,
+5,2019-01-01T12:00:00+00:00,Sell,GBP,3000,BTC,0.5,GBP,0,6000,,,,,
+";
+        let prices = Prices::default();
+        let output = || {
+            let transactions = transactions::read_csv(txs.as_bytes()).unwrap();
+            let report = calculate(transactions, &prices, &Options::default()).unwrap();
+            let mut csv = Vec::new();
+            TaxEvent::write_csv(report.gains(None), Rounding::Exact, &mut csv).unwrap();
+            csv
+        };
+
+        let first = output();
+        for _ in 0..10 {
+            assert_eq!(output(), first);
+        }
+    }
+
+    #[test]
+    fn hmrc_rounding_is_in_the_taxpayers_favour() {
+        let buy = trade(
+            "2017-06-01",
+            TradeKind::Buy,
+            gbp!(600.02),
+            btc!(1),
+            dec!(600.02),
+        );
+        let sell = trade(
+            "2018-01-01",
+            TradeKind::Sell,
+            btc!(0.5),
+            gbp!(900.99),
+            dec!(1801.98),
+        );
+        let prices = Prices::default();
+        let report = calculate(vec![buy, sell], &prices, &Options::default()).unwrap();
+        let csv = |rounding| {
+            let mut csv = Vec::new();
+            TaxEvent::write_csv(report.gains(None), rounding, &mut csv).unwrap();
+            let csv = String::from_utf8(csv).unwrap();
+            let mut rows = csv.lines().map(|line| line.split(',').collect::<Vec<_>>());
+            let header = rows.next().unwrap();
+            let disposal = rows.nth(1).unwrap();
+            ["sell_gbp", "allowable_cost", "gain"]
+                .iter()
+                .map(|column| {
+                    let index = header.iter().position(|h| h == column).unwrap();
+                    disposal[index].to_string()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(csv(Rounding::Exact), ["900.99", "300.01", "600.98"]);
+        assert_eq!(csv(Rounding::Hmrc), ["900.00", "301.00", "599.00"]);
+
+        let mut json = Vec::new();
+        TaxEvent::write_json(report.gains(None), Rounding::Hmrc, &mut json).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert!(
+            json[0].get("rounded").is_none(),
+            "Acquisitions are not rounded"
+        );
+        assert_eq!(json[1]["sell_gbp"], "900.99");
+        assert_eq!(json[1]["rounded"]["proceeds"], "900.00");
+        assert_eq!(json[1]["rounded"]["allowable_cost"], "301.00");
+        assert_eq!(json[1]["rounded"]["gain"], "599.00");
+    }
+
+    #[test]
+    fn transfers_changing_ownership_are_disposals_by_policy() {
+        let txs = "\
+version,date_time,kind,buy_asset,buy_amount,sell_asset,sell_amount,fee_asset,fee_amount,rate,exchange,exchange_id,notes,ownership_changed,received_date_time
+5,2018-01-01T12:00:00+00:00,Buy,BTC,1,GBP,1000,GBP,0,1000,,,,,
+5,2018-02-01T12:00:00+00:00,Withdrawal,,,BTC,0.25,BTC,0,0,,,,,
+5,2018-03-01T12:00:00+00:00,Withdrawal,,,BTC,0.5,BTC,0,0,,,,true,
+";
+        let prices = "\
+base_currency,quote_currency,date_time,rate
+BTC,GBP,2018-02-01T00:00:00+00:00,6000
+BTC,GBP,2018-03-01T00:00:00+00:00,8000
+";
+        let prices = Prices::read_csv(prices.as_bytes()).unwrap();
+        let disposals = |ownership_transfers| {
+            let options = Options {
+                ownership_transfers,
+                ..Options::default()
+            };
+            let transactions = transactions::read_csv(txs.as_bytes()).unwrap();
+            let report = calculate(transactions, &prices, &options).unwrap();
+            report
+                .gains(None)
+                .disposals()
+                .map(|d| {
+                    (
+                        d.trade().date_time.date().to_string(),
+                        d.proceeds().to_string(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            disposals(OwnershipTransferPolicy::Disposal),
+            vec![("2018-03-01".to_string(), gbp!(4000).to_string())]
+        );
+        assert!(disposals(OwnershipTransferPolicy::Ignore).is_empty());
+    }
+
+    #[test]
+    fn disposals_link_to_their_matched_acquisitions() {
+        let pooled = trade("2017-06-01", TradeKind::Buy, gbp!(1000), btc!(1), 1000);
+        let sell = trade("2018-01-01", TradeKind::Sell, btc!(0.5), gbp!(2000), 4000);
+        let rebought = trade("2018-01-10", TradeKind::Buy, gbp!(800), btc!(0.2), 4000);
+        let prices = Prices::default();
+        let report = calculate(vec![pooled, sell, rebought], &prices, &Options::default()).unwrap();
+
+        let mut csv = Vec::new();
+        TaxEvent::write_csv(report.gains(None), Rounding::Exact, &mut csv).unwrap();
+        let mut rdr = csv::Reader::from_reader(csv.as_slice());
+        let matched = rdr.headers().unwrap().iter().position(|h| h == "matched");
+        let rows = rdr.records().map(Result::unwrap).collect::<Vec<_>>();
+        let matched = rows[1].get(matched.expect("matched column")).unwrap();
+        assert!(matched.starts_with("30-day #3 "), "{}", matched);
+        assert!(matched.contains("; s104 "), "{}", matched);
+
+        let mut json = Vec::new();
+        TaxEvent::write_json(report.gains(None), Rounding::Exact, &mut json).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        let matches = &json[1]["matches"];
+        assert_eq!(matches[0]["rule"], "30-day");
+        assert_eq!(matches[0]["trade_id"], 3);
+        assert_eq!(matches[0]["asset"], "BTC");
+        assert_eq!(matches[1]["rule"], "s104");
+        assert!(matches[1]["trade_id"].is_null());
+        assert_eq!(json[0]["matches"].as_array().map(Vec::len), Some(0));
+    }
+
+    #[test]
+    fn disposals_exceeding_the_pool_have_unknown_basis() {
+        let buy = trade("2018-06-01", TradeKind::Buy, gbp!(1000), btc!(1), 1000);
+        let sell = trade("2018-07-01", TradeKind::Sell, btc!(2), gbp!(4000), 2000);
+        let prices = Prices::default();
+        let gains = |unknown_basis| {
+            let options = Options {
+                unknown_basis,
+                ..Default::default()
+            };
+            let trades = vec![buy.clone(), sell.clone()];
+            let report = calculate(trades, &prices, &options).unwrap();
+            report.gains(Some(TaxYear::uk(2019)))
+        };
+
+        let zero_cost = gains(UnknownBasis::ZeroCost);
+        let disposal = zero_cost.disposals().next().unwrap();
+        assert_eq!(disposal.matches()[1].rule(), MatchingRule::Unknown);
+        assert_money_eq!(disposal.matches()[1].amount(), btc!(1));
+        assert_money_eq!(zero_cost.total_unknown_basis(), gbp!(2000.00));
+        assert_money_eq!(zero_cost.total_gain(), gbp!(3000.00));
+
+        let excluded = gains(UnknownBasis::Exclude);
+        assert_money_eq!(excluded.total_unknown_basis(), gbp!(2000.00));
+        assert_money_eq!(excluded.total_proceeds(), gbp!(2000.00));
+        assert_money_eq!(excluded.total_gain(), gbp!(1000.00));
+    }
+
+    #[test]
+    fn opening_pools_start_the_pools() {
+        let opening_pools = "\
+asset,quantity,total_cost,as_of
+BTC,10,1000,2018-03-31
+";
+        let trades = vec![trade(
+            "2018-06-01",
+            TradeKind::Sell,
+            btc!(5),
+            gbp!(5000),
+            1000,
+        )];
+        let prices = Prices::default();
+        let options = Options {
+            opening_pools: OpeningPool::read_csv(opening_pools.as_bytes()).unwrap(),
+            ..Default::default()
+        };
+        let report = calculate(trades, &prices, &options).unwrap();
+
+        let gains_2019 = report.gains(Some(TaxYear::uk(2019)));
+        assert_money_eq!(gains_2019.total_allowable_costs(), gbp!(500.00));
+        assert_money_eq!(gains_2019.total_gain(), gbp!(4500.00));
+
+        let btc_pool = report.pools.get("BTC").expect("BTC should have a Pool");
+        assert_money_eq!(btc_pool.total, btc!(5), "Remaining in pool");
+        assert_money_eq!(btc_pool.costs, gbp!(500.00), "Remaining allowable costs");
+    }
+
+    #[test]
+    fn negligible_value_claim_crystallises_the_loss() {
+        let trades = vec![
+            trade("2017-01-01", TradeKind::Buy, gbp!(1000), btc!(100), 10),
+            trade("2017-02-01", TradeKind::Sell, btc!(50), gbp!(1000), 20),
+        ];
+        let mut prices = Prices::default();
+        let from = NaiveDate::from_ymd(2018, 1, 1);
+        prices.mark_worthless(BTC, from);
+        let options = Options {
+            worthless: vec![Worthless {
+                currency: BTC,
+                from,
+                claim: true,
+            }],
+            ..Default::default()
+        };
+        let report = calculate(trades, &prices, &options).unwrap();
+
+        let gains_2018 = report.gains(Some(TaxYear::uk(2018)));
+        assert_eq!(gains_2018.disposal_count(), 1);
+        assert_money_eq!(gains_2018.total_proceeds(), gbp!(0));
+        assert_money_eq!(gains_2018.total_allowable_costs(), gbp!(500));
+        assert!(prices
+            .get(
+                CurrencyPair {
+                    base: BTC,
+                    quote: GBP
+                },
+                from
+            )
+            .is_some());
+    }
+
+    #[test]
+    fn negligible_value_claim_reacquires_the_holding_at_nil() {
+        let eth = Money::from_decimal(dec!(1), crate::currencies::ETH);
+        let trades = vec![
+            trade("2017-01-01", TradeKind::Buy, gbp!(1000), btc!(100), 10),
+            trade("2017-02-01", TradeKind::Sell, btc!(50), gbp!(1000), 20),
+            // priced at zero from the options, without marking the prices
+            trade("2018-06-01", TradeKind::Buy, btc!(10), eth, 10),
+        ];
+        let prices = Prices::default();
+        let options = Options {
+            worthless: vec![Worthless {
+                currency: BTC,
+                from: NaiveDate::from_ymd(2018, 1, 1),
+                claim: true,
+            }],
+            ..Default::default()
+        };
+        let report = calculate(trades, &prices, &options).unwrap();
+
+        let gains_2018 = report.gains(Some(TaxYear::uk(2018)));
+        assert_money_eq!(gains_2018.total_allowable_costs(), gbp!(500));
+        let gains_2019 = report.gains(Some(TaxYear::uk(2019)));
+        let sale = gains_2019
+            .disposals()
+            .next()
+            .expect("The sale after the claim is a disposal");
+        assert_money_eq!(sale.proceeds(), gbp!(0));
+        assert_money_eq!(sale.allowable_costs(), gbp!(0));
+        let rules = sale
+            .matches()
+            .iter()
+            .map(|matched| matched.rule())
+            .collect::<Vec<_>>();
+        assert_eq!(rules, vec![MatchingRule::Pool]);
+
+        let btc_pool = report.pools.get("BTC").expect("BTC should have a Pool");
+        assert_money_eq!(btc_pool.total, btc!(40), "Reacquired less the sale");
+        assert_money_eq!(btc_pool.costs, gbp!(0), "Reacquired at nil");
+    }
+
+    #[test]
+    fn swap_gas_can_be_capitalised_into_the_acquisition() {
+        let txs = "\
+version,date_time,kind,buy_asset,buy_amount,sell_asset,sell_amount,fee_asset,fee_amount,rate,exchange,exchange_id,notes,ownership_changed,received_date_time
+5,2018-01-01T12:00:00+00:00,Buy,ETH,10,GBP,5000,GBP,0,500,,,,,
+5,2018-02-01T12:00:00+00:00,Buy,BTC,1,ETH,10,ETH,0.01,10,Ethereum,,Swap,,
+";
+        let prices = "\
+base_currency,quote_currency,date_time,rate
+ETH,GBP,2018-02-01T00:00:00+00:00,600
+BTC,GBP,2018-02-01T00:00:00+00:00,6000
+";
+        let prices = Prices::read_csv(prices.as_bytes()).unwrap();
+        let report = |capitalise_swap_gas| {
+            let transactions = transactions::read_csv(txs.as_bytes()).unwrap();
+            let options = Options {
+                capitalise_swap_gas,
+                ..Default::default()
+            };
+            calculate(transactions, &prices, &options).unwrap()
+        };
+
+        let deducted = report(false);
+        assert_money_eq!(
+            deducted.gains(Some(TaxYear::uk(2018))).total_gain(),
+            gbp!(994)
+        );
+        assert_money_eq!(deducted.pools["BTC"].costs, gbp!(6000));
+
+        let capitalised = report(true);
+        assert_money_eq!(
+            capitalised.gains(Some(TaxYear::uk(2018))).total_gain(),
+            gbp!(1000)
+        );
+        assert_money_eq!(capitalised.pools["BTC"].costs, gbp!(6006));
+    }
+
+    #[test]
+    fn purchase_fees_are_costs_and_disposal_fees_are_costs_or_reduce_proceeds() {
+        let txs = "\
+version,date_time,kind,buy_asset,buy_amount,sell_asset,sell_amount,fee_asset,fee_amount,rate,exchange,exchange_id,notes,ownership_changed,received_date_time
+5,2018-01-01T12:00:00+00:00,Buy,BTC,1,GBP,1000,GBP,10,1000,,,,,
+5,2018-06-01T12:00:00+00:00,Sell,GBP,2000,BTC,1,GBP,20,2000,,,,,
+5,2018-06-10T12:00:00+00:00,Buy,BTC,0.5,GBP,600,GBP,6,1200,,,,,
+";
+        let prices = Prices::default();
+        let report = |disposal_fees| {
+            let transactions = transactions::read_csv(txs.as_bytes()).unwrap();
+            let options = Options {
+                disposal_fees,
+                ..Default::default()
+            };
+            calculate(transactions, &prices, &options).unwrap()
+        };
+
+        let costs = report(DisposalFees::Costs);
+        let gains = costs.gains(Some(TaxYear::uk(2019)));
+        let disposal = gains.disposals().next().unwrap();
+        // half matched with the later purchase at £606, half from the pool at £505
+        assert_money_eq!(disposal.matches()[0].costs(), gbp!(606));
+        assert_money_eq!(disposal.proceeds(), gbp!(2000));
+        assert_money_eq!(disposal.allowable_costs(), gbp!(1131));
+        assert_money_eq!(disposal.gain(), gbp!(869));
+        assert_money_eq!(costs.pools["BTC"].costs, gbp!(505));
+
+        let proceeds = report(DisposalFees::Proceeds);
+        let gains = proceeds.gains(Some(TaxYear::uk(2019)));
+        let disposal = gains.disposals().next().unwrap();
+        assert_money_eq!(disposal.proceeds(), gbp!(1980));
+        assert_money_eq!(disposal.allowable_costs(), gbp!(1111));
+        assert_money_eq!(disposal.gain(), gbp!(869));
+        assert!(invariants::check(&proceeds).is_empty());
+    }
+
+    #[test]
+    fn crypto_to_crypto_trade_is_a_disposal_and_an_acquisition() {
+        let txs = "\
+version,date_time,kind,buy_asset,buy_amount,sell_asset,sell_amount,fee_asset,fee_amount,rate,exchange,exchange_id,notes,ownership_changed,received_date_time
+5,2018-01-01T12:00:00+00:00,Buy,ETH,10,GBP,1000,GBP,0,100,,,,,
+5,2018-02-01T12:00:00+00:00,Buy,BTC,1,ETH,5,ETH,0,5,,,,,
+";
+        let prices = "\
+base_currency,quote_currency,date_time,rate
+ETH,GBP,2018-02-01T00:00:00+00:00,300
+";
+        let transactions = transactions::read_csv(txs.as_bytes()).unwrap();
+        let prices = Prices::read_csv(prices.as_bytes()).unwrap();
+        let report = calculate(transactions, &prices, &Options::default()).unwrap();
+
+        let gains = report.gains(Some(TaxYear::uk(2018)));
+        let legs = gains
+            .gains
+            .iter()
+            .map(|g| (g.id(), g.leg()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            legs,
+            [
+                (1, None),
+                (2, Some(Leg::Disposal)),
+                (2, Some(Leg::Acquisition))
+            ]
+        );
+        assert_eq!(gains.disposal_count(), 1);
+        assert_money_eq!(gains.total_proceeds(), gbp!(1500));
+        assert_money_eq!(gains.total_allowable_costs(), gbp!(500));
+
+        let disposal = &gains.gains[1];
+        let pools = disposal
+            .pools()
+            .map(|p| p.currency().code)
+            .collect::<Vec<_>>();
+        assert_eq!(pools, ["ETH"]);
+
+        let acquisition = &gains.gains[2];
+        assert!(acquisition.matches().is_empty());
+        let pool = acquisition.pools().next().expect("BTC should have a Pool");
+        assert_eq!(pool.currency().code, "BTC");
+        assert_money_eq!(pool.costs(), gbp!(1500), "Acquired at the value given");
+        assert!(invariants::check(&report).is_empty());
+    }
+
+    #[test]
+    fn fees_in_a_third_currency_are_valued_at_their_own_price() {
+        let txs = "\
+version,date_time,kind,buy_asset,buy_amount,sell_asset,sell_amount,fee_asset,fee_amount,rate,exchange,exchange_id,notes,ownership_changed,received_date_time
+5,2018-01-01T12:00:00+00:00,Buy,ETH,10,GBP,1000,GBP,0,100,,,,,
+5,2018-02-01T12:00:00+00:00,Sell,GBP,1500,ETH,5,BNB,2,300,,,,,
+";
+        let prices = "\
+base_currency,quote_currency,date_time,rate
+BNB,GBP,2018-02-01T00:00:00+00:00,8
+";
+        let prices = Prices::read_csv(prices.as_bytes()).unwrap();
+        let report = calculate(
+            transactions::read_csv(txs.as_bytes()).unwrap(),
+            &prices,
+            &Options::default(),
+        )
+        .unwrap();
+
+        let gains = report.gains(Some(TaxYear::uk(2018)));
+        let disposal = &gains.gains[1];
+        assert_money_eq!(disposal.fee(), gbp!(16));
+
+        let without_fee_price = calculate(
+            transactions::read_csv(txs.as_bytes()).unwrap(),
+            &Prices::default(),
+            &Options::default(),
+        );
+        assert!(without_fee_price
+            .err()
+            .expect("No BNB price")
+            .to_string()
+            .starts_with("Should have price for fee: "));
+    }
+
+    #[test]
+    fn selling_the_whole_pool_takes_all_of_its_remaining_costs() {
+        let mut pool = Pool::new(BTC);
+        pool.buy(&btc!(3), &gbp!(1000));
+        let first = pool.sell(btc!(1));
+        let rest = pool.sell(btc!(2));
+        assert_eq!(first.amount() + rest.amount(), dec!(1000));
+        assert!(pool.total().amount().is_zero());
+        assert!(pool.costs().amount().is_zero());
+    }
+
+    #[test]
+    fn multiple_acquisitions_within_30_days_are_matched_earliest_first() {
+        let buy1 = trade("2018-01-01", TradeKind::Buy, gbp!(10_000), btc!(100), 100);
+        let sell = trade("2018-05-01", TradeKind::Sell, btc!(10), gbp!(5000), 500);
+        let buy2 = trade("2018-05-10", TradeKind::Buy, gbp!(2400), btc!(6), 400);
+        let buy3 = trade("2018-05-20", TradeKind::Buy, gbp!(3000), btc!(6), 500);
+
+        let trades = vec![buy1, sell, buy2, buy3];
+        let prices = Prices::default();
+        let report = calculate(trades, &prices, &Options::default()).unwrap();
+
+        let gains_2019 = report.gains(Some(TaxYear::uk(2019)));
+        let disposal = gains_2019.disposals().next().unwrap();
+
+        assert_eq!(disposal.matches().len(), 2, "Nothing from the pool");
+        assert_eq!(disposal.matches()[0].rule(), MatchingRule::ThirtyDay);
+        assert_eq!(disposal.matches()[0].trade_id(), Some(3));
+        assert_money_eq!(disposal.matches()[0].amount(), btc!(6), "All of the first");
+        assert_eq!(disposal.matches()[1].rule(), MatchingRule::ThirtyDay);
+        assert_eq!(disposal.matches()[1].trade_id(), Some(4));
+        assert_money_eq!(
+            disposal.matches()[1].amount(),
+            btc!(4),
+            "Part of the second"
+        );
+        assert_money_eq!(disposal.allowable_costs, gbp!(4400.00), "Allowable costs");
+        assert_money_eq!(disposal.gain(), gbp!(600.00), "Gain 30 days");
+
+        let btc_pool = report.pools.get("BTC").expect("BTC should have a Pool");
 
-    // todo: test 30 days with multiple buys
+        assert_money_eq!(btc_pool.total, btc!(102), "Remaining in pool");
+        assert_money_eq!(btc_pool.costs, gbp!(11_000.00), "Remaining allowable costs");
+    }
 }
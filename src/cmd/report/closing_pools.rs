@@ -0,0 +1,140 @@
+//! The Section 104 pools held at the end of a period, for carrying the balances over to another
+//! tool or an accountant's software without the full history of transactions.
+//!
+//! The csv has the columns:
+//!
+//! - `asset`: the currency code e.g. BTC
+//! - `quantity`: the amount held in the pool
+//! - `total_cost`: the pooled allowable costs in GBP
+//! - `as_of`: the date of the holding e.g. 2021-04-05, after the transactions on that day
+//! - `method`: how the cost was determined, always `s104` for the pooled average cost
+//!
+//! It can be read back with `report --opening-pools`, which ignores the method.
+
+use super::cgt::{MatchingRule, OpeningPool, Pool, TaxReport};
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::{collections::BTreeMap, io::Write};
+
+#[derive(Debug, Serialize)]
+struct ClosingPoolRecord {
+    asset: String,
+    quantity: String,
+    total_cost: String,
+    as_of: NaiveDate,
+    method: &'static str,
+}
+
+/// Writes the pools as they were at the end of the day as csv
+pub fn write_closing_pools<W>(
+    report: &TaxReport,
+    opening_pools: &[OpeningPool],
+    as_of: NaiveDate,
+    writer: W,
+) -> color_eyre::Result<()>
+where
+    W: Write,
+{
+    let records = closing_pools(report, opening_pools, as_of)
+        .into_iter()
+        .map(|(code, (total, costs))| ClosingPoolRecord {
+            asset: code,
+            quantity: total.normalize().to_string(),
+            total_cost: costs.normalize().to_string(),
+            as_of,
+            method: "s104",
+        })
+        .collect();
+    crate::utils::write_csv(records, writer)
+}
+
+/// The quantity and costs of each pool after the last event on or before the date, or the
+/// opening pool if there are no events of the asset by then. Empty pools are left out.
+pub fn closing_pools(
+    report: &TaxReport,
+    opening_pools: &[OpeningPool],
+    as_of: NaiveDate,
+) -> BTreeMap<String, (Decimal, Decimal)> {
+    let mut pools = opening_pools
+        .iter()
+        .filter(|opening| opening.as_of <= as_of)
+        .map(|opening| {
+            let code = opening.currency.code.to_string();
+            (code, (opening.quantity, opening.total_cost))
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    let gains = report.gains(None);
+    let events = gains
+        .gains
+        .iter()
+        .filter(|event| event.trade().date_time.date() <= as_of);
+    let mut across_as_of = 0;
+    for event in events {
+        for pool in event.pools() {
+            pools.insert(pool.currency().code.to_string(), totals(pool));
+        }
+        across_as_of += event
+            .matches()
+            .iter()
+            .filter(|m| {
+                m.rule() == MatchingRule::ThirtyDay
+                    && m.date_time().map_or(false, |dt| dt.date() > as_of)
+            })
+            .count();
+    }
+    if across_as_of > 0 {
+        tracing::warn!(
+            "{} disposals on or before {} are matched with acquisitions after it by the 30 day \
+             rule, which only add the unmatched part to the pools. Another tool continuing from \
+             the closing pools would need the same matches.",
+            across_as_of,
+            as_of
+        );
+    }
+    pools.retain(|_, (total, costs)| !total.is_zero() || !costs.is_zero());
+    pools
+}
+
+fn totals(pool: &Pool) -> (Decimal, Decimal) {
+    (*pool.total().amount(), *pool.costs().amount())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cmd::{
+            prices::Prices,
+            report::cgt::{self, Options},
+        },
+        transactions,
+    };
+
+    #[test]
+    fn closing_pools_can_be_read_back_as_opening_pools() {
+        let txs = "\
+version,date_time,kind,buy_asset,buy_amount,sell_asset,sell_amount,fee_asset,fee_amount,rate,exchange,exchange_id,notes,ownership_changed,received_date_time
+5,2018-01-01T12:00:00+00:00,Buy,BTC,2,GBP,200,GBP,0,100,,,,,
+5,2018-02-01T12:00:00+00:00,Sell,GBP,300,BTC,0.5,GBP,0,600,,,,,
+5,2018-05-10T12:00:00+00:00,Buy,BTC,1,GBP,150,GBP,0,150,,,,,
+";
+        let transactions = transactions::read_csv(txs.as_bytes()).unwrap();
+        let prices = Prices::default();
+        let report = cgt::calculate(transactions, &prices, &Options::default()).unwrap();
+        let year_end = cgt::TaxYear::uk(2018).last_day();
+
+        let mut csv = Vec::new();
+        write_closing_pools(&report, &[], year_end, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+
+        assert_eq!(
+            csv,
+            "asset,quantity,total_cost,as_of,method\nBTC,1.5,150,2018-04-05,s104\n"
+        );
+        let opening = OpeningPool::read_csv(csv.as_bytes()).unwrap();
+        assert_eq!(opening[0].currency.code, "BTC");
+        assert_eq!(opening[0].as_of, year_end);
+    }
+}
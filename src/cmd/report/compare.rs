@@ -0,0 +1,272 @@
+//! The gains of each tax year under other methods of matching disposals with acquisitions, side
+//! by side with the UK rules, e.g. for assessing a move to a jurisdiction with different rules.
+//!
+//! The other methods reuse the GBP values of the UK calculation, only matching differently:
+//!
+//! - `s104`: the UK same day and 30 day rules, then the Section 104 pool
+//! - `fifo`: first in first out, disposing of the earliest acquisitions first
+//! - `acb`: adjusted cost base, the average cost of everything held, without the same day or 30
+//!   day rules
+//!
+//! Disposals of more than is held are at zero cost with every method.
+
+use super::{
+    cgt::{OpeningPool, TaxReport, TaxYear},
+    ReportFormat,
+};
+use crate::{currencies::GBP, money::display_amount, Money};
+use prettytable::{Cell, Row, Table};
+use rust_decimal::Decimal;
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    io::Write,
+};
+
+/// A method of matching disposals with acquisitions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    S104,
+    Fifo,
+    Acb,
+}
+
+impl std::fmt::Display for Method {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Method::S104 => write!(f, "s104"),
+            Method::Fifo => write!(f, "fifo"),
+            Method::Acb => write!(f, "acb"),
+        }
+    }
+}
+
+/// The methods to compare, parsed from a comma separated list e.g. `s104,fifo,acb`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Methods(pub Vec<Method>);
+
+impl std::str::FromStr for Methods {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(|method| match method.trim() {
+                "s104" => Ok(Method::S104),
+                "fifo" => Ok(Method::Fifo),
+                "acb" => Ok(Method::Acb),
+                m => Err(format!(
+                    "Unsupported method {}, expected s104, fifo or acb",
+                    m
+                )),
+            })
+            .collect::<Result<_, _>>()
+            .map(Methods)
+    }
+}
+
+/// The quantity and cost of what is held of an asset
+#[derive(Default)]
+struct Holding {
+    /// The lots in the order acquired, for FIFO, or a single lot of the average cost
+    lots: VecDeque<(Decimal, Decimal)>,
+}
+
+impl Holding {
+    fn acquire(&mut self, method: Method, quantity: Decimal, cost: Decimal) {
+        match (method, self.lots.front_mut()) {
+            (Method::Acb, Some((held, costs))) => {
+                *held += quantity;
+                *costs += cost;
+            }
+            _ => self.lots.push_back((quantity, cost)),
+        }
+    }
+
+    /// Removes the quantity from the earliest lots, returning their cost. With a single lot of
+    /// everything held this is the average cost.
+    fn dispose(&mut self, mut quantity: Decimal) -> Decimal {
+        let mut cost = Decimal::new(0, 0);
+        while quantity > Decimal::new(0, 0) {
+            let (held, costs) = match self.lots.front_mut() {
+                Some(lot) => lot,
+                None => break,
+            };
+            if quantity < *held {
+                let part = *costs * quantity / *held;
+                *held -= quantity;
+                *costs -= part;
+                cost += part;
+                break;
+            }
+            quantity -= *held;
+            cost += *costs;
+            self.lots.pop_front();
+        }
+        cost
+    }
+}
+
+/// The total gain of each tax year with the method
+fn gains_by_year(
+    report: &TaxReport,
+    opening_pools: &[OpeningPool],
+    method: Method,
+) -> BTreeMap<TaxYear, Decimal> {
+    let gains = report.gains(None);
+    let mut years = BTreeMap::new();
+    if method == Method::S104 {
+        for disposal in gains.disposals() {
+            *years
+                .entry(disposal.tax_year())
+                .or_insert(Decimal::new(0, 0)) += *disposal.gain().amount();
+        }
+        return years;
+    }
+
+    let mut holdings: HashMap<&str, Holding> = HashMap::new();
+    for opening in opening_pools {
+        holdings.entry(opening.currency.code).or_default().acquire(
+            method,
+            opening.quantity,
+            opening.total_cost,
+        );
+    }
+    for event in gains.gains.iter() {
+        let trade = event.trade();
+        if event.is_disposal() {
+            let matched = event
+                .matches()
+                .iter()
+                .fold(Decimal::new(0, 0), |acc, m| acc + *m.costs().amount());
+            // the fee, when counted as an allowable cost rather than deducted from the proceeds
+            let fee = *event.allowable_costs().amount() - matched;
+            let cost = holdings
+                .entry(trade.sell.currency().code)
+                .or_default()
+                .dispose(*trade.sell.amount());
+            *years.entry(event.tax_year()).or_insert(Decimal::new(0, 0)) +=
+                *event.proceeds().amount() - cost - fee;
+        } else if let Some(cost) = event.acquisition_cost() {
+            holdings
+                .entry(trade.buy.currency().code)
+                .or_default()
+                .acquire(method, *trade.buy.amount(), *cost.amount());
+        }
+    }
+    years
+}
+
+/// Writes the total gain of each tax year with each of the methods, as csv or a table
+pub fn write_comparison<W>(
+    report: &TaxReport,
+    opening_pools: &[OpeningPool],
+    methods: &Methods,
+    format: &ReportFormat,
+    mut writer: W,
+) -> color_eyre::Result<()>
+where
+    W: Write,
+{
+    let by_method = methods
+        .0
+        .iter()
+        .map(|method| gains_by_year(report, opening_pools, *method))
+        .collect::<Vec<_>>();
+    let years = by_method
+        .iter()
+        .flat_map(|years| years.keys().cloned())
+        .collect::<std::collections::BTreeSet<_>>();
+    let gbp = |amount: Decimal| display_amount(&Money::from_decimal(amount, GBP));
+    let mut rows = years
+        .iter()
+        .map(|year| {
+            let mut row = vec![year.to_string()];
+            row.extend(by_method.iter().map(|years| {
+                gbp(years
+                    .get(year)
+                    .cloned()
+                    .unwrap_or_else(|| Decimal::new(0, 0)))
+            }));
+            row
+        })
+        .collect::<Vec<_>>();
+    let mut total = vec!["total".to_string()];
+    total.extend(
+        by_method
+            .iter()
+            .map(|years| gbp(years.values().cloned().sum())),
+    );
+    rows.push(total);
+
+    let mut titles = vec!["tax_year".to_string()];
+    titles.extend(methods.0.iter().map(|method| method.to_string()));
+    match format {
+        ReportFormat::Table => {
+            let cells = |values: &[String]| Row::new(values.iter().map(|v| Cell::new(v)).collect());
+            let mut table = Table::new();
+            table.set_titles(cells(&titles));
+            for row in rows.iter() {
+                table.add_row(cells(row));
+            }
+            table.print(&mut writer)?;
+        }
+        ReportFormat::Json => {
+            let objects = rows
+                .into_iter()
+                .map(|row| {
+                    titles
+                        .iter()
+                        .cloned()
+                        .zip(row.into_iter().map(serde_json::Value::String))
+                        .collect::<serde_json::Map<_, _>>()
+                })
+                .collect::<Vec<_>>();
+            serde_json::to_writer_pretty(writer, &objects)?;
+        }
+        ReportFormat::Csv => {
+            let mut wtr = csv::Writer::from_writer(writer);
+            wtr.write_record(&titles)?;
+            for row in rows.iter() {
+                wtr.write_record(row)?;
+            }
+            wtr.flush()?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cmd::{
+            prices::Prices,
+            report::cgt::{self, Options},
+        },
+        transactions,
+    };
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn methods_match_the_same_disposal_differently() {
+        let txs = "\
+version,date_time,kind,buy_asset,buy_amount,sell_asset,sell_amount,fee_asset,fee_amount,rate,exchange,exchange_id,notes,ownership_changed,received_date_time
+5,2018-01-01T12:00:00+00:00,Buy,BTC,1,GBP,100,GBP,0,100,,,,,
+5,2018-02-01T12:00:00+00:00,Buy,BTC,1,GBP,300,GBP,0,300,,,,,
+5,2018-03-01T12:00:00+00:00,Sell,GBP,400,BTC,1,GBP,0,400,,,,,
+5,2018-03-10T12:00:00+00:00,Buy,BTC,1,GBP,500,GBP,0,500,,,,,
+";
+        let transactions = transactions::read_csv(txs.as_bytes()).unwrap();
+        let prices = Prices::default();
+        let report = cgt::calculate(transactions, &prices, &Options::default()).unwrap();
+        let year = TaxYear::uk(2018);
+        let gain = |method| gains_by_year(&report, &[], method)[&year];
+
+        assert_eq!(
+            gain(Method::S104),
+            dec!(-100),
+            "Matched with the buy 9 days later"
+        );
+        assert_eq!(gain(Method::Fifo), dec!(300), "Matched with the first buy");
+        assert_eq!(gain(Method::Acb), dec!(200), "Matched at the average cost");
+    }
+}
@@ -0,0 +1,328 @@
+//! Profit and loss of derivatives. Funding payments on perpetual futures, option premiums and
+//! the settlement of options and futures are imported as income when received and an expense
+//! when paid, categorised here by the start of their notes. Funding payments are netted per day
+//! and asset when imported. Amounts paid in BTC or ETH are valued in GBP at the price of the day.
+
+use super::cgt::{self, Options, ReportPeriod, TaxReport};
+use crate::{
+    cmd::prices::{CurrencyPair, Prices},
+    currencies::{Currency, GBP},
+    money::display_amount,
+    transactions::{Movement, Transaction},
+    Money,
+};
+use chrono::{NaiveDate, NaiveDateTime};
+use color_eyre::eyre;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::Write,
+};
+
+/// The start of the notes of funding payments
+const FUNDING: &str = "Funding";
+
+/// The realised profit or loss of a derivative
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, derive_more::Display)]
+pub enum DerivativeCategory {
+    #[display(fmt = "funding")]
+    Funding,
+    /// Received for writing an option, or paid for buying one
+    #[display(fmt = "premium")]
+    Premium,
+    /// An option or future settled or delivered
+    #[display(fmt = "settlement")]
+    Settlement,
+}
+
+/// The starts of the notes of the income and expenses of each category
+const CATEGORIES: &[(&str, DerivativeCategory)] = &[
+    (FUNDING, DerivativeCategory::Funding),
+    ("Option premium", DerivativeCategory::Premium),
+    ("Option settlement", DerivativeCategory::Settlement),
+    ("Futures settlement", DerivativeCategory::Settlement),
+];
+
+/// A funding payment on a perpetual future, received if positive and paid if negative
+pub struct FundingPayment {
+    pub time: NaiveDateTime,
+    /// The perpetual e.g. BTCUSDT
+    pub symbol: String,
+    pub asset: &'static Currency,
+    pub amount: Decimal,
+}
+
+/// Nets the funding payments of each day and asset into income if more was received than paid,
+/// otherwise an expense, at the time of the last payment of the day
+pub fn funding_by_day<'a>(payments: Vec<FundingPayment>, exchange: &str) -> Vec<Transaction<'a>> {
+    let mut days: BTreeMap<
+        (NaiveDate, &str),
+        (NaiveDateTime, &Currency, Decimal, BTreeSet<String>),
+    > = BTreeMap::new();
+    for payment in payments {
+        let (time, _, net, symbols) = days
+            .entry((payment.time.date(), payment.asset.code))
+            .or_insert((
+                payment.time,
+                payment.asset,
+                Decimal::new(0, 0),
+                BTreeSet::new(),
+            ));
+        *time = (*time).max(payment.time);
+        *net += payment.amount;
+        symbols.insert(payment.symbol);
+    }
+    days.into_iter()
+        .filter(|(_, (_, _, net, _))| !net.is_zero())
+        .map(|(_, (time, asset, net, symbols))| {
+            let movement = Movement {
+                date_time: time,
+                amount: Money::from_decimal(net.abs().round_dp(asset.exponent), asset),
+                fee: Money::from_major(0, asset),
+                exchange: Some(exchange.to_string()),
+                account: None,
+                ownership_changed: false,
+                notes: Some(format!(
+                    "{} payments on {}",
+                    FUNDING,
+                    symbols.into_iter().collect::<Vec<_>>().join(" ")
+                )),
+            };
+            if net.is_sign_positive() {
+                Transaction::Income(movement)
+            } else {
+                Transaction::Expense(movement)
+            }
+        })
+        .collect()
+}
+
+/// The category of a derivative profit or loss, whether it was received or paid, and the amount
+pub fn derivative<'a, 'b>(
+    tx: &'b Transaction<'a>,
+) -> Option<(DerivativeCategory, bool, &'b Movement<'a>)> {
+    let (received, movement) = match tx {
+        Transaction::Income(movement) => (true, movement),
+        Transaction::Expense(movement) => (false, movement),
+        _ => return None,
+    };
+    let notes = movement.notes.as_deref().unwrap_or_default();
+    CATEGORIES
+        .iter()
+        .find(|(start, _)| notes.starts_with(start))
+        .map(|(_, category)| (*category, received, movement))
+}
+
+#[derive(Serialize)]
+struct DerivativesRecord {
+    tax_year: cgt::TaxYear,
+    exchange: String,
+    category: String,
+    asset: String,
+    transactions: usize,
+    received: String,
+    paid: String,
+    net: String,
+    gbp_value: String,
+}
+
+/// Writes the derivative profits and losses within the period as csv, totalled for each tax
+/// year, exchange, category and asset. The fees paid are included in the amount paid.
+pub fn write_derivatives<'a, W>(
+    report: &TaxReport<'a>,
+    prices: &'a Prices<'a>,
+    options: &Options,
+    period: Option<&ReportPeriod>,
+    writer: W,
+) -> color_eyre::Result<()>
+where
+    W: Write,
+{
+    let mut totals = BTreeMap::new();
+    for tx in report.transactions.iter() {
+        let (category, received, movement) = match derivative(tx) {
+            Some(derivative) => derivative,
+            None => continue,
+        };
+        let date_time = movement.date_time;
+        if !period.map_or(true, |p| p.contains(date_time, &options.year_start)) {
+            continue;
+        }
+        let asset = movement.amount.currency();
+        let value = |amount: &Money<'a>| -> color_eyre::Result<Money<'a>> {
+            if asset == GBP {
+                return Ok(amount.clone());
+            }
+            let pair = CurrencyPair {
+                base: asset,
+                quote: GBP,
+            };
+            let price = prices.get(pair, date_time.date()).ok_or(eyre::eyre!(
+                "Should have price for derivative: {} at {}",
+                amount,
+                date_time
+            ))?;
+            Ok(Money::from_decimal(*amount.amount() * price.rate, GBP))
+        };
+        let fee = if movement.fee.currency() == asset {
+            movement.fee.clone()
+        } else {
+            Money::from_major(0, asset)
+        };
+        let key = (
+            options.year_start.tax_year(date_time),
+            movement.exchange.clone().unwrap_or_default(),
+            category,
+            asset.code,
+        );
+        let (count, total_received, total_paid, net_value) = totals.entry(key).or_insert((
+            0,
+            Money::from_major(0, asset),
+            Money::from_major(0, asset),
+            Money::from_major(0, GBP),
+        ));
+        *count += 1;
+        if received {
+            *total_received = total_received.clone() + movement.amount.clone();
+            *net_value = net_value.clone() + value(&movement.amount)?;
+        } else {
+            *total_paid = total_paid.clone() + movement.amount.clone();
+            *net_value = net_value.clone() - value(&movement.amount)?;
+        }
+        *total_paid = total_paid.clone() + fee.clone();
+        *net_value = net_value.clone() - value(&fee)?;
+    }
+
+    let records = totals
+        .into_iter()
+        .map(
+            |((tax_year, exchange, category, asset), (transactions, received, paid, gbp_value))| {
+                DerivativesRecord {
+                    tax_year,
+                    exchange,
+                    category: category.to_string(),
+                    asset: asset.to_string(),
+                    transactions,
+                    net: display_amount(&(received.clone() - paid.clone())),
+                    received: display_amount(&received),
+                    paid: display_amount(&paid),
+                    gbp_value: display_amount(&gbp_value),
+                }
+            },
+        )
+        .collect::<Vec<_>>();
+    tracing::info!("Derivatives {} categories", records.len());
+    crate::utils::write_csv(records, writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::money::currencies::USDT;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn funding_is_netted_by_day_and_totalled_by_year() {
+        let at = |day, hour| NaiveDate::from_ymd(2021, 3, day).and_hms(hour, 0, 0);
+        let payment = |time, symbol: &str, amount| FundingPayment {
+            time,
+            symbol: symbol.to_string(),
+            asset: USDT,
+            amount,
+        };
+        let payments = vec![
+            payment(at(1, 0), "BTCUSDT", dec!(1.5)),
+            payment(at(1, 8), "ETHUSDT", dec!(-0.5)),
+            payment(at(1, 16), "BTCUSDT", dec!(1)),
+            payment(at(2, 0), "BTCUSDT", dec!(-1)),
+            payment(at(3, 0), "BTCUSDT", dec!(1)),
+            payment(at(3, 8), "BTCUSDT", dec!(-1)),
+        ];
+        let funding = funding_by_day(payments, "Binance");
+        assert_eq!(funding.len(), 2, "Funding netting to zero is left out");
+        match funding[0] {
+            Transaction::Income(ref income) => {
+                assert_eq!(income.date_time, at(1, 16));
+                assert_eq!(income.amount, Money::from_decimal(dec!(2), USDT));
+                assert_eq!(
+                    income.notes.as_deref(),
+                    Some("Funding payments on BTCUSDT ETHUSDT")
+                );
+            }
+            _ => panic!("Expected the funding received to be income"),
+        }
+        match funding[1] {
+            Transaction::Expense(ref expense) => {
+                assert_eq!(expense.amount, Money::from_decimal(dec!(1), USDT))
+            }
+            _ => panic!("Expected the funding paid to be an expense"),
+        }
+
+        let prices = "\
+base_currency,quote_currency,date_time,rate
+USDT,GBP,2021-03-01T00:00:00+00:00,0.75
+USDT,GBP,2021-03-02T00:00:00+00:00,0.80
+";
+        let prices = Prices::read_csv(prices.as_bytes()).unwrap();
+        let options = Options::default();
+        let report = cgt::calculate(funding, &prices, &options).unwrap();
+
+        let mut csv = Vec::new();
+        write_derivatives(&report, &prices, &options, None, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let rows = csv
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0][..5], ["2021", "Binance", "funding", "USDT", "2"]);
+        assert_eq!(rows[0][8], "0.70", "Received 1.50 less paid 0.80");
+    }
+
+    #[test]
+    fn option_premiums_and_settlements_are_valued_daily() {
+        let txs = "\
+version,date_time,kind,buy_asset,buy_amount,sell_asset,sell_amount,fee_asset,fee_amount,rate,exchange,exchange_id,notes,ownership_changed,received_date_time
+5,2021-05-01T09:00:00+00:00,Buy,BTC,2,GBP,80000,GBP,0,40000,Deribit,,,,
+5,2021-05-03T10:30:00+00:00,Expense,,,BTC,0.015,BTC,0.0003,0,Deribit,,Option premium open buy 1 BTC-25JUN21-60000-C,,
+5,2021-05-04T11:00:00+00:00,Income,BTC,0.04,,,BTC,0.0006,0,Deribit,,Option premium open sell 2 BTC-25JUN21-50000-P,,
+5,2021-05-05T12:00:00+00:00,Fee,,,BTC,0.0001,BTC,0,0,Deribit,,Futures trade BTC-PERPETUAL,,
+5,2021-06-25T08:00:00+00:00,Expense,,,BTC,1.125,BTC,0,0,Deribit,,Option settlement BTC-25JUN21-50000-P,,
+";
+        let prices = "\
+base_currency,quote_currency,date_time,rate
+BTC,GBP,2021-05-03T00:00:00+00:00,40000
+BTC,GBP,2021-05-04T00:00:00+00:00,40000
+BTC,GBP,2021-05-05T00:00:00+00:00,40000
+BTC,GBP,2021-06-25T00:00:00+00:00,30000
+";
+        let transactions = crate::transactions::read_csv(txs.as_bytes()).unwrap();
+        let prices = Prices::read_csv(prices.as_bytes()).unwrap();
+        let options = Options::default();
+        let report = cgt::calculate(transactions, &prices, &options).unwrap();
+
+        let mut csv = Vec::new();
+        write_derivatives(&report, &prices, &options, None, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let rows = csv
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            rows.len(),
+            2,
+            "Futures trade fees are not derivative profits"
+        );
+        assert_eq!(rows[0][..5], ["2022", "Deribit", "premium", "BTC", "2"]);
+        assert_eq!(
+            rows[0][8], "964.00",
+            "Premium received less paid and fees, at 40000"
+        );
+        assert_eq!(rows[1][..5], ["2022", "Deribit", "settlement", "BTC", "1"]);
+    }
+}
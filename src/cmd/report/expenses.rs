@@ -0,0 +1,111 @@
+//! Report of expenses paid in crypto e.g. loan interest or subscription fees. Paying an expense
+//! is a disposal of the crypto, included in the gains report, and the GBP value paid may also be
+//! deductible.
+
+use super::cgt::{self, Options, ReportPeriod, TaxReport};
+use crate::{
+    cmd::prices::Prices, currencies::GBP, money::display_amount, transactions::Transaction, Money,
+};
+use serde::Serialize;
+use std::io::Write;
+
+#[derive(Serialize)]
+struct ExpenseRecord {
+    date_time: String,
+    tax_year: cgt::TaxYear,
+    asset: String,
+    amount: String,
+    gbp_value: String,
+    exchange: String,
+    description: String,
+}
+
+/// Writes all the expenses within the period as csv
+pub fn write_expenses<'a, W>(
+    report: &TaxReport<'a>,
+    prices: &'a Prices<'a>,
+    options: &Options,
+    period: Option<&ReportPeriod>,
+    writer: W,
+) -> color_eyre::Result<()>
+where
+    W: Write,
+{
+    let mut total = Money::from_major(0, GBP);
+    let records = report
+        .transactions
+        .iter()
+        .filter_map(|tx| match tx {
+            Transaction::Expense(expense) => Some((tx.kind(), expense)),
+            _ => None,
+        })
+        .filter(|(_, expense)| {
+            period.map_or(true, |p| p.contains(expense.date_time, &options.year_start))
+        })
+        .map(|(kind, expense)| {
+            let gbp_value = if expense.amount.currency() == GBP {
+                expense.amount.clone()
+            } else {
+                let rate = cgt::market_rate(kind, expense, prices)?;
+                Money::from_decimal(*expense.amount.amount() * rate, GBP)
+            };
+            total = total.clone() + gbp_value.clone();
+            Ok(ExpenseRecord {
+                date_time: expense.date_time.to_string(),
+                tax_year: options.year_start.tax_year(expense.date_time),
+                asset: expense.amount.currency().code.to_string(),
+                amount: display_amount(&expense.amount),
+                gbp_value: display_amount(&gbp_value),
+                exchange: expense.exchange.clone().unwrap_or(String::new()),
+                description: expense.notes.clone().unwrap_or(String::new()),
+            })
+        })
+        .collect::<color_eyre::Result<Vec<_>>>()?;
+
+    tracing::info!("Expenses {}", records.len());
+    tracing::info!("Total Expenses {}", total);
+    crate::utils::write_csv(records, writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transactions;
+
+    #[test]
+    fn expenses_in_the_period_are_valued_in_gbp() {
+        let txs = "\
+version,date_time,kind,buy_asset,buy_amount,sell_asset,sell_amount,fee_asset,fee_amount,rate,exchange,exchange_id,notes,ownership_changed,received_date_time
+5,2018-01-01T12:00:00+00:00,Buy,BTC,1,GBP,1000,GBP,0,1000,Kraken,,,,
+5,2018-06-01T12:00:00+00:00,Expense,,,BTC,0.1,BTC,0,0,Kraken,,Loan interest,,
+5,2018-07-01T12:00:00+00:00,Expense,,,GBP,25,GBP,0,0,,,Subscription,,
+5,2019-06-01T12:00:00+00:00,Expense,,,GBP,30,GBP,0,0,,,Subscription,,
+";
+        let prices = "\
+base_currency,quote_currency,date_time,rate
+BTC,GBP,2018-06-01T00:00:00+00:00,5000
+";
+        let prices = Prices::read_csv(prices.as_bytes()).unwrap();
+        let options = Options::default();
+        let transactions = transactions::read_csv(txs.as_bytes()).unwrap();
+        let report = cgt::calculate(transactions, &prices, &options).unwrap();
+
+        let mut csv = Vec::new();
+        let period = ReportPeriod::TaxYear(cgt::TaxYear::uk(2019));
+        write_expenses(&report, &prices, &options, Some(&period), &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let rows = csv
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').skip(2).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rows,
+            vec![
+                vec!["BTC", "0.10000000", "500.00", "Kraken", "Loan interest"],
+                vec!["GBP", "25.00", "25.00", "", "Subscription"],
+            ]
+        );
+        assert_eq!(report.gains(None).disposals().count(), 1);
+    }
+}
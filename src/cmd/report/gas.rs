@@ -0,0 +1,322 @@
+//! Gas paid on EVM chains, valued in GBP and categorised by what it was spent on. Gas is a
+//! disposal of the chain's native asset, so it is already in the gains report, but the totals are
+//! useful for checking on chain activity and deciding whether to capitalise the gas of swaps.
+
+use super::cgt::{self, Options, ReportPeriod, TaxReport};
+use crate::{
+    cmd::prices::{CurrencyPair, Prices},
+    currencies::GBP,
+    money::display_amount,
+    trades::Trade,
+    transactions::{self, Movement, Transaction, TransactionKind},
+    Money,
+};
+use color_eyre::eyre;
+use serde::Serialize;
+use std::{collections::BTreeMap, io::Write};
+
+/// EVM chains, as the exchange of their transactions, and the native asset gas is paid in
+const CHAINS: &[(&str, &str)] = &[
+    ("ethereum", "ETH"),
+    ("ethereum classic", "ETC"),
+    ("bnb smart chain", "BNB"),
+];
+
+/// What the gas was spent on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, derive_more::Display)]
+pub enum GasCategory {
+    #[display(fmt = "transfer")]
+    Transfer,
+    #[display(fmt = "swap")]
+    Swap,
+    #[display(fmt = "approval")]
+    Approval,
+    #[display(fmt = "failed")]
+    Failed,
+    /// Other contract interactions e.g. claiming or staking
+    #[display(fmt = "other")]
+    Other,
+}
+
+/// Whether the transaction is on an EVM chain and paid in its gas asset
+fn is_gas<'a>(exchange: Option<&str>, gas: &Money<'a>) -> bool {
+    CHAINS.iter().any(|(chain, asset)| {
+        transactions::account_matches(exchange, chain) && gas.currency().code == *asset
+    })
+}
+
+/// Whether the fee of the trade is gas paid for a swap on an EVM chain
+pub fn is_swap_gas(kind: TransactionKind, trade: &Trade) -> bool {
+    matches!(kind, TransactionKind::Buy | TransactionKind::Sell)
+        && is_gas(trade.exchange.as_deref(), &trade.fee)
+}
+
+/// The gas paid by the transaction and what it was spent on, if it is on an EVM chain. Standalone
+/// fees are categorised by their notes, which on chain importers fill with the contract method.
+pub fn gas<'a, 'b>(tx: &'b Transaction<'a>) -> Option<(GasCategory, &'b Money<'a>)> {
+    let (category, gas) = match tx {
+        Transaction::Trade(trade) => (GasCategory::Swap, &trade.fee),
+        Transaction::Contribution(contribution) => (GasCategory::Other, &contribution.trade.fee),
+        Transaction::Fee(fee) => {
+            let notes = fee.notes.clone().unwrap_or_default().to_lowercase();
+            let category = if notes.starts_with("failed") {
+                GasCategory::Failed
+            } else if notes.contains("approv") {
+                GasCategory::Approval
+            } else if notes.contains("swap") {
+                GasCategory::Swap
+            } else {
+                GasCategory::Other
+            };
+            (category, &fee.amount)
+        }
+        tx => (GasCategory::Transfer, tx.fee()),
+    };
+    if gas.amount().is_zero() || !is_gas(tx.exchange().map(String::as_str), gas) {
+        return None;
+    }
+    Some((category, gas))
+}
+
+/// Combines the gas of approvals and other contract interactions which don't move any value into
+/// a fee per day for each account and category, so thousands of them don't each have a row.
+/// Transfers, swaps and failed transactions are left as they are.
+pub fn combine_by_day<'a>(transactions: Vec<Transaction<'a>>) -> Vec<Transaction<'a>> {
+    let mut days: BTreeMap<_, (Movement<'a>, usize)> = BTreeMap::new();
+    let mut others = Vec::new();
+    for tx in transactions {
+        let combine = match gas(&tx) {
+            Some((category, _)) if matches!(tx, Transaction::Fee(_)) => {
+                Some(category).filter(|c| matches!(c, GasCategory::Approval | GasCategory::Other))
+            }
+            _ => None,
+        };
+        let (category, fee) = match (combine, tx) {
+            (Some(category), Transaction::Fee(fee)) => (category, fee),
+            (_, tx) => {
+                others.push(tx);
+                continue;
+            }
+        };
+        let key = (
+            fee.date_time.date(),
+            fee.exchange.clone(),
+            category,
+            fee.amount.currency().code,
+        );
+        match days.get_mut(&key) {
+            Some((combined, count)) => {
+                combined.amount = combined.amount.clone() + fee.amount;
+                combined.date_time = combined.date_time.max(fee.date_time);
+                *count += 1;
+            }
+            None => {
+                days.insert(key, (fee, 1));
+            }
+        }
+    }
+    let mut combined = days
+        .into_iter()
+        .map(|((_, _, category, _), (mut fee, count))| {
+            if count > 1 {
+                let label = match category {
+                    GasCategory::Approval => "Approvals",
+                    _ => "Contract interactions",
+                };
+                fee.notes = Some(format!("{}: {} combined", label, count));
+            }
+            Transaction::Fee(fee)
+        })
+        .collect::<Vec<_>>();
+    tracing::info!(
+        "Combined the gas of contract interactions into {} fees",
+        combined.len()
+    );
+    combined.append(&mut others);
+    combined.sort_by_key(|tx| tx.date_time());
+    combined
+}
+
+#[derive(Serialize)]
+struct GasRecord {
+    tax_year: cgt::TaxYear,
+    category: String,
+    asset: String,
+    transactions: usize,
+    gas: String,
+    gbp_value: String,
+    /// Whether the gas was added to the cost of the acquisitions, instead of being deducted as a
+    /// cost of the disposals
+    capitalised: bool,
+}
+
+/// Writes the gas paid within the period as csv, totalled for each tax year and category
+pub fn write_gas<'a, W>(
+    report: &TaxReport<'a>,
+    prices: &'a Prices<'a>,
+    options: &Options,
+    period: Option<&ReportPeriod>,
+    writer: W,
+) -> color_eyre::Result<()>
+where
+    W: Write,
+{
+    let mut totals = BTreeMap::new();
+    for tx in report.transactions.iter() {
+        let (category, gas) = match gas(tx) {
+            Some(gas) => gas,
+            None => continue,
+        };
+        let date_time = tx.date_time();
+        if !period.map_or(true, |p| p.contains(date_time, &options.year_start)) {
+            continue;
+        }
+        let pair = CurrencyPair {
+            base: gas.currency(),
+            quote: GBP,
+        };
+        let price = prices.get(pair, date_time.date()).ok_or(eyre::eyre!(
+            "Should have price for gas: {} at {}",
+            gas,
+            date_time
+        ))?;
+        let gbp_value = Money::from_decimal(*gas.amount() * price.rate, GBP);
+        let key = (
+            options.year_start.tax_year(date_time),
+            category,
+            gas.currency().code,
+        );
+        let (count, total_gas, total_value) = totals.entry(key).or_insert((
+            0,
+            Money::from_major(0, gas.currency()),
+            Money::from_major(0, GBP),
+        ));
+        *count += 1;
+        *total_gas = total_gas.clone() + gas.clone();
+        *total_value = total_value.clone() + gbp_value;
+    }
+
+    let records = totals
+        .into_iter()
+        .map(
+            |((tax_year, category, asset), (transactions, gas, gbp_value))| GasRecord {
+                tax_year,
+                category: category.to_string(),
+                asset: asset.to_string(),
+                transactions,
+                gas: display_amount(&gas),
+                gbp_value: display_amount(&gbp_value),
+                capitalised: options.capitalise_swap_gas && category == GasCategory::Swap,
+            },
+        )
+        .collect::<Vec<_>>();
+    tracing::info!("Gas {} categories", records.len());
+    crate::utils::write_csv(records, writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn gas_is_totalled_by_year_and_category() {
+        let txs = "\
+version,date_time,kind,buy_asset,buy_amount,sell_asset,sell_amount,fee_asset,fee_amount,rate,exchange,exchange_id,notes,ownership_changed,received_date_time
+5,2018-01-01T12:00:00+00:00,Buy,ETH,10,GBP,5000,GBP,0,500,,,,,
+5,2018-02-01T12:00:00+00:00,Withdrawal,,,ETH,1,ETH,0.01,0,Ethereum,,,,
+5,2018-02-02T12:00:00+00:00,Fee,,,ETH,0.002,ETH,0,0,Ethereum:ledger,,Approve,,
+5,2018-02-03T12:00:00+00:00,Fee,,,ETH,0.003,ETH,0,0,Ethereum,,Approve,,
+5,2018-02-04T12:00:00+00:00,Fee,,,ETH,0.004,ETH,0,0,Ethereum,,Failed: Swap Exact ETH For Tokens,,
+5,2018-03-01T12:00:00+00:00,Withdrawal,,,ETH,1,ETH,0.01,0,Binance,,,,
+";
+        let prices = "\
+base_currency,quote_currency,date_time,rate
+ETH,GBP,2018-02-01T00:00:00+00:00,500
+ETH,GBP,2018-02-02T00:00:00+00:00,500
+ETH,GBP,2018-02-03T00:00:00+00:00,1000
+ETH,GBP,2018-02-04T00:00:00+00:00,1000
+";
+        let transactions = transactions::read_csv(txs.as_bytes()).unwrap();
+        let prices = Prices::read_csv(prices.as_bytes()).unwrap();
+        let options = Options::default();
+        let report = cgt::calculate(transactions, &prices, &options).unwrap();
+
+        let mut csv = Vec::new();
+        write_gas(&report, &prices, &options, None, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let rows = csv
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+
+        assert_eq!(rows.len(), 3, "Binance withdrawal fee is not gas");
+        assert_eq!(rows[0][..4], ["2018", "transfer", "ETH", "1"]);
+        assert_eq!(rows[0][5..], ["5.00", "false"]);
+        assert_eq!(rows[1][..4], ["2018", "approval", "ETH", "2"]);
+        assert_eq!(rows[1][5..], ["4.00", "false"]);
+        assert_eq!(rows[2][..4], ["2018", "failed", "ETH", "1"]);
+        assert_eq!(rows[2][5..], ["4.00", "false"]);
+    }
+
+    #[test]
+    fn contract_interactions_are_combined_by_day() {
+        let txs = "\
+version,date_time,kind,buy_asset,buy_amount,sell_asset,sell_amount,fee_asset,fee_amount,rate,exchange,exchange_id,notes,ownership_changed,received_date_time
+5,2018-02-02T09:00:00+00:00,Fee,,,ETH,0.002,ETH,0,0,Ethereum,,Approve; Tx 0x01,,
+5,2018-02-02T10:00:00+00:00,Fee,,,ETH,0.003,ETH,0,0,Ethereum,,Approve; Tx 0x02,,
+5,2018-02-02T11:00:00+00:00,Fee,,,ETH,0.001,ETH,0,0,Ethereum,,Claim; Tx 0x03,,
+5,2018-02-02T12:00:00+00:00,Fee,,,ETH,0.004,ETH,0,0,Ethereum,,Failed: Approve; Tx 0x04,,
+5,2018-02-02T13:00:00+00:00,Withdrawal,,,ETH,1,ETH,0.01,0,Ethereum,,Transfer; Tx 0x05,,
+5,2018-02-03T09:00:00+00:00,Fee,,,ETH,0.002,ETH,0,0,Ethereum,,Approve; Tx 0x06,,
+5,2018-02-03T10:00:00+00:00,Fee,,,BTC,0.0001,BTC,0,0,NiceHash,,Withdrawal fee,,
+";
+        let transactions = transactions::read_csv(txs.as_bytes()).unwrap();
+        let combined = combine_by_day(transactions);
+
+        let summary = combined
+            .iter()
+            .map(|tx| {
+                let (category, gas) = gas(tx).map_or((None, None), |(c, g)| (Some(c), Some(g)));
+                (
+                    tx.date_time().to_string(),
+                    category,
+                    gas.map(|g| *g.amount()),
+                )
+            })
+            .collect::<Vec<_>>();
+        let at = |date_time: &str| date_time.to_string();
+        assert_eq!(
+            summary,
+            vec![
+                (
+                    at("2018-02-02 10:00:00"),
+                    Some(GasCategory::Approval),
+                    Some(dec!(0.005))
+                ),
+                (
+                    at("2018-02-02 11:00:00"),
+                    Some(GasCategory::Other),
+                    Some(dec!(0.001))
+                ),
+                (
+                    at("2018-02-02 12:00:00"),
+                    Some(GasCategory::Failed),
+                    Some(dec!(0.004))
+                ),
+                (
+                    at("2018-02-02 13:00:00"),
+                    Some(GasCategory::Transfer),
+                    Some(dec!(0.01))
+                ),
+                (
+                    at("2018-02-03 09:00:00"),
+                    Some(GasCategory::Approval),
+                    Some(dec!(0.002))
+                ),
+                (at("2018-02-03 10:00:00"), None, None),
+            ]
+        );
+    }
+}
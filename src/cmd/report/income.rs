@@ -0,0 +1,211 @@
+//! Income received in crypto e.g. staking rewards, mining payouts, airdrops and lending interest,
+//! valued in GBP at the price of the day it was received. The same value is the cost of the
+//! acquisition added to the Section 104 pool, so it isn't taxed again as a gain when disposed of.
+//! The kind of income is recognised from the exchange and notes of the transaction. Income from
+//! derivatives is in the derivatives report instead.
+
+use super::{
+    cgt::{self, Options, ReportPeriod, TaxReport},
+    derivatives,
+};
+use crate::{
+    cmd::prices::Prices,
+    currencies::GBP,
+    money::display_amount,
+    transactions::{Movement, Transaction, TransactionKind},
+    Money,
+};
+use serde::Serialize;
+use std::{collections::BTreeMap, io::Write};
+
+/// What the income was received for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, derive_more::Display)]
+pub enum IncomeKind {
+    #[display(fmt = "staking")]
+    Staking,
+    #[display(fmt = "mining")]
+    Mining,
+    #[display(fmt = "airdrop")]
+    Airdrop,
+    /// Interest on lending or savings
+    #[display(fmt = "interest")]
+    Interest,
+    #[display(fmt = "other")]
+    Other,
+}
+
+/// Words in the exchange or notes of income of each kind, checked in order
+const KEYWORDS: &[(&str, IncomeKind)] = &[
+    ("mining", IncomeKind::Mining),
+    ("ethermine", IncomeKind::Mining),
+    ("nicehash", IncomeKind::Mining),
+    ("interest", IncomeKind::Interest),
+    ("lending", IncomeKind::Interest),
+    ("savings", IncomeKind::Interest),
+    ("staking", IncomeKind::Staking),
+    ("reward", IncomeKind::Staking),
+];
+
+/// Income received, with its value in GBP when received
+pub struct IncomeEvent<'a> {
+    pub kind: IncomeKind,
+    pub movement: &'a Movement<'a>,
+    /// Also the cost of the acquisition of the income
+    pub gbp_value: Money<'a>,
+}
+
+impl IncomeKind {
+    fn of(tx: &Transaction, movement: &Movement) -> Self {
+        if tx.kind() == TransactionKind::Airdrop {
+            return IncomeKind::Airdrop;
+        }
+        let text = format!(
+            "{} {}",
+            movement.exchange.as_deref().unwrap_or_default(),
+            movement.notes.as_deref().unwrap_or_default()
+        )
+        .to_lowercase();
+        KEYWORDS
+            .iter()
+            .find(|(keyword, _)| text.contains(keyword))
+            .map_or(IncomeKind::Other, |(_, kind)| *kind)
+    }
+}
+
+/// The income and airdrops received, other than from derivatives, valued at the price of the day
+/// as they are when added to the pools
+pub fn income_events<'a>(
+    report: &'a TaxReport<'a>,
+    prices: &'a Prices<'a>,
+) -> color_eyre::Result<Vec<IncomeEvent<'a>>> {
+    report
+        .transactions
+        .iter()
+        .filter(|tx| derivatives::derivative(tx).is_none())
+        .filter_map(|tx| match tx {
+            Transaction::Income(movement) | Transaction::Airdrop(movement) => Some((tx, movement)),
+            _ => None,
+        })
+        .map(|(tx, movement)| {
+            let gbp_value = if movement.amount.currency() == GBP {
+                movement.amount.clone()
+            } else {
+                let rate = cgt::market_rate(tx.kind(), movement, prices)?;
+                Money::from_decimal(*movement.amount.amount() * rate, GBP)
+            };
+            Ok(IncomeEvent {
+                kind: IncomeKind::of(tx, movement),
+                movement,
+                gbp_value,
+            })
+        })
+        .collect()
+}
+
+#[derive(Serialize)]
+struct IncomeRecord {
+    date_time: String,
+    tax_year: cgt::TaxYear,
+    kind: String,
+    asset: String,
+    amount: String,
+    gbp_value: String,
+    exchange: String,
+    notes: String,
+}
+
+/// Writes the income received within the period as csv, logging the total of each kind
+pub fn write_income<'a, W>(
+    report: &'a TaxReport<'a>,
+    prices: &'a Prices<'a>,
+    options: &Options,
+    period: Option<&ReportPeriod>,
+    writer: W,
+) -> color_eyre::Result<()>
+where
+    W: Write,
+{
+    let mut totals = BTreeMap::new();
+    let records = income_events(report, prices)?
+        .into_iter()
+        .filter(|income| {
+            period.map_or(true, |p| {
+                p.contains(income.movement.date_time, &options.year_start)
+            })
+        })
+        .map(|income| {
+            let total = totals
+                .entry(income.kind)
+                .or_insert_with(|| Money::from_major(0, GBP));
+            *total = total.clone() + income.gbp_value.clone();
+            let movement = income.movement;
+            IncomeRecord {
+                date_time: movement.date_time.to_string(),
+                tax_year: options.year_start.tax_year(movement.date_time),
+                kind: income.kind.to_string(),
+                asset: movement.amount.currency().code.to_string(),
+                amount: display_amount(&movement.amount),
+                gbp_value: display_amount(&income.gbp_value),
+                exchange: movement.exchange.clone().unwrap_or_default(),
+                notes: movement.notes.clone().unwrap_or_default(),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    tracing::info!("Income {}", records.len());
+    for (kind, total) in totals {
+        tracing::info!("Total {} income {}", kind, total);
+    }
+    crate::utils::write_csv(records, writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transactions;
+
+    #[test]
+    fn income_is_valued_when_received_and_is_the_cost_in_the_pool() {
+        let txs = "\
+version,date_time,kind,buy_asset,buy_amount,sell_asset,sell_amount,fee_asset,fee_amount,rate,exchange,exchange_id,notes,ownership_changed,received_date_time
+5,2021-05-01T09:00:00+00:00,Income,ETH,0.2,,,ETH,0,0,Kraken,,Staking reward,,
+5,2021-05-02T09:00:00+00:00,Income,ETH,0.1,,,ETH,0,0,Aave,,interest,,
+5,2021-05-02T10:00:00+00:00,Airdrop,DOT,10,,,DOT,0,0,,,,,
+5,2021-05-03T11:00:00+00:00,Income,ETH,0.2,,,ETH,0,0,Deribit,,Option premium open sell 1 ETH-25JUN21-3000-P,,
+";
+        let prices = "\
+base_currency,quote_currency,date_time,rate
+ETH,GBP,2021-05-01T00:00:00+00:00,2000
+ETH,GBP,2021-05-02T00:00:00+00:00,2500
+ETH,GBP,2021-05-03T00:00:00+00:00,2500
+DOT,GBP,2021-05-02T00:00:00+00:00,20
+";
+        let transactions = transactions::read_csv(txs.as_bytes()).unwrap();
+        let prices = Prices::read_csv(prices.as_bytes()).unwrap();
+        let options = Options::default();
+        let report = cgt::calculate(transactions, &prices, &options).unwrap();
+
+        let mut csv = Vec::new();
+        write_income(&report, &prices, &options, None, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let rows = csv
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+
+        assert_eq!(rows.len(), 3, "Derivative income is left out");
+        assert_eq!(rows[0][2..4], ["staking", "ETH"]);
+        assert_eq!(rows[0][5], "400.00");
+        assert_eq!(rows[1][2..4], ["interest", "ETH"]);
+        assert_eq!(rows[2][2..4], ["airdrop", "DOT"]);
+        assert_eq!(rows[2][5], "200.00");
+
+        let eth = report.pools.get("ETH").expect("ETH should have a Pool");
+        assert_eq!(
+            *eth.costs().amount(),
+            rust_decimal_macros::dec!(1150),
+            "The staking, interest and option premium are all acquisitions at their value"
+        );
+    }
+}
@@ -0,0 +1,189 @@
+//! Invariants which should hold for any tax report whatever the trades, asserted after every
+//! calculation in debug builds and usable by property tests driving random trade sequences.
+
+use super::cgt::{MatchingRule, TaxReport};
+use crate::{currencies::GBP, money::display_amount, Money};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+#[derive(Debug, Clone, PartialEq, derive_more::Display)]
+pub enum Violation {
+    /// The costs remaining in a pool differ from those acquired less those allocated to disposals
+    #[display(
+        fmt = "{} pool costs {} should equal acquisition costs {} less allocated costs {}",
+        currency,
+        costs,
+        acquisition_costs,
+        allocated_costs
+    )]
+    PoolCosts {
+        currency: String,
+        costs: String,
+        acquisition_costs: String,
+        allocated_costs: String,
+    },
+    /// The gain, costs of the matched acquisitions and fee of a disposal don't add up to the
+    /// value of the asset disposed of
+    #[display(
+        fmt = "Event #{} gain {} plus matched costs {} and fee {} should equal value {}",
+        id,
+        gain,
+        allowable_costs,
+        fee,
+        proceeds
+    )]
+    Proceeds {
+        id: usize,
+        gain: String,
+        allowable_costs: String,
+        fee: String,
+        proceeds: String,
+    },
+    /// A pool holds a negative amount or negative costs, either after an event or at the end
+    #[display(
+        fmt = "{} pool is negative with total {} and costs {}{}",
+        currency,
+        total,
+        costs,
+        "event.map_or(String::new(), |id| format!(\" after event #{}\", id))"
+    )]
+    NegativePool {
+        currency: String,
+        event: Option<usize>,
+        total: String,
+        costs: String,
+    },
+}
+
+/// Checks the invariants of the report, returning any which were violated
+pub fn check(report: &TaxReport) -> Vec<Violation> {
+    let events = report.gains(None).gains;
+    let mut violations = Vec::new();
+
+    for (code, pool) in report.pools.iter() {
+        let allocated_costs = events
+            .iter()
+            .filter(|event| event.trade().sell.currency().code == code.as_str())
+            .flat_map(|event| event.matches().iter())
+            .filter(|matched| matched.rule() == MatchingRule::Pool)
+            .fold(Money::from_major(0, GBP), |acc, matched| {
+                acc + matched.costs().clone()
+            });
+        let expected = pool.acquisition_costs().clone() - allocated_costs.clone();
+        if !approx_eq(pool.costs(), &expected) {
+            violations.push(Violation::PoolCosts {
+                currency: code.clone(),
+                costs: display_amount(pool.costs()),
+                acquisition_costs: display_amount(pool.acquisition_costs()),
+                allocated_costs: display_amount(&allocated_costs),
+            })
+        }
+    }
+
+    for event in events.iter().filter(|event| event.is_disposal()) {
+        let matched_costs = event
+            .matches()
+            .iter()
+            .fold(Money::from_major(0, GBP), |acc, matched| {
+                acc + matched.costs().clone()
+            });
+        let total = event.gain() + matched_costs.clone() + event.fee().clone();
+        if !approx_eq(&total, event.sell_value()) {
+            violations.push(Violation::Proceeds {
+                id: event.id(),
+                gain: display_amount(&event.gain()),
+                allowable_costs: display_amount(&matched_costs),
+                fee: display_amount(event.fee()),
+                proceeds: display_amount(event.sell_value()),
+            })
+        }
+    }
+
+    let pools = events
+        .iter()
+        .flat_map(|event| event.pools().map(move |pool| (Some(event.id()), pool)))
+        .chain(report.pools.values().map(|pool| (None, pool)));
+    for (event, pool) in pools {
+        if pool.total().amount().is_sign_negative() || *pool.costs().amount() < -tolerance() {
+            violations.push(Violation::NegativePool {
+                currency: pool.currency().code.to_string(),
+                event,
+                total: display_amount(pool.total()),
+                costs: display_amount(pool.costs()),
+            })
+        }
+    }
+
+    violations
+}
+
+/// Rounding differences below a penny are not violations
+fn tolerance() -> Decimal {
+    dec!(0.01)
+}
+
+fn approx_eq(left: &Money, right: &Money) -> bool {
+    (left.amount() - right.amount()).abs() <= tolerance()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cmd::{
+            prices::Prices,
+            report::cgt::{self, Options},
+        },
+        money::amount,
+        trades::{TradeBuilder, TradeKind},
+        transactions::Transaction,
+    };
+    use chrono::NaiveDate;
+
+    /// xorshift, so random trade sequences are reproducible from the seed without a dependency
+    struct Random(u64);
+
+    impl Random {
+        fn next(&mut self, max: u64) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % max
+        }
+    }
+
+    fn random_trades<'a>(random: &mut Random, count: usize) -> Vec<Transaction<'a>> {
+        let start = NaiveDate::from_ymd(2018, 1, 1).and_hms(12, 0, 0);
+        (0..count)
+            .map(|_| {
+                let date_time = start + chrono::Duration::hours(random.next(24 * 365) as i64);
+                let asset = ["BTC", "ETH"][random.next(2) as usize];
+                let base = amount(asset, Decimal::new(random.next(10_000) as i64 + 1, 3)).unwrap();
+                let quote =
+                    amount("GBP", Decimal::new(random.next(1_000_000) as i64 + 1, 2)).unwrap();
+                let (kind, buy, sell) = if random.next(2) == 0 {
+                    (TradeKind::Buy, base, quote)
+                } else {
+                    (TradeKind::Sell, quote, base)
+                };
+                TradeBuilder::new(date_time, kind, buy, sell)
+                    .build()
+                    .expect("Random trade should be valid")
+                    .into()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn random_trade_sequences_hold_invariants() {
+        let prices = Prices::default();
+        for seed in 1..=50 {
+            let mut random = Random(seed * 7919);
+            let count = random.next(40) as usize + 1;
+            let trades = random_trades(&mut random, count);
+            let report = cgt::calculate(trades, &prices, &Options::default()).unwrap();
+
+            assert_eq!(check(&report), Vec::new(), "Seed {}", seed);
+        }
+    }
+}
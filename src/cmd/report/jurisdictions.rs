@@ -0,0 +1,130 @@
+//! Gains grouped by where the exchange is based, for completing the foreign pages of a return or
+//! assessing reporting obligations for assets held abroad.
+
+use super::cgt::Gains;
+use crate::{money::display_amount, transactions};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    io::{Read, Write},
+};
+
+/// The label for exchanges without a jurisdiction, and transactions without an exchange
+const UNKNOWN: &str = "Unknown";
+
+/// exchange,jurisdiction
+#[derive(Deserialize)]
+struct Record {
+    /// An exchange or account, matched as by the account filter e.g. binance or binance:personal
+    exchange: String,
+    /// e.g. a country code such as GB or MT
+    jurisdiction: String,
+}
+
+/// The jurisdictions where exchanges are based
+pub struct Jurisdictions {
+    exchanges: Vec<Record>,
+}
+
+impl Jurisdictions {
+    pub fn read_csv<R>(reader: R) -> color_eyre::Result<Self>
+    where
+        R: Read,
+    {
+        let mut rdr = csv::Reader::from_reader(reader);
+        let exchanges = rdr.deserialize().collect::<Result<Vec<Record>, _>>()?;
+        Ok(Jurisdictions { exchanges })
+    }
+
+    /// The jurisdiction of the exchange or account, preferring the most specific match so an
+    /// account can be based somewhere other than the rest of the exchange
+    fn of(&self, exchange: Option<&str>, account: Option<&str>) -> &str {
+        self.exchanges
+            .iter()
+            .filter(|record| transactions::venue_matches(exchange, account, &record.exchange))
+            .max_by_key(|record| record.exchange.len())
+            .map_or(UNKNOWN, |record| record.jurisdiction.as_str())
+    }
+}
+
+#[derive(Serialize)]
+struct JurisdictionSummaryRecord {
+    jurisdiction: String,
+    exchanges: String,
+    disposals: usize,
+    proceeds: String,
+    allowable_costs: String,
+    gains: String,
+    losses: String,
+    chargeable_gains: String,
+}
+
+/// Writes the totals of the disposals on the exchanges in each jurisdiction as csv
+pub fn write_by_jurisdiction<W>(
+    gains: &Gains,
+    jurisdictions: &Jurisdictions,
+    writer: W,
+) -> color_eyre::Result<()>
+where
+    W: Write,
+{
+    let mut grouped = BTreeMap::new();
+    for disposal in gains.disposals() {
+        let trade = disposal.trade();
+        grouped
+            .entry(jurisdictions.of(trade.exchange.as_deref(), trade.account.as_deref()))
+            .or_insert_with(Vec::new)
+            .push(disposal.clone());
+    }
+
+    let records = grouped
+        .into_iter()
+        .map(|(jurisdiction, events)| {
+            let mut exchanges = events
+                .iter()
+                .map(|event| event.trade().exchange.clone().unwrap_or_default())
+                .filter(|exchange| exchange != "")
+                .collect::<Vec<_>>();
+            exchanges.sort();
+            exchanges.dedup();
+            let gains = Gains {
+                year: gains.year,
+                gains: events,
+            };
+            JurisdictionSummaryRecord {
+                jurisdiction: jurisdiction.to_string(),
+                exchanges: exchanges.join("; "),
+                disposals: gains.disposal_count(),
+                proceeds: display_amount(&gains.total_proceeds()),
+                allowable_costs: display_amount(&gains.total_allowable_costs()),
+                gains: display_amount(&gains.total_gains()),
+                losses: display_amount(&gains.total_losses()),
+                chargeable_gains: display_amount(&gains.total_gain()),
+            }
+        })
+        .collect();
+    crate::utils::write_csv(records, writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn most_specific_exchange_jurisdiction_applies() {
+        let csv = "\
+exchange,jurisdiction
+binance,MT
+binance:us,US
+coinbase,GB
+";
+        let jurisdictions = Jurisdictions::read_csv(csv.as_bytes()).unwrap();
+
+        let binance = Some("Binance");
+        assert_eq!(jurisdictions.of(binance, None), "MT");
+        assert_eq!(jurisdictions.of(binance, Some("binance:personal")), "MT");
+        assert_eq!(jurisdictions.of(binance, Some("binance:us")), "US");
+        assert_eq!(jurisdictions.of(Some("kraken"), None), UNKNOWN);
+        assert_eq!(jurisdictions.of(None, None), UNKNOWN);
+    }
+}
@@ -0,0 +1,318 @@
+//! One page summary letters per tax year, for accountants producing client packs. Written as a
+//! minimal PDF using the standard Helvetica fonts, so no font files need to be embedded.
+
+use super::{
+    cgt::{TaxReport, TaxYear},
+    locale::Locale,
+    rates::TaxRates,
+};
+use crate::{currencies::GBP, Money};
+use chrono::NaiveDate;
+use std::io::Write;
+
+/// A4 in points
+pub(super) const PAGE_WIDTH: u32 = 595;
+pub(super) const PAGE_HEIGHT: u32 = 842;
+const MARGIN: u32 = 72;
+
+/// The resource names of the standard fonts used by the pages, regular then bold
+pub(super) const FONTS: [(&str, &str); 2] = [("F1", "Helvetica"), ("F2", "Helvetica-Bold")];
+
+/// The sender and recipient of the summary letters
+pub struct Letter {
+    /// Lines of the letterhead e.g. the name and address of the practice, the first is in bold
+    pub letterhead: Vec<String>,
+    pub client: String,
+    pub date: NaiveDate,
+    pub locale: Locale,
+    /// The annual exempt amounts and rates for estimating the tax due
+    pub rates: TaxRates,
+}
+
+impl Letter {
+    /// Reads the letterhead from a text file, one line per line of the letterhead
+    pub fn read_letterhead<R>(mut reader: R) -> color_eyre::Result<Vec<String>>
+    where
+        R: std::io::Read,
+    {
+        let mut letterhead = String::new();
+        reader.read_to_string(&mut letterhead)?;
+        Ok(letterhead
+            .lines()
+            .map(|line| line.trim_end().to_string())
+            .collect())
+    }
+
+    /// Writes a PDF with a page summarising the gains of each of the tax years
+    pub fn write_pdf<W>(
+        &self,
+        report: &TaxReport,
+        years: &[TaxYear],
+        writer: W,
+    ) -> color_eyre::Result<()>
+    where
+        W: Write,
+    {
+        let pages = years
+            .iter()
+            .map(|year| self.page(report, *year))
+            .collect::<Vec<_>>();
+        write_pdf(&pages, writer)
+    }
+
+    /// The content stream of the page summarising the gains of the tax year, using the `FONTS`
+    pub(super) fn page_contents(&self, report: &TaxReport, year: TaxYear) -> String {
+        contents(&self.page(report, year))
+    }
+
+    fn page(&self, report: &TaxReport, year: TaxYear) -> Vec<Text> {
+        let gains = report.gains(Some(year));
+        let money = |money: Money| self.locale.money(&money);
+        let mut page = Page {
+            y: PAGE_HEIGHT - MARGIN,
+            texts: Vec::new(),
+        };
+
+        for (i, letterhead) in self.letterhead.iter().enumerate() {
+            let font = if i == 0 { Font::Bold } else { Font::Regular };
+            page.text(font, 12, MARGIN, letterhead);
+            page.skip(16);
+        }
+        page.skip(32);
+        page.text(Font::Regular, 11, MARGIN, &self.locale.date(self.date));
+        page.skip(16);
+        page.text(
+            Font::Regular,
+            11,
+            MARGIN,
+            &format!("Client: {}", self.client),
+        );
+        page.skip(40);
+        let title = format!(
+            "Capital Gains Tax summary for the tax year {}-{:02}",
+            year.end_year() - 1,
+            year.end_year() % 100
+        );
+        page.text(Font::Bold, 14, MARGIN, &title);
+        page.skip(32);
+
+        let rows = vec![
+            ("Disposals", gains.disposal_count().to_string()),
+            ("Proceeds", money(gains.total_proceeds())),
+            ("Allowable costs", money(gains.total_allowable_costs())),
+            ("Gains", money(gains.total_gains())),
+            ("Losses", money(gains.total_losses())),
+            ("Net gain", money(gains.total_gain())),
+            (
+                "Estimated tax due",
+                money(gains.estimated_liability(&self.rates)),
+            ),
+        ];
+        for (label, value) in rows {
+            page.text(Font::Regular, 11, MARGIN, label);
+            page.text(Font::Bold, 11, MARGIN + 200, &value);
+            page.skip(20);
+        }
+        page.skip(20);
+        if let Some(rate) = self.rates.for_year(year) {
+            let note = format!(
+                "The tax due is an estimate at {}% of the net gain above the {} annual exempt \
+                 amount.",
+                rate.higher_rate,
+                money(Money::from_decimal(rate.annual_exempt_amount, GBP))
+            );
+            page.text(Font::Regular, 9, MARGIN, &note);
+        }
+        page.skip(12);
+        page.text(
+            Font::Regular,
+            9,
+            MARGIN,
+            "It does not account for other income, reliefs or losses brought forward.",
+        );
+        page.texts
+    }
+}
+
+/// Lays out lines of text down the page
+struct Page {
+    y: u32,
+    texts: Vec<Text>,
+}
+
+impl Page {
+    fn text(&mut self, font: Font, size: u32, x: u32, text: &str) {
+        self.texts.push(Text {
+            font,
+            size,
+            x,
+            y: self.y,
+            text: text.to_string(),
+        });
+    }
+
+    fn skip(&mut self, points: u32) {
+        self.y -= points;
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Font {
+    Regular,
+    Bold,
+}
+
+/// A line of text positioned from the bottom left of the page
+struct Text {
+    font: Font,
+    size: u32,
+    x: u32,
+    y: u32,
+    text: String,
+}
+
+fn write_pdf<W>(pages: &[Vec<Text>], mut writer: W) -> color_eyre::Result<()>
+where
+    W: Write,
+{
+    // catalog, pages and the two fonts, followed by a page and its contents for each page
+    let page_id = |i: usize| 5 + 2 * i;
+    let mut objects = vec![
+        "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            (0..pages.len())
+                .map(|i| format!("{} 0 R", page_id(i)))
+                .collect::<Vec<_>>()
+                .join(" "),
+            pages.len()
+        ),
+    ];
+    for (_, base_font) in FONTS.iter() {
+        objects.push(format!(
+            "<< /Type /Font /Subtype /Type1 /BaseFont /{} /Encoding /WinAnsiEncoding >>",
+            base_font
+        ));
+    }
+    for (i, texts) in pages.iter().enumerate() {
+        let contents = contents(texts);
+        objects.push(format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] \
+             /Resources << /Font << /F1 3 0 R /F2 4 0 R >> >> /Contents {} 0 R >>",
+            PAGE_WIDTH,
+            PAGE_HEIGHT,
+            page_id(i) + 1
+        ));
+        objects.push(format!(
+            "<< /Length {} >>\nstream\n{}\nendstream",
+            contents.len(),
+            contents
+        ));
+    }
+
+    let mut pdf = b"%PDF-1.4\n".to_vec();
+    let mut offsets = Vec::new();
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf.extend(format!("{} 0 obj\n{}\nendobj\n", i + 1, object).bytes());
+    }
+    let xref = pdf.len();
+    pdf.extend(format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).bytes());
+    for offset in offsets {
+        pdf.extend(format!("{:010} 00000 n \n", offset).bytes());
+    }
+    pdf.extend(
+        format!(
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+            objects.len() + 1,
+            xref
+        )
+        .bytes(),
+    );
+    writer.write_all(&pdf)?;
+    Ok(())
+}
+
+/// The content stream drawing the texts of a page
+fn contents(texts: &[Text]) -> String {
+    texts
+        .iter()
+        .map(|text| {
+            let (font, _) = match text.font {
+                Font::Regular => FONTS[0],
+                Font::Bold => FONTS[1],
+            };
+            format!(
+                "BT /{} {} Tf {} {} Td ({}) Tj ET",
+                font,
+                text.size,
+                text.x,
+                text.y,
+                escape(&text.text)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Escapes text for a PDF string in the WinAnsi encoding of the standard fonts, which covers the
+/// currency symbols. Other characters outside ASCII are replaced.
+fn escape(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '(' | ')' | '\\' => format!("\\{}", c),
+            '£' => "\\243".to_string(),
+            '€' => "\\200".to_string(),
+            '\u{a0}' => " ".to_string(),
+            c if c.is_ascii() && !c.is_ascii_control() => c.to_string(),
+            _ => "?".to_string(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd::{
+        prices::Prices,
+        report::cgt::{self, Options},
+    };
+    use crate::transactions;
+
+    #[test]
+    fn writes_a_page_per_tax_year() {
+        let txs = "\
+version,date_time,kind,buy_asset,buy_amount,sell_asset,sell_amount,fee_asset,fee_amount,rate,exchange,exchange_id,notes,ownership_changed,received_date_time
+5,2017-01-01T12:00:00+00:00,Buy,BTC,1,GBP,1000,GBP,0,1000,,,,,
+5,2018-01-01T12:00:00+00:00,Sell,GBP,2000,BTC,0.5,GBP,0,4000,,,,,
+5,2019-01-01T12:00:00+00:00,Sell,GBP,3000,BTC,0.5,GBP,0,6000,,,,,
+";
+        let transactions = transactions::read_csv(txs.as_bytes()).unwrap();
+        let prices = Prices::default();
+        let report = cgt::calculate(transactions, &prices, &Options::default()).unwrap();
+        let letter = Letter {
+            letterhead: vec!["Smith & Co (Accountants)".into(), "1 High Street".into()],
+            client: "A Client".into(),
+            date: NaiveDate::from_ymd(2021, 5, 1),
+            locale: Locale::EnGb,
+            rates: TaxRates::embedded(),
+        };
+
+        let mut pdf = Vec::new();
+        letter
+            .write_pdf(&report, &[TaxYear::uk(2018), TaxYear::uk(2019)], &mut pdf)
+            .unwrap();
+        let pdf = String::from_utf8_lossy(&pdf);
+
+        assert!(pdf.starts_with("%PDF-1.4"));
+        assert!(pdf.contains("/Count 2"));
+        assert!(pdf.contains("(Smith & Co \\(Accountants\\))"));
+        assert!(pdf.contains("(Client: A Client)"));
+        assert!(pdf.contains("(Capital Gains Tax summary for the tax year 2017-18)"));
+        assert!(pdf.contains("(\\2431,500.00)"), "Net gain for 2017-18");
+
+        let startxref = pdf.rsplit("startxref\n").next().unwrap();
+        let xref = startxref.lines().next().unwrap().parse::<usize>().unwrap();
+        assert!(pdf[xref..].starts_with("xref"));
+    }
+}
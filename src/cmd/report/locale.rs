@@ -0,0 +1,291 @@
+//! Locale aware formatting of reports for reading, rather than for further processing. The csv
+//! and json formats are always written in their canonical form.
+
+use super::cgt::{Gains, Rounding};
+use crate::{money::display_precision, Money};
+use chrono::NaiveDate;
+use prettytable::{Cell, Row, Table};
+use rust_decimal::Decimal;
+use std::io::Write;
+
+/// The conventions for formatting numbers and dates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Locale {
+    /// 1,234.56 and 31/12/2020
+    EnGb,
+    /// 1,234.56 and 12/31/2020
+    EnUs,
+    /// 1.234,56 and 31.12.2020
+    DeDe,
+    /// 1 234,56 and 31/12/2020
+    FrFr,
+}
+
+impl Default for Locale {
+    fn default() -> Self {
+        Locale::EnGb
+    }
+}
+
+impl std::str::FromStr for Locale {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.replace('_', "-").to_lowercase().as_str() {
+            "en-gb" => Ok(Locale::EnGb),
+            "en-us" => Ok(Locale::EnUs),
+            "de-de" => Ok(Locale::DeDe),
+            "fr-fr" => Ok(Locale::FrFr),
+            l => Err(format!(
+                "Unsupported locale {}, expected en-GB, en-US, de-DE or fr-FR",
+                l
+            )),
+        }
+    }
+}
+
+impl Locale {
+    fn separators(&self) -> (char, char) {
+        match self {
+            Locale::EnGb | Locale::EnUs => (',', '.'),
+            Locale::DeDe => ('.', ','),
+            Locale::FrFr => ('\u{a0}', ','),
+        }
+    }
+
+    /// Formats the date e.g. 31/12/2020
+    pub fn date(&self, date: NaiveDate) -> String {
+        let format = match self {
+            Locale::EnGb | Locale::FrFr => "%d/%m/%Y",
+            Locale::EnUs => "%m/%d/%Y",
+            Locale::DeDe => "%d.%m.%Y",
+        };
+        date.format(format).to_string()
+    }
+
+    /// Formats the number with thousands separators, to the given number of decimal places
+    pub fn number(&self, number: Decimal, decimal_places: u32) -> String {
+        let (thousands, decimal) = self.separators();
+        let rounded = number.abs().round_dp(decimal_places);
+        let formatted = format!("{:.*}", decimal_places as usize, rounded);
+        let mut parts = formatted.splitn(2, '.');
+        let integer = parts.next().unwrap_or_default();
+        let mut grouped = String::new();
+        for (i, digit) in integer.chars().enumerate() {
+            if i > 0 && (integer.len() - i) % 3 == 0 {
+                grouped.push(thousands);
+            }
+            grouped.push(digit);
+        }
+        if let Some(fraction) = parts.next() {
+            grouped.push(decimal);
+            grouped.push_str(fraction);
+        }
+        if number.is_sign_negative() && !number.is_zero() {
+            format!("-{}", grouped)
+        } else {
+            grouped
+        }
+    }
+
+    /// Formats the amount with its currency symbol e.g. £1,234.56, or code for crypto assets, to
+    /// the display precision of the currency
+    pub fn money(&self, money: &Money) -> String {
+        let currency = money.currency();
+        let number = self.number(*money.amount(), display_precision(currency));
+        let (sign, number) = match number.strip_prefix('-') {
+            Some(number) => ("-", number.to_string()),
+            None => ("", number),
+        };
+        match currency.code {
+            "GBP" | "USD" => format!("{}{}{}", sign, currency.symbol, number),
+            "EUR" if *self == Locale::EnGb || *self == Locale::EnUs => {
+                format!("{}{}{}", sign, currency.symbol, number)
+            }
+            "EUR" => format!("{}{}\u{a0}{}", sign, number, currency.symbol),
+            code => format!("{}{} {}", sign, number, code),
+        }
+    }
+
+    /// Formats the amount with its value in another currency e.g. 0.5 BTC (£12,000.00), or just
+    /// the amount if it's already in that currency
+    pub fn money_with_value(&self, money: &Money, value: &Money) -> String {
+        if money.currency().code == value.currency().code {
+            self.money(money)
+        } else {
+            format!("{} ({})", self.money(money), self.money(value))
+        }
+    }
+}
+
+/// Writes the disposals and their totals as a table formatted for the locale, with the figures
+/// rounded. With `values`, the amounts sold and received are shown with their GBP value.
+pub fn write_table<W>(
+    gains: &Gains,
+    locale: Locale,
+    rounding: Rounding,
+    values: bool,
+    mut writer: W,
+) -> color_eyre::Result<()>
+where
+    W: Write,
+{
+    let cells = |values: Vec<String>| Row::new(values.iter().map(|v| Cell::new(v)).collect());
+    let mut titles = vec!["Date".to_string(), "Sold".into()];
+    if values {
+        titles.push("Received".into());
+    }
+    titles.extend(vec![
+        "Proceeds".into(),
+        "Allowable Costs".into(),
+        "Gain".into(),
+    ]);
+    let mut table = Table::new();
+    table.set_titles(cells(titles));
+    for disposal in gains.disposals() {
+        let trade = disposal.trade();
+        let mut row = vec![locale.date(trade.date_time.date())];
+        if values {
+            row.push(locale.money_with_value(&trade.sell, disposal.sell_value()));
+            row.push(locale.money_with_value(&trade.buy, disposal.sell_value()));
+        } else {
+            row.push(locale.money(&trade.sell));
+        }
+        row.extend(vec![
+            locale.money(&rounding.proceeds(disposal.proceeds())),
+            locale.money(&rounding.costs(disposal.allowable_costs())),
+            locale.money(&rounding.gain(disposal.proceeds(), &disposal.gain())),
+        ]);
+        table.add_row(cells(row));
+    }
+    let mut total = vec!["Total".to_string(), String::new()];
+    if values {
+        total.push(String::new());
+    }
+    total.extend(vec![
+        locale.money(&rounding.proceeds(&gains.total_proceeds())),
+        locale.money(&rounding.costs(&gains.total_allowable_costs())),
+        locale.money(&rounding.gain(&gains.total_proceeds(), &gains.total_gain())),
+    ]);
+    table.add_row(cells(total));
+    table.print(&mut writer)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cmd::{prices::Prices, report::cgt},
+        currencies::{BTC, EUR, GBP},
+        trades::{TradeBuilder, TradeKind},
+        transactions::Transaction,
+    };
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn formats_numbers_and_dates_for_the_locale() {
+        let gbp = Money::from_decimal(dec!(-1234567.891), GBP);
+        let eur = Money::from_decimal(dec!(1234.5), EUR);
+        let btc = Money::from_decimal(dec!(1234.5), BTC);
+        let date = NaiveDate::from_ymd(2020, 12, 31);
+
+        assert_eq!(Locale::EnGb.money(&gbp), "-£1,234,567.89");
+        assert_eq!(Locale::DeDe.money(&eur), "1.234,50\u{a0}€");
+        assert_eq!(Locale::EnUs.money(&btc), "1,234.50000000 BTC");
+        assert_eq!(Locale::FrFr.number(dec!(999), 0), "999");
+        assert_eq!(
+            Locale::EnGb.money_with_value(&Money::from_decimal(dec!(0.5), BTC), &gbp),
+            "0.50000000 BTC (-£1,234,567.89)"
+        );
+        assert_eq!(Locale::EnGb.money_with_value(&gbp, &gbp), "-£1,234,567.89");
+
+        assert_eq!(Locale::EnGb.date(date), "31/12/2020");
+        assert_eq!(Locale::EnUs.date(date), "12/31/2020");
+        assert_eq!(Locale::DeDe.date(date), "31.12.2020");
+    }
+
+    #[test]
+    fn amounts_received_are_shown_in_their_own_column_with_values() {
+        let trade = |date, kind, sell, buy, rate| {
+            Transaction::Trade(
+                TradeBuilder::new(date, kind, buy, sell)
+                    .fee(Money::from_decimal(dec!(0), GBP))
+                    .rate(rate)
+                    .build()
+                    .unwrap(),
+            )
+        };
+        let transactions = vec![
+            trade(
+                NaiveDate::from_ymd(2018, 5, 1).and_hms(12, 0, 0),
+                TradeKind::Buy,
+                Money::from_decimal(dec!(100), GBP),
+                Money::from_decimal(dec!(2), BTC),
+                dec!(50),
+            ),
+            trade(
+                NaiveDate::from_ymd(2018, 6, 1).and_hms(12, 0, 0),
+                TradeKind::Sell,
+                Money::from_decimal(dec!(1), BTC),
+                Money::from_decimal(dec!(80), GBP),
+                dec!(80),
+            ),
+        ];
+        let report =
+            cgt::calculate(transactions, &Prices::default(), &cgt::Options::default()).unwrap();
+        let gains = report.gains(Some(cgt::TaxYear::uk(2019)));
+        let table = |values| {
+            let mut output = Vec::new();
+            write_table(&gains, Locale::EnGb, Rounding::Exact, values, &mut output).unwrap();
+            let output = String::from_utf8(output).unwrap();
+            let line = |text| {
+                output
+                    .lines()
+                    .find(|line| line.contains(text))
+                    .unwrap()
+                    .split('|')
+                    .map(str::trim)
+                    .filter(|cell| !cell.is_empty())
+                    .map(String::from)
+                    .collect::<Vec<_>>()
+            };
+            (line("Date"), line("01/06/2018"), line("Total"))
+        };
+
+        let (titles, disposal, total) = table(false);
+        assert_eq!(
+            titles,
+            vec!["Date", "Sold", "Proceeds", "Allowable Costs", "Gain"]
+        );
+        assert_eq!(
+            disposal,
+            vec!["01/06/2018", "1.00000000 BTC", "£80.00", "£50.00", "£30.00"]
+        );
+        assert_eq!(total, vec!["Total", "£80.00", "£50.00", "£30.00"]);
+
+        let (titles, disposal, _) = table(true);
+        assert_eq!(
+            titles,
+            vec![
+                "Date",
+                "Sold",
+                "Received",
+                "Proceeds",
+                "Allowable Costs",
+                "Gain"
+            ]
+        );
+        assert_eq!(
+            disposal,
+            vec![
+                "01/06/2018",
+                "1.00000000 BTC (£80.00)",
+                "£80.00",
+                "£80.00",
+                "£50.00",
+                "£30.00"
+            ]
+        );
+    }
+}
@@ -1,9 +1,34 @@
-use crate::{cmd::prices::Prices, currencies::GBP, trades, Money};
+use crate::{
+    cmd::prices::Prices,
+    currencies::GBP,
+    encryption,
+    money::Instrument,
+    store::Store,
+    transactions::{self, Transaction},
+    warnings, Money,
+};
 use argh::FromArgs;
-use rust_decimal::Decimal;
-use std::{fs::File, io, path::PathBuf};
+use color_eyre::eyre;
+use std::{fs::File, path::PathBuf};
 
-mod cgt;
+mod attribution;
+pub mod cgt;
+pub mod closing_pools;
+mod compare;
+pub mod derivatives;
+mod expenses;
+pub mod gas;
+pub mod income;
+pub mod invariants;
+mod jurisdictions;
+mod letter;
+mod locale;
+mod non_taxable;
+mod periods;
+pub(crate) mod rates;
+mod realtime;
+mod sa108;
+mod timeline;
 
 #[derive(FromArgs, PartialEq, Debug)]
 #[argh(subcommand, name = "report")]
@@ -11,37 +36,522 @@ mod cgt;
 pub struct ReportCommand {
     /// the csv file containing the transactions
     #[argh(option)]
-    txs: PathBuf,
+    txs: Option<PathBuf>,
+    /// the SQLite database to read the transactions from instead of --txs, as imported with
+    /// `import csv --into-db`. The prices are stored in it for later reports, which use them
+    /// unless given --prices or --refresh or they are older than the latest transaction, and
+    /// the disposals are replaced with those calculated.
+    #[argh(option)]
+    from_db: Option<PathBuf>,
     /// optional csv file with prices in GBP for ETH and BTC, instead of fetching from Coingecko.
     #[argh(option)]
     prices: Option<PathBuf>,
-    /// the tax year for which to produce the report
+    /// fetch the prices from Coingecko even if they were already cached today
+    #[argh(switch)]
+    refresh: bool,
+    /// the tax year for which to produce the report e.g. 2020-21, 2021 (the year ending on 5 April
+    /// 2021) or current. Alternatively a date range e.g. 2020-06-01..2020-12-31
+    #[argh(option)]
+    year: Option<cgt::ReportPeriod>,
+    /// when each tax year starts: uk (6 April, the default), calendar, july or a custom start
+    /// date MM-DD
+    #[argh(option, default = "cgt::YearStart::UK")]
+    year_start: cgt::YearStart,
+    /// how to treat transfers flagged as changing beneficial ownership e.g. collateral posted to
+    /// a lender: disposal (the default) or ignore
+    #[argh(option, default = "cgt::OwnershipTransferPolicy::Disposal")]
+    ownership_transfers: cgt::OwnershipTransferPolicy,
+    /// how to value trades with a GBP, EUR or USD leg: consideration (the default) prefers the
+    /// actual amount paid or received, market prefers the external daily price of the asset.
+    /// The basis used for each trade is shown in the price_basis column.
+    #[argh(option, default = "cgt::Valuation::Consideration")]
+    valuation: cgt::Valuation,
+    /// the number of days before or after a trade without a price on its day to use the nearest
+    /// price from, default 0 for only prices on the day. The date of the price used is shown in
+    /// the price_date column.
+    #[argh(option, default = "0")]
+    price_tolerance: u32,
+    /// a token which is worthless from a date e.g. delisted or rug pulled, priced at zero from
+    /// then on e.g. LUNA:2022-05-13. Add :claim to make a negligible value claim, disposing of
+    /// the holding for nil on that date. Can be repeated.
+    #[argh(option)]
+    worthless: Vec<cgt::Worthless>,
+    /// optional csv file of existing Section 104 holdings to start the pools with, with columns
+    /// asset, quantity, total_cost and as_of e.g. when migrating from another tool
+    #[argh(option)]
+    opening_pools: Option<PathBuf>,
+    /// output the Section 104 pools at the end of the --year or date range, or as of today, in
+    /// the format of --opening-pools instead of the disposals e.g. when migrating to another tool
+    #[argh(switch)]
+    closing_pools: bool,
+    /// how to treat disposals of more than is held, when the transactions don't go back far
+    /// enough: zero (the default) assumes a zero cost, exclude leaves them out of the gains. They
+    /// are flagged as unknown in the matched column either way.
+    #[argh(option, default = "cgt::UnknownBasis::ZeroCost")]
+    unknown_basis: cgt::UnknownBasis,
+    /// an accounting period to summarise chargeable gains for e.g. 2020-01-01..2020-12-31, can be
+    /// repeated. Outputs a summary per period instead of the individual disposals.
+    #[argh(option)]
+    period: Vec<periods::AccountingPeriod>,
+    /// only output disposals on this exchange or account e.g. binance or binance:personal. Gains
+    /// are still calculated over all accounts, since pools are shared between them.
+    #[argh(option)]
+    account: Option<String>,
+    /// only output disposals of this kind of asset: crypto or security, for tokenised stocks
+    /// added with `currencies add --isin`, which are taxed under different rules. Each asset is
+    /// pooled separately either way.
+    #[argh(option)]
+    instrument: Option<Instrument>,
+    /// optional csv file of where each exchange is based, with columns exchange and
+    /// jurisdiction. Outputs the gains grouped by jurisdiction instead of the disposals.
+    #[argh(option)]
+    jurisdictions: Option<PathBuf>,
+    /// output a Graphviz timeline of the events instead of the disposals, linking each disposal
+    /// to the acquisitions it was matched against e.g. render with `dot -Tsvg`
+    #[argh(switch)]
+    timeline: bool,
+    /// output data quality warnings about the transactions, instead of the disposals
+    #[argh(switch)]
+    warnings: bool,
+    /// output the allowable costs of each tax year matched by the same day, 30 day and pool
+    /// rules, instead of the disposals
+    #[argh(switch)]
+    attribution: bool,
+    /// output the total gain of each tax year with each of the comma separated methods of
+    /// matching disposals side by side, instead of the disposals: s104 (the UK rules), fifo or
+    /// acb (the average cost) e.g. s104,fifo,acb
+    #[argh(option)]
+    compare: Option<compare::Methods>,
+    /// output the expenses paid in crypto, with their GBP values, instead of the disposals
+    #[argh(switch)]
+    expenses: bool,
+    /// output the staking rewards, mining payouts, airdrops and interest received with their GBP
+    /// value when received, which is also their cost in the pools, instead of the disposals
+    #[argh(switch)]
+    income: bool,
+    /// output the gas paid on EVM chains each tax year with its GBP value, by what it was spent
+    /// on, instead of the disposals
+    #[argh(switch)]
+    gas: bool,
+    /// output the transactions excluded from the gains each tax year, such as internal transfers,
+    /// with their counts, GBP values and why, instead of the disposals
+    #[argh(switch)]
+    non_taxable: bool,
+    /// output the funding payments on perpetual futures each tax year with their GBP value, by
+    /// exchange and asset, instead of the disposals
+    #[argh(switch)]
+    derivatives: bool,
+    /// add the gas paid for swaps on EVM chains to the cost of the tokens acquired, instead of
+    /// deducting it from the gain on the tokens disposed of
+    #[argh(switch)]
+    capitalise_swap_gas: bool,
+    /// how to present the fees paid on disposals: costs (the default) includes them in the
+    /// allowable costs, proceeds deducts them from the proceeds. The gain is the same either way,
+    /// and fees paid on purchases for GBP are always added to the cost of the asset.
+    #[argh(option, default = "cgt::DisposalFees::Costs")]
+    disposal_fees: cgt::DisposalFees,
+    /// output a PDF summary letter with a page for each tax year, or the tax year given by
+    /// --year, instead of the disposals
+    #[argh(switch)]
+    letter: bool,
+    /// output the boxes of the SA108 capital gains summary for the tax year given by --year as
+    /// XFDF, for filling the fields of HMRC's PDF form, instead of the disposals
+    #[argh(switch)]
+    sa108: bool,
+    /// HMRC's SA108 PDF form to fill with the boxes of --sa108, output with the summary letter
+    /// of the year appended as the computation, instead of the XFDF
+    #[argh(option)]
+    sa108_form: Option<PathBuf>,
+    /// optional toml file of the names of the SA108 form fields by box number e.g. 15 = "name",
+    /// since they change between versions of the form. Defaults to box14, box15 etc.
     #[argh(option)]
-    year: Option<i32>,
+    sa108_fields: Option<PathBuf>,
+    /// the name of the client to address the summary letter to
+    #[argh(option, default = "String::new()")]
+    client: String,
+    /// optional text file with the letterhead of the summary letter e.g. the name and address of
+    /// the practice, one line per line
+    #[argh(option)]
+    letterhead: Option<PathBuf>,
+    /// output reports for HMRC's real time Capital Gains Tax service instead of the disposals,
+    /// for each disposal or month
+    #[argh(option)]
+    real_time: Option<realtime::RealTimePeriod>,
+    /// log only the computation path of the trade with this id, as shown in the report output
+    #[argh(option)]
+    trace_trade: Option<usize>,
+    /// optional toml file of annual exempt amounts and rates for estimating the tax due, adding
+    /// to or overriding those built in and any in tax_rates.toml in the config directory
+    #[argh(option)]
+    tax_rates: Option<PathBuf>,
+    /// encrypt the output with the passphrase in the TAXC_PASSPHRASE environment variable
+    #[argh(switch)]
+    encrypt: bool,
+    /// the output format of the report: csv (default), json, or table for reading in a terminal
+    #[argh(option, default = "ReportFormat::Csv")]
+    format: ReportFormat,
+    /// how to round the proceeds, allowable costs and gains in the report: exact (the default)
+    /// or hmrc, to whole pounds with proceeds rounded down and costs up. The json format also
+    /// includes the exact figures.
+    #[argh(option, default = "cgt::Rounding::Exact")]
+    rounding: cgt::Rounding,
+    /// the locale for formatting numbers and dates in the table format: en-GB (default), en-US,
+    /// de-DE or fr-FR. The csv and json formats are unaffected.
+    #[argh(option, default = "locale::Locale::EnGb")]
+    locale: locale::Locale,
+    /// in the table format, show the amounts sold and received in their own currency with their
+    /// GBP value e.g. 0.5 BTC (£12,000.00), for portfolios of many assets
+    #[argh(switch)]
+    values: bool,
+}
+
+#[derive(PartialEq, Debug)]
+pub enum ReportFormat {
+    Csv,
+    Json,
+    Table,
+}
+
+impl std::str::FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            "table" => Ok(Self::Table),
+            f => Err(format!("Unsupported report format {}", f)),
+        }
+    }
 }
 
 impl ReportCommand {
-    pub fn exec(&self) -> color_eyre::Result<()> {
+    pub fn trace_trade(&self) -> Option<usize> {
+        self.trace_trade
+    }
+
+    /// The transactions file, none when reading from a database
+    pub fn txs(&self) -> Option<&std::path::Path> {
+        self.txs.as_deref()
+    }
+
+    /// Whether the report writes the prices and disposals to a database
+    pub fn modifies_data(&self) -> bool {
+        self.from_db.is_some()
+    }
+
+    /// Returns the number of data quality warnings about the transactions
+    pub fn exec(&self) -> color_eyre::Result<usize> {
+        let store = match self.from_db {
+            Some(ref db) => Some(Store::open(db)?),
+            None => None,
+        };
+        let transactions = match (&store, &self.txs) {
+            (Some(store), None) => store.transactions()?,
+            (None, Some(txs)) => transactions::read_csv(encryption::open(txs)?)?,
+            _ => return Err(eyre::eyre!("Either --txs or --from-db is required")),
+        };
+        let warnings = warnings::check(&transactions);
+        let found = warnings.len();
+        if self.warnings {
+            encryption::write_stdout(self.encrypt, |out| match self.format {
+                ReportFormat::Csv | ReportFormat::Table => crate::utils::write_csv(warnings, out),
+                ReportFormat::Json => Ok(serde_json::to_writer_pretty(out, &warnings)?),
+            })?;
+            return Ok(found);
+        }
+        for warning in warnings.iter() {
+            tracing::warn!("{}", warning);
+        }
+        self.report(store, transactions)?;
+        Ok(found)
+    }
+
+    fn report(
+        &self,
+        mut store: Option<Store>,
+        transactions: Vec<Transaction>,
+    ) -> color_eyre::Result<()> {
         // todo: in the future support other quote currencies
         let quote_currency = GBP;
 
-        let trades = trades::read_csv(File::open(&self.txs)?)?;
-        let prices = match self.prices {
-            None => Prices::from_coingecko_api(quote_currency)?,
-            Some(ref path) => Prices::read_csv(File::open(path)?)?,
+        // fetched again when the stored prices are older than the latest transaction
+        let latest = transactions.iter().map(|tx| tx.date_time().date()).max();
+        let stored_prices = match store {
+            Some(ref store) if self.prices.is_none() && !self.refresh => {
+                store.prices()?.filter(|prices| match latest {
+                    Some(latest) if !prices.covers(latest) => {
+                        tracing::info!("The stored prices don't cover {}, fetching them", latest);
+                        false
+                    }
+                    _ => true,
+                })
+            }
+            _ => None,
+        };
+        let mut prices = match (self.prices.as_ref(), stored_prices) {
+            (Some(path), _) => Prices::read_csv(File::open(path)?)?,
+            (None, Some(prices)) => prices,
+            (None, None) => Prices::from_coingecko_api(quote_currency, self.refresh)?,
+        };
+        if let Some(ref mut store) = store {
+            store.insert_prices(&prices)?;
+        }
+        // also marked by the calculation, for the reports which look up prices themselves
+        for worthless in self.worthless.iter() {
+            prices.mark_worthless(worthless.currency, worthless.from);
+        }
+        prices.set_nearest_within(self.price_tolerance);
+        let options = cgt::Options {
+            year_start: self.year_start,
+            ownership_transfers: self.ownership_transfers,
+            valuation: self.valuation,
+            worthless: self.worthless.clone(),
+            opening_pools: match self.opening_pools {
+                None => Vec::new(),
+                Some(ref path) => cgt::OpeningPool::read_csv(encryption::open(path)?)?,
+            },
+            unknown_basis: self.unknown_basis,
+            capitalise_swap_gas: self.capitalise_swap_gas,
+            disposal_fees: self.disposal_fees,
+        };
+        let report = cgt::calculate(transactions, &prices, &options)?;
+        if let Some(ref mut store) = store {
+            store.replace_disposals(&report.gains(None))?;
+        }
+        let rates = rates::TaxRates::load(self.tax_rates.as_deref())?;
+        if !self.period.is_empty() {
+            return encryption::write_stdout(self.encrypt, |out| {
+                periods::write_summaries(&report, &self.period, out)
+            });
+        }
+        if self.attribution {
+            return encryption::write_stdout(self.encrypt, |out| {
+                attribution::write_attribution(&report, out)
+            });
+        }
+        if let Some(ref methods) = self.compare {
+            return encryption::write_stdout(self.encrypt, |out| {
+                compare::write_comparison(
+                    &report,
+                    &options.opening_pools,
+                    methods,
+                    &self.format,
+                    out,
+                )
+            });
+        }
+        if self.closing_pools {
+            let today = chrono::Utc::now().naive_utc();
+            let as_of = match self.year {
+                None | Some(cgt::ReportPeriod::Current) => today.date(),
+                Some(cgt::ReportPeriod::TaxYear(year)) => {
+                    year.with_start(self.year_start).last_day()
+                }
+                Some(cgt::ReportPeriod::DateRange(_, to)) => to,
+            };
+            return encryption::write_stdout(self.encrypt, |out| {
+                closing_pools::write_closing_pools(&report, &options.opening_pools, as_of, out)
+            });
+        }
+        if self.expenses {
+            return encryption::write_stdout(self.encrypt, |out| {
+                expenses::write_expenses(&report, &prices, &options, self.year.as_ref(), out)
+            });
+        }
+        if self.income {
+            return encryption::write_stdout(self.encrypt, |out| {
+                income::write_income(&report, &prices, &options, self.year.as_ref(), out)
+            });
+        }
+        if self.gas {
+            return encryption::write_stdout(self.encrypt, |out| {
+                gas::write_gas(&report, &prices, &options, self.year.as_ref(), out)
+            });
+        }
+        if self.non_taxable {
+            return encryption::write_stdout(self.encrypt, |out| {
+                non_taxable::write_non_taxable(&report, &prices, &options, self.year.as_ref(), out)
+            });
+        }
+        if self.derivatives {
+            return encryption::write_stdout(self.encrypt, |out| {
+                derivatives::write_derivatives(&report, &prices, &options, self.year.as_ref(), out)
+            });
+        }
+        if let Some(period) = self.real_time {
+            return encryption::write_stdout(self.encrypt, |out| {
+                realtime::write_reports(&report, period, out)
+            });
+        }
+        if self.letter {
+            let years = match self.year {
+                None => report.years.keys().cloned().collect(),
+                Some(cgt::ReportPeriod::Current) => {
+                    let today = chrono::Utc::now().naive_utc();
+                    vec![self.year_start.tax_year(today)]
+                }
+                Some(cgt::ReportPeriod::TaxYear(year)) => vec![year.with_start(self.year_start)],
+                Some(cgt::ReportPeriod::DateRange(_, _)) => {
+                    return Err(eyre::eyre!(
+                        "The summary letter is per tax year, not date range"
+                    ))
+                }
+            };
+            let letter = self.summary_letter(rates)?;
+            return encryption::write_stdout(self.encrypt, |out| {
+                letter.write_pdf(&report, &years, out)
+            });
+        }
+        if self.sa108 {
+            let year = match self.year {
+                Some(cgt::ReportPeriod::Current) => {
+                    let today = chrono::Utc::now().naive_utc();
+                    self.year_start.tax_year(today)
+                }
+                Some(cgt::ReportPeriod::TaxYear(year)) => year.with_start(self.year_start),
+                None | Some(cgt::ReportPeriod::DateRange(_, _)) => {
+                    return Err(eyre::eyre!(
+                        "The SA108 is filled for a tax year given by --year"
+                    ))
+                }
+            };
+            let fields = match self.sa108_fields {
+                None => sa108::FormFields::default(),
+                Some(ref path) => sa108::FormFields::parse(&std::fs::read_to_string(path)?)?,
+            };
+            let securities = report
+                .gains(Some(year))
+                .for_instrument(Instrument::Security)
+                .disposal_count();
+            if securities > 0 {
+                tracing::warn!(
+                    "{} disposals of securities are left out of the other property boxes, report \
+                     them in the listed shares and securities section",
+                    securities
+                );
+            }
+            if let Some(ref path) = self.sa108_form {
+                let form = std::fs::read(path)?;
+                let computation = vec![self.summary_letter(rates)?.page_contents(&report, year)];
+                return encryption::write_stdout(self.encrypt, |out| {
+                    sa108::fill_pdf(&form, &report, year, &fields, &computation, out)
+                });
+            }
+            return encryption::write_stdout(self.encrypt, |out| {
+                sa108::write_xfdf(&report, year, &fields, out)
+            });
+        }
+        let gains = match self.year {
+            None => report.gains(None),
+            Some(cgt::ReportPeriod::Current) => {
+                let today = chrono::Utc::now().naive_utc();
+                report.gains(Some(self.year_start.tax_year(today)))
+            }
+            Some(cgt::ReportPeriod::TaxYear(year)) => {
+                report.gains(Some(year.with_start(self.year_start)))
+            }
+            Some(cgt::ReportPeriod::DateRange(from, to)) => report.gains_between(from, to),
         };
-        let report = cgt::calculate(trades, &prices)?;
-        let gains = report.gains(self.year);
+        let gains = match self.account {
+            Some(ref account) => gains.for_account(account),
+            None => gains,
+        };
+        let gains = match self.instrument {
+            Some(instrument) => gains.for_instrument(instrument),
+            None => gains,
+        };
+        if self.timeline {
+            return encryption::write_stdout(self.encrypt, |out| timeline::write_dot(&gains, out));
+        }
+        if let Some(ref path) = self.jurisdictions {
+            let jurisdictions = jurisdictions::Jurisdictions::read_csv(File::open(path)?)?;
+            return encryption::write_stdout(self.encrypt, |out| {
+                jurisdictions::write_by_jurisdiction(&gains, &jurisdictions, out)
+            });
+        }
 
-        let estimated_liability =
-            (gains.total_gain() - Money::from_major(11_300, GBP)) * Decimal::new(20, 2);
+        let estimated_liability = gains.estimated_liability(&rates);
 
-        log::info!("Disposals {}", gains.len());
-        log::info!("Proceeds {}", gains.total_proceeds());
-        log::info!("Allowable Costs {}", gains.total_allowable_costs());
-        log::info!("Gains {}", gains.total_gain());
-        log::info!("Estimated Liability {}", estimated_liability);
+        tracing::info!("Disposals {}", gains.disposal_count());
+        let rounding = self.rounding;
+        tracing::info!("Proceeds {}", rounding.proceeds(&gains.total_proceeds()));
+        tracing::info!(
+            "Allowable Costs {}",
+            rounding.costs(&gains.total_allowable_costs())
+        );
+        tracing::info!(
+            "Gains {}",
+            rounding.gain(&gains.total_proceeds(), &gains.total_gain())
+        );
+        tracing::info!("Estimated Liability {}", estimated_liability);
+        let years = gains
+            .disposals()
+            .map(|disposal| disposal.tax_year())
+            .collect::<std::collections::BTreeSet<_>>();
+        for rate in years.into_iter().filter_map(|year| rates.for_year(year)) {
+            tracing::info!(
+                "Tax rates {}: annual exempt amount {}, basic rate {}%, higher rate {}%",
+                rate.year,
+                rate.annual_exempt_amount,
+                rate.basic_rate,
+                rate.higher_rate
+            );
+        }
+        let unknown_basis = gains.total_unknown_basis();
+        if unknown_basis > Money::from_major(0, GBP) {
+            tracing::warn!(
+                "Disposals worth {} exceed the pools, acquired before the transactions: {}",
+                unknown_basis,
+                match self.unknown_basis {
+                    cgt::UnknownBasis::ZeroCost => "assumed zero cost",
+                    cgt::UnknownBasis::Exclude => "excluded from gains",
+                }
+            );
+        }
+        let approximated = gains
+            .gains
+            .iter()
+            .filter(|event| event.price_date().is_some())
+            .count();
+        if approximated > 0 {
+            tracing::warn!(
+                "{} events are valued with the price of a nearby day, check the price_date column",
+                approximated
+            );
+        }
+        let at_year_boundary = gains
+            .gains
+            .iter()
+            .filter(|event| event.year_boundary().is_some())
+            .count();
+        if at_year_boundary > 0 {
+            tracing::warn!(
+                "{} events are at the edge of their tax year, check the year_boundary column",
+                at_year_boundary
+            );
+        }
+
+        encryption::write_stdout(self.encrypt, |out| match self.format {
+            ReportFormat::Csv => cgt::TaxEvent::write_csv(gains, rounding, out),
+            ReportFormat::Json => cgt::TaxEvent::write_json(gains, rounding, out),
+            ReportFormat::Table => {
+                locale::write_table(&gains, self.locale, rounding, self.values, out)
+            }
+        })
+    }
 
-        cgt::TaxEvent::write_csv(gains, io::stdout())
+    /// The summary letter of the --client and --letterhead
+    fn summary_letter(&self, rates: rates::TaxRates) -> color_eyre::Result<letter::Letter> {
+        Ok(letter::Letter {
+            letterhead: match self.letterhead {
+                None => Vec::new(),
+                Some(ref path) => letter::Letter::read_letterhead(File::open(path)?)?,
+            },
+            client: self.client.clone(),
+            date: chrono::Utc::now().naive_utc().date(),
+            locale: self.locale,
+            rates,
+        })
     }
 }
@@ -0,0 +1,224 @@
+//! Summary of the transactions deliberately excluded from the gains, with why, so users and HMRC
+//! reviewers can see what was left out. These are the transactions the calculation doesn't treat
+//! as a disposal or acquisition: transfers without a change of beneficial ownership and movements
+//! of GBP.
+
+use super::cgt::{self, Options, OwnershipTransferPolicy, ReportPeriod, TaxReport};
+use crate::{
+    cmd::prices::{CurrencyPair, Prices},
+    currencies::GBP,
+    money::display_amount,
+    transactions::Transaction,
+    Money,
+};
+use serde::Serialize;
+use std::{collections::BTreeMap, io::Write};
+
+/// Why a transaction is not taxable
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, derive_more::Display)]
+pub enum NonTaxableReason {
+    /// Between the user's own accounts or wallets
+    #[display(fmt = "internal transfer")]
+    InternalTransfer,
+    /// Of an asset for its wrapped equivalent e.g. ETH for WETH, recorded as transfers
+    #[display(fmt = "wrapping")]
+    Wrapping,
+    /// To or from a spouse or civil partner
+    #[display(fmt = "spouse transfer")]
+    SpouseTransfer,
+    /// Flagged as changing beneficial ownership, when the policy is to ignore those
+    #[display(fmt = "ownership transfer ignored")]
+    OwnershipTransferIgnored,
+    /// Deposits, withdrawals, income and spending of GBP itself
+    #[display(fmt = "GBP movement")]
+    Gbp,
+}
+
+impl NonTaxableReason {
+    fn explanation(&self) -> &'static str {
+        match self {
+            NonTaxableReason::InternalTransfer => {
+                "Beneficial ownership is unchanged, so there is no disposal"
+            }
+            NonTaxableReason::Wrapping => {
+                "Treated as the same asset held in another form, so there is no disposal"
+            }
+            NonTaxableReason::SpouseTransfer => {
+                "Between spouses or civil partners living together, so no gain and no loss"
+            }
+            NonTaxableReason::OwnershipTransferIgnored => {
+                "Excluded by --ownership-transfers ignore, check the terms of the arrangement"
+            }
+            NonTaxableReason::Gbp => "GBP is not a chargeable asset",
+        }
+    }
+}
+
+/// Why a transaction is not taxable, the inverse of `cgt::taxable_trade`. Wrapping and spouse
+/// transfers are transfers with notes mentioning them.
+pub fn non_taxable_reason(tx: &Transaction, options: &Options) -> Option<NonTaxableReason> {
+    let movement = tx.movement()?;
+    let transfer = matches!(tx, Transaction::Deposit(_) | Transaction::Withdrawal(_));
+    if transfer
+        && movement.ownership_changed
+        && options.ownership_transfers == OwnershipTransferPolicy::Disposal
+    {
+        return None;
+    }
+    if movement.amount.currency() == GBP {
+        return Some(NonTaxableReason::Gbp);
+    }
+    if !transfer {
+        return None;
+    }
+    let notes = movement.notes.as_deref().unwrap_or_default().to_lowercase();
+    if notes.contains("spouse") || notes.contains("civil partner") {
+        Some(NonTaxableReason::SpouseTransfer)
+    } else if notes.contains("wrap") {
+        Some(NonTaxableReason::Wrapping)
+    } else if movement.ownership_changed {
+        Some(NonTaxableReason::OwnershipTransferIgnored)
+    } else {
+        Some(NonTaxableReason::InternalTransfer)
+    }
+}
+
+#[derive(Serialize)]
+struct NonTaxableRecord {
+    tax_year: cgt::TaxYear,
+    reason: String,
+    asset: String,
+    transactions: usize,
+    amount: String,
+    gbp_value: String,
+    explanation: String,
+}
+
+/// Writes the non taxable transactions within the period as csv, counted and totalled for each
+/// tax year, reason and asset. The GBP value is at the market price of the day, and left out of
+/// the total when there is no price.
+pub fn write_non_taxable<'a, W>(
+    report: &TaxReport<'a>,
+    prices: &'a Prices<'a>,
+    options: &Options,
+    period: Option<&ReportPeriod>,
+    writer: W,
+) -> color_eyre::Result<()>
+where
+    W: Write,
+{
+    let mut totals = BTreeMap::new();
+    for tx in report.transactions.iter() {
+        let reason = match non_taxable_reason(tx, options) {
+            Some(reason) => reason,
+            None => continue,
+        };
+        let movement = tx
+            .movement()
+            .expect("Non taxable transaction is a movement");
+        let date_time = movement.date_time;
+        if !period.map_or(true, |p| p.contains(date_time, &options.year_start)) {
+            continue;
+        }
+        let asset = movement.amount.currency();
+        let gbp_value = if asset == GBP {
+            Some(movement.amount.clone())
+        } else {
+            let pair = CurrencyPair {
+                base: asset,
+                quote: GBP,
+            };
+            prices
+                .get(pair, date_time.date())
+                .map(|price| Money::from_decimal(*movement.amount.amount() * price.rate, GBP))
+        };
+        if gbp_value.is_none() {
+            tracing::warn!(
+                "No price to value {} {} at {}",
+                reason,
+                movement.amount,
+                date_time
+            );
+        }
+        tracing::debug!("{} {} at {}", reason, movement.amount, date_time);
+        let key = (options.year_start.tax_year(date_time), reason, asset.code);
+        let (count, total_amount, total_value) = totals.entry(key).or_insert((
+            0,
+            Money::from_major(0, asset),
+            Money::from_major(0, GBP),
+        ));
+        *count += 1;
+        *total_amount = total_amount.clone() + movement.amount.clone();
+        if let Some(gbp_value) = gbp_value {
+            *total_value = total_value.clone() + gbp_value;
+        }
+    }
+
+    let records = totals
+        .into_iter()
+        .map(
+            |((tax_year, reason, asset), (transactions, amount, gbp_value))| NonTaxableRecord {
+                tax_year,
+                reason: reason.to_string(),
+                asset: asset.to_string(),
+                transactions,
+                amount: display_amount(&amount),
+                gbp_value: display_amount(&gbp_value),
+                explanation: reason.explanation().to_string(),
+            },
+        )
+        .collect::<Vec<_>>();
+    tracing::info!("Non taxable {} reasons and assets", records.len());
+    crate::utils::write_csv(records, writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transactions;
+
+    #[test]
+    fn excluded_transactions_are_counted_by_reason() {
+        let txs = "\
+version,date_time,kind,buy_asset,buy_amount,sell_asset,sell_amount,fee_asset,fee_amount,rate,exchange,exchange_id,notes,ownership_changed,received_date_time
+5,2018-01-01T12:00:00+00:00,Deposit,GBP,5000,,,GBP,0,0,Coinbase,,,,
+5,2018-01-01T13:00:00+00:00,Buy,ETH,10,GBP,5000,GBP,0,500,Coinbase,,,,
+5,2018-02-01T12:00:00+00:00,Withdrawal,,,ETH,4,ETH,0,0,Coinbase,,To ledger,,
+5,2018-02-01T12:10:00+00:00,Deposit,ETH,4,,,ETH,0,0,Ethereum:ledger,,From Coinbase,,
+5,2018-02-02T12:00:00+00:00,Withdrawal,,,ETH,1,ETH,0,0,Ethereum:ledger,,Wrap ETH,,
+5,2018-02-03T12:00:00+00:00,Withdrawal,,,ETH,1,ETH,0,0,Ethereum:ledger,,To spouse,,
+5,2018-02-04T12:00:00+00:00,Withdrawal,,,ETH,1,ETH,0,0,Ethereum:ledger,,Collateral,true,
+";
+        let prices = "\
+base_currency,quote_currency,date_time,rate
+ETH,GBP,2018-02-01T00:00:00+00:00,500
+ETH,GBP,2018-02-02T00:00:00+00:00,600
+ETH,GBP,2018-02-03T00:00:00+00:00,600
+ETH,GBP,2018-02-04T00:00:00+00:00,600
+";
+        let transactions = transactions::read_csv(txs.as_bytes()).unwrap();
+        let prices = Prices::read_csv(prices.as_bytes()).unwrap();
+        let options = Options::default();
+        let report = cgt::calculate(transactions, &prices, &options).unwrap();
+
+        let mut csv = Vec::new();
+        write_non_taxable(&report, &prices, &options, None, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let rows = csv
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').take(4).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            rows,
+            vec![
+                vec!["2018", "internal transfer", "ETH", "2"],
+                vec!["2018", "wrapping", "ETH", "1"],
+                vec!["2018", "spouse transfer", "ETH", "1"],
+                vec!["2018", "GBP movement", "GBP", "1"],
+            ],
+            "The collateral is a disposal by default, so is taxable"
+        );
+    }
+}
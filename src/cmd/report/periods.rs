@@ -0,0 +1,92 @@
+//! Summaries of chargeable gains per accounting period, for companies holding crypto assets whose
+//! accounting periods don't line up with tax years.
+
+use super::cgt::TaxReport;
+use crate::money::display_amount;
+use chrono::NaiveDate;
+use serde::Serialize;
+use std::io::Write;
+
+/// An accounting period from the `start` to the `end` date inclusive
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountingPeriod {
+    start: NaiveDate,
+    end: NaiveDate,
+}
+
+impl std::str::FromStr for AccountingPeriod {
+    type Err = String;
+
+    /// Parses a period in the format `2020-01-01..2020-12-31`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid period {}, expected e.g. 2020-01-01..2020-12-31", s);
+        let mut parts = s.splitn(2, "..");
+        let mut parse = || {
+            parts
+                .next()
+                .and_then(|d| NaiveDate::parse_from_str(d, "%Y-%m-%d").ok())
+                .ok_or_else(invalid)
+        };
+        let start = parse()?;
+        let end = parse()?;
+        if start > end {
+            return Err(format!("Invalid period {}, {} is after {}", s, start, end));
+        }
+        Ok(AccountingPeriod { start, end })
+    }
+}
+
+#[derive(Serialize)]
+struct PeriodSummaryRecord {
+    period_start: String,
+    period_end: String,
+    disposals: usize,
+    proceeds: String,
+    allowable_costs: String,
+    gains: String,
+    losses: String,
+    chargeable_gains: String,
+}
+
+/// Writes the totals of the disposals within each of the given periods as csv
+pub fn write_summaries<W>(
+    report: &TaxReport,
+    periods: &[AccountingPeriod],
+    writer: W,
+) -> color_eyre::Result<()>
+where
+    W: Write,
+{
+    for (i, period) in periods.iter().enumerate() {
+        if let Some(other) = periods[i + 1..]
+            .iter()
+            .find(|other| other.start <= period.end && period.start <= other.end)
+        {
+            tracing::warn!(
+                "Accounting periods {}..{} and {}..{} overlap, disposals will be counted twice",
+                period.start,
+                period.end,
+                other.start,
+                other.end
+            );
+        }
+    }
+
+    let records = periods
+        .iter()
+        .map(|period| {
+            let gains = report.gains_between(period.start, period.end);
+            PeriodSummaryRecord {
+                period_start: period.start.to_string(),
+                period_end: period.end.to_string(),
+                disposals: gains.disposal_count(),
+                proceeds: display_amount(&gains.total_proceeds()),
+                allowable_costs: display_amount(&gains.total_allowable_costs()),
+                gains: display_amount(&gains.total_gains()),
+                losses: display_amount(&gains.total_losses()),
+                chargeable_gains: display_amount(&gains.total_gain()),
+            }
+        })
+        .collect();
+    crate::utils::write_csv(records, writer)
+}
@@ -0,0 +1,114 @@
+//! The history of the UK annual exempt amount and Capital Gains Tax rates, used to estimate the
+//! tax due. Shipped as data rather than code so the figures for a new tax year can be added
+//! before a release, and so it's clear which were applied.
+
+use super::cgt::TaxYear;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::{collections::BTreeMap, path::Path};
+
+const EMBEDDED: &str = include_str!("rates.toml");
+
+/// The annual exempt amount and rates of a tax year
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct TaxRate {
+    /// The tax year, identified by the calendar year in which it ends
+    pub year: i32,
+    /// In GBP
+    pub annual_exempt_amount: Decimal,
+    /// The percentage on gains within the basic rate band
+    pub basic_rate: Decimal,
+    /// The percentage on gains above the basic rate band
+    pub higher_rate: Decimal,
+}
+
+#[derive(Deserialize)]
+struct RatesFile {
+    years: Vec<TaxRate>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TaxRates {
+    years: BTreeMap<i32, TaxRate>,
+}
+
+impl TaxRates {
+    /// The rates shipped with this release
+    pub fn embedded() -> Self {
+        Self::parse(EMBEDDED).expect("Embedded tax rates should be valid")
+    }
+
+    pub fn parse(toml: &str) -> color_eyre::Result<Self> {
+        let file: RatesFile = toml::from_str(toml)?;
+        let years = file
+            .years
+            .into_iter()
+            .map(|rate| (rate.year, rate))
+            .collect();
+        Ok(TaxRates { years })
+    }
+
+    /// The embedded rates, overridden by any years in `tax_rates.toml` in the config directory,
+    /// which are in turn overridden by those in the given file
+    pub fn load(path: Option<&Path>) -> color_eyre::Result<Self> {
+        let mut rates = Self::embedded();
+        let user = crate::utils::config_dir().map(|dir| dir.join("tax_rates.toml"));
+        for file in user
+            .iter()
+            .filter(|p| p.exists())
+            .map(|p| p.as_path())
+            .chain(path)
+        {
+            tracing::info!("Reading tax rates from {}", file.display());
+            let overrides = Self::parse(&std::fs::read_to_string(file)?)?;
+            rates.years.extend(overrides.years);
+        }
+        Ok(rates)
+    }
+
+    /// The rates of the tax year, or of the latest year before it if it's not in the table yet
+    pub fn for_year(&self, year: TaxYear) -> Option<&TaxRate> {
+        self.years
+            .range(..=year.end_year())
+            .next_back()
+            .map(|(_, rate)| rate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn overrides_replace_embedded_years() {
+        let mut rates = TaxRates::embedded();
+        assert_eq!(
+            rates
+                .for_year(TaxYear::uk(2018))
+                .unwrap()
+                .annual_exempt_amount,
+            dec!(11300)
+        );
+        assert!(rates.for_year(TaxYear::uk(2010)).is_none());
+
+        let overrides = TaxRates::parse(
+            "\
+[[years]]
+year = 2099
+annual_exempt_amount = 1000
+basic_rate = 15
+higher_rate = 25
+",
+        )
+        .unwrap();
+        rates.years.extend(overrides.years);
+
+        assert_eq!(rates.for_year(TaxYear::uk(2100)).unwrap().year, 2099);
+        assert_eq!(
+            rates.for_year(TaxYear::uk(2099)).unwrap().higher_rate,
+            dec!(25)
+        );
+        assert_eq!(rates.for_year(TaxYear::uk(2098)).unwrap().year, 2026);
+    }
+}
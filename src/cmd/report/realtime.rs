@@ -0,0 +1,150 @@
+//! Mini reports of disposals for HMRC's real time Capital Gains Tax service, for those who report
+//! and pay as they go rather than through a Self Assessment return.
+
+use super::cgt::{Gains, TaxEvent, TaxReport, TaxYear};
+use crate::money::display_amount;
+use chrono::{Datelike, NaiveDate};
+use serde::Serialize;
+use std::io::Write;
+
+/// How often to report disposals to the real time service
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RealTimePeriod {
+    /// A report for each disposal
+    Disposal,
+    /// A report of all the disposals in each month, split at the end of the tax year
+    Month,
+}
+
+impl std::str::FromStr for RealTimePeriod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "disposal" => Ok(RealTimePeriod::Disposal),
+            "month" => Ok(RealTimePeriod::Month),
+            p => Err(format!(
+                "Invalid real time period {}, expected disposal or month",
+                p
+            )),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct RealTimeRecord {
+    period: String,
+    tax_year: TaxYear,
+    /// The real time service accepts reports until 31 December after the end of the tax year
+    report_by: String,
+    assets: String,
+    disposals: usize,
+    proceeds: String,
+    allowable_costs: String,
+    gains: String,
+    losses: String,
+    chargeable_gains: String,
+}
+
+/// Writes a report of the disposals in each period as csv
+pub fn write_reports<W>(
+    report: &TaxReport,
+    period: RealTimePeriod,
+    writer: W,
+) -> color_eyre::Result<()>
+where
+    W: Write,
+{
+    // disposals are ordered by date, so each period's are consecutive
+    let mut groups: Vec<(String, Vec<TaxEvent>)> = Vec::new();
+    for disposal in report.gains(None).disposals() {
+        let date = disposal.trade().date_time.date();
+        let key = match period {
+            RealTimePeriod::Disposal => format!("{} #{}", date, disposal.id()),
+            RealTimePeriod::Month => format!("{}-{:02}", date.year(), date.month()),
+        };
+        match groups.last_mut() {
+            Some((last, events)) if *last == key && events[0].tax_year() == disposal.tax_year() => {
+                events.push(disposal.clone())
+            }
+            _ => groups.push((key, vec![disposal.clone()])),
+        }
+    }
+
+    let records = groups
+        .into_iter()
+        .map(|(period, events)| {
+            let tax_year = events[0].tax_year();
+            let mut assets = events
+                .iter()
+                .map(|event| match events.len() {
+                    1 => format!(
+                        "{} {}",
+                        display_amount(&event.trade().sell),
+                        event.trade().sell.currency().code
+                    ),
+                    _ => event.trade().sell.currency().code.to_string(),
+                })
+                .collect::<Vec<_>>();
+            assets.dedup();
+            let gains = Gains {
+                year: Some(tax_year),
+                gains: events,
+            };
+            RealTimeRecord {
+                period,
+                tax_year,
+                report_by: NaiveDate::from_ymd(tax_year.end_year(), 12, 31).to_string(),
+                assets: assets.join("; "),
+                disposals: gains.disposal_count(),
+                proceeds: display_amount(&gains.total_proceeds()),
+                allowable_costs: display_amount(&gains.total_allowable_costs()),
+                gains: display_amount(&gains.total_gains()),
+                losses: display_amount(&gains.total_losses()),
+                chargeable_gains: display_amount(&gains.total_gain()),
+            }
+        })
+        .collect();
+    crate::utils::write_csv(records, writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cmd::{
+            prices::Prices,
+            report::cgt::{self, Options},
+        },
+        transactions,
+    };
+
+    #[test]
+    fn monthly_reports_are_split_at_the_end_of_the_tax_year() {
+        let txs = "\
+version,date_time,kind,buy_asset,buy_amount,sell_asset,sell_amount,fee_asset,fee_amount,rate,exchange,exchange_id,notes,ownership_changed,received_date_time
+5,2017-01-01T12:00:00+00:00,Buy,BTC,1,GBP,1000,GBP,0,1000,,,,,
+5,2018-04-02T12:00:00+00:00,Sell,GBP,200,BTC,0.1,GBP,0,2000,,,,,
+5,2018-04-05T12:00:00+00:00,Sell,GBP,200,BTC,0.1,GBP,0,2000,,,,,
+5,2018-04-20T12:00:00+00:00,Sell,GBP,300,BTC,0.1,GBP,0,3000,,,,,
+";
+        let transactions = transactions::read_csv(txs.as_bytes()).unwrap();
+        let prices = Prices::default();
+        let report = cgt::calculate(transactions, &prices, &Options::default()).unwrap();
+
+        let mut csv = Vec::new();
+        write_reports(&report, RealTimePeriod::Month, &mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let rows = csv
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').take(7).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0][..3], ["2018-04", "2018", "2018-12-31"]);
+        assert_eq!(rows[0][4..], ["2", "400.00", "200.00"]);
+        assert_eq!(rows[1][..3], ["2018-04", "2019", "2019-12-31"]);
+        assert_eq!(rows[1][4..], ["1", "300.00", "100.00"]);
+    }
+}
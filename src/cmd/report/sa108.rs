@@ -0,0 +1,334 @@
+//! The figures of the "Other property, assets and gains" section of the SA108 capital gains
+//! summary, filled in the fields of HMRC's PDF form with the computation appended as extra
+//! pages, or as XFDF for filling the form with another tool e.g.
+//! `pdftk SA108.pdf fill_form sa108.xfdf output SA108-filled.pdf`.
+//!
+//! The names of the form fields change between the versions of the form, so they are read from
+//! a toml file of box numbers to field names, defaulting to `box14` etc.
+
+use super::{
+    cgt::{Rounding, TaxReport, TaxYear},
+    letter,
+};
+use crate::money::Instrument;
+use color_eyre::eyre;
+use lopdf::{dictionary, Document, Object, ObjectId, Stream};
+use serde::Deserialize;
+use std::{collections::BTreeMap, io::Write};
+
+/// The form field names of the boxes, by box number
+#[derive(Debug, Default, Deserialize)]
+pub struct FormFields(BTreeMap<String, String>);
+
+impl FormFields {
+    pub fn parse(toml: &str) -> color_eyre::Result<Self> {
+        Ok(toml::from_str(toml)?)
+    }
+
+    fn name(&self, number: u32) -> String {
+        self.0
+            .get(&number.to_string())
+            .cloned()
+            .unwrap_or_else(|| format!("box{}", number))
+    }
+}
+
+/// The boxes of the SA108 for the year, rounded to whole pounds in the taxpayer's favour. Only
+/// crypto is other property, securities are reported in their own section.
+fn boxes(report: &TaxReport, year: TaxYear) -> Vec<(u32, String)> {
+    let gains = report.gains(Some(year)).for_instrument(Instrument::Crypto);
+    let pounds = |amount: rust_decimal::Decimal| amount.to_string();
+    vec![
+        (14, gains.disposal_count().to_string()),
+        (
+            15,
+            pounds(*Rounding::Hmrc.proceeds(&gains.total_proceeds()).amount()),
+        ),
+        (
+            16,
+            pounds(
+                *Rounding::Hmrc
+                    .costs(&gains.total_allowable_costs())
+                    .amount(),
+            ),
+        ),
+        (17, pounds(gains.total_gains().amount().floor())),
+        (19, pounds(gains.total_losses().amount().ceil())),
+    ]
+}
+
+/// Writes the SA108 boxes of the tax year as XFDF
+pub fn write_xfdf<W>(
+    report: &TaxReport,
+    year: TaxYear,
+    fields: &FormFields,
+    mut writer: W,
+) -> color_eyre::Result<()>
+where
+    W: Write,
+{
+    writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        writer,
+        r#"<xfdf xmlns="http://ns.adobe.com/xfdf/" xml:space="preserve">"#
+    )?;
+    writeln!(writer, "  <fields>")?;
+    for (number, value) in boxes(report, year) {
+        writeln!(
+            writer,
+            r#"    <field name="{}"><value>{}</value></field>"#,
+            escape(&fields.name(number)),
+            escape(&value)
+        )?;
+    }
+    writeln!(writer, "  </fields>")?;
+    writeln!(writer, "</xfdf>")?;
+    Ok(())
+}
+
+/// Fills the SA108 boxes of the tax year in the fields of the PDF form, appending a page for
+/// each of the content streams of the computation e.g. of the summary letter. It is an error if
+/// a box has no field in the form, rather than leaving it blank.
+pub fn fill_pdf<W>(
+    form: &[u8],
+    report: &TaxReport,
+    year: TaxYear,
+    fields: &FormFields,
+    computation: &[String],
+    mut writer: W,
+) -> color_eyre::Result<()>
+where
+    W: Write,
+{
+    let mut doc = Document::load_mem(form)?;
+    let catalog_id = doc.trailer.get(b"Root")?.as_reference()?;
+    let acro_form = doc
+        .catalog()?
+        .get(b"AcroForm")
+        .map_err(|_| eyre::eyre!("The PDF has no form fields to fill"))?
+        .clone();
+    let acro_form_id = acro_form.as_reference().ok();
+    let acro_form = match acro_form_id {
+        Some(id) => doc.get_dictionary(id)?.clone(),
+        None => acro_form.as_dict()?.clone(),
+    };
+
+    let mut form_fields = Vec::new();
+    collect_fields(
+        &doc,
+        acro_form.get(b"Fields")?.as_array()?,
+        "",
+        &mut form_fields,
+    )?;
+    for (number, value) in boxes(report, year) {
+        let name = fields.name(number);
+        let id = form_fields
+            .iter()
+            .find(|(_, field)| *field == name)
+            .map(|(id, _)| *id)
+            .ok_or_else(|| {
+                eyre::eyre!(
+                    "No field {} for box {} in the form, map the box to its field with \
+                     --sa108-fields",
+                    name,
+                    number
+                )
+            })?;
+        doc.get_object_mut(id)?
+            .as_dict_mut()?
+            .set("V", Object::string_literal(value));
+    }
+    // the fields have no appearance of their new values, so have the viewer draw them
+    let acro_form = match acro_form_id {
+        Some(id) => doc.get_object_mut(id)?,
+        None => doc
+            .get_object_mut(catalog_id)?
+            .as_dict_mut()?
+            .get_mut(b"AcroForm")?,
+    };
+    acro_form.as_dict_mut()?.set("NeedAppearances", true);
+
+    let pages_id = doc.catalog()?.get(b"Pages")?.as_reference()?;
+    let fonts = letter::FONTS
+        .iter()
+        .map(|(name, base_font)| {
+            let font = doc.add_object(dictionary! {
+                "Type" => "Font",
+                "Subtype" => "Type1",
+                "BaseFont" => *base_font,
+                "Encoding" => "WinAnsiEncoding",
+            });
+            (*name, Object::from(font))
+        })
+        .collect::<lopdf::Dictionary>();
+    let media_box: Vec<Object> = vec![
+        Object::Integer(0),
+        Object::Integer(0),
+        letter::PAGE_WIDTH.into(),
+        letter::PAGE_HEIGHT.into(),
+    ];
+    for contents in computation {
+        let contents = doc.add_object(Stream::new(dictionary! {}, contents.as_bytes().to_vec()));
+        let page = doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "MediaBox" => media_box.clone(),
+            "Resources" => dictionary! { "Font" => fonts.clone() },
+            "Contents" => contents,
+        });
+        let pages = doc.get_object_mut(pages_id)?.as_dict_mut()?;
+        pages.get_mut(b"Kids")?.as_array_mut()?.push(page.into());
+        let count = pages.get(b"Count")?.as_i64()?;
+        pages.set("Count", count + 1);
+    }
+    doc.save_to(&mut writer)?;
+    Ok(())
+}
+
+/// The ids and full names of the fields of the form, the names of nested fields being joined by
+/// `.` to their parents' as in XFDF
+fn collect_fields(
+    doc: &Document,
+    kids: &[Object],
+    parent: &str,
+    found: &mut Vec<(ObjectId, String)>,
+) -> color_eyre::Result<()> {
+    for id in kids.iter().filter_map(|kid| kid.as_reference().ok()) {
+        let field = doc.get_dictionary(id)?;
+        // kids without a name are the widgets of their parent field
+        let name = match field.get(b"T").and_then(Object::as_str) {
+            Ok(name) if parent.is_empty() => String::from_utf8_lossy(name).to_string(),
+            Ok(name) => format!("{}.{}", parent, String::from_utf8_lossy(name)),
+            Err(_) => continue,
+        };
+        if let Ok(kids) = field.get(b"Kids").and_then(Object::as_array) {
+            collect_fields(doc, kids, &name, found)?;
+        }
+        found.push((id, name));
+    }
+    Ok(())
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cmd::{
+            prices::Prices,
+            report::cgt::{self, Options},
+        },
+        transactions,
+    };
+
+    #[test]
+    fn fills_the_boxes_in_whole_pounds() {
+        let txs = "\
+version,date_time,kind,buy_asset,buy_amount,sell_asset,sell_amount,fee_asset,fee_amount,rate,exchange,exchange_id,notes,ownership_changed,received_date_time
+5,2017-01-01T12:00:00+00:00,Buy,BTC,3,GBP,1000.50,GBP,0,333.5,,,,,
+5,2018-01-01T12:00:00+00:00,Sell,GBP,900.75,BTC,1,GBP,0,900.75,,,,,
+5,2018-02-01T12:00:00+00:00,Sell,GBP,200,BTC,1,GBP,0,200,,,,,
+";
+        let transactions = transactions::read_csv(txs.as_bytes()).unwrap();
+        let prices = Prices::default();
+        let report = cgt::calculate(transactions, &prices, &Options::default()).unwrap();
+        let fields = FormFields::parse("15 = \"Proceeds & costs\"").unwrap();
+
+        let mut xfdf = Vec::new();
+        write_xfdf(&report, TaxYear::uk(2018), &fields, &mut xfdf).unwrap();
+        let xfdf = String::from_utf8(xfdf).unwrap();
+
+        assert!(xfdf.contains(r#"<field name="box14"><value>2</value></field>"#));
+        let proceeds = r#"<field name="Proceeds &amp; costs"><value>1100</value></field>"#;
+        assert!(xfdf.contains(proceeds), "Field names are escaped");
+        assert!(xfdf.contains(r#"<field name="box16"><value>667</value></field>"#));
+        assert!(xfdf.contains(r#"<field name="box17"><value>567</value></field>"#));
+        assert!(xfdf.contains(r#"<field name="box19"><value>134</value></field>"#));
+    }
+
+    #[test]
+    fn fills_the_form_fields_and_appends_the_computation() {
+        let txs = "\
+version,date_time,kind,buy_asset,buy_amount,sell_asset,sell_amount,fee_asset,fee_amount,rate,exchange,exchange_id,notes,ownership_changed,received_date_time
+5,2017-01-01T12:00:00+00:00,Buy,BTC,3,GBP,1000.50,GBP,0,333.5,,,,,
+5,2018-01-01T12:00:00+00:00,Sell,GBP,900.75,BTC,1,GBP,0,900.75,,,,,
+";
+        let transactions = transactions::read_csv(txs.as_bytes()).unwrap();
+        let report = cgt::calculate(transactions, &Prices::default(), &Options::default()).unwrap();
+
+        // a form with a page, the proceeds nested in a parent field
+        let mut form = Document::with_version("1.5");
+        let pages_id = form.new_object_id();
+        let page = form.add_object(dictionary! { "Type" => "Page", "Parent" => pages_id });
+        form.objects.insert(
+            pages_id,
+            Object::from(dictionary! {
+                "Type" => "Pages",
+                "Kids" => vec![Object::from(page)],
+                "Count" => 1,
+            }),
+        );
+        let field = |form: &mut Document, name: &str| {
+            form.add_object(dictionary! { "FT" => "Tx", "T" => Object::string_literal(name) })
+        };
+        let proceeds = field(&mut form, "box15");
+        let mut fields: Vec<Object> = vec![14, 16, 17, 19]
+            .into_iter()
+            .map(|number| field(&mut form, &format!("box{}", number)).into())
+            .collect();
+        fields.push(
+            form.add_object(dictionary! {
+                "T" => Object::string_literal("SA108"),
+                "Kids" => vec![Object::from(proceeds)],
+            })
+            .into(),
+        );
+        let catalog = form.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages_id,
+            "AcroForm" => dictionary! { "Fields" => fields },
+        });
+        form.trailer.set("Root", catalog);
+        let mut pdf = Vec::new();
+        form.save_to(&mut pdf).unwrap();
+
+        let computation = vec!["BT /F1 11 Tf 72 770 Td (Computation) Tj ET".to_string()];
+        let fill = |fields: &FormFields| {
+            let mut filled = Vec::new();
+            fill_pdf(
+                &pdf,
+                &report,
+                TaxYear::uk(2018),
+                fields,
+                &computation,
+                &mut filled,
+            )
+            .map(|_| filled)
+        };
+        assert!(fill(&FormFields::default())
+            .err()
+            .expect("no box15 field")
+            .to_string()
+            .starts_with("No field box15 for box 15 in the form"));
+
+        let fields = FormFields::parse("15 = \"SA108.box15\"").unwrap();
+        let filled = Document::load_mem(&fill(&fields).unwrap()).unwrap();
+        let value = |id| {
+            let value = filled.get_dictionary(id).unwrap().get(b"V").unwrap();
+            String::from_utf8_lossy(value.as_str().unwrap()).to_string()
+        };
+        assert_eq!(value(proceeds), "900");
+        assert_eq!(filled.get_pages().len(), 2);
+        let computation_page = filled.get_pages()[&2];
+        assert_eq!(
+            filled.get_page_content(computation_page).unwrap(),
+            computation[0].as_bytes()
+        );
+    }
+}
@@ -0,0 +1,115 @@
+//! A Graphviz timeline of the events, linking each disposal to the acquisitions and pools its
+//! allowable costs were matched against, for visually reviewing the same day and 30 day matching.
+
+use super::cgt::{Gains, Leg, MatchingRule};
+use crate::money::display_amount;
+use std::{collections::BTreeSet, io::Write};
+
+/// Writes the events as a Graphviz DOT digraph, render with e.g. `dot -Tsvg timeline.dot`
+pub fn write_dot<W>(gains: &Gains, mut writer: W) -> color_eyre::Result<()>
+where
+    W: Write,
+{
+    writeln!(writer, "digraph timeline {{")?;
+    writeln!(writer, "  rankdir=LR;")?;
+    writeln!(writer, "  node [shape=box, fontname=\"Helvetica\"];")?;
+
+    let mut pools = BTreeSet::new();
+    // the acquisition of a trade of one asset for another is the same node as its disposal
+    for event in gains
+        .gains
+        .iter()
+        .filter(|event| event.leg() != Some(Leg::Acquisition))
+    {
+        let trade = event.trade();
+        let colour = if event.is_disposal() {
+            "lightcoral"
+        } else {
+            "lightblue"
+        };
+        writeln!(
+            writer,
+            "  e{} [label=\"#{} {}\\n{} {} {} for {} {}\", style=filled, fillcolor={}];",
+            event.id(),
+            event.id(),
+            trade.date_time.date(),
+            event.kind(),
+            display_amount(&trade.buy),
+            trade.buy.currency().code,
+            display_amount(&trade.sell),
+            trade.sell.currency().code,
+            colour
+        )?;
+
+        for matched in event.matches() {
+            let from = match (matched.rule(), matched.trade_id()) {
+                (MatchingRule::Pool, _) | (_, None) => {
+                    let code = matched.amount().currency().code;
+                    pools.insert(code);
+                    format!("pool_{}", code)
+                }
+                (_, Some(trade_id)) => format!("e{}", trade_id),
+            };
+            let style = match matched.rule() {
+                MatchingRule::SameDay | MatchingRule::ThirtyDay => "bold",
+                MatchingRule::Pool => "solid",
+                MatchingRule::Unknown => "dashed",
+            };
+            writeln!(
+                writer,
+                "  {} -> e{} [label=\"{} {} {}\\n{} GBP\", style={}];",
+                from,
+                event.id(),
+                matched.rule(),
+                display_amount(matched.amount()),
+                matched.amount().currency().code,
+                display_amount(matched.costs()),
+                style
+            )?;
+        }
+    }
+
+    for code in pools {
+        writeln!(
+            writer,
+            "  pool_{} [label=\"{} pool\", shape=cylinder];",
+            code, code
+        )?;
+    }
+    writeln!(writer, "}}")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cmd::{
+            prices::Prices,
+            report::cgt::{self, Options},
+        },
+        transactions,
+    };
+
+    #[test]
+    fn links_disposals_to_matched_acquisitions() {
+        let txs = "\
+version,date_time,kind,buy_asset,buy_amount,sell_asset,sell_amount,fee_asset,fee_amount,rate,exchange,exchange_id,notes,ownership_changed,received_date_time
+5,2018-01-01T12:00:00+00:00,Buy,BTC,2,GBP,200,GBP,0,100,,,,,
+5,2018-02-01T12:00:00+00:00,Sell,GBP,300,BTC,1.5,GBP,0,200,,,,,
+5,2018-02-10T12:00:00+00:00,Buy,BTC,1,GBP,150,GBP,0,150,,,,,
+";
+        let transactions = transactions::read_csv(txs.as_bytes()).unwrap();
+        let prices = Prices::default();
+        let report = cgt::calculate(transactions, &prices, &Options::default()).unwrap();
+
+        let mut dot = Vec::new();
+        write_dot(&report.gains(None), &mut dot).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+
+        assert!(dot.starts_with("digraph timeline {"));
+        assert!(dot.contains("e3 -> e2 [label=\"30-day"));
+        assert!(dot.contains("pool_BTC -> e2 [label=\"s104"));
+        assert!(dot.contains("pool_BTC [label=\"BTC pool\", shape=cylinder];"));
+    }
+}
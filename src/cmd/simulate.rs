@@ -0,0 +1,142 @@
+use crate::{
+    cmd::{
+        prices::Prices,
+        report::cgt::{self, Options},
+    },
+    currencies::GBP,
+    encryption,
+    money::display_amount,
+    transactions::{self, Transaction},
+};
+use argh::FromArgs;
+use serde::Serialize;
+use std::{collections::BTreeSet, fs::File, io, path::PathBuf};
+
+/// Project the CGT effect of hypothetical trades on top of the existing transactions
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "simulate")]
+pub struct SimulateCommand {
+    /// the csv file containing the existing transactions
+    #[argh(option)]
+    txs: PathBuf,
+    /// csv file containing the planned transactions, in the same format as the transactions file
+    #[argh(option)]
+    hypothetical: PathBuf,
+    /// optional csv file with prices in GBP for ETH and BTC, instead of fetching from Coingecko.
+    #[argh(option)]
+    prices: Option<PathBuf>,
+    /// when each tax year starts: uk (6 April, the default), calendar, july or a custom start
+    /// date MM-DD
+    #[argh(option, default = "cgt::YearStart::UK")]
+    year_start: cgt::YearStart,
+}
+
+#[derive(Serialize)]
+struct ProjectionRecord {
+    tax_year: cgt::TaxYear,
+    disposals: usize,
+    gains: String,
+    projected_disposals: usize,
+    projected_gains: String,
+    difference: String,
+}
+
+impl SimulateCommand {
+    pub fn exec(&self) -> color_eyre::Result<()> {
+        let history = transactions::read_csv(encryption::open(&self.txs)?)?;
+        let hypothetical = transactions::read_csv(encryption::open(&self.hypothetical)?)?;
+        let prices = match self.prices {
+            None => Prices::from_coingecko_api(GBP, false)?,
+            Some(ref path) => Prices::read_csv(File::open(path)?)?,
+        };
+        let options = Options {
+            year_start: self.year_start,
+            ..Default::default()
+        };
+
+        tracing::info!(
+            "Simulating {} hypothetical transactions",
+            hypothetical.len()
+        );
+        let records = project(history, hypothetical, &prices, &options)?;
+        crate::utils::write_csv(records, io::stdout())
+    }
+}
+
+/// The gains of each tax year with and without the hypothetical transactions
+fn project<'a>(
+    history: Vec<Transaction<'a>>,
+    hypothetical: Vec<Transaction<'a>>,
+    prices: &'a Prices<'a>,
+    options: &Options,
+) -> color_eyre::Result<Vec<ProjectionRecord>> {
+    let mut projected = history.clone();
+    projected.extend(hypothetical);
+
+    let current = cgt::calculate(history, prices, options)?;
+    let projected = cgt::calculate(projected, prices, options)?;
+
+    let years = current
+        .years
+        .keys()
+        .chain(projected.years.keys())
+        .cloned()
+        .collect::<BTreeSet<_>>();
+    Ok(years
+        .into_iter()
+        .map(|year| {
+            let gains = current.gains(Some(year));
+            let projected_gains = projected.gains(Some(year));
+            ProjectionRecord {
+                tax_year: year,
+                disposals: gains.disposal_count(),
+                gains: display_amount(&gains.total_gain()),
+                projected_disposals: projected_gains.disposal_count(),
+                projected_gains: display_amount(&projected_gains.total_gain()),
+                difference: display_amount(&(projected_gains.total_gain() - gains.total_gain())),
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hypothetical_trades_are_projected_on_the_history() {
+        let header = "version,date_time,kind,buy_asset,buy_amount,sell_asset,sell_amount,\
+                      fee_asset,fee_amount,rate,exchange,exchange_id,notes,ownership_changed,\
+                      received_date_time\n";
+        let read = |rows: &str| transactions::read_csv(format!("{}{}", header, rows).as_bytes());
+        let history = read(
+            "5,2018-01-01T12:00:00+00:00,Buy,BTC,1,GBP,1000,GBP,0,1000,,,,,\n\
+             5,2018-06-01T12:00:00+00:00,Sell,GBP,1000,BTC,0.5,GBP,0,2000,,,,,\n",
+        )
+        .unwrap();
+        let hypothetical =
+            read("5,2019-06-01T12:00:00+00:00,Sell,GBP,1000,BTC,0.5,GBP,0,2000,,,,,\n").unwrap();
+        let prices = Prices::default();
+
+        let records = project(history, hypothetical, &prices, &Options::default()).unwrap();
+        let summary = records
+            .iter()
+            .filter(|r| r.projected_disposals > 0)
+            .map(|r| {
+                (
+                    r.tax_year.to_string(),
+                    r.disposals,
+                    r.projected_disposals,
+                    r.difference.as_str(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                ("2018/19".to_string(), 1, 1, "0.00"),
+                ("2019/20".to_string(), 0, 1, "500.00"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,332 @@
+use crate::{
+    cmd::{import::ImportApiCommand, imports},
+    store::Store,
+    trades::TradeRecord,
+};
+use argh::FromArgs;
+use color_eyre::eyre;
+use serde::{Deserialize, Serialize};
+use std::{
+    io,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex},
+    thread,
+};
+
+/// Run the exchange API imports configured in sync.toml in the config directory concurrently,
+/// appending the transactions not already in the transactions file or database
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "sync")]
+pub struct SyncCommand {
+    /// the csv file of the transactions to append to
+    #[argh(option)]
+    txs: Option<PathBuf>,
+    /// the SQLite database to add the transactions to instead of --txs, for `report --from-db`
+    #[argh(option)]
+    into_db: Option<PathBuf>,
+    /// run all the configured importers
+    #[argh(switch)]
+    all: bool,
+    /// only run the configured importer with this name, can be repeated
+    #[argh(option)]
+    importer: Vec<String>,
+    /// optional toml file of the importers, instead of sync.toml in the config directory. Each
+    /// [[importers]] has a name and the args of `import api` e.g.
+    /// args = ["binance", "--api-key", "...", "--secret", "...", "--symbol", "BTCGBP"]
+    #[argh(option)]
+    config: Option<PathBuf>,
+    /// the most importers to run at once, default 4
+    #[argh(option, default = "4")]
+    jobs: usize,
+}
+
+#[derive(Debug, Deserialize)]
+struct SyncConfig {
+    importers: Vec<ImporterConfig>,
+}
+
+/// An API import, run as if by `import api` with the args
+#[derive(Debug, Clone, Deserialize)]
+struct ImporterConfig {
+    name: String,
+    args: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct SyncRecord {
+    importer: String,
+    fetched: usize,
+    appended: usize,
+    /// The id of the import session, for undoing it with `imports undo`
+    import_id: Option<u32>,
+    error: String,
+}
+
+impl SyncCommand {
+    pub fn exec(&self) -> color_eyre::Result<()> {
+        let config = read_config(self.config.as_deref())?;
+        let importers = config
+            .importers
+            .into_iter()
+            .filter(|importer| self.all || self.importer.contains(&importer.name))
+            .collect::<Vec<_>>();
+        if !self.all && self.importer.is_empty() {
+            return Err(eyre::eyre!("Either --all or an --importer is required"));
+        }
+        if self.txs.is_some() == self.into_db.is_some() {
+            return Err(eyre::eyre!("Either --txs or --into-db is required"));
+        }
+        for name in self.importer.iter() {
+            if !importers.iter().any(|importer| &importer.name == name) {
+                return Err(eyre::eyre!("No importer named {} is configured", name));
+            }
+        }
+        let commands = importers
+            .iter()
+            .map(|importer| {
+                let args = importer.args.iter().map(String::as_str).collect::<Vec<_>>();
+                let command = ImportApiCommand::from_args(&["api"], &args).map_err(|exit| {
+                    eyre::eyre!(
+                        "Invalid args for importer {}: {}",
+                        importer.name,
+                        exit.output
+                    )
+                })?;
+                Ok((importer.name.clone(), command))
+            })
+            .collect::<color_eyre::Result<Vec<_>>>()?;
+
+        let results = run_concurrently(commands, self.jobs, ImportApiCommand::fetch);
+
+        // appended one importer at a time, so each is a separate import which can be undone
+        let mut store = match self.into_db {
+            Some(ref db) => Some(Store::open(db)?),
+            None => None,
+        };
+        let mut existing = match self.txs {
+            Some(ref txs) => imports::read_records(txs)?,
+            None => Vec::new(),
+        };
+        let mut records = Vec::new();
+        for (importer, result) in results {
+            match result {
+                Ok(fetched) => {
+                    let count = fetched.len();
+                    let source = format!("sync {}", importer);
+                    let (appended, import_id) = match (&mut store, &self.txs) {
+                        (Some(store), _) => (store.insert_transactions(&fetched, &source)?, None),
+                        (None, Some(txs)) => {
+                            let new = new_records(&existing, fetched);
+                            let appended = new.len();
+                            if new.is_empty() {
+                                (appended, None)
+                            } else {
+                                existing.extend(new.iter().cloned());
+                                (appended, Some(imports::append_import(txs, source, new)?))
+                            }
+                        }
+                        (None, None) => unreachable!("Either --txs or --into-db is checked above"),
+                    };
+                    records.push(SyncRecord {
+                        importer,
+                        fetched: count,
+                        appended,
+                        import_id,
+                        error: String::new(),
+                    });
+                }
+                Err(err) => {
+                    tracing::error!("Importer {} failed: {:?}", importer, err);
+                    records.push(SyncRecord {
+                        importer,
+                        fetched: 0,
+                        appended: 0,
+                        import_id: None,
+                        error: format!("{:#}", err),
+                    });
+                }
+            }
+        }
+        let failed = records.iter().filter(|r| !r.error.is_empty()).count();
+        crate::utils::write_csv(records, io::stdout())?;
+        if failed > 0 {
+            return Err(eyre::eyre!("{} importers failed", failed));
+        }
+        Ok(())
+    }
+}
+
+/// The importers in the given file, or `sync.toml` in the config directory
+fn read_config(path: Option<&Path>) -> color_eyre::Result<SyncConfig> {
+    let path = match path {
+        Some(path) => path.to_path_buf(),
+        None => crate::utils::config_dir()
+            .map(|dir| dir.join("sync.toml"))
+            .ok_or(eyre::eyre!(
+                "No config directory, set TAXC_CONFIG_DIR or HOME"
+            ))?,
+    };
+    tracing::info!("Reading importers from {}", path.display());
+    Ok(toml::from_str(&std::fs::read_to_string(path)?)?)
+}
+
+/// Run the imports on at most `jobs` threads, returning the results in the order of the commands.
+/// An importer which panics fails with an error, without stopping the others.
+fn run_concurrently<C, F>(
+    commands: Vec<(String, C)>,
+    jobs: usize,
+    fetch: F,
+) -> Vec<(String, color_eyre::Result<Vec<TradeRecord>>)>
+where
+    C: Send + 'static,
+    F: Fn(&C) -> color_eyre::Result<Vec<TradeRecord>> + Send + Sync + 'static,
+{
+    let count = commands.len();
+    let queue = Arc::new(Mutex::new(commands.into_iter().enumerate()));
+    let fetch = Arc::new(fetch);
+    let (sender, receiver) = mpsc::channel();
+    let workers = (0..jobs.max(1).min(count))
+        .map(|_| {
+            let queue = Arc::clone(&queue);
+            let sender = sender.clone();
+            let fetch = Arc::clone(&fetch);
+            thread::spawn(move || loop {
+                let next = queue.lock().expect("Queue lock poisoned").next();
+                let (index, (name, command)) = match next {
+                    Some(next) => next,
+                    None => break,
+                };
+                tracing::info!("Syncing {}", name);
+                let result = panic::catch_unwind(AssertUnwindSafe(|| fetch(&command)))
+                    .unwrap_or_else(|_| Err(eyre::eyre!("The importer panicked")));
+                if sender.send((index, name, result)).is_err() {
+                    break;
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    drop(sender);
+
+    let mut results = receiver.iter().collect::<Vec<_>>();
+    for worker in workers {
+        worker.join().expect("Importer panics are caught");
+    }
+    results.sort_by_key(|(index, _, _)| *index);
+    results
+        .into_iter()
+        .map(|(_, name, result)| (name, result))
+        .collect()
+}
+
+/// The fetched records which aren't already in the transactions, e.g. from an earlier sync of
+/// an overlapping period. Each existing record accounts for one fetched, since identical fills
+/// are separate trades.
+fn new_records(existing: &[TradeRecord], fetched: Vec<TradeRecord>) -> Vec<TradeRecord> {
+    let mut unmatched = existing.iter().collect::<Vec<_>>();
+    fetched
+        .into_iter()
+        .filter(|record| match unmatched.iter().position(|e| *e == record) {
+            Some(index) => {
+                unmatched.swap_remove(index);
+                false
+            }
+            None => true,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn importers_are_configured_with_the_args_of_import_api() {
+        let config: SyncConfig = toml::from_str(
+            r#"
+[[importers]]
+name = "mining"
+args = ["ethermine", "--address", "0x1234"]
+
+[[importers]]
+name = "wallet"
+args = ["utxo", "--chain", "btc", "--address", "bc1q"]
+"#,
+        )
+        .unwrap();
+
+        let names = config
+            .importers
+            .iter()
+            .map(|importer| importer.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["mining", "wallet"]);
+        for importer in config.importers.iter() {
+            let args = importer.args.iter().map(String::as_str).collect::<Vec<_>>();
+            assert!(ImportApiCommand::from_args(&["api"], &args).is_ok());
+        }
+        assert!(ImportApiCommand::from_args(&["api"], &["kraken"]).is_err());
+        let into_db = ["--into-db", "taxc.db", "ethermine", "--address", "0x1234"];
+        assert!(ImportApiCommand::from_args(&["api"], &into_db).is_ok());
+    }
+
+    #[test]
+    fn only_records_not_already_imported_are_new() {
+        let record = |date_time: &str| TradeRecord {
+            version: crate::trades::SCHEMA_VERSION,
+            date_time: date_time.into(),
+            kind: "Income".into(),
+            buy_asset: "ETH".into(),
+            buy_amount: "0.1".into(),
+            sell_asset: "".into(),
+            sell_amount: "".into(),
+            fee_asset: "ETH".into(),
+            fee_amount: "0".into(),
+            rate: Default::default(),
+            exchange: "Ethermine".into(),
+            exchange_id: String::new(),
+            notes: String::new(),
+            ownership_changed: None,
+            received_date_time: None,
+            account: None,
+        };
+        let existing = vec![record("2021-01-01T00:00:00"), record("2021-01-02T00:00:00")];
+        let fetched = vec![
+            record("2021-01-02T00:00:00"),
+            record("2021-01-02T00:00:00"),
+            record("2021-01-03T00:00:00"),
+        ];
+
+        assert_eq!(
+            new_records(&existing, fetched),
+            vec![record("2021-01-02T00:00:00"), record("2021-01-03T00:00:00")]
+        );
+    }
+
+    #[test]
+    fn importers_which_panic_fail_by_name() {
+        let commands = ["first", "panics", "last"]
+            .iter()
+            .map(|name| (name.to_string(), name.to_string()))
+            .collect();
+        let results = run_concurrently(commands, 2, |name: &String| {
+            if name == "panics" {
+                panic!("Invalid response");
+            }
+            Ok(Vec::new())
+        });
+
+        let results = results
+            .iter()
+            .map(|(name, result)| (name.as_str(), result.as_ref().map_err(|e| e.to_string())))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            results,
+            vec![
+                ("first", Ok(&Vec::new())),
+                ("panics", Err("The importer panicked".to_string())),
+                ("last", Ok(&Vec::new())),
+            ]
+        );
+    }
+}
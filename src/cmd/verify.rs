@@ -0,0 +1,236 @@
+use crate::transactions::{self, Transaction};
+use argh::FromArgs;
+use chrono::{NaiveDate, NaiveDateTime};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::{fs::File, io, path::PathBuf};
+
+/// Compare the balances computed from the transactions against the expected closing balances of
+/// each asset on each exchange or wallet, reporting when records are likely missing
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "verify")]
+pub struct VerifyCommand {
+    /// the csv file containing the transactions
+    #[argh(option)]
+    txs: PathBuf,
+    /// the csv file of expected balances, with columns asset, venue, balance and as_of e.g.
+    /// ETH,binance,1.5,2021-04-05 for the balance at the end of that day. A venue matches the
+    /// exchange or account of the transactions, as for `report --account`.
+    #[argh(option)]
+    balances: PathBuf,
+    /// the difference above which a balance is reported as a discrepancy, default 0
+    #[argh(option, default = "Decimal::new(0, 0)")]
+    tolerance: Decimal,
+}
+
+/// A balance the user expects from e.g. an exchange statement at the end of the tax year
+#[derive(Debug, Deserialize)]
+struct ExpectedBalance {
+    asset: String,
+    venue: String,
+    balance: Decimal,
+    as_of: NaiveDate,
+}
+
+#[derive(Debug, Serialize)]
+struct VerifyRecord {
+    as_of: NaiveDate,
+    asset: String,
+    venue: String,
+    expected: Decimal,
+    computed: Decimal,
+    difference: Decimal,
+    status: &'static str,
+    /// The window in which the missing records are likely to be, from the last balance which
+    /// matched or the first transaction, to the first time the balance went negative or the
+    /// balance date
+    missing_from: Option<NaiveDateTime>,
+    missing_to: Option<NaiveDateTime>,
+}
+
+impl VerifyCommand {
+    /// Returns the number of discrepancies
+    pub fn exec(&self) -> color_eyre::Result<usize> {
+        let transactions = transactions::read_csv(crate::encryption::open(&self.txs)?)?;
+        let mut rdr = csv::Reader::from_reader(File::open(&self.balances)?);
+        let expected = rdr
+            .deserialize()
+            .collect::<Result<Vec<ExpectedBalance>, _>>()?;
+        let records = verify(&transactions, expected, self.tolerance);
+        let discrepancies = records.iter().filter(|r| r.status != "ok").count();
+        tracing::info!(
+            "Verified {} balances, {} discrepancies",
+            records.len(),
+            discrepancies
+        );
+        crate::utils::write_csv(records, io::stdout())?;
+        Ok(discrepancies)
+    }
+}
+
+/// The changes to the balance of the asset on the venue, in time order
+fn balance_changes(
+    transactions: &[Transaction],
+    asset: &str,
+    venue: &str,
+) -> Vec<(NaiveDateTime, Decimal)> {
+    let mut changes = transactions
+        .iter()
+        .filter(|tx| tx.is_on_account(venue))
+        .filter_map(|tx| {
+            let mut change = Decimal::new(0, 0);
+            if let Some(buy) = tx.buy().filter(|buy| buy.currency().code == asset) {
+                change += *buy.amount();
+            }
+            if let Some(sell) = tx.sell().filter(|sell| sell.currency().code == asset) {
+                change -= *sell.amount();
+            }
+            if tx.fee().currency().code == asset {
+                change -= *tx.fee().amount();
+            }
+            if change.is_zero() {
+                None
+            } else {
+                Some((tx.date_time(), change))
+            }
+        })
+        .collect::<Vec<_>>();
+    changes.sort_by_key(|(date_time, _)| *date_time);
+    changes
+}
+
+fn verify(
+    transactions: &[Transaction],
+    mut expected: Vec<ExpectedBalance>,
+    tolerance: Decimal,
+) -> Vec<VerifyRecord> {
+    expected.sort_by(|a, b| {
+        (a.asset.to_uppercase(), a.venue.to_lowercase(), a.as_of).cmp(&(
+            b.asset.to_uppercase(),
+            b.venue.to_lowercase(),
+            b.as_of,
+        ))
+    });
+    let mut records: Vec<VerifyRecord> = Vec::new();
+    for balance in expected {
+        let asset = balance.asset.to_uppercase();
+        let changes = balance_changes(transactions, &asset, &balance.venue);
+        let end = balance.as_of.succ().and_hms(0, 0, 0);
+        let computed = changes
+            .iter()
+            .filter(|(date_time, _)| *date_time < end)
+            .map(|(_, change)| *change)
+            .sum::<Decimal>();
+        let difference = computed - balance.balance;
+        let status = if difference.abs() <= tolerance {
+            "ok"
+        } else if difference.is_sign_negative() {
+            "missing inflows"
+        } else {
+            "missing outflows"
+        };
+
+        let (missing_from, missing_to) = if status == "ok" {
+            (None, None)
+        } else {
+            // from the end of the last balance of the asset and venue which matched
+            let last_ok = records
+                .iter()
+                .rev()
+                .filter(|r| r.asset == asset && r.venue.eq_ignore_ascii_case(&balance.venue))
+                .find(|r| r.status == "ok")
+                .map(|r| r.as_of.succ().and_hms(0, 0, 0));
+            let from = last_ok.or(changes.first().map(|(date_time, _)| *date_time));
+            // a balance going negative means an inflow before then is missing
+            let mut running = Decimal::new(0, 0);
+            let first_negative = changes
+                .iter()
+                .filter(|(date_time, _)| *date_time < end)
+                .find(|(date_time, change)| {
+                    running += *change;
+                    running.is_sign_negative()
+                        && !running.is_zero()
+                        && from.map_or(true, |from| *date_time >= from)
+                })
+                .map(|(date_time, _)| *date_time);
+            let to = match status {
+                "missing inflows" => first_negative.unwrap_or(end),
+                _ => end,
+            };
+            (from, Some(to))
+        };
+        if status != "ok" {
+            tracing::warn!(
+                "{} on {} at the end of {} is {}, expected {}: {}",
+                asset,
+                balance.venue,
+                balance.as_of,
+                computed,
+                balance.balance,
+                status
+            );
+        }
+        records.push(VerifyRecord {
+            as_of: balance.as_of,
+            asset,
+            venue: balance.venue,
+            expected: balance.balance,
+            computed,
+            difference,
+            status,
+            missing_from,
+            missing_to,
+        });
+    }
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn discrepancies_are_reported_with_the_window_since_the_last_matching_balance() {
+        let txs = "\
+version,date_time,kind,buy_asset,buy_amount,sell_asset,sell_amount,fee_asset,fee_amount,rate,exchange,exchange_id,notes,ownership_changed,received_date_time,account
+5,2020-01-01T12:00:00+00:00,Buy,ETH,10,GBP,5000,GBP,0,500,Binance,,,,,binance:personal
+5,2020-05-01T12:00:00+00:00,Withdrawal,,,ETH,4,ETH,0.01,0,Binance,,,,,binance:personal
+5,2020-06-01T12:00:00+00:00,Withdrawal,,,ETH,5,ETH,0.01,0,Binance,,,,,binance:personal
+5,2020-07-01T12:00:00+00:00,Sell,GBP,2000,ETH,2,GBP,0,1000,Binance,,,,,binance:personal
+5,2021-01-01T12:00:00+00:00,Deposit,ETH,1,,,ETH,0,0,Kraken,,,,,
+";
+        let balances = "\
+asset,venue,balance,as_of
+ETH,binance,10,2020-04-05
+eth,binance:personal,1,2021-04-05
+ETH,kraken,1,2021-04-05
+";
+        let transactions = transactions::read_csv(txs.as_bytes()).unwrap();
+        let expected = csv::Reader::from_reader(balances.as_bytes())
+            .deserialize()
+            .collect::<Result<Vec<ExpectedBalance>, _>>()
+            .unwrap();
+
+        let records = verify(&transactions, expected, Decimal::new(0, 0));
+        let summary = records
+            .iter()
+            .map(|r| (r.venue.as_str(), r.computed, r.status))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                ("binance", dec!(10), "ok"),
+                ("binance:personal", dec!(-1.02), "missing inflows"),
+                ("kraken", dec!(1), "ok"),
+            ]
+        );
+        let at = |y, m, d, h| NaiveDate::from_ymd(y, m, d).and_hms(h, 0, 0);
+        assert_eq!(records[1].missing_from, Some(at(2020, 4, 6, 0)));
+        assert_eq!(
+            records[1].missing_to,
+            Some(at(2020, 7, 1, 12)),
+            "The balance went negative with the sale"
+        );
+    }
+}
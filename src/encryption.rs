@@ -0,0 +1,98 @@
+//! Optional passphrase encryption of transaction files and report outputs using
+//! [age](https://age-encryption.org), since they are sensitive financial records.
+
+use age::secrecy::Secret;
+use color_eyre::eyre;
+use std::{
+    fs,
+    io::{self, Read, Write},
+    path::Path,
+};
+
+/// Environment variable from which the passphrase is read
+const PASSPHRASE_VAR: &str = "TAXC_PASSPHRASE";
+/// The header line every age encrypted file starts with
+const AGE_HEADER: &[u8] = b"age-encryption.org/v1";
+
+fn passphrase() -> color_eyre::Result<Secret<String>> {
+    std::env::var(PASSPHRASE_VAR).map(Secret::new).map_err(|_| {
+        eyre::eyre!(
+            "Set the {} environment variable to the passphrase",
+            PASSPHRASE_VAR
+        )
+    })
+}
+
+/// Open a file for reading, transparently decrypting it if it was encrypted with a passphrase
+pub fn open<P: AsRef<Path>>(path: P) -> color_eyre::Result<Box<dyn Read>> {
+    let contents = fs::read(path)?;
+    if !contents.starts_with(AGE_HEADER) {
+        return Ok(Box::new(io::Cursor::new(contents)));
+    }
+    let decrypted = decrypt(&contents, &passphrase()?)?;
+    Ok(Box::new(io::Cursor::new(decrypted)))
+}
+
+fn decrypt(contents: &[u8], passphrase: &Secret<String>) -> color_eyre::Result<Vec<u8>> {
+    let decryptor = match age::Decryptor::new(contents)? {
+        age::Decryptor::Passphrase(decryptor) => decryptor,
+        _ => return Err(eyre::eyre!("Only passphrase encrypted files are supported")),
+    };
+    let mut decrypted = Vec::new();
+    decryptor
+        .decrypt(passphrase, None)?
+        .read_to_end(&mut decrypted)?;
+    Ok(decrypted)
+}
+
+/// Write output to stdout, encrypted with the passphrase if `encrypt` is set
+pub fn write_stdout<F>(encrypt: bool, write: F) -> color_eyre::Result<()>
+where
+    F: FnOnce(&mut dyn Write) -> color_eyre::Result<()>,
+{
+    if !encrypt {
+        return write(&mut io::stdout());
+    }
+    write_encrypted(passphrase()?, io::stdout(), write)
+}
+
+fn write_encrypted<W, F>(passphrase: Secret<String>, output: W, write: F) -> color_eyre::Result<()>
+where
+    W: Write,
+    F: FnOnce(&mut dyn Write) -> color_eyre::Result<()>,
+{
+    let encryptor = age::Encryptor::with_user_passphrase(passphrase);
+    let mut writer = encryptor.wrap_output(output)?;
+    write(&mut writer)?;
+    writer.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encrypted_output_is_decrypted_with_the_passphrase() {
+        let passphrase = || Secret::new("correct horse battery staple".to_string());
+        let mut encrypted = Vec::new();
+        write_encrypted(passphrase(), &mut encrypted, |out| {
+            Ok(out.write_all(b"version,date_time\n")?)
+        })
+        .unwrap();
+
+        assert!(encrypted.starts_with(AGE_HEADER));
+        assert_eq!(
+            decrypt(&encrypted, &passphrase()).unwrap(),
+            b"version,date_time\n"
+        );
+        assert!(decrypt(&encrypted, &Secret::new("wrong".to_string())).is_err());
+
+        let path = std::env::temp_dir().join(format!("taxc-plain-{}.csv", std::process::id()));
+        fs::write(&path, "version,date_time\n").unwrap();
+        let mut plain = String::new();
+        open(&path).unwrap().read_to_string(&mut plain).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(plain, "version,date_time\n");
+    }
+}
@@ -0,0 +1,68 @@
+//! Calculate UK Capital Gains Tax (CGT) on cryptocurrency trades.
+//!
+//! The `taxc` binary is a thin command line interface over this library, which can also be used
+//! by other tools e.g. a web UI or a notebook, to run the same calculation on their own
+//! transactions:
+//!
+//! ```no_run
+//! use std::fs::File;
+//! use taxc::{cgt, prices::Prices, transactions};
+//!
+//! fn main() -> color_eyre::Result<()> {
+//!     let transactions = transactions::read_csv(File::open("txs.csv")?)?;
+//!     let prices = Prices::read_csv(File::open("prices.csv")?)?;
+//!     let report = cgt::calculate(transactions, &prices, &cgt::Options::default())?;
+//!     let gains = report.gains(Some(cgt::TaxYear::uk(2021)));
+//!     println!("{} disposals, gain {}", gains.disposal_count(), gains.total_gain());
+//!     Ok(())
+//! }
+//! ```
+
+#![recursion_limit = "128"]
+
+mod addresses;
+pub mod cmd;
+mod encryption;
+pub mod money;
+pub mod store;
+pub mod trades;
+pub mod transactions;
+mod utils;
+pub mod warnings;
+
+pub use cmd::{prices, report::cgt};
+pub use money::{currencies, Money};
+
+#[cfg(test)]
+mod tests {
+    use crate::{cgt, currencies::GBP, prices::Prices, transactions, Money};
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn gains_are_calculated_from_transactions_read_with_the_public_api() {
+        let transactions = "\
+version,date_time,kind,buy_asset,buy_amount,sell_asset,sell_amount,fee_asset,fee_amount,rate,exchange,exchange_id,notes,ownership_changed,received_date_time
+5,2020-05-01T12:00:00+00:00,Buy,BTC,2,GBP,200,GBP,0,100,,,,,
+5,2020-06-01T12:00:00+00:00,Sell,GBP,300,BTC,1,GBP,0,300,,,,,
+5,2020-07-01T12:00:00+00:00,Sell,GBP,50,BTC,1,GBP,0,50,,,,,
+";
+        let prices = "\
+base_currency,quote_currency,date_time,rate
+BTC,GBP,2020-05-01T00:00:00+00:00,100
+";
+        let transactions = transactions::read_csv(transactions.as_bytes()).unwrap();
+        let prices = Prices::read_csv(prices.as_bytes()).unwrap();
+        let report = cgt::calculate(transactions, &prices, &cgt::Options::default()).unwrap();
+
+        let gains = report.gains(Some(cgt::TaxYear::uk(2021)));
+        assert_eq!(gains.disposal_count(), 2);
+        assert_eq!(gains.total_proceeds(), Money::from_decimal(dec!(350), GBP));
+        assert_eq!(gains.total_gains(), Money::from_decimal(dec!(200), GBP));
+        assert_eq!(gains.total_losses(), Money::from_decimal(dec!(50), GBP));
+        assert_eq!(gains.total_gain(), Money::from_decimal(dec!(150), GBP));
+        assert_eq!(
+            report.gains(Some(cgt::TaxYear::uk(2020))).disposal_count(),
+            0
+        );
+    }
+}
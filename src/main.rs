@@ -1,41 +1,238 @@
-#![recursion_limit = "128"]
-
-mod cmd;
-mod money;
-mod trades;
-mod utils;
-
 use argh::FromArgs;
-use cmd::{import::ImportTradesCommand, report::ReportCommand};
-use money::{currencies, Money};
+use taxc::cmd::{
+    self,
+    add_trade::AddTradeCommand,
+    addresses::AddressesCommand,
+    audit::AuditCommand,
+    backup::{BackupCommand, RestoreCommand},
+    crosscheck::CrosscheckCommand,
+    currencies::CurrenciesCommand,
+    diagnose::DiagnoseCommand,
+    explain_price::ExplainPriceCommand,
+    gui::GuiCommand,
+    holdings::HoldingsCommand,
+    import::ImportTradesCommand,
+    imports::ImportsCommand,
+    journal::JournalCommand,
+    migrate::MigrateCommand,
+    notify::NotifyCommand,
+    prices::PricesCommand,
+    report::ReportCommand,
+    simulate::SimulateCommand,
+    sync::SyncCommand,
+    verify::VerifyCommand,
+};
+use tracing_subscriber::EnvFilter;
 
 #[derive(FromArgs, PartialEq, Debug)]
 /// Top-level command.
+///
+/// Exits with 0 on success, 1 if data quality warnings were found and 2 on errors.
 struct Taxc {
+    /// only log errors, for use in scripts and cron jobs
+    #[argh(switch, short = 'q')]
+    quiet: bool,
+    /// the format of log messages written to stderr: text (default) or json
+    #[argh(option, default = "LogFormat::Text")]
+    log_format: LogFormat,
+    /// refuse to run commands which can modify transaction, price or other data files, for
+    /// reviewing someone else's data
+    #[argh(switch)]
+    read_only: bool,
     #[argh(subcommand)]
     cmd: Command,
 }
 
+impl Taxc {
+    /// Refuse commands which can modify data with --read-only
+    fn check_read_only(&self) -> color_eyre::Result<()> {
+        if self.read_only && self.cmd.modifies_data() {
+            Err(color_eyre::eyre::eyre!(
+                "The command can modify data files, so is not available with --read-only"
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[derive(PartialEq, Debug)]
+enum LogFormat {
+    Text,
+    Json,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            f => Err(format!(
+                "Unsupported log format {}, expected text or json",
+                f
+            )),
+        }
+    }
+}
+
 #[derive(FromArgs, PartialEq, Debug)]
 #[argh(subcommand)]
 /// Calculate UK Capital Gains Tax (CGT)
 enum Command {
+    AddTrade(AddTradeCommand),
+    Addresses(AddressesCommand),
+    Audit(AuditCommand),
+    Backup(BackupCommand),
+    Crosscheck(CrosscheckCommand),
+    Currencies(CurrenciesCommand),
+    Diagnose(DiagnoseCommand),
+    ExplainPrice(ExplainPriceCommand),
+    Gui(GuiCommand),
+    Holdings(HoldingsCommand),
     Import(ImportTradesCommand),
+    Imports(ImportsCommand),
+    Journal(JournalCommand),
+    Migrate(MigrateCommand),
+    Notify(NotifyCommand),
+    Prices(PricesCommand),
     Report(ReportCommand),
+    Restore(RestoreCommand),
+    Simulate(SimulateCommand),
+    Sync(SyncCommand),
+    Verify(VerifyCommand),
 }
 
 impl Command {
-    fn exec(&self) -> color_eyre::Result<()> {
+    /// Whether the command can modify existing data files, rather than only reading them or
+    /// writing new output
+    fn modifies_data(&self) -> bool {
+        matches!(
+            self,
+            Command::AddTrade(_)
+                | Command::Imports(_)
+                | Command::Notify(_)
+                | Command::Prices(_)
+                | Command::Restore(_)
+                | Command::Sync(_)
+        ) || matches!(self, Command::Import(import) if !import.dry_run())
+            || matches!(self, Command::Report(report) if report.modifies_data())
+            || matches!(self, Command::Currencies(currencies) if currencies.modifies_data())
+            || matches!(self, Command::Addresses(addresses) if addresses.modifies_data())
+    }
+
+    /// Run the command, returning the number of data quality problems it found
+    fn exec(&self) -> color_eyre::Result<usize> {
+        let no_problems = |result: color_eyre::Result<()>| result.map(|()| 0);
         match self {
+            Command::AddTrade(add_trade) => no_problems(add_trade.exec()),
+            Command::Addresses(addresses) => no_problems(addresses.exec()),
+            Command::Audit(audit) => no_problems(audit.exec()),
+            Command::Backup(backup) => no_problems(backup.exec()),
+            Command::Crosscheck(crosscheck) => crosscheck.exec(),
+            Command::Currencies(currencies) => no_problems(currencies.exec()),
+            Command::Diagnose(diagnose) => no_problems(diagnose.exec()),
+            Command::ExplainPrice(explain_price) => no_problems(explain_price.exec()),
+            Command::Gui(gui) => no_problems(gui.exec()),
+            Command::Holdings(holdings) => no_problems(holdings.exec()),
             Command::Import(import) => import.exec(),
+            Command::Imports(imports) => no_problems(imports.exec()),
+            Command::Journal(journal) => journal.exec(),
+            Command::Migrate(migrate) => no_problems(migrate.exec()),
+            Command::Notify(notify) => no_problems(notify.exec()),
+            Command::Prices(prices) => prices.exec(),
             Command::Report(report) => report.exec(),
+            Command::Restore(restore) => no_problems(restore.exec()),
+            Command::Simulate(simulate) => no_problems(simulate.exec()),
+            Command::Sync(sync) => no_problems(sync.exec()),
+            Command::Verify(verify) => verify.exec(),
         }
     }
 }
 
-fn main() -> color_eyre::Result<()> {
-    pretty_env_logger::init();
+fn main() {
     let taxc: Taxc = argh::from_env();
 
-    taxc.cmd.exec()
+    let trace_trade = match taxc.cmd {
+        Command::Report(ref report) => report.trace_trade(),
+        _ => None,
+    };
+    let filter = match trace_trade {
+        // only output the computation path of a single trade
+        Some(id) => EnvFilter::new(cmd::report::cgt::trace_trade_filter(id)),
+        None if taxc.quiet => EnvFilter::new("error"),
+        None => EnvFilter::from_default_env(),
+    };
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+    match taxc.log_format {
+        LogFormat::Text => subscriber.init(),
+        LogFormat::Json => subscriber.json().init(),
+    }
+
+    let result = taxc.check_read_only().and_then(|()| taxc.cmd.exec());
+    // the journal is only appended to when data files can be modified
+    if let (Ok(_), Command::Report(ref report)) = (&result, &taxc.cmd) {
+        if let (false, Some(txs)) = (taxc.read_only, report.txs()) {
+            let args = std::env::args().skip(1).collect::<Vec<_>>().join(" ");
+            if let Err(err) = cmd::journal::record(txs, "report", args) {
+                tracing::warn!("Failed to record the report in the journal: {}", err);
+            }
+        }
+    }
+    match result {
+        Ok(problems) if problems > 0 => std::process::exit(1),
+        Ok(_) => (),
+        Err(err) => {
+            match taxc.log_format {
+                LogFormat::Text => eprintln!("Error: {:?}", err),
+                LogFormat::Json => tracing::error!(error = %format!("{:#}", err), "Failed"),
+            }
+            std::process::exit(2)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_writing_user_data_are_refused_when_read_only() {
+        let taxc = |args: &[&str]| Taxc::from_args(&["taxc"], args).unwrap();
+        for args in [
+            &[
+                "--read-only",
+                "currencies",
+                "add",
+                "LINK",
+                "--coingecko-id",
+                "chainlink",
+            ][..],
+            &["--read-only", "currencies", "alias", "IOTA", "MIOTA"],
+            &[
+                "--read-only",
+                "addresses",
+                "add",
+                "0xd8da6bf26964af9d7eed9e03e53415d37aa96045",
+            ],
+        ]
+        .iter()
+        {
+            assert!(
+                taxc(*args).check_read_only().is_err(),
+                "{} should be refused",
+                args.join(" ")
+            );
+            assert!(taxc(&args[1..]).check_read_only().is_ok());
+        }
+        assert!(taxc(&["--read-only", "currencies", "list"])
+            .check_read_only()
+            .is_ok());
+        assert!(taxc(&["--read-only", "addresses", "list"])
+            .check_read_only()
+            .is_ok());
+    }
 }
@@ -1,163 +1,681 @@
 use rust_decimal_macros::dec;
 use rusty_money::define_currency_set;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    path::PathBuf,
+};
 
 pub type Money<'a> = rusty_money::Money<'a, currencies::Currency>;
 
-define_currency_set!(
-    currencies {
-        EUR: {
-            code: "EUR",
-            exponent: 2,
-            locale: EnUs,
-            minor_units: 100,
-            name: "Euro",
-            symbol: "€",
-            symbol_first: true,
-        },
-        GBP: {
-            code: "GBP",
-            exponent: 2,
-            locale: EnUs,
-            minor_units: 100,
-            name: "British Pound",
-            symbol: "£",
-            symbol_first: true,
-        },
-        USD: {
-            code: "USD",
-            exponent: 2,
-            locale: EnUs,
-            minor_units: 100,
-            name: "United States Dollar",
-            symbol: "$",
-            symbol_first: true,
-        },
-        BTC: {
-            code: "BTC",
-            exponent: 8,
-            locale: EnUs,
-            minor_units: 100_000_000,
-            name: "Bitcoin",
-            symbol: "₿",
-            symbol_first: true,
-        },
-        ETH: {
-            code: "ETH",
-            exponent: 18,
-            locale: EnUs,
-            minor_units: 1_000_000_000_000_000_000,
-            name: "Ethereum",
-            symbol: "ETH",
-            symbol_first: false,
-        },
-        ETC: {
-            code: "ETC",
-            exponent: 18,
-            locale: EnUs,
-            minor_units: 1_000_000_000_000_000_000,
-            name: "Ethereum Classic",
-            symbol: "ETC",
-            symbol_first: false,
-        },
-        ATOM: {
-            code: "ATOM",
-            exponent: 6,
-            locale: EnUs,
-            minor_units: 1_000_000,
-            name: "Cosmos ATOM",
-            symbol: "ATOM",
-            symbol_first: false,
-        },
-        XRP: {
-            code: "XRP",
-            exponent: 6,
-            locale: EnUs,
-            minor_units: 1_000_000,
-            name: "Ripple",
-            symbol: "XRP",
-            symbol_first: false,
-        },
-        REP: {
-            code: "REP",
-            exponent: 18,
-            locale: EnUs,
-            minor_units: 1_000_000_000_000_000_000,
-            name: "Augur",
-            symbol: "REP",
-            symbol_first: false,
-        },
-        DGD: {
-            code: "DGD",
-            exponent: 18,
-            locale: EnUs,
-            minor_units: 1_000_000_000_000_000_000,
-            name: "Digix DAO",
-            symbol: "DGD",
-            symbol_first: false,
-        },
-        UKG: {
-            code: "UKG",
-            exponent: 18,
-            locale: EnUs,
-            minor_units: 1_000_000_000_000_000_000,
-            name: "Unikoin Gold",
-            symbol: "UKG",
-            symbol_first: false,
-        },
-        OMG: {
-            code: "OMG",
-            exponent: 18,
-            locale: EnUs,
-            minor_units: 1_000_000_000_000_000_000,
-            name: "OMG Network",
-            symbol: "OMG",
-            symbol_first: false,
-        },
-        DOT: {
-            code: "DOT",
-            exponent: 18,
-            locale: EnUs,
-            minor_units: 1_000_000_000_000_000_000,
-            name: "Polkadot",
-            symbol: "DOT",
-            symbol_first: false,
-        },
-        USDC: {
-            code: "USDC",
-            exponent: 6,
-            locale: EnUs,
-            minor_units: 1_000_000,
-            name: "USD Coin",
-            symbol: "USDC",
-            symbol_first: false,
-        }
+/// Defines the built in currencies with `define_currency_set!`, and their codes
+macro_rules! built_in_currencies {
+    ($($currency:ident: { code: $code:tt, $($field:ident: $value:tt,)+ }),+ $(,)?) => {
+        define_currency_set!(
+            currencies {
+                $($currency: { code: $code, $($field: $value,)+ }),+
+            }
+        );
+
+        /// Codes of the built in currencies
+        pub const CODES: &[&str] = &[$($code),+];
+    };
+}
+
+built_in_currencies!(
+    EUR: {
+        code: "EUR",
+        exponent: 2,
+        locale: EnUs,
+        minor_units: 100,
+        name: "Euro",
+        symbol: "€",
+        symbol_first: true,
+    },
+    GBP: {
+        code: "GBP",
+        exponent: 2,
+        locale: EnUs,
+        minor_units: 100,
+        name: "British Pound",
+        symbol: "£",
+        symbol_first: true,
+    },
+    USD: {
+        code: "USD",
+        exponent: 2,
+        locale: EnUs,
+        minor_units: 100,
+        name: "United States Dollar",
+        symbol: "$",
+        symbol_first: true,
+    },
+    BTC: {
+        code: "BTC",
+        exponent: 8,
+        locale: EnUs,
+        minor_units: 100_000_000,
+        name: "Bitcoin",
+        symbol: "₿",
+        symbol_first: true,
+    },
+    ETH: {
+        code: "ETH",
+        exponent: 18,
+        locale: EnUs,
+        minor_units: 1_000_000_000_000_000_000,
+        name: "Ethereum",
+        symbol: "ETH",
+        symbol_first: false,
+    },
+    ETC: {
+        code: "ETC",
+        exponent: 18,
+        locale: EnUs,
+        minor_units: 1_000_000_000_000_000_000,
+        name: "Ethereum Classic",
+        symbol: "ETC",
+        symbol_first: false,
+    },
+    ATOM: {
+        code: "ATOM",
+        exponent: 6,
+        locale: EnUs,
+        minor_units: 1_000_000,
+        name: "Cosmos ATOM",
+        symbol: "ATOM",
+        symbol_first: false,
+    },
+    XRP: {
+        code: "XRP",
+        exponent: 6,
+        locale: EnUs,
+        minor_units: 1_000_000,
+        name: "Ripple",
+        symbol: "XRP",
+        symbol_first: false,
+    },
+    REP: {
+        code: "REP",
+        exponent: 18,
+        locale: EnUs,
+        minor_units: 1_000_000_000_000_000_000,
+        name: "Augur",
+        symbol: "REP",
+        symbol_first: false,
+    },
+    DGD: {
+        code: "DGD",
+        exponent: 18,
+        locale: EnUs,
+        minor_units: 1_000_000_000_000_000_000,
+        name: "Digix DAO",
+        symbol: "DGD",
+        symbol_first: false,
+    },
+    UKG: {
+        code: "UKG",
+        exponent: 18,
+        locale: EnUs,
+        minor_units: 1_000_000_000_000_000_000,
+        name: "Unikoin Gold",
+        symbol: "UKG",
+        symbol_first: false,
+    },
+    OMG: {
+        code: "OMG",
+        exponent: 18,
+        locale: EnUs,
+        minor_units: 1_000_000_000_000_000_000,
+        name: "OMG Network",
+        symbol: "OMG",
+        symbol_first: false,
+    },
+    DOT: {
+        code: "DOT",
+        exponent: 18,
+        locale: EnUs,
+        minor_units: 1_000_000_000_000_000_000,
+        name: "Polkadot",
+        symbol: "DOT",
+        symbol_first: false,
+    },
+    USDC: {
+        code: "USDC",
+        exponent: 6,
+        locale: EnUs,
+        minor_units: 1_000_000,
+        name: "USD Coin",
+        symbol: "USDC",
+        symbol_first: false,
+    },
+    USDT: {
+        code: "USDT",
+        exponent: 6,
+        locale: EnUs,
+        minor_units: 1_000_000,
+        name: "Tether",
+        symbol: "USDT",
+        symbol_first: false,
+    },
+    BUSD: {
+        code: "BUSD",
+        exponent: 8,
+        locale: EnUs,
+        minor_units: 100_000_000,
+        name: "Binance USD",
+        symbol: "BUSD",
+        symbol_first: false,
+    },
+    BNB: {
+        code: "BNB",
+        exponent: 8,
+        locale: EnUs,
+        minor_units: 100_000_000,
+        name: "Binance Coin",
+        symbol: "BNB",
+        symbol_first: false,
+    },
+    SHIB: {
+        code: "SHIB",
+        exponent: 18,
+        locale: EnUs,
+        minor_units: 1_000_000_000_000_000_000,
+        name: "Shiba Inu",
+        symbol: "SHIB",
+        symbol_first: false,
+    },
+    XMR: {
+        code: "XMR",
+        exponent: 12,
+        locale: EnUs,
+        minor_units: 1_000_000_000_000,
+        name: "Monero",
+        symbol: "XMR",
+        symbol_first: false,
+    },
+    ZEC: {
+        code: "ZEC",
+        exponent: 8,
+        locale: EnUs,
+        minor_units: 100_000_000,
+        name: "Zcash",
+        symbol: "ZEC",
+        symbol_first: false,
+    },
+    LTC: {
+        code: "LTC",
+        exponent: 8,
+        locale: EnUs,
+        minor_units: 100_000_000,
+        name: "Litecoin",
+        symbol: "LTC",
+        symbol_first: false,
+    },
+    DOGE: {
+        code: "DOGE",
+        exponent: 8,
+        locale: EnUs,
+        minor_units: 100_000_000,
+        name: "Dogecoin",
+        symbol: "DOGE",
+        symbol_first: false,
+    },
+    BCH: {
+        code: "BCH",
+        exponent: 8,
+        locale: EnUs,
+        minor_units: 100_000_000,
+        name: "Bitcoin Cash",
+        symbol: "BCH",
+        symbol_first: false,
     }
 );
 
-// todo: make this return Result instead of panicking
-pub fn amount<'a>(currency: &str, amount: rust_decimal::Decimal) -> crate::Money<'a> {
-    let currency =
-        currencies::find(currency).expect(&format!("No currency with code {} found", currency));
+/// The kind of asset of a currency, which are pooled separately and taxed under different
+/// rules, so are reported separately
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Instrument {
+    /// Cryptocurrencies and fiat, the default
+    Crypto,
+    /// Tokenised stocks and other equity like instruments identified by an ISIN e.g. on eToro
+    Security,
+}
+
+impl Default for Instrument {
+    fn default() -> Self {
+        Instrument::Crypto
+    }
+}
+
+impl std::fmt::Display for Instrument {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Instrument::Crypto => write!(f, "crypto"),
+            Instrument::Security => write!(f, "security"),
+        }
+    }
+}
+
+impl std::str::FromStr for Instrument {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "crypto" => Ok(Instrument::Crypto),
+            "security" => Ok(Instrument::Security),
+            i => Err(format!(
+                "Unsupported instrument {}, expected crypto or security",
+                i
+            )),
+        }
+    }
+}
+
+/// A token added with `currencies add`, stored in the user config
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserCurrency {
+    pub code: String,
+    pub name: String,
+    pub exponent: u32,
+    /// Empty for securities, which are priced from a prices file
+    pub coingecko_id: String,
+    #[serde(default)]
+    pub instrument: Instrument,
+    /// The ISIN of the underlying security e.g. US0378331005, for tokenised stocks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub isin: Option<String>,
+}
+
+/// Whether the ISIN has the country code, identifier and check digit of ISO 6166
+pub fn is_valid_isin(isin: &str) -> bool {
+    let chars = isin.chars().collect::<Vec<_>>();
+    if chars.len() != 12
+        || !chars[..2].iter().all(|c| c.is_ascii_uppercase())
+        || !chars[2..11]
+            .iter()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+        || !chars[11].is_ascii_digit()
+    {
+        return false;
+    }
+    // letters are expanded to two digits A = 10 to Z = 35, then the Luhn check digit is applied
+    let digits = chars
+        .iter()
+        .flat_map(|c| {
+            let value = c.to_digit(36).expect("ISIN characters are alphanumeric");
+            if value >= 10 {
+                vec![value / 10, value % 10]
+            } else {
+                vec![value]
+            }
+        })
+        .collect::<Vec<_>>();
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, d)| {
+            if i % 2 == 1 {
+                let doubled = d * 2;
+                doubled / 10 + doubled % 10
+            } else {
+                *d
+            }
+        })
+        .sum();
+    sum % 10 == 0
+}
+
+/// A currency added by the user, leaked so it lives as long as the built in ones
+struct UserCurrencyRef {
+    currency: &'static currencies::Currency,
+    coingecko_id: &'static str,
+    instrument: Instrument,
+    isin: Option<&'static str>,
+}
+
+lazy_static::lazy_static! {
+    static ref USER_CURRENCIES: Vec<UserCurrencyRef> =
+        read_user_currencies()
+            .unwrap_or_else(|err| {
+                tracing::warn!("Unable to read user currencies: {}", err);
+                Vec::new()
+            })
+            .into_iter()
+            .map(|c| {
+                let leak = |s: String| -> &'static str { Box::leak(s.into_boxed_str()) };
+                let code = leak(c.code);
+                let currency = Box::leak(Box::new(currencies::Currency {
+                    code,
+                    exponent: c.exponent,
+                    locale: rusty_money::Locale::EnUs,
+                    minor_units: 10u64.pow(c.exponent),
+                    name: leak(c.name),
+                    symbol: code,
+                    symbol_first: false,
+                }));
+                UserCurrencyRef {
+                    currency,
+                    coingecko_id: leak(c.coingecko_id),
+                    instrument: c.instrument,
+                    isin: c.isin.map(leak),
+                }
+            })
+            .collect();
+}
+
+/// The file containing the user currencies, in the config directory
+pub fn user_currencies_path() -> Option<PathBuf> {
+    Some(crate::utils::config_dir()?.join("currencies.json"))
+}
+
+pub fn read_user_currencies() -> color_eyre::Result<Vec<UserCurrency>> {
+    match user_currencies_path() {
+        Some(path) if path.exists() => Ok(serde_json::from_reader(File::open(path)?)?),
+        _ => Ok(Vec::new()),
+    }
+}
+
+pub fn write_user_currencies(user_currencies: &[UserCurrency]) -> color_eyre::Result<()> {
+    let path = user_currencies_path().ok_or(color_eyre::eyre::eyre!(
+        "No config directory, set TAXC_CONFIG_DIR or HOME"
+    ))?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    serde_json::to_writer_pretty(File::create(path)?, user_currencies)?;
+    Ok(())
+}
+
+fn find_user(code: &str) -> Option<&'static UserCurrencyRef> {
+    USER_CURRENCIES
+        .iter()
+        .find(|user| user.currency.code == code)
+}
+
+lazy_static::lazy_static! {
+    static ref ALIASES: BTreeMap<String, String> =
+        read_aliases().unwrap_or_else(|err| {
+            tracing::warn!("Unable to read currency aliases: {}", err);
+            BTreeMap::new()
+        });
+}
+
+/// The file of the codes exchanges use for known currencies e.g. `{"IOTA": "MIOTA"}`, added with
+/// `currencies alias`, in the config directory
+pub fn aliases_path() -> Option<PathBuf> {
+    Some(crate::utils::config_dir()?.join("currency_aliases.json"))
+}
+
+pub fn read_aliases() -> color_eyre::Result<BTreeMap<String, String>> {
+    match aliases_path() {
+        Some(path) if path.exists() => Ok(serde_json::from_reader(File::open(path)?)?),
+        _ => Ok(BTreeMap::new()),
+    }
+}
+
+pub fn write_aliases(aliases: &BTreeMap<String, String>) -> color_eyre::Result<()> {
+    let path = aliases_path().ok_or(color_eyre::eyre::eyre!(
+        "No config directory, set TAXC_CONFIG_DIR or HOME"
+    ))?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    serde_json::to_writer_pretty(File::create(path)?, aliases)?;
+    Ok(())
+}
+
+fn find_code(code: &str) -> Option<&'static currencies::Currency> {
+    currencies::find(code).or_else(|| find_user(code).map(|user| user.currency))
+}
+
+/// Find a built in or user currency by its code, or by an alias of its code
+pub fn find(code: &str) -> Option<&'static currencies::Currency> {
+    find_code(code).or_else(|| ALIASES.get(code).and_then(|target| find_code(target)))
+}
+
+/// The number of single character insertions, deletions and substitutions between the codes
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    for (i, x) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, y) in b.iter().enumerate() {
+            let substitution = previous[j] + if x == *y { 0 } else { 1 };
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// The known currencies with codes close to the unknown code, closest first e.g. MIOTA for IOTA
+pub fn suggestions(code: &str) -> Vec<&'static str> {
+    let code = code.to_uppercase();
+    let mut close = CODES
+        .iter()
+        .cloned()
+        .chain(USER_CURRENCIES.iter().map(|user| user.currency.code))
+        .map(|known| (edit_distance(&code, known), known))
+        // a single typo in a short code, more in longer ones
+        .filter(|(distance, _)| *distance <= (code.chars().count() / 3).max(1))
+        .collect::<Vec<_>>();
+    close.sort();
+    close.into_iter().take(3).map(|(_, known)| known).collect()
+}
+
+/// The CoinGecko id of a user currency, none for securities
+pub fn user_coingecko_id(code: &str) -> Option<&'static str> {
+    find_user(code)
+        .map(|user| user.coingecko_id)
+        .filter(|id| !id.is_empty())
+}
+
+/// The kind of asset of a currency, only user currencies can be securities
+pub fn instrument(currency: &currencies::Currency) -> Instrument {
+    find_user(currency.code).map_or(Instrument::Crypto, |user| user.instrument)
+}
+
+/// The ISIN of a user currency which is a security
+pub fn isin(currency: &currencies::Currency) -> Option<&'static str> {
+    find_user(currency.code).and_then(|user| user.isin)
+}
+
+/// A currency code which isn't a built in or user currency, or an alias of either
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnknownCurrency {
+    pub code: String,
+    /// The known currencies with close codes to map it to, closest first
+    pub suggestions: Vec<&'static str>,
+}
+
+impl UnknownCurrency {
+    pub fn new(code: &str) -> Self {
+        UnknownCurrency {
+            code: code.to_string(),
+            suggestions: suggestions(code),
+        }
+    }
+}
+
+impl std::fmt::Display for UnknownCurrency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "No currency with code {} found", self.code)?;
+        match self.suggestions.as_slice() {
+            [] => write!(f, ", add it with `currencies add {}`", self.code),
+            suggestions => write!(
+                f,
+                ", did you mean {}? Map it with `currencies alias {} {}`, or add it with \
+                 `currencies add {}`",
+                suggestions.join(" or "),
+                self.code,
+                suggestions[0],
+                self.code
+            ),
+        }
+    }
+}
+
+impl std::error::Error for UnknownCurrency {}
+
+/// Find a currency like `find`, with the close matches if it isn't known
+pub fn lookup(code: &str) -> Result<&'static currencies::Currency, UnknownCurrency> {
+    find(code).ok_or_else(|| UnknownCurrency::new(code))
+}
+
+/// The amount of the currency with the code, rounded to the decimal places of the currency
+pub fn amount<'a>(
+    currency: &str,
+    amount: rust_decimal::Decimal,
+) -> Result<crate::Money<'a>, UnknownCurrency> {
+    let currency = lookup(currency)?;
     let rounded = amount.round_dp(currency.exponent);
-    rusty_money::Money::from_decimal(rounded, currency)
+    Ok(rusty_money::Money::from_decimal(rounded, currency))
 }
 
 pub fn zero(currency: &currencies::Currency) -> rusty_money::Money<currencies::Currency> {
     rusty_money::Money::from_decimal(dec!(0), currency)
 }
 
+#[derive(Debug, derive_more::From, derive_more::Display)]
+pub enum ParseMoneyError {
+    UnknownCurrency(UnknownCurrency),
+    InvalidAmount(rusty_money::MoneyError),
+}
+
+impl std::error::Error for ParseMoneyError {}
+
 pub fn parse_money_parts<'a>(
     currency: &str,
     amount: &str,
-) -> Result<crate::Money<'a>, rusty_money::MoneyError> {
-    let currency = currencies::find(currency).unwrap();
-    rusty_money::Money::from_str(amount, currency)
+) -> Result<crate::Money<'a>, ParseMoneyError> {
+    let currency = lookup(currency)?;
+    Ok(rusty_money::Money::from_str(amount, currency)?)
 }
 
+lazy_static::lazy_static! {
+    /// The configured decimal places to display amounts of each currency with
+    static ref DISPLAY_PRECISION: HashMap<String, u32> =
+        read_display_precision().unwrap_or_else(|err| {
+            tracing::warn!("Unable to read display precision: {}", err);
+            HashMap::new()
+        });
+}
+
+/// The file of the decimal places to display amounts of currencies with in reports, where fewer
+/// than the currency has e.g. `ETH = 8`, in the config directory
+pub fn display_precision_path() -> Option<PathBuf> {
+    Some(crate::utils::config_dir()?.join("display_precision.toml"))
+}
+
+fn read_display_precision() -> color_eyre::Result<HashMap<String, u32>> {
+    match display_precision_path() {
+        Some(path) if path.exists() => Ok(toml::from_str(&std::fs::read_to_string(path)?)?),
+        _ => Ok(HashMap::new()),
+    }
+}
+
+/// The decimal places to display amounts of the currency with: all of its own, unless fewer
+/// are configured in `display_precision.toml`
+pub fn display_precision(currency: &currencies::Currency) -> u32 {
+    configured_precision(&DISPLAY_PRECISION, currency)
+}
+
+fn configured_precision(configured: &HashMap<String, u32>, currency: &currencies::Currency) -> u32 {
+    configured
+        .get(currency.code)
+        .map_or(currency.exponent, |places| (*places).min(currency.exponent))
+}
+
+/// Formats the amount for reports, to the display precision of its currency
 pub fn display_amount(amt: &crate::Money) -> String {
+    format_amount(amt, display_precision(amt.currency()))
+}
+
+/// Formats the amount to all the decimal places of its currency, for data files which are read
+/// back such as the transactions csv
+pub fn exact_amount(amt: &crate::Money) -> String {
+    format_amount(amt, amt.currency().exponent)
+}
+
+fn format_amount(amt: &crate::Money, decimal_places: u32) -> String {
     let params = rusty_money::Params {
-        rounding: Some(amt.currency().exponent),
+        rounding: Some(decimal_places),
         ..Default::default()
     };
     rusty_money::Formatter::money(&amt, params)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn isins_are_checked_with_their_check_digit() {
+        assert!(is_valid_isin("US0378331005"));
+        assert!(is_valid_isin("GB0002634946"));
+        assert!(!is_valid_isin("US0378331006"), "Wrong check digit");
+        assert!(!is_valid_isin("US037833100"), "Too short");
+        assert!(!is_valid_isin("us0378331005"), "Lower case country code");
+    }
+
+    #[test]
+    fn amounts_of_unknown_currencies_are_errors() {
+        assert_eq!(
+            amount("BTC", dec!(0.123456789)).unwrap(),
+            rusty_money::Money::from_decimal(dec!(0.12345679), currencies::BTC)
+        );
+        assert_eq!(
+            amount("XYZ", dec!(1)).unwrap_err(),
+            UnknownCurrency {
+                code: "XYZ".into(),
+                suggestions: vec![]
+            }
+        );
+        assert!(matches!(
+            parse_money_parts("XYZ", "1"),
+            Err(ParseMoneyError::UnknownCurrency(_))
+        ));
+    }
+
+    #[test]
+    fn unknown_codes_suggest_the_closest_currencies() {
+        assert_eq!(edit_distance("IOTA", "MIOTA"), 1);
+        assert_eq!(edit_distance("USTD", "USDT"), 2);
+        assert_eq!(suggestions("usdd"), vec!["USD", "USDC", "USDT"]);
+        assert_eq!(suggestions("ETHW"), vec!["ETH"]);
+        assert!(
+            suggestions("XYZ").is_empty(),
+            "XMR and XRP are two typos away"
+        );
+        assert_eq!(
+            UnknownCurrency::new("ETHW").to_string(),
+            "No currency with code ETHW found, did you mean ETH? Map it with `currencies alias \
+             ETHW ETH`, or add it with `currencies add ETHW`"
+        );
+    }
+
+    #[test]
+    fn amounts_are_displayed_to_the_configured_precision() {
+        let configured: HashMap<String, u32> = toml::from_str("ETH = 8\nGBP = 4").unwrap();
+        assert_eq!(configured_precision(&configured, currencies::ETH), 8);
+        assert_eq!(
+            configured_precision(&configured, currencies::GBP),
+            2,
+            "No more places than the currency has"
+        );
+        assert_eq!(configured_precision(&configured, currencies::BTC), 8);
+
+        let eth = Money::from_decimal(dec!(1.123456789012345678), currencies::ETH);
+        assert_eq!(format_amount(&eth, 8), "1.12345679");
+        assert_eq!(exact_amount(&eth), "1.123456789012345678");
+        let btc = Money::from_decimal(dec!(0.1), currencies::BTC);
+        assert_eq!(
+            format_amount(&btc, configured_precision(&configured, currencies::BTC)),
+            "0.10000000"
+        );
+    }
+}
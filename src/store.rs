@@ -0,0 +1,457 @@
+//! A SQLite database of the imported transactions, the prices and the disposals of the last
+//! report, as an alternative to the csv files e.g. `taxc import csv kraken trades.csv --into-db
+//! taxc.db` then `taxc report --from-db taxc.db`.
+//!
+//! Imports are incremental: transactions already in the database are skipped, so overlapping
+//! exports can be imported again. Identical transactions e.g. two fills of an order at the same
+//! price and time are each kept, as long as the import has more of them than the database. The prices used by a report are kept, so later reports don't
+//! need the prices csv or to fetch them again. The disposals are replaced by each report, for
+//! querying the results with other tools.
+
+use crate::{
+    cmd::{
+        prices::{self, Prices},
+        report::cgt::Gains,
+    },
+    money::exact_amount,
+    trades::{TradeRecord, SCHEMA_VERSION},
+    transactions::Transaction,
+};
+use chrono::Utc;
+use color_eyre::eyre;
+use rusqlite::{params, params_from_iter, Connection};
+use rust_decimal::Decimal;
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    convert::TryFrom,
+    path::Path,
+    str::FromStr,
+};
+
+/// The version of the tables, stored in the `user_version` of the database. Version 0 had a
+/// unique constraint on the transactions, which dropped identical fills. Version 1 had no account
+/// column, the account label replaced the exchange.
+const STORE_VERSION: i64 = 2;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS transactions (
+    version INTEGER NOT NULL,
+    date_time TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    buy_asset TEXT NOT NULL,
+    buy_amount TEXT NOT NULL,
+    sell_asset TEXT NOT NULL,
+    sell_amount TEXT NOT NULL,
+    fee_asset TEXT NOT NULL,
+    fee_amount TEXT NOT NULL,
+    rate TEXT NOT NULL,
+    exchange TEXT NOT NULL,
+    exchange_id TEXT NOT NULL,
+    notes TEXT NOT NULL,
+    ownership_changed INTEGER,
+    received_date_time TEXT,
+    source TEXT NOT NULL,
+    imported_at TEXT NOT NULL,
+    account TEXT
+);
+CREATE INDEX IF NOT EXISTS transactions_by_key ON transactions (date_time, kind, buy_asset,
+    buy_amount, sell_asset, sell_amount, fee_asset, fee_amount, exchange, exchange_id, notes);
+CREATE TABLE IF NOT EXISTS prices (
+    base_currency TEXT NOT NULL,
+    quote_currency TEXT NOT NULL,
+    date_time TEXT NOT NULL,
+    rate TEXT NOT NULL,
+    PRIMARY KEY (base_currency, quote_currency, date_time)
+);
+CREATE TABLE IF NOT EXISTS disposals (
+    id INTEGER NOT NULL,
+    date_time TEXT NOT NULL,
+    tax_year TEXT NOT NULL,
+    asset TEXT NOT NULL,
+    amount TEXT NOT NULL,
+    proceeds TEXT NOT NULL,
+    allowable_costs TEXT NOT NULL,
+    gain TEXT NOT NULL,
+    calculated_at TEXT NOT NULL
+);
+";
+
+/// Create the tables, or bring those of an earlier version up to date
+fn migrate(conn: &mut Connection) -> color_eyre::Result<()> {
+    let version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    let tx = conn.transaction()?;
+    let existing: i64 = tx.query_row(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'transactions'",
+        [],
+        |row| row.get(0),
+    )?;
+    if version == 0 && existing > 0 {
+        // the unique constraint can only be dropped by copying the table
+        tx.execute_batch("ALTER TABLE transactions RENAME TO transactions_v0")?;
+        tx.execute_batch(SCHEMA)?;
+        tx.execute_batch(
+            "INSERT INTO transactions (version, date_time, kind, buy_asset, buy_amount, \
+             sell_asset, sell_amount, fee_asset, fee_amount, rate, exchange, exchange_id, notes, \
+             ownership_changed, received_date_time, source, imported_at) \
+             SELECT * FROM transactions_v0; DROP TABLE transactions_v0;",
+        )?;
+    } else if version == 1 && existing > 0 {
+        tx.execute_batch("ALTER TABLE transactions ADD COLUMN account TEXT")?;
+    }
+    tx.execute_batch(SCHEMA)?;
+    if version < 2 && existing > 0 {
+        // move the account labels e.g. binance:personal out of the exchange, as `migrate` does
+        // for the transactions csv
+        tx.execute(
+            "UPDATE transactions SET account = exchange, \
+             exchange = substr(exchange, 1, instr(exchange, ':') - 1) \
+             WHERE instr(exchange, ':') > 0",
+            [],
+        )?;
+        tx.execute(
+            "UPDATE transactions SET version = ?1 WHERE version < ?1",
+            params![SCHEMA_VERSION],
+        )?;
+    }
+    tx.pragma_update(None, "user_version", &STORE_VERSION)?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// The database of the transactions, prices and disposals
+pub struct Store {
+    conn: Connection,
+}
+
+impl Store {
+    /// Open the database, creating it and its tables if they don't exist
+    pub fn open(path: &Path) -> color_eyre::Result<Self> {
+        let mut conn = Connection::open(path)?;
+        migrate(&mut conn)?;
+        Ok(Store { conn })
+    }
+
+    /// Add the transactions which aren't already in the database, returning how many were added.
+    /// Transactions are the same if all their amounts, the exchange and its id and the notes are.
+    /// Each transaction already in the database accounts for one of the same in the import, so
+    /// identical fills are added until there are as many as in the import.
+    pub fn insert_transactions(
+        &mut self,
+        records: &[TradeRecord],
+        source: &str,
+    ) -> color_eyre::Result<usize> {
+        let imported_at = Utc::now().to_rfc3339();
+        let tx = self.conn.transaction()?;
+        let mut added = 0;
+        {
+            let mut count = tx.prepare(
+                "SELECT COUNT(*) FROM transactions WHERE date_time = ?1 AND kind = ?2 AND \
+                 buy_asset = ?3 AND buy_amount = ?4 AND sell_asset = ?5 AND sell_amount = ?6 AND \
+                 fee_asset = ?7 AND fee_amount = ?8 AND exchange = ?9 AND exchange_id = ?10 AND \
+                 notes = ?11 AND IFNULL(account, '') = ?12",
+            )?;
+            // the number of each transaction in the database before the import, and how many of
+            // them have been seen in the import
+            let mut seen: HashMap<[&str; 12], (i64, i64)> = HashMap::new();
+            let mut insert = tx.prepare(
+                "INSERT INTO transactions (version, date_time, kind, buy_asset, \
+                 buy_amount, sell_asset, sell_amount, fee_asset, fee_amount, rate, exchange, \
+                 exchange_id, notes, ownership_changed, received_date_time, source, imported_at, \
+                 account) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, \
+                 ?17, ?18)",
+            )?;
+            for record in records {
+                let key = [
+                    record.date_time.as_str(),
+                    &record.kind,
+                    &record.buy_asset,
+                    &record.buy_amount,
+                    &record.sell_asset,
+                    &record.sell_amount,
+                    &record.fee_asset,
+                    &record.fee_amount,
+                    &record.exchange,
+                    &record.exchange_id,
+                    &record.notes,
+                    record.account.as_deref().unwrap_or_default(),
+                ];
+                let (existing, imported) = match seen.entry(key) {
+                    Entry::Occupied(entry) => entry.into_mut(),
+                    Entry::Vacant(entry) => {
+                        let key = params_from_iter(entry.key().iter());
+                        let existing = count.query_row(key, |row| row.get(0))?;
+                        entry.insert((existing, 0))
+                    }
+                };
+                *imported += 1;
+                if *imported <= *existing {
+                    continue;
+                }
+                added += insert.execute(params![
+                    record.version,
+                    record.date_time,
+                    record.kind,
+                    record.buy_asset,
+                    record.buy_amount,
+                    record.sell_asset,
+                    record.sell_amount,
+                    record.fee_asset,
+                    record.fee_amount,
+                    record.rate.to_string(),
+                    record.exchange,
+                    record.exchange_id,
+                    record.notes,
+                    record.ownership_changed,
+                    record.received_date_time,
+                    source,
+                    imported_at,
+                    record.account,
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(added)
+    }
+
+    /// All the transactions, sorted by date
+    pub fn transactions<'a>(&self) -> color_eyre::Result<Vec<Transaction<'a>>> {
+        let mut select = self.conn.prepare(
+            "SELECT version, date_time, kind, buy_asset, buy_amount, sell_asset, sell_amount, \
+             fee_asset, fee_amount, rate, exchange, exchange_id, notes, ownership_changed, \
+             received_date_time, account FROM transactions ORDER BY date_time",
+        )?;
+        let rows = select.query_map([], |row| {
+            Ok((
+                TradeRecord {
+                    version: row.get(0)?,
+                    date_time: row.get(1)?,
+                    kind: row.get(2)?,
+                    buy_asset: row.get(3)?,
+                    buy_amount: row.get(4)?,
+                    sell_asset: row.get(5)?,
+                    sell_amount: row.get(6)?,
+                    fee_asset: row.get(7)?,
+                    fee_amount: row.get(8)?,
+                    rate: Decimal::new(0, 0),
+                    exchange: row.get(10)?,
+                    exchange_id: row.get(11)?,
+                    notes: row.get(12)?,
+                    ownership_changed: row.get(13)?,
+                    received_date_time: row.get(14)?,
+                    account: row.get(15)?,
+                },
+                row.get::<_, String>(9)?,
+            ))
+        })?;
+        let mut transactions = Vec::new();
+        for row in rows {
+            let (mut record, rate) = row?;
+            if record.version != SCHEMA_VERSION {
+                return Err(eyre::eyre!(
+                    "Transactions in the database are version {}, not the supported version {}",
+                    record.version,
+                    SCHEMA_VERSION
+                ));
+            }
+            record.rate = Decimal::from_str(&rate)?;
+            transactions.push(Transaction::try_from(record)?);
+        }
+        transactions.sort_by_key(|tx| tx.date_time());
+        Ok(transactions)
+    }
+
+    /// Add the prices, replacing any already in the database for the same pair and time
+    pub fn insert_prices(&mut self, prices: &Prices) -> color_eyre::Result<()> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut insert = tx.prepare(
+                "INSERT OR REPLACE INTO prices (base_currency, quote_currency, date_time, rate) \
+                 VALUES (?1, ?2, ?3, ?4)",
+            )?;
+            for record in prices.records() {
+                insert.execute(params![
+                    record.base_currency,
+                    record.quote_currency,
+                    record.date_time,
+                    record.rate.to_string(),
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// All the prices, none if there are none in the database
+    pub fn prices<'a>(&self) -> color_eyre::Result<Option<Prices<'a>>> {
+        let mut select = self.conn.prepare(
+            "SELECT base_currency, quote_currency, date_time, rate FROM prices ORDER BY date_time",
+        )?;
+        let rows = select.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+            ))
+        })?;
+        let mut records = Vec::new();
+        for row in rows {
+            let (base_currency, quote_currency, date_time, rate) = row?;
+            records.push(prices::Record {
+                base_currency,
+                quote_currency,
+                date_time,
+                rate: Decimal::from_str(&rate)?,
+            });
+        }
+        if records.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Prices::from_records(records)))
+    }
+
+    /// Replace the disposals with those of the latest report
+    pub fn replace_disposals(&mut self, gains: &Gains) -> color_eyre::Result<()> {
+        let calculated_at = Utc::now().to_rfc3339();
+        let tx = self.conn.transaction()?;
+        tx.execute("DELETE FROM disposals", [])?;
+        {
+            let mut insert = tx.prepare(
+                "INSERT INTO disposals (id, date_time, tax_year, asset, amount, proceeds, \
+                 allowable_costs, gain, calculated_at) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            )?;
+            for disposal in gains.disposals() {
+                let trade = disposal.trade();
+                insert.execute(params![
+                    disposal.id() as i64,
+                    trade.date_time.to_string(),
+                    disposal.tax_year().to_string(),
+                    trade.sell.currency().code,
+                    exact_amount(&trade.sell),
+                    disposal.proceeds().amount().to_string(),
+                    disposal.allowable_costs().amount().to_string(),
+                    disposal.gain().amount().to_string(),
+                    calculated_at,
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn imports_are_incremental_and_read_back() {
+        let txs = "\
+version,date_time,kind,buy_asset,buy_amount,sell_asset,sell_amount,fee_asset,fee_amount,rate,exchange,exchange_id,notes,ownership_changed,received_date_time
+5,2018-01-01T12:00:00+00:00,Buy,BTC,2,GBP,200,GBP,0,100,,,,,
+5,2018-02-01T12:00:00+00:00,Sell,GBP,300,BTC,1,GBP,0,300,,,,,
+";
+        let mut rdr = csv::Reader::from_reader(txs.as_bytes());
+        let records = rdr
+            .deserialize::<TradeRecord>()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let mut store = Store::open(Path::new(":memory:")).unwrap();
+
+        assert_eq!(
+            store.insert_transactions(&records[..1], "first").unwrap(),
+            1
+        );
+        assert_eq!(store.insert_transactions(&records, "second").unwrap(), 1);
+
+        let transactions = store.transactions().unwrap();
+        assert_eq!(transactions.len(), 2);
+        let sell = TradeRecord::from(&transactions[1]);
+        assert_eq!((sell.kind.as_str(), sell.rate), ("Sell", records[1].rate));
+        assert!(store.prices().unwrap().is_none());
+
+        let prices = Prices::read_csv(
+            "\
+base_currency,quote_currency,date_time,rate
+BTC,GBP,2018-01-01T00:00:00+00:00,10000
+BTC,GBP,2018-01-31T00:00:00+00:00,9000
+"
+            .as_bytes(),
+        )
+        .unwrap();
+        store.insert_prices(&prices).unwrap();
+        let stored = store.prices().unwrap().unwrap();
+        assert!(stored.covers(transactions[0].date_time().date()));
+        assert!(
+            !stored.covers(transactions[1].date_time().date()),
+            "The prices should be fetched again for the sale"
+        );
+    }
+
+    #[test]
+    fn identical_fills_are_each_imported() {
+        let txs = "\
+version,date_time,kind,buy_asset,buy_amount,sell_asset,sell_amount,fee_asset,fee_amount,rate,exchange,exchange_id,notes,ownership_changed,received_date_time
+5,2018-01-01T12:00:00+00:00,Buy,BTC,1,GBP,100,GBP,0,100,Kraken,,,,
+5,2018-01-01T12:00:00+00:00,Buy,BTC,1,GBP,100,GBP,0,100,Kraken,,,,
+5,2018-01-01T12:00:00+00:00,Buy,BTC,1,GBP,100,GBP,0,100,Kraken,,,,
+";
+        let mut rdr = csv::Reader::from_reader(txs.as_bytes());
+        let records = rdr
+            .deserialize::<TradeRecord>()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let mut store = Store::open(Path::new(":memory:")).unwrap();
+
+        assert_eq!(
+            store.insert_transactions(&records[..2], "first").unwrap(),
+            2
+        );
+        assert_eq!(
+            store.insert_transactions(&records[..2], "again").unwrap(),
+            0
+        );
+        assert_eq!(store.insert_transactions(&records, "more").unwrap(), 1);
+        assert_eq!(store.transactions().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn databases_of_version_0_are_migrated() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE transactions (version INTEGER NOT NULL, date_time TEXT NOT NULL, \
+             kind TEXT NOT NULL, buy_asset TEXT NOT NULL, buy_amount TEXT NOT NULL, \
+             sell_asset TEXT NOT NULL, sell_amount TEXT NOT NULL, fee_asset TEXT NOT NULL, \
+             fee_amount TEXT NOT NULL, rate TEXT NOT NULL, exchange TEXT NOT NULL, \
+             exchange_id TEXT NOT NULL, notes TEXT NOT NULL, ownership_changed INTEGER, \
+             received_date_time TEXT, source TEXT NOT NULL, imported_at TEXT NOT NULL, \
+             UNIQUE (date_time, kind, buy_asset, buy_amount, sell_asset, sell_amount, \
+             fee_asset, fee_amount, exchange, exchange_id, notes));
+             INSERT INTO transactions VALUES (4, '2018-01-01T12:00:00+00:00', 'Buy', 'BTC', \
+             '1', 'GBP', '100', 'GBP', '0', '100', 'binance:bot', '', '', NULL, NULL, 'old', '');",
+        )
+        .unwrap();
+
+        migrate(&mut conn).unwrap();
+        let duplicate = "INSERT INTO transactions SELECT * FROM transactions";
+        assert_eq!(conn.execute(duplicate, []).unwrap(), 1);
+        let version: i64 = conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, STORE_VERSION);
+
+        let store = Store { conn };
+        let transactions = store.transactions().unwrap();
+        assert_eq!(transactions.len(), 2);
+        assert_eq!(
+            (transactions[0].exchange(), transactions[0].account()),
+            (
+                Some(&"binance".to_string()),
+                Some(&"binance:bot".to_string())
+            ),
+            "The account label is moved out of the exchange"
+        );
+    }
+}
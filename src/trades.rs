@@ -1,11 +1,11 @@
 use crate::{
-    money::{currencies::Currency, display_amount, parse_money_parts, zero},
+    money::{currencies::Currency, exact_amount, zero},
     Money,
 };
 use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, io::Read, ops::Add};
+use std::{collections::HashMap, ops::Add};
 
 #[derive(Clone)]
 pub struct TradeAmount<'a> {
@@ -33,24 +33,42 @@ pub struct Trade<'a> {
     pub fee: Money<'a>,
     pub rate: Decimal,
     pub exchange: Option<String>,
+    /// The label of the account on the exchange e.g. `binance:personal`, for users with more
+    /// than one
+    pub account: Option<String>,
+    /// Free text description e.g. the counterparty of an OTC trade
+    pub notes: Option<String>,
 }
 
 impl<'a> Trade<'a> {
-    /// Unique key for Trade
-    pub fn key(&self) -> TradeKey {
+    /// Unique key for Trade, the `sequence` number disambiguates otherwise identical trades at the
+    /// same time e.g. bot trades filled in the same second
+    pub fn key(&self, sequence: usize) -> TradeKey {
         TradeKey {
+            sequence,
             date_time: self.date_time,
             buy: self.buy.to_string(),
             sell: self.sell.to_string(),
         }
     }
 
+    /// The rate implied by the buy and sell amounts: the amount of the quote currency per unit of
+    /// the base currency
+    pub fn implied_rate(&self) -> Option<Decimal> {
+        let (base, quote) = match self.kind {
+            TradeKind::Buy => (&self.buy, &self.sell),
+            TradeKind::Sell => (&self.sell, &self.buy),
+        };
+        quote.amount().checked_div(*base.amount())
+    }
+
     /// Use to group similar trades on the same day
     pub fn key_by_day(&self) -> TradeByDayKey<'a> {
         TradeByDayKey {
             date: self.date_time.date(),
             kind: self.kind.clone(),
             exchange: self.exchange.clone(),
+            account: self.account.clone(),
             buy: self.buy.currency(),
             sell: self.sell.currency(),
             fee: self.fee.currency(),
@@ -58,39 +76,108 @@ impl<'a> Trade<'a> {
     }
 }
 
-impl<'a> From<TradeRecord> for Trade<'a> {
-    fn from(tr: TradeRecord) -> Self {
-        let date_time = DateTime::parse_from_rfc3339(tr.date_time.as_ref())
-            .expect(format!("Invalid date_time {}", tr.date_time).as_ref())
-            .naive_utc();
-        let exchange = if tr.exchange == "" {
-            None
-        } else {
-            Some(tr.exchange.clone())
-        };
-        let buy = parse_money_parts(&tr.buy_asset, &tr.buy_amount)
-            .expect(format!("BUY amount: {}", tr.buy_amount).as_ref());
-        let sell = parse_money_parts(&tr.sell_asset, &tr.sell_amount)
-            .expect(format!("SELL amount: {}", tr.sell_amount).as_ref());
-        let fee = parse_money_parts(&tr.fee_asset, &tr.fee_amount)
-            .expect(format!("FEE amount: {}", tr.fee_amount).as_ref());
-        let kind = match tr.kind.as_ref() {
-            "Buy" => TradeKind::Buy,
-            "Sell" => TradeKind::Sell,
-            x => panic!("Invalid trade kind {}", x),
-        };
-        Trade {
+/// Constructs a `Trade`, validating the amounts and inferring the rate from them if not given
+pub struct TradeBuilder<'a> {
+    date_time: NaiveDateTime,
+    kind: TradeKind,
+    buy: Money<'a>,
+    sell: Money<'a>,
+    fee: Option<Money<'a>>,
+    rate: Option<Decimal>,
+    exchange: Option<String>,
+    account: Option<String>,
+    notes: Option<String>,
+}
+
+impl<'a> TradeBuilder<'a> {
+    pub fn new(date_time: NaiveDateTime, kind: TradeKind, buy: Money<'a>, sell: Money<'a>) -> Self {
+        TradeBuilder {
             date_time,
+            kind,
             buy,
             sell,
-            fee,
-            rate: tr.rate,
-            exchange,
-            kind,
+            fee: None,
+            rate: None,
+            exchange: None,
+            account: None,
+            notes: None,
+        }
+    }
+
+    /// The fee paid, defaults to zero in the currency sold
+    pub fn fee(mut self, fee: Money<'a>) -> Self {
+        self.fee = Some(fee);
+        self
+    }
+
+    /// The amount of the quote currency per unit of the base currency, defaults to the rate
+    /// implied by the buy and sell amounts
+    pub fn rate(mut self, rate: Decimal) -> Self {
+        self.rate = Some(rate);
+        self
+    }
+
+    pub fn exchange<S: Into<String>>(mut self, exchange: S) -> Self {
+        self.exchange = Some(exchange.into());
+        self
+    }
+
+    pub fn account<S: Into<String>>(mut self, account: S) -> Self {
+        self.account = Some(account.into());
+        self
+    }
+
+    pub fn notes<S: Into<String>>(mut self, notes: S) -> Self {
+        self.notes = Some(notes.into());
+        self
+    }
+
+    pub fn build(self) -> Result<Trade<'a>, TradeError> {
+        if self.buy.currency() == self.sell.currency() {
+            return Err(TradeError::SameCurrency(
+                self.buy.currency().code.to_string(),
+            ));
+        }
+        let fee = self.fee.unwrap_or_else(|| zero(self.sell.currency()));
+        for (name, money) in [("buy", &self.buy), ("sell", &self.sell), ("fee", &fee)].iter() {
+            if money.amount().is_sign_negative() {
+                return Err(TradeError::NegativeAmount(*name));
+            }
         }
+        let mut trade = Trade {
+            date_time: self.date_time,
+            kind: self.kind,
+            buy: self.buy,
+            sell: self.sell,
+            fee,
+            rate: Decimal::new(0, 0),
+            exchange: self.exchange,
+            account: self.account,
+            notes: self.notes,
+        };
+        trade.rate = match self.rate {
+            Some(rate) if rate.is_sign_negative() => return Err(TradeError::NegativeRate),
+            Some(rate) => rate,
+            None => trade.implied_rate().ok_or(TradeError::UnknownRate)?,
+        };
+        Ok(trade)
     }
 }
 
+#[derive(Debug, PartialEq, derive_more::Display)]
+pub enum TradeError {
+    #[display(fmt = "Trade should not buy and sell the same currency {}", _0)]
+    SameCurrency(String),
+    #[display(fmt = "The {} amount should not be negative", _0)]
+    NegativeAmount(&'static str),
+    #[display(fmt = "The rate should not be negative")]
+    NegativeRate,
+    #[display(fmt = "The rate can't be inferred from a zero amount")]
+    UnknownRate,
+}
+
+impl std::error::Error for TradeError {}
+
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub enum TradeKind {
     Buy,
@@ -99,6 +186,7 @@ pub enum TradeKind {
 
 #[derive(Eq, PartialEq, Hash)]
 pub struct TradeKey {
+    sequence: usize,
     date_time: NaiveDateTime,
     buy: String,
     sell: String,
@@ -109,6 +197,7 @@ pub struct TradeByDayKey<'a> {
     date: NaiveDate,
     kind: TradeKind,
     exchange: Option<String>,
+    account: Option<String>,
     buy: &'a Currency,
     sell: &'a Currency,
     fee: &'a Currency,
@@ -119,6 +208,7 @@ impl<'a> std::hash::Hash for TradeByDayKey<'a> {
         self.date.hash(state);
         self.kind.hash(state);
         self.exchange.hash(state);
+        self.account.hash(state);
         self.buy.code.hash(state);
         self.sell.code.hash(state);
         self.fee.code.hash(state);
@@ -126,13 +216,14 @@ impl<'a> std::hash::Hash for TradeByDayKey<'a> {
 }
 
 /// groups trades that occur for a currency on the same day/account
-pub fn group_trades_by_day<'a>(trades: &'a [Trade<'a>]) -> Vec<Trade<'a>> {
+pub fn group_trades_by_day<'a, 'b>(trades: &'b [Trade<'a>]) -> Vec<Trade<'a>> {
     let mut days = HashMap::new();
     for trade in trades.iter() {
         let day = days.entry(trade.key_by_day()).or_insert(Vec::new());
         day.push(trade);
     }
-    days.iter()
+    let mut grouped = days
+        .iter()
         .map(|(key, day_trades)| {
             let (total_buy, total_sell, total_fee) = day_trades.iter().fold(
                 (zero(&key.buy), zero(&key.sell), zero(&key.fee)),
@@ -174,18 +265,36 @@ pub fn group_trades_by_day<'a>(trades: &'a [Trade<'a>]) -> Vec<Trade<'a>> {
             Trade {
                 date_time: latest_trade.date_time,
                 exchange: key.exchange.clone(),
+                account: key.account.clone(),
                 buy: total_buy,
                 sell: total_sell,
                 fee: total_fee,
                 rate: average_rate,
+                notes: None,
                 kind: key.kind.clone(),
             }
         })
-        .collect()
+        .collect::<Vec<_>>();
+    // the days are grouped in a HashMap, so order them for consistent output
+    grouped.sort_by_key(|trade| {
+        (
+            trade.date_time,
+            trade.buy.currency().code,
+            trade.sell.currency().code,
+            trade.exchange.clone(),
+            trade.account.clone(),
+        )
+    });
+    grouped
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Current version of the transactions csv schema, bump this and add a migration in
+/// `cmd::migrate` when changing the columns of `TradeRecord`.
+pub const SCHEMA_VERSION: u32 = 5;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct TradeRecord {
+    pub version: u32,
     pub date_time: String,
     pub kind: String,
     pub buy_asset: String,
@@ -196,23 +305,42 @@ pub struct TradeRecord {
     pub fee_amount: String,
     pub rate: Decimal,
     pub exchange: String,
+    /// The id of the trade or transaction on the exchange
+    pub exchange_id: String,
+    pub notes: String,
+    /// Whether a transfer changed the beneficial ownership of the asset e.g. when posted as
+    /// collateral to a lender, empty for trades
+    #[serde(default)]
+    pub ownership_changed: Option<bool>,
+    /// When the tokens of an ICO contribution were received, empty for other transactions
+    #[serde(default)]
+    pub received_date_time: Option<String>,
+    /// The label of the account on the exchange e.g. `binance:personal`, empty if there's only
+    /// one
+    #[serde(default)]
+    pub account: Option<String>,
 }
 
 impl<'a> From<&Trade<'a>> for TradeRecord {
     fn from(trade: &Trade) -> Self {
-        let date_time = DateTime::<Utc>::from_utc(trade.date_time, Utc)
-            .to_rfc3339();
+        let date_time = DateTime::<Utc>::from_utc(trade.date_time, Utc).to_rfc3339();
 
         TradeRecord {
+            version: SCHEMA_VERSION,
             date_time,
             buy_asset: trade.buy.currency().code.to_string(),
-            buy_amount: display_amount(&trade.buy),
+            buy_amount: exact_amount(&trade.buy),
             sell_asset: trade.sell.currency().code.to_string(),
-            sell_amount: display_amount(&trade.sell),
+            sell_amount: exact_amount(&trade.sell),
             fee_asset: trade.fee.currency().code.to_string(),
-            fee_amount: display_amount(&trade.fee),
+            fee_amount: exact_amount(&trade.fee),
             rate: trade.rate,
             exchange: trade.exchange.clone().unwrap_or(String::new()),
+            exchange_id: String::new(),
+            notes: trade.notes.clone().unwrap_or(String::new()),
+            ownership_changed: None,
+            received_date_time: None,
+            account: trade.account.clone(),
             kind: match &trade.kind {
                 TradeKind::Buy => "Buy",
                 TradeKind::Sell => "Sell",
@@ -222,13 +350,74 @@ impl<'a> From<&Trade<'a>> for TradeRecord {
     }
 }
 
-pub fn read_csv<'a, R>(reader: R) -> color_eyre::Result<Vec<Trade<'a>>>
-where
-    R: Read,
-{
-    let mut rdr = csv::Reader::from_reader(reader);
-    let records: Result<Vec<TradeRecord>, _> = rdr.deserialize::<TradeRecord>().collect();
-    let mut trades: Vec<Trade> = records?.into_iter().map(Into::into).collect();
-    trades.sort_by(|tx1, tx2| tx1.date_time.cmp(&tx2.date_time));
-    Ok(trades)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::money::{
+        amount,
+        currencies::{BTC, GBP},
+    };
+    use rust_decimal_macros::dec;
+
+    fn date_time() -> NaiveDateTime {
+        NaiveDate::from_ymd(2021, 1, 31).and_hms(14, 0, 0)
+    }
+
+    #[test]
+    fn builder_infers_rate_and_zero_fee() {
+        let trade = TradeBuilder::new(
+            date_time(),
+            TradeKind::Buy,
+            amount("BTC", dec!(2)).unwrap(),
+            amount("GBP", dec!(50_000)).unwrap(),
+        )
+        .build()
+        .unwrap();
+
+        assert_eq!(trade.rate, dec!(25_000));
+        assert_eq!(trade.fee, Money::from_major(0, GBP));
+        assert_eq!(trade.exchange, None);
+    }
+
+    #[test]
+    fn builder_keeps_explicit_rate() {
+        let trade = TradeBuilder::new(
+            date_time(),
+            TradeKind::Sell,
+            amount("GBP", dec!(50_000)).unwrap(),
+            amount("BTC", dec!(2)).unwrap(),
+        )
+        .rate(dec!(24_000))
+        .fee(Money::from_decimal(dec!(0.001), BTC))
+        .exchange("OTC")
+        .build()
+        .unwrap();
+
+        assert_eq!(trade.rate, dec!(24_000));
+        assert_eq!(trade.exchange, Some("OTC".to_string()));
+    }
+
+    #[test]
+    fn builder_rejects_invalid_trades() {
+        let build = |(buy, buy_amount): (&str, Decimal), (sell, sell_amount): (&str, Decimal)| {
+            let buy = amount(buy, buy_amount).unwrap();
+            let sell = amount(sell, sell_amount).unwrap();
+            TradeBuilder::new(date_time(), TradeKind::Buy, buy, sell)
+                .build()
+                .err()
+        };
+
+        assert_eq!(
+            build(("BTC", dec!(-1)), ("GBP", dec!(100))),
+            Some(TradeError::NegativeAmount("buy"))
+        );
+        assert_eq!(
+            build(("BTC", dec!(1)), ("BTC", dec!(1))),
+            Some(TradeError::SameCurrency("BTC".into()))
+        );
+        assert_eq!(
+            build(("BTC", dec!(0)), ("GBP", dec!(100))),
+            Some(TradeError::UnknownRate)
+        );
+    }
 }
@@ -0,0 +1,691 @@
+use crate::{
+    money::{exact_amount, parse_money_parts, zero, ParseMoneyError},
+    trades::{self, Trade, TradeKind, TradeRecord, SCHEMA_VERSION},
+    Money,
+};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use color_eyre::eyre;
+use rust_decimal::Decimal;
+use std::{convert::TryFrom, fmt, io::Read, str::FromStr};
+
+/// Decimal places of a rate recomputed from the buy and sell amounts
+const RATE_DECIMAL_PLACES: u32 = 12;
+
+/// A normalised transaction imported from an exchange or wallet
+#[derive(Clone)]
+pub enum Transaction<'a> {
+    /// Exchange of one currency for another
+    Trade(Trade<'a>),
+    /// Contribution to an ICO or presale, for tokens received at a later date
+    Contribution(Contribution<'a>),
+    /// Transfer in from another account e.g. collateral returned from a lender
+    Deposit(Movement<'a>),
+    /// Transfer out to another account e.g. collateral posted to a lender
+    Withdrawal(Movement<'a>),
+    /// Received as income e.g. staking rewards, lending interest, mining
+    Income(Movement<'a>),
+    /// Spent on goods or services
+    Spend(Movement<'a>),
+    /// Paid as a deductible expense e.g. loan interest or a subscription fee
+    Expense(Movement<'a>),
+    /// Given away to another person
+    Gift(Movement<'a>),
+    /// Paid as a standalone fee e.g. a network fee
+    Fee(Movement<'a>),
+    /// Received as the result of a chain split
+    Fork(Movement<'a>),
+    /// Received for free from a token distribution
+    Airdrop(Movement<'a>),
+    /// Lost, stolen or otherwise of negligible value
+    Loss(Movement<'a>),
+}
+
+/// Contribution of e.g. ETH to an ICO, recorded as a single acquisition of the tokens at the time
+/// of the contribution so the cost is the GBP value of the contribution, even though the tokens
+/// were only received later.
+#[derive(Clone)]
+pub struct Contribution<'a> {
+    /// The acquisition of the tokens, buying the tokens and selling the contribution
+    pub trade: Trade<'a>,
+    /// When the tokens were received
+    pub received: NaiveDateTime,
+}
+
+/// Movement of a single currency in or out of an account
+#[derive(Clone)]
+pub struct Movement<'a> {
+    pub date_time: NaiveDateTime,
+    pub amount: Money<'a>,
+    pub fee: Money<'a>,
+    pub exchange: Option<String>,
+    /// The label of the account on the exchange e.g. `binance:personal`, for users with more
+    /// than one
+    pub account: Option<String>,
+    /// For transfers, whether the beneficial ownership of the asset changed. For example
+    /// collateral posted to a lender which is free to use it, depending on the terms.
+    pub ownership_changed: bool,
+    /// Free text description e.g. what an expense was paid for
+    pub notes: Option<String>,
+}
+
+impl<'a> Transaction<'a> {
+    /// Create a transaction of the given kind, for non trade kinds `buy` and `sell` are the
+    /// inflow and outflow respectively, only one of which should be present.
+    fn new(
+        kind: TransactionKind,
+        date_time: NaiveDateTime,
+        buy: Option<Money<'a>>,
+        sell: Option<Money<'a>>,
+        fee: Option<Money<'a>>,
+        rate: Decimal,
+        exchange: Option<String>,
+    ) -> Result<Self, RecordError> {
+        let missing = |leg| RecordError::MissingAmount(kind, leg);
+        if let Some(trade_kind) = kind.trade_kind() {
+            let buy = buy.ok_or_else(|| missing("BUY"))?;
+            let sell = sell.ok_or_else(|| missing("SELL"))?;
+            let fee = fee.unwrap_or(zero(sell.currency()));
+            return Ok(Transaction::Trade(Trade {
+                date_time,
+                kind: trade_kind,
+                buy,
+                sell,
+                fee,
+                rate,
+                exchange,
+                account: None,
+                notes: None,
+            }));
+        }
+        let amount = if kind.is_inflow() {
+            buy.ok_or_else(|| missing("BUY"))?
+        } else {
+            sell.ok_or_else(|| missing("SELL"))?
+        };
+        let movement = Movement {
+            date_time,
+            fee: fee.unwrap_or(zero(amount.currency())),
+            amount,
+            exchange,
+            account: None,
+            ownership_changed: false,
+            notes: None,
+        };
+        Ok(match kind {
+            TransactionKind::Deposit => Transaction::Deposit(movement),
+            TransactionKind::Withdrawal => Transaction::Withdrawal(movement),
+            TransactionKind::Income => Transaction::Income(movement),
+            TransactionKind::Spend => Transaction::Spend(movement),
+            TransactionKind::Expense => Transaction::Expense(movement),
+            TransactionKind::Gift => Transaction::Gift(movement),
+            TransactionKind::Fee => Transaction::Fee(movement),
+            TransactionKind::Fork => Transaction::Fork(movement),
+            TransactionKind::Airdrop => Transaction::Airdrop(movement),
+            TransactionKind::Loss => Transaction::Loss(movement),
+            TransactionKind::Buy | TransactionKind::Sell | TransactionKind::Contribution => {
+                unreachable!("handled above")
+            }
+        })
+    }
+
+    pub fn kind(&self) -> TransactionKind {
+        match self {
+            Transaction::Trade(trade) => match trade.kind {
+                TradeKind::Buy => TransactionKind::Buy,
+                TradeKind::Sell => TransactionKind::Sell,
+            },
+            Transaction::Contribution(_) => TransactionKind::Contribution,
+            Transaction::Deposit(_) => TransactionKind::Deposit,
+            Transaction::Withdrawal(_) => TransactionKind::Withdrawal,
+            Transaction::Income(_) => TransactionKind::Income,
+            Transaction::Spend(_) => TransactionKind::Spend,
+            Transaction::Expense(_) => TransactionKind::Expense,
+            Transaction::Gift(_) => TransactionKind::Gift,
+            Transaction::Fee(_) => TransactionKind::Fee,
+            Transaction::Fork(_) => TransactionKind::Fork,
+            Transaction::Airdrop(_) => TransactionKind::Airdrop,
+            Transaction::Loss(_) => TransactionKind::Loss,
+        }
+    }
+
+    /// The trade of trades and contributions
+    pub fn trade(&self) -> Option<&Trade<'a>> {
+        match self {
+            Transaction::Trade(trade) => Some(trade),
+            Transaction::Contribution(contribution) => Some(&contribution.trade),
+            _ => None,
+        }
+    }
+
+    fn trade_mut(&mut self) -> Option<&mut Trade<'a>> {
+        match self {
+            Transaction::Trade(trade) => Some(trade),
+            Transaction::Contribution(contribution) => Some(&mut contribution.trade),
+            _ => None,
+        }
+    }
+
+    /// The currency movement for all non trade transactions
+    pub fn movement(&self) -> Option<&Movement<'a>> {
+        match self {
+            Transaction::Trade(_) | Transaction::Contribution(_) => None,
+            Transaction::Deposit(m)
+            | Transaction::Withdrawal(m)
+            | Transaction::Income(m)
+            | Transaction::Spend(m)
+            | Transaction::Expense(m)
+            | Transaction::Gift(m)
+            | Transaction::Fee(m)
+            | Transaction::Fork(m)
+            | Transaction::Airdrop(m)
+            | Transaction::Loss(m) => Some(m),
+        }
+    }
+
+    fn movement_mut(&mut self) -> Option<&mut Movement<'a>> {
+        match self {
+            Transaction::Trade(_) | Transaction::Contribution(_) => None,
+            Transaction::Deposit(m)
+            | Transaction::Withdrawal(m)
+            | Transaction::Income(m)
+            | Transaction::Spend(m)
+            | Transaction::Expense(m)
+            | Transaction::Gift(m)
+            | Transaction::Fee(m)
+            | Transaction::Fork(m)
+            | Transaction::Airdrop(m)
+            | Transaction::Loss(m) => Some(m),
+        }
+    }
+
+    pub fn date_time(&self) -> NaiveDateTime {
+        match self.trade() {
+            Some(trade) => trade.date_time,
+            None => self.movement().expect("Non trade is a movement").date_time,
+        }
+    }
+
+    /// The amount received
+    pub fn buy(&self) -> Option<&Money<'a>> {
+        match self.trade() {
+            Some(trade) => Some(&trade.buy),
+            None if self.kind().is_inflow() => self.movement().map(|m| &m.amount),
+            None => None,
+        }
+    }
+
+    /// The amount sent
+    pub fn sell(&self) -> Option<&Money<'a>> {
+        match self.trade() {
+            Some(trade) => Some(&trade.sell),
+            None if !self.kind().is_inflow() => self.movement().map(|m| &m.amount),
+            None => None,
+        }
+    }
+
+    pub fn fee(&self) -> &Money<'a> {
+        match self.trade() {
+            Some(trade) => &trade.fee,
+            None => &self.movement().expect("Non trade is a movement").fee,
+        }
+    }
+
+    /// The trade rate, zero for non trade transactions
+    pub fn rate(&self) -> Decimal {
+        match self.trade() {
+            Some(trade) => trade.rate,
+            None => Decimal::new(0, 0),
+        }
+    }
+
+    pub fn exchange(&self) -> Option<&String> {
+        match self.trade() {
+            Some(trade) => trade.exchange.as_ref(),
+            None => self
+                .movement()
+                .expect("Non trade is a movement")
+                .exchange
+                .as_ref(),
+        }
+    }
+
+    /// The label of the account on the exchange, if any
+    pub fn account(&self) -> Option<&String> {
+        match self.trade() {
+            Some(trade) => trade.account.as_ref(),
+            None => self
+                .movement()
+                .expect("Non trade is a movement")
+                .account
+                .as_ref(),
+        }
+    }
+
+    /// Tag the transaction with an account label e.g. `binance:personal`, keeping the exchange
+    pub fn set_account(&mut self, account: &str) {
+        match self.trade_mut() {
+            Some(trade) => trade.account = Some(account.to_string()),
+            None => {
+                self.movement_mut()
+                    .expect("Non trade is a movement")
+                    .account = Some(account.to_string())
+            }
+        }
+    }
+
+    /// Whether the exchange or the account of the transaction matches the account filter, see
+    /// [`account_matches`]
+    pub fn is_on_account(&self, filter: &str) -> bool {
+        venue_matches(
+            self.exchange().map(|e| e.as_str()),
+            self.account().map(|a| a.as_str()),
+            filter,
+        )
+    }
+
+    /// Replace the rate of a trade with the rate implied by its buy and sell amounts, if it is
+    /// missing or differs by more than the tolerance as a proportion of the implied rate.
+    /// Returns the original rate if it was replaced.
+    pub fn recompute_rate(&mut self, tolerance: Decimal) -> Option<Decimal> {
+        let trade = self.trade_mut()?;
+        let implied_rate = trade.implied_rate()?.round_dp(RATE_DECIMAL_PLACES);
+        if implied_rate.is_zero() {
+            return None;
+        }
+        let difference = (implied_rate - trade.rate).abs() / implied_rate;
+        if trade.rate.is_zero() || difference > tolerance {
+            Some(std::mem::replace(&mut trade.rate, implied_rate))
+        } else {
+            None
+        }
+    }
+}
+
+/// Whether an exchange or account label matches the account filter, either exactly or as a
+/// prefix e.g. `binance` matches `binance:personal` and `binance:bot`. Case insensitive.
+pub fn account_matches(exchange: Option<&str>, account: &str) -> bool {
+    let exchange = exchange.unwrap_or_default().to_lowercase();
+    let account = account.to_lowercase();
+    exchange == account || exchange.starts_with(&format!("{}:", account))
+}
+
+/// Whether the exchange or the label of the account on it matches the account filter, so that
+/// `binance` matches the trades of all the accounts on Binance
+pub fn venue_matches(exchange: Option<&str>, account: Option<&str>, filter: &str) -> bool {
+    account_matches(exchange, filter) || account.map_or(false, |a| account_matches(Some(a), filter))
+}
+
+impl<'a> From<Trade<'a>> for Transaction<'a> {
+    fn from(trade: Trade<'a>) -> Self {
+        Transaction::Trade(trade)
+    }
+}
+
+/// The kind of a transaction, as stored in the `kind` column of the transactions csv
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum TransactionKind {
+    Buy,
+    Sell,
+    Contribution,
+    Deposit,
+    Withdrawal,
+    Income,
+    Spend,
+    Expense,
+    Gift,
+    Fee,
+    Fork,
+    Airdrop,
+    Loss,
+}
+
+impl TransactionKind {
+    fn trade_kind(&self) -> Option<TradeKind> {
+        match self {
+            TransactionKind::Buy => Some(TradeKind::Buy),
+            TransactionKind::Sell => Some(TradeKind::Sell),
+            // the contribution is a buy of the tokens
+            TransactionKind::Contribution => Some(TradeKind::Buy),
+            _ => None,
+        }
+    }
+
+    /// Whether a non trade transaction of this kind is received, or otherwise sent
+    pub fn is_inflow(&self) -> bool {
+        match self {
+            TransactionKind::Deposit
+            | TransactionKind::Income
+            | TransactionKind::Fork
+            | TransactionKind::Airdrop => true,
+            TransactionKind::Withdrawal
+            | TransactionKind::Spend
+            | TransactionKind::Expense
+            | TransactionKind::Gift
+            | TransactionKind::Fee
+            | TransactionKind::Loss => false,
+            TransactionKind::Buy | TransactionKind::Sell | TransactionKind::Contribution => false,
+        }
+    }
+}
+
+impl fmt::Display for TransactionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            TransactionKind::Buy => "Buy",
+            TransactionKind::Sell => "Sell",
+            TransactionKind::Contribution => "Contribution",
+            TransactionKind::Deposit => "Deposit",
+            TransactionKind::Withdrawal => "Withdrawal",
+            TransactionKind::Income => "Income",
+            TransactionKind::Spend => "Spend",
+            TransactionKind::Expense => "Expense",
+            TransactionKind::Gift => "Gift",
+            TransactionKind::Fee => "Fee",
+            TransactionKind::Fork => "Fork",
+            TransactionKind::Airdrop => "Airdrop",
+            TransactionKind::Loss => "Loss",
+        };
+        write!(f, "{}", kind)
+    }
+}
+
+impl FromStr for TransactionKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Buy" => Ok(TransactionKind::Buy),
+            "Sell" => Ok(TransactionKind::Sell),
+            "Contribution" => Ok(TransactionKind::Contribution),
+            "Deposit" => Ok(TransactionKind::Deposit),
+            "Withdrawal" => Ok(TransactionKind::Withdrawal),
+            "Income" => Ok(TransactionKind::Income),
+            "Spend" => Ok(TransactionKind::Spend),
+            "Expense" => Ok(TransactionKind::Expense),
+            "Gift" => Ok(TransactionKind::Gift),
+            "Fee" => Ok(TransactionKind::Fee),
+            "Fork" => Ok(TransactionKind::Fork),
+            "Airdrop" => Ok(TransactionKind::Airdrop),
+            "Loss" => Ok(TransactionKind::Loss),
+            x => Err(format!("Invalid transaction kind {}", x)),
+        }
+    }
+}
+
+/// Why a record of the transactions csv isn't a valid transaction
+#[derive(Debug, derive_more::Display)]
+pub enum RecordError {
+    #[display(fmt = "{}", _0)]
+    Kind(String),
+    #[display(fmt = "Invalid {} {}: {}", _0, _1, _2)]
+    DateTime(&'static str, String, chrono::ParseError),
+    #[display(fmt = "Invalid {} amount {} {}: {}", _0, _1, _2, _3)]
+    Amount(&'static str, String, String, ParseMoneyError),
+    #[display(fmt = "{} should have a {} amount", _0, _1)]
+    MissingAmount(TransactionKind, &'static str),
+}
+
+impl std::error::Error for RecordError {}
+
+fn parse_date_time(name: &'static str, date_time: &str) -> Result<NaiveDateTime, RecordError> {
+    DateTime::parse_from_rfc3339(date_time)
+        .map(|dt| dt.naive_utc())
+        .map_err(|err| RecordError::DateTime(name, date_time.to_string(), err))
+}
+
+impl<'a> TryFrom<TradeRecord> for Transaction<'a> {
+    type Error = RecordError;
+
+    fn try_from(tr: TradeRecord) -> Result<Self, Self::Error> {
+        let kind: TransactionKind = tr.kind.parse().map_err(RecordError::Kind)?;
+        let date_time = parse_date_time("date_time", &tr.date_time)?;
+        let exchange = if tr.exchange == "" {
+            None
+        } else {
+            Some(tr.exchange.clone())
+        };
+        let parse = |name: &'static str, asset: &str, amount: &str| {
+            if asset == "" {
+                Ok(None)
+            } else {
+                parse_money_parts(asset, amount).map(Some).map_err(|err| {
+                    RecordError::Amount(name, asset.to_string(), amount.to_string(), err)
+                })
+            }
+        };
+        let buy = parse("BUY", &tr.buy_asset, &tr.buy_amount)?;
+        let sell = parse("SELL", &tr.sell_asset, &tr.sell_amount)?;
+        let fee = parse("FEE", &tr.fee_asset, &tr.fee_amount)?;
+        let mut tx = Transaction::new(kind, date_time, buy, sell, fee, tr.rate, exchange)?;
+        if let Some(ref account) = tr.account {
+            tx.set_account(account);
+        }
+        let notes = if tr.notes == "" {
+            None
+        } else {
+            Some(tr.notes.clone())
+        };
+        match tx.movement_mut() {
+            Some(movement) => {
+                movement.ownership_changed = tr.ownership_changed.unwrap_or(false);
+                movement.notes = notes;
+            }
+            None => {
+                if let Transaction::Trade(ref mut trade) = tx {
+                    trade.notes = notes;
+                }
+            }
+        }
+        match (kind, tx) {
+            (TransactionKind::Contribution, Transaction::Trade(trade)) => {
+                let received = match tr.received_date_time {
+                    Some(ref dt) => parse_date_time("received_date_time", dt)?,
+                    None => trade.date_time,
+                };
+                Ok(Transaction::Contribution(Contribution { trade, received }))
+            }
+            (_, tx) => Ok(tx),
+        }
+    }
+}
+
+impl<'a> From<&Transaction<'a>> for TradeRecord {
+    fn from(tx: &Transaction<'a>) -> Self {
+        match tx {
+            Transaction::Trade(trade) => return trade.into(),
+            Transaction::Contribution(contribution) => {
+                let received = DateTime::<Utc>::from_utc(contribution.received, Utc);
+                return TradeRecord {
+                    kind: tx.kind().to_string(),
+                    received_date_time: Some(received.to_rfc3339()),
+                    ..(&contribution.trade).into()
+                };
+            }
+            _ => (),
+        }
+        let date_time = DateTime::<Utc>::from_utc(tx.date_time(), Utc).to_rfc3339();
+        let asset_amount = |money: Option<&Money>| {
+            money.map_or((String::new(), String::new()), |m| {
+                (m.currency().code.to_string(), exact_amount(m))
+            })
+        };
+        let (buy_asset, buy_amount) = asset_amount(tx.buy());
+        let (sell_asset, sell_amount) = asset_amount(tx.sell());
+        let (fee_asset, fee_amount) = asset_amount(Some(tx.fee()));
+
+        TradeRecord {
+            version: SCHEMA_VERSION,
+            date_time,
+            kind: tx.kind().to_string(),
+            buy_asset,
+            buy_amount,
+            sell_asset,
+            sell_amount,
+            fee_asset,
+            fee_amount,
+            rate: tx.rate(),
+            exchange: tx.exchange().cloned().unwrap_or(String::new()),
+            exchange_id: String::new(),
+            notes: tx
+                .movement()
+                .and_then(|m| m.notes.clone())
+                .unwrap_or(String::new()),
+            ownership_changed: tx
+                .movement()
+                .map(|m| m.ownership_changed)
+                .filter(|changed| *changed),
+            received_date_time: None,
+            account: tx.account().cloned(),
+        }
+    }
+}
+
+/// Combines trades on the same pair on the same day into a single trade, other transactions are
+/// left as they are.
+pub fn group_trades_by_day<'a>(transactions: Vec<Transaction<'a>>) -> Vec<Transaction<'a>> {
+    let (trades, mut others): (Vec<_>, Vec<_>) = transactions
+        .into_iter()
+        .partition(|tx| matches!(tx, Transaction::Trade(_)));
+    let trades = trades
+        .into_iter()
+        .filter_map(|tx| match tx {
+            Transaction::Trade(trade) => Some(trade),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    let mut grouped = trades::group_trades_by_day(&trades)
+        .into_iter()
+        .map(Transaction::Trade)
+        .collect::<Vec<_>>();
+    grouped.append(&mut others);
+    grouped.sort_by_key(|tx| tx.date_time());
+    grouped
+}
+
+pub fn read_csv<'a, R>(reader: R) -> color_eyre::Result<Vec<Transaction<'a>>>
+where
+    R: Read,
+{
+    let mut rdr = csv::Reader::from_reader(reader);
+    if !rdr.headers()?.iter().any(|header| header == "version") {
+        return Err(eyre::eyre!(
+            "Transactions csv has no version column, upgrade it with the `migrate` command"
+        ));
+    }
+    let records: Result<Vec<TradeRecord>, _> = rdr.deserialize::<TradeRecord>().collect();
+    let records = records?;
+    if let Some(record) = records.iter().find(|r| r.version != SCHEMA_VERSION) {
+        return Err(eyre::eyre!(
+            "Transactions csv version {} is not the supported version {}, upgrade it with the \
+            `migrate` command",
+            record.version,
+            SCHEMA_VERSION
+        ));
+    }
+    // the header is row 1
+    let mut transactions = records
+        .into_iter()
+        .enumerate()
+        .map(|(i, record)| {
+            Transaction::try_from(record)
+                .map_err(|err| eyre::eyre!("Invalid transaction in row {}: {}", i + 2, err))
+        })
+        .collect::<color_eyre::Result<Vec<_>>>()?;
+    transactions.sort_by_key(|tx| tx.date_time());
+    Ok(transactions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "version,date_time,kind,buy_asset,buy_amount,sell_asset,sell_amount,\
+                          fee_asset,fee_amount,rate,exchange,exchange_id,notes,\
+                          ownership_changed,received_date_time\n";
+
+    fn read(rows: &[&str]) -> color_eyre::Result<Vec<Transaction<'static>>> {
+        let mut csv = HEADER.to_string();
+        for row in rows {
+            csv.push_str(&format!("{},{}\n", SCHEMA_VERSION, row));
+        }
+        read_csv(csv.as_bytes())
+    }
+
+    #[test]
+    fn invalid_records_are_errors_with_their_row() {
+        let buy = "2018-01-01T12:00:00+00:00,Buy,BTC,1,GBP,100,GBP,0,100,,,,,";
+        assert_eq!(read(&[buy]).unwrap().len(), 1);
+
+        let error = |rows: &[&str]| read(rows).err().expect("Invalid record").to_string();
+        assert_eq!(
+            error(&[
+                buy,
+                "2018-01-02T12:00:00+00:00,Buy,XYZ,1,GBP,100,GBP,0,100,,,,,"
+            ]),
+            format!(
+                "Invalid transaction in row 3: Invalid BUY amount XYZ 1: {}",
+                crate::money::UnknownCurrency::new("XYZ")
+            )
+        );
+        assert!(error(&["2018-01-01,Buy,BTC,1,GBP,100,GBP,0,100,,,,,"])
+            .starts_with("Invalid transaction in row 2: Invalid date_time 2018-01-01:"));
+        assert_eq!(
+            error(&["2018-01-01T12:00:00+00:00,Buy,,,GBP,100,GBP,0,100,,,,,"]),
+            "Invalid transaction in row 2: Buy should have a BUY amount"
+        );
+        assert!(error(&[
+            "2018-01-01T12:00:00+00:00,Contribution,BTC,1,GBP,100,GBP,0,100,,,,,2018-02-01"
+        ])
+        .starts_with("Invalid transaction in row 2: Invalid received_date_time 2018-02-01:"));
+    }
+
+    #[test]
+    fn accounts_are_labelled_without_replacing_the_exchange() {
+        let mut tx = read(&["2018-01-01T12:00:00+00:00,Buy,BTC,1,GBP,100,GBP,0,100,Binance,,,,"])
+            .unwrap()
+            .remove(0);
+        tx.set_account("binance:bot");
+
+        let record = TradeRecord::from(&tx);
+        assert_eq!(record.exchange, "Binance");
+        assert_eq!(record.account, Some("binance:bot".to_string()));
+        let tx = Transaction::try_from(record).unwrap();
+        assert_eq!(tx.account(), Some(&"binance:bot".to_string()));
+        assert!(tx.is_on_account("binance"));
+        assert!(tx.is_on_account("Binance:Bot"));
+        assert!(!tx.is_on_account("binance:personal"));
+    }
+
+    #[test]
+    fn rates_are_recomputed_only_beyond_the_tolerance() {
+        let trade = |rate: &str| {
+            read(&[&format!(
+                "2018-01-01T12:00:00+00:00,Buy,BTC,1,GBP,100,GBP,0,{},,,,,",
+                rate
+            )])
+            .unwrap()
+            .remove(0)
+        };
+        let tolerance = Decimal::new(1, 2);
+
+        let mut at_tolerance = trade("99");
+        assert_eq!(at_tolerance.recompute_rate(tolerance), None);
+        assert_eq!(at_tolerance.rate(), Decimal::new(99, 0));
+
+        let mut beyond_tolerance = trade("98.9");
+        assert_eq!(
+            beyond_tolerance.recompute_rate(tolerance),
+            Some(Decimal::new(989, 1))
+        );
+        assert_eq!(beyond_tolerance.rate(), Decimal::new(100, 0));
+
+        let mut missing = trade("0");
+        assert_eq!(missing.recompute_rate(tolerance), Some(Decimal::new(0, 0)));
+        assert_eq!(missing.rate(), Decimal::new(100, 0));
+
+        let mut deposit = read(&["2018-01-01T12:00:00+00:00,Deposit,BTC,1,,,,,0,,,,,"])
+            .unwrap()
+            .remove(0);
+        assert_eq!(deposit.recompute_rate(tolerance), None);
+    }
+}
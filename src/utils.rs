@@ -10,3 +10,43 @@ where
     wtr.flush()?;
     Ok(())
 }
+
+/// Replace the contents of a file by writing them to a temporary file alongside it, then renaming
+/// it over the file, so that the file is never left half written
+pub fn replace_file<F>(path: &std::path::Path, write: F) -> color_eyre::Result<()>
+where
+    F: FnOnce(&mut std::fs::File) -> color_eyre::Result<()>,
+{
+    let mut tmp = path.as_os_str().to_os_string();
+    tmp.push(".tmp");
+    let tmp = std::path::PathBuf::from(tmp);
+    let mut file = std::fs::File::create(&tmp)?;
+    write(&mut file)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// The directory of cached data such as prices, `$TAXC_CACHE_DIR`, `$XDG_CACHE_HOME/taxc` or
+/// `~/.cache/taxc`
+pub fn cache_dir() -> Option<std::path::PathBuf> {
+    use std::path::PathBuf;
+    std::env::var_os("TAXC_CACHE_DIR")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("XDG_CACHE_HOME").map(|dir| PathBuf::from(dir).join("taxc")))
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache").join("taxc"))
+        })
+}
+
+/// The directory of the user config, `$TAXC_CONFIG_DIR`, `$XDG_CONFIG_HOME/taxc` or
+/// `~/.config/taxc`
+pub fn config_dir() -> Option<std::path::PathBuf> {
+    use std::path::PathBuf;
+    std::env::var_os("TAXC_CONFIG_DIR")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("XDG_CONFIG_HOME").map(|dir| PathBuf::from(dir).join("taxc")))
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config").join("taxc"))
+        })
+}
@@ -0,0 +1,201 @@
+//! Data quality heuristics over imported transactions, flagging records which are likely to be
+//! wrong or incomplete before they silently distort a report.
+
+use crate::{money::display_amount, transactions::Transaction};
+use chrono::NaiveDateTime;
+use rust_decimal::Decimal;
+use serde::Serialize;
+use std::{collections::HashMap, fmt};
+
+/// Maximum relative difference between a trade's rate and the rate implied by its amounts
+const RATE_TOLERANCE: (i64, u32) = (1, 2);
+/// Maximum fee as a proportion of the amount of the same currency traded
+const FEE_THRESHOLD: (i64, u32) = (10, 2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub enum Rule {
+    /// The trade rate doesn't match the buy and sell amounts
+    InconsistentRate,
+    /// The fee is more than 10% of the value of the trade
+    ExcessiveFee,
+    /// Another transaction has the same timestamp and amounts
+    Duplicate,
+    /// The currency only appears in a single transaction
+    SingleOccurrence,
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Warning {
+    pub rule: Rule,
+    pub date_time: NaiveDateTime,
+    pub message: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {}: {}", self.rule, self.date_time, self.message)
+    }
+}
+
+/// The default maximum relative difference between a trade's rate and its implied rate
+pub fn rate_tolerance() -> Decimal {
+    Decimal::new(RATE_TOLERANCE.0, RATE_TOLERANCE.1)
+}
+
+/// Run all the rules over the transactions, returning warnings ordered by date, rule and message
+pub fn check(transactions: &[Transaction]) -> Vec<Warning> {
+    let mut warnings = Vec::new();
+    warnings.extend(transactions.iter().filter_map(inconsistent_rate));
+    warnings.extend(transactions.iter().filter_map(excessive_fee));
+    warnings.extend(duplicates(transactions));
+    warnings.extend(single_occurrences(transactions));
+    warnings.sort_by(|w1, w2| {
+        (w1.date_time, w1.rule, &w1.message).cmp(&(w2.date_time, w2.rule, &w2.message))
+    });
+    warnings
+}
+
+fn inconsistent_rate(tx: &Transaction) -> Option<Warning> {
+    let trade = match tx {
+        Transaction::Trade(trade) => trade,
+        _ => return None,
+    };
+    let implied_rate = trade.implied_rate()?;
+    let difference = (implied_rate - trade.rate).abs().checked_div(trade.rate)?;
+    if difference > rate_tolerance() {
+        Some(Warning {
+            rule: Rule::InconsistentRate,
+            date_time: trade.date_time,
+            message: format!(
+                "rate {} differs from {:.8} implied by buy {} {} and sell {} {}",
+                trade.rate,
+                implied_rate,
+                display_amount(&trade.buy),
+                trade.buy.currency().code,
+                display_amount(&trade.sell),
+                trade.sell.currency().code,
+            ),
+        })
+    } else {
+        None
+    }
+}
+
+fn excessive_fee(tx: &Transaction) -> Option<Warning> {
+    let fee = tx.fee();
+    let amount = tx
+        .buy()
+        .filter(|buy| buy.currency() == fee.currency())
+        .or_else(|| tx.sell().filter(|sell| sell.currency() == fee.currency()))?;
+    let proportion = fee.amount().checked_div(*amount.amount())?;
+    if proportion > Decimal::new(FEE_THRESHOLD.0, FEE_THRESHOLD.1) {
+        Some(Warning {
+            rule: Rule::ExcessiveFee,
+            date_time: tx.date_time(),
+            message: format!(
+                "{} fee {} {} is more than 10% of {} {}",
+                tx.kind(),
+                display_amount(fee),
+                fee.currency().code,
+                display_amount(amount),
+                amount.currency().code,
+            ),
+        })
+    } else {
+        None
+    }
+}
+
+fn duplicates(transactions: &[Transaction]) -> Vec<Warning> {
+    let mut seen = HashMap::new();
+    for tx in transactions {
+        let key = (
+            tx.date_time(),
+            tx.kind(),
+            tx.buy().map(ToString::to_string),
+            tx.sell().map(ToString::to_string),
+        );
+        *seen.entry(key).or_insert(0) += 1;
+    }
+    seen.into_iter()
+        .filter(|(_, count)| *count > 1)
+        .map(|((date_time, kind, buy, sell), count)| Warning {
+            rule: Rule::Duplicate,
+            date_time,
+            message: format!(
+                "{} {} transactions with buy {} and sell {}",
+                count,
+                kind,
+                buy.unwrap_or_default(),
+                sell.unwrap_or_default()
+            ),
+        })
+        .collect()
+}
+
+fn single_occurrences(transactions: &[Transaction]) -> Vec<Warning> {
+    let mut occurrences = HashMap::new();
+    for tx in transactions {
+        for money in tx.buy().into_iter().chain(tx.sell()) {
+            let entry = occurrences
+                .entry(money.currency().code)
+                .or_insert((0, tx.date_time()));
+            entry.0 += 1;
+        }
+    }
+    occurrences
+        .into_iter()
+        .filter(|(_, (count, _))| *count == 1)
+        .map(|(code, (_, date_time))| Warning {
+            rule: Rule::SingleOccurrence,
+            date_time,
+            message: format!("{} only appears in a single transaction", code),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{trades::SCHEMA_VERSION, transactions};
+
+    #[test]
+    fn warnings_are_returned_by_date_and_rule() {
+        let mut csv = "version,date_time,kind,buy_asset,buy_amount,sell_asset,sell_amount,\
+                       fee_asset,fee_amount,rate,exchange,exchange_id,notes,\
+                       ownership_changed,received_date_time\n"
+            .to_string();
+        for row in &[
+            "2018-01-01T12:00:00+00:00,Buy,BTC,1,GBP,100,GBP,0,100",
+            "2018-01-02T12:00:00+00:00,Buy,BTC,1,GBP,100,GBP,0,50",
+            "2018-01-03T12:00:00+00:00,Buy,ETH,10,GBP,100,ETH,2,10",
+            "2018-01-04T12:00:00+00:00,Buy,BTC,1,GBP,100,GBP,0,100",
+            "2018-01-04T12:00:00+00:00,Buy,BTC,1,GBP,100,GBP,0,100",
+        ] {
+            csv.push_str(&format!("{},{},,,,,\n", SCHEMA_VERSION, row));
+        }
+        let transactions = transactions::read_csv(csv.as_bytes()).unwrap();
+
+        let warnings = check(&transactions);
+        assert_eq!(
+            warnings
+                .iter()
+                .map(|w| (w.date_time.date().to_string(), w.rule))
+                .collect::<Vec<_>>(),
+            vec![
+                ("2018-01-02".to_string(), Rule::InconsistentRate),
+                ("2018-01-03".to_string(), Rule::ExcessiveFee),
+                ("2018-01-03".to_string(), Rule::SingleOccurrence),
+                ("2018-01-04".to_string(), Rule::Duplicate),
+            ]
+        );
+        // nothing is kept between checks, so the same transactions give the same warnings
+        assert_eq!(check(&transactions).len(), warnings.len());
+    }
+}